@@ -0,0 +1,96 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Builds with the denoise feature link against the Open Image Denoise
+// library, see src/denoise.rs. Without it the link fails at the very end
+// with a wall of undefined symbols, so the library is looked for here first
+// and the build stops with a plain error when it is nowhere to be found.
+fn main() {
+    println!("cargo:rerun-if-env-changed=OIDN_DIR");
+    println!("cargo:rerun-if-env-changed=LIBRARY_PATH");
+    if env::var_os("CARGO_FEATURE_DENOISE").is_none() {
+        return;
+    }
+
+    let dirs = library_dirs();
+    match dirs.iter().find(|d| has_library(d)) {
+        Some(dir) => println!("cargo:rustc-link-search=native={}", dir.display()),
+        None => {
+            eprintln!(
+                "The denoise feature needs Intel Open Image Denoise \
+                 (https://www.openimagedenoise.org), but its library was not found in any of:\n\
+                 {}Install it, set OIDN_DIR to where it is installed, or build without the \
+                 denoise feature (--all-features includes it).",
+                dirs.iter()
+                    .map(|d| format!("    {}\n", d.display()))
+                    .collect::<String>()
+            );
+            process::exit(1);
+        }
+    }
+}
+
+// Where the library may be: under OIDN_DIR, then the directories given to
+// the linker with -L in RUSTFLAGS or with LIBRARY_PATH, then the usual
+// system directories
+fn library_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    match env::var_os("OIDN_DIR") {
+        Some(root) => {
+            let root = PathBuf::from(root);
+            dirs.push(root.join("lib"));
+            dirs.push(root.join("lib64"));
+            dirs.push(root);
+        }
+        None => {}
+    }
+    match env::var("CARGO_ENCODED_RUSTFLAGS") {
+        Ok(flags) => {
+            let flags: Vec<&str> = flags.split('\x1f').collect();
+            for (i, flag) in flags.iter().enumerate() {
+                let dir = match flag.strip_prefix("-L") {
+                    Some("") => flags.get(i + 1).copied().unwrap_or(""),
+                    Some(d) => d,
+                    None => continue,
+                };
+                // -L takes an optional kind, as in -L native=path
+                let dir = match dir.find('=') {
+                    Some(at) => &dir[at + 1..],
+                    None => dir,
+                };
+                dirs.push(PathBuf::from(dir));
+            }
+        }
+        Err(_) => {}
+    }
+    match env::var_os("LIBRARY_PATH") {
+        Some(paths) => dirs.extend(env::split_paths(&paths)),
+        None => {}
+    }
+    for dir in [
+        "/usr/local/lib",
+        "/usr/local/lib64",
+        "/usr/lib",
+        "/usr/lib64",
+        "/usr/lib/x86_64-linux-gnu",
+        "/usr/lib/aarch64-linux-gnu",
+        "/opt/homebrew/lib",
+    ]
+    .iter()
+    {
+        dirs.push(PathBuf::from(dir));
+    }
+    return dirs;
+}
+
+fn has_library(dir: &Path) -> bool {
+    return [
+        "libOpenImageDenoise.so",
+        "libOpenImageDenoise.dylib",
+        "libOpenImageDenoise.a",
+        "OpenImageDenoise.lib",
+    ]
+    .iter()
+    .any(|name| dir.join(name).exists());
+}
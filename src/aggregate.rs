@@ -1,60 +1,230 @@
 use crate::color::RGB;
+use crate::light::{LightSample, SceneLight};
 use crate::material::Reflectance;
 use crate::pdf;
 use crate::point::Point3;
-use crate::ray::Ray;
-use crate::shape::SyncShape;
+use crate::profile;
+use crate::profile::Stage;
+use crate::ray::{Ray, RayKind};
+use crate::resampling;
+use crate::resampling::TileReservoirs;
+use crate::rng;
+use crate::scene::Scene;
+use crate::shape::{Hit, SyncShape};
+use crate::stats;
+use crate::stats::Statistics;
 use crate::utils;
-use crate::vector::Axis;
+use crate::vector::{Axis, Vector3};
 
 use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{Read, Write};
 use std::mem;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 
 const MAX_DEPTH: i32 = 50;
 
+// The kind of the ray decides which materials it sees, and the shape it
+// leaves from, keyed as in the stats module, decides which light linked
+// emitters it can see. Camera rays leave from none and see every emitter.
 pub fn trace(
     r: &Ray,
-    shape_aggregate: &SyncAggregate,
-    important_samples: &pdf::PDF,
+    scene: &Scene,
     workspace: &mut Workspace,
     bg_func: &dyn Fn(&Ray) -> RGB,
     depth: i32,
+    kind: RayKind,
+    from_shape: Option<usize>,
+    stats: &mut Statistics,
 ) -> RGB {
-    let hit_shape = hit(shape_aggregate, workspace, r);
+    return trace_range(
+        r,
+        scene.ray_epsilon,
+        utils::T_MAX,
+        scene,
+        workspace,
+        bg_func,
+        depth,
+        kind,
+        from_shape,
+        None,
+        stats,
+    );
+}
 
+// Like trace, but only hits between 't_min' and 't_max' along the ray count.
+// Camera rays use this for clipping planes, and pass the tile's reservoirs
+// when light resampling reuses samples across pixels.
+pub fn trace_range(
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+    scene: &Scene,
+    workspace: &mut Workspace,
+    bg_func: &dyn Fn(&Ray) -> RGB,
+    depth: i32,
+    kind: RayKind,
+    from_shape: Option<usize>,
+    reservoirs: Option<&mut TileReservoirs>,
+    stats: &mut Statistics,
+) -> RGB {
+    stats.record_ray();
+    stats.shape = 0;
+    stats.material = 0;
+    // The clock is only read for sampled paths
+    let hit_start = if stats.sampling {
+        Some(Instant::now())
+    } else {
+        None
+    };
+    let hit_timer = profile::start(Stage::Intersection);
+    let mut hit_shape = hit(&(*scene.shape_aggregate), workspace, r, t_min, t_max);
+    // Shapes with materials hidden from this kind of ray, or cut out by
+    // their alpha masks, are passed through to whatever is behind them
+    while !scene.material_visibility.is_empty() || !scene.alpha_masks.is_empty() {
+        match hit_shape {
+            Some((s, h)) if !sees(scene, s, kind) || cut_out(scene, s, r, &h) => {
+                hit_shape = hit(
+                    &(*scene.shape_aggregate),
+                    workspace,
+                    r,
+                    h.t + scene.ray_epsilon,
+                    t_max,
+                );
+            }
+            _ => break,
+        }
+    }
+    drop(hit_timer);
+    match hit_start {
+        Some(start) => stats.record_intersection(
+            match hit_shape {
+                Some((s, _)) => Some(s),
+                None => None,
+            },
+            start.elapsed(),
+        ),
+        None => {}
+    }
+
+    let radiance = shade(
+        r, hit_shape, scene, workspace, bg_func, depth, from_shape, reservoirs, stats,
+    );
+    match &scene.fog {
+        Some(fog) => {
+            let t = match hit_shape {
+                Some((_, h)) => h.t,
+                None => std::f32::INFINITY,
+            };
+            return fog.apply(r, t, radiance);
+        }
+        None => return radiance,
+    }
+}
+
+// Finds the radiance arriving along the ray from whatever it hit, or from
+// the background if it hit nothing.
+fn shade(
+    r: &Ray,
+    hit_shape: Option<(&SyncShape, Hit)>,
+    scene: &Scene,
+    workspace: &mut Workspace,
+    bg_func: &dyn Fn(&Ray) -> RGB,
+    depth: i32,
+    from_shape: Option<usize>,
+    reservoirs: Option<&mut TileReservoirs>,
+    stats: &mut Statistics,
+) -> RGB {
     if depth < MAX_DEPTH {
         match hit_shape {
             // Some if we have a hit
-            Some((s, t)) => {
-                let mut hit_props = s.get_hit_properties(r, t);
+            Some((s, h)) => {
+                let t = h.t;
+                // Shading time covers everything up to the recursive trace
+                let shading_start = if stats.sampling {
+                    Some(Instant::now())
+                } else {
+                    None
+                };
+                let shading_timer = profile::start(Stage::Shading);
+                let shape_key = stats::shape_key(s);
+                stats.shape = shape_key;
+                stats.material = stats::material_key(&(**s.get_material()));
+                let mut hit_props = s.get_hit_properties(r, &h);
                 hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+                hit_props.set_footprint(r, t);
 
                 match s.get_material().emit(r, &hit_props) {
                     Some(e) => {
+                        match shading_start {
+                            Some(start) => {
+                                stats.record_shading(&(**s.get_material()), start.elapsed())
+                            }
+                            None => {}
+                        }
+                        let linked_away = match (scene.emitter_links.get(&shape_key), from_shape) {
+                            (Some(links), Some(from)) => !links.lights(from),
+                            _ => false,
+                        };
+                        // One sided emitters are dark from behind
+                        let from_behind =
+                            !s.get_material().is_two_sided() && !s.is_front_face(r, &h);
+                        if linked_away || from_behind {
+                            return RGB::black();
+                        }
                         return e;
                     }
                     None => {}
                 }
 
-                match s.get_material().scatter(r, &hit_props) {
+                let scattered = s.get_material().scatter(r, &hit_props);
+                drop(shading_timer);
+                match shading_start {
+                    Some(start) => stats.record_shading(&(**s.get_material()), start.elapsed()),
+                    None => {}
+                }
+                match scattered {
                     // Some if we scattered
                     Some(scattered_props) => {
                         match scattered_props.reflectance {
                             // Specular rays get normal recursive case
-                            Reflectance::Specular(r) => {
+                            Reflectance::Specular(scattered) => {
                                 return scattered_props.attenuation
                                     * trace(
-                                        &r,
-                                        shape_aggregate,
-                                        important_samples,
+                                        &scattered.continuing(r),
+                                        scene,
                                         workspace,
                                         bg_func,
                                         depth + 1,
+                                        RayKind::Glossy,
+                                        Some(shape_key),
+                                        stats,
                                     )
                             }
+                            // Flat bands of the direct light, without tracing on
+                            Reflectance::Toon(toon) => {
+                                let direct = direct_lighting(
+                                    &hit_props.hit_point,
+                                    r.time,
+                                    shape_key,
+                                    &pdf::PDF::Cosine(pdf::Cosine::new(toon.normal)),
+                                    scene,
+                                    workspace,
+                                    stats,
+                                );
+                                return scattered_props.attenuation * toon.banded(direct)
+                                    + toon.rim;
+                            }
                             // Otherwise use importance sampling
                             Reflectance::PDF(hit_pdf) => {
+                                let important_samples = &(*scene.important_samples);
+                                let pdf_timer = profile::start(Stage::PDF);
                                 let (scattered, pdf_val) = if important_samples.is_valid() {
                                     let scattered = Ray::new(
                                         hit_props.hit_point,
@@ -63,7 +233,9 @@ pub fn trace(
                                             &hit_pdf,
                                             &hit_props.hit_point,
                                         ),
-                                    );
+                                        r.time,
+                                    )
+                                    .continuing(r);
                                     let val =
                                         pdf::pair_value(important_samples, &hit_pdf, &scattered);
                                     (scattered, val)
@@ -71,22 +243,57 @@ pub fn trace(
                                     let scattered = Ray::new(
                                         hit_props.hit_point,
                                         hit_pdf.generate(&hit_props.hit_point),
-                                    );
+                                        r.time,
+                                    )
+                                    .continuing(r);
                                     let val = hit_pdf.value(&scattered);
                                     (scattered, val)
                                 };
+                                let scatter_val = hit_pdf.scattering(&scattered);
+                                let scattered_kind = match hit_pdf {
+                                    pdf::PDF::GGX(_) => RayKind::Glossy,
+                                    _ => RayKind::Diffuse,
+                                };
+                                drop(pdf_timer);
 
-                                return scattered_props.attenuation
-                                    * hit_pdf.value(&scattered)
-                                    * trace(
-                                        &scattered,
-                                        shape_aggregate,
-                                        important_samples,
+                                // Lights that rays can not hit are sampled directly
+                                let direct = match &scene.logistics.light_resampling {
+                                    Some(settings) => resampling::direct_lighting(
+                                        settings, &hit_props, r, t, shape_key, &hit_pdf,
+                                        reservoirs, scene, workspace, stats,
+                                    ),
+                                    None => direct_lighting(
+                                        &hit_props.hit_point,
+                                        r.time,
+                                        shape_key,
+                                        &hit_pdf,
+                                        scene,
                                         workspace,
-                                        bg_func,
-                                        depth + 1,
-                                    )
-                                    / pdf_val;
+                                        stats,
+                                    ),
+                                };
+
+                                // A direction no PDF can produce adds nothing, and
+                                // skipping it avoids a 0/0 from samples that graze a
+                                // curved light's silhouette
+                                if !(pdf_val > 0.0_f32) {
+                                    return scattered_props.attenuation * direct;
+                                }
+
+                                return scattered_props.attenuation * direct
+                                    + scattered_props.attenuation
+                                        * scatter_val
+                                        * trace(
+                                            &scattered,
+                                            scene,
+                                            workspace,
+                                            bg_func,
+                                            depth + 1,
+                                            scattered_kind,
+                                            Some(shape_key),
+                                            stats,
+                                        )
+                                        / pdf_val;
                             }
                         }
                     }
@@ -104,6 +311,291 @@ pub fn trace(
     return bg_func(r);
 }
 
+// Like trace, for a ray whose hit was already found, for callers that need
+// to know what it hit. Fog, alpha masks and hidden materials are left out.
+pub fn trace_hit(
+    r: &Ray,
+    hit_shape: Option<(&SyncShape, Hit)>,
+    scene: &Scene,
+    workspace: &mut Workspace,
+    bg_func: &dyn Fn(&Ray) -> RGB,
+    depth: i32,
+    from_shape: Option<usize>,
+    stats: &mut Statistics,
+) -> RGB {
+    stats.record_ray();
+    return shade(
+        r, hit_shape, scene, workspace, bg_func, depth, from_shape, None, stats,
+    );
+}
+
+// What a camera ray finds at its first hit, split up for gradient domain
+// rendering (see the gradient module) into the light the scene's lights
+// send straight to the hit, and what else the hit emits or scatters, which
+// the caller traces on. Scattering and direct lighting draw their random
+// numbers from the given seeds, so that the same ray through a neighboring
+// pixel draws the same ones. Like trace_hit, for scenes without fog, alpha
+// masks, hidden materials or emitter links.
+pub struct FirstHit {
+    pub direct: RGB,
+    pub scattering: FirstScattering,
+}
+
+pub enum FirstScattering {
+    // The ray missed, or hit a Toon material or one that absorbed it
+    None,
+    Emits(RGB),
+    Specular(RGB, Ray),
+    // Scattered by a PDF, from the point on the shape with the key, where
+    // it has the normal
+    Rough {
+        hit_point: Point3,
+        normal: Vector3,
+        shape_key: usize,
+        attenuation: RGB,
+        pdf: pdf::PDF,
+    },
+}
+
+pub fn first_hit(
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+    scene: &Scene,
+    workspace: &mut Workspace,
+    seeds: (u64, u64),
+    stats: &mut Statistics,
+) -> FirstHit {
+    stats.record_ray();
+    let nothing = FirstHit {
+        direct: RGB::black(),
+        scattering: FirstScattering::None,
+    };
+    let (s, h) = match hit(&(*scene.shape_aggregate), workspace, r, t_min, t_max) {
+        Some(hit_shape) => hit_shape,
+        None => return nothing,
+    };
+    let t = h.t;
+    let shape_key = stats::shape_key(s);
+    stats.shape = shape_key;
+    stats.material = stats::material_key(&(**s.get_material()));
+    let mut hit_props = s.get_hit_properties(r, &h);
+    hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+    hit_props.set_footprint(r, t);
+
+    match s.get_material().emit(r, &hit_props) {
+        Some(e) => {
+            if !s.get_material().is_two_sided() && !s.is_front_face(r, &h) {
+                return nothing;
+            }
+            return FirstHit {
+                direct: RGB::black(),
+                scattering: FirstScattering::Emits(e),
+            };
+        }
+        None => {}
+    }
+
+    rng::replay(seeds.0);
+    let scattered_props = match s.get_material().scatter(r, &hit_props) {
+        Some(p) => p,
+        None => return nothing,
+    };
+    rng::replay(seeds.1);
+    match scattered_props.reflectance {
+        Reflectance::Specular(scattered) => {
+            return FirstHit {
+                direct: RGB::black(),
+                scattering: FirstScattering::Specular(
+                    scattered_props.attenuation,
+                    scattered.continuing(r),
+                ),
+            };
+        }
+        Reflectance::Toon(toon) => {
+            let direct = direct_lighting(
+                &hit_props.hit_point,
+                r.time,
+                shape_key,
+                &pdf::PDF::Cosine(pdf::Cosine::new(toon.normal)),
+                scene,
+                workspace,
+                stats,
+            );
+            return FirstHit {
+                direct: scattered_props.attenuation * toon.banded(direct) + toon.rim,
+                scattering: FirstScattering::None,
+            };
+        }
+        Reflectance::PDF(hit_pdf) => {
+            let direct = match &scene.logistics.light_resampling {
+                Some(settings) => resampling::direct_lighting(
+                    settings, &hit_props, r, t, shape_key, &hit_pdf, None, scene, workspace, stats,
+                ),
+                None => direct_lighting(
+                    &hit_props.hit_point,
+                    r.time,
+                    shape_key,
+                    &hit_pdf,
+                    scene,
+                    workspace,
+                    stats,
+                ),
+            };
+            return FirstHit {
+                direct: scattered_props.attenuation * direct,
+                scattering: FirstScattering::Rough {
+                    hit_point: hit_props.hit_point,
+                    normal: hit_props.normal,
+                    shape_key: shape_key,
+                    attenuation: scattered_props.attenuation,
+                    pdf: hit_pdf,
+                },
+            };
+        }
+    }
+}
+
+// Whether a ray passes through a shape at a hit, at random as often as the
+// shape's alpha mask is dark there
+fn cut_out(scene: &Scene, shape: &SyncShape, r: &Ray, h: &Hit) -> bool {
+    match scene.alpha_masks.get(&stats::shape_key(shape)) {
+        Some(mask) => {
+            let hit_props = shape.get_hit_properties(r, h);
+            let alpha = mask
+                .value(&hit_props.uv.clamp_to_valid_coords(), &hit_props.hit_point)
+                .luminance();
+            return rng::random() >= utils::clamp(alpha, 0.0_f32, 1.0_f32);
+        }
+        None => return false,
+    }
+}
+
+fn sees(scene: &Scene, shape: &SyncShape, kind: RayKind) -> bool {
+    match scene
+        .material_visibility
+        .get(&stats::material_key(&(**shape.get_material())))
+    {
+        Some(v) => return v.sees(kind),
+        None => return true,
+    }
+}
+
+// Next event estimation for the scene's lights: each one is sampled from the
+// point and weighted by how much the surface scatters its light, which for
+// diffuse surfaces is the cosine term of the BRDF. A shadow ray checks that
+// nothing blocks the light. Lights linked away from the shape are skipped.
+fn direct_lighting(
+    point: &Point3,
+    time: f32,
+    shape_key: usize,
+    scatter_pdf: &pdf::PDF,
+    scene: &Scene,
+    workspace: &mut Workspace,
+    stats: &mut Statistics,
+) -> RGB {
+    let mut direct = RGB::black();
+    for scene_light in &scene.lights {
+        let u = (rng::random(), rng::random());
+        direct = direct
+            + light_contribution(
+                point,
+                time,
+                shape_key,
+                scatter_pdf,
+                scene_light,
+                u,
+                scene,
+                workspace,
+                stats,
+            );
+    }
+    return direct;
+}
+
+// The light scattered from one sample of a light, picked by the pair of
+// numbers in [0, 1), after shadowing
+pub fn light_contribution(
+    point: &Point3,
+    time: f32,
+    shape_key: usize,
+    scatter_pdf: &pdf::PDF,
+    scene_light: &SceneLight,
+    u: (f32, f32),
+    scene: &Scene,
+    workspace: &mut Workspace,
+    stats: &mut Statistics,
+) -> RGB {
+    let (shadow_ray, light_sample, weight) =
+        match unshadowed_light(point, time, shape_key, scatter_pdf, scene_light, u) {
+            Some(l) => l,
+            None => return RGB::black(),
+        };
+
+    let shadows = &scene_light.shadows;
+    let mut shadow_color = RGB::new(1.0_f32, 1.0_f32, 1.0_f32);
+    let mut t_min = scene.ray_epsilon;
+    loop {
+        stats.record_ray();
+        let hit_timer = profile::start(Stage::Intersection);
+        let hit = scene
+            .shape_aggregate
+            .hit(&shadow_ray, t_min, light_sample.distance, workspace);
+        drop(hit_timer);
+        match hit {
+            // Shapes that cast no shadow from this light, or are cut out
+            // by their alpha masks, are passed through to whatever is
+            // behind them
+            Some((s, h))
+                if !shadows.casts(stats::shape_key(s)) || cut_out(scene, s, &shadow_ray, &h) =>
+            {
+                t_min = h.t + scene.ray_epsilon;
+            }
+            Some(_) => {
+                shadow_color = shadows.transmission();
+                break;
+            }
+            None => break,
+        }
+    }
+    if shadow_color.r().max(shadow_color.g()).max(shadow_color.b()) <= 0.0_f32 {
+        return RGB::black();
+    }
+
+    let transmittance = match &scene.fog {
+        Some(fog) => fog.transmittance(&shadow_ray, light_sample.distance),
+        None => 1.0_f32,
+    };
+    return light_sample.radiance * shadow_color * (weight * transmittance);
+}
+
+// The ray toward one sample of a light, the sample, and how much of it the
+// surface scatters, before anything can shadow it. None when the light is
+// linked away from the shape, can not reach the point, or the surface
+// scatters none of it.
+pub fn unshadowed_light(
+    point: &Point3,
+    time: f32,
+    shape_key: usize,
+    scatter_pdf: &pdf::PDF,
+    scene_light: &SceneLight,
+    u: (f32, f32),
+) -> Option<(Ray, LightSample, f32)> {
+    match &scene_light.links {
+        Some(l) if !l.lights(shape_key) => return None,
+        _ => {}
+    }
+    let light_sample = scene_light.light.sample(point, u)?;
+    let shadow_ray = Ray::new(*point, light_sample.dir, time);
+    let pdf_timer = profile::start(Stage::PDF);
+    let weight = scatter_pdf.scattering(&shadow_ray);
+    drop(pdf_timer);
+    if !(weight > 0.0_f32) {
+        return None;
+    }
+    return Some((shadow_ray, light_sample, weight));
+}
+
 // Workspaces are optional, but some aggregate structures (like BVH)
 // can use them to improve performance.
 pub enum Workspace {
@@ -112,8 +604,8 @@ pub enum Workspace {
 }
 
 pub trait Aggregate {
-    // Hit for an aggregate returns the shape that was hit and the time
-    // 't' at which it was hit along the path of the ray.
+    // Hit for an aggregate returns the shape that was hit and the hit on it,
+    // with the time 't' at which it was hit along the path of the ray.
     // None is returned if no shape is hit.
     fn hit(
         &self,
@@ -121,11 +613,23 @@ pub trait Aggregate {
         t_min: f32,
         t_max: f32,
         workspaces: &mut Workspace,
-    ) -> Option<(&SyncShape, f32)>;
+    ) -> Option<(&SyncShape, Hit<'_>)>;
 
     fn get_workspace(&self) -> Workspace {
         return Workspace::Void;
     }
+
+    // Human readable summary of the structure, used by the info subcommand.
+    fn info(&self) -> String;
+
+    // Bounding boxes of the nodes at the given depth below the root, along
+    // with any leaves above it, or of every leaf when no depth is given.
+    // Used by the info subcommand to export bounds for debugging.
+    fn node_bounds(&self, depth: Option<usize>) -> Vec<AABB>;
+
+    // Every shape in the aggregate. Shapes split during construction are
+    // listed once for each piece.
+    fn shapes(&self) -> Vec<&SyncShape>;
 }
 pub type SyncAggregate = dyn Aggregate + Send + Sync;
 
@@ -134,45 +638,92 @@ fn hit<'a>(
     aggregate: &'a SyncAggregate,
     workspace: &mut Workspace,
     r: &Ray,
-) -> Option<(&'a SyncShape, f32)> {
-    aggregate.hit(r, utils::T_MIN, utils::T_MAX, workspace)
+    t_min: f32,
+    t_max: f32,
+) -> Option<(&'a SyncShape, Hit<'a>)> {
+    aggregate.hit(r, t_min, t_max, workspace)
 }
 
 // Simple list aggregate
 type List = Vec<Arc<SyncShape>>;
 
-impl Aggregate for List {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, _: &mut Workspace) -> Option<(&SyncShape, f32)> {
-        let mut modified_t_max = t_max;
-        let mut hit_shape: Option<&SyncShape> = None;
+// Closest hit among a run of shapes, shared by lists and BVH leaves
+fn hit_shapes<'a>(
+    shapes: &'a [Arc<SyncShape>],
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(&'a SyncShape, Hit<'a>)> {
+    let mut modified_t_max = t_max;
+    let mut hit_shape: Option<(&SyncShape, Hit)> = None;
 
-        for shape in self {
-            match shape.hit(r, t_min, modified_t_max) {
-                Some(t) => {
-                    modified_t_max = t;
-                    hit_shape = Some(&(*(*shape)));
-                }
-                // No-op
-                None => {}
+    for shape in shapes {
+        match shape.hit(r, t_min, modified_t_max) {
+            Some(h) => {
+                modified_t_max = h.t;
+                hit_shape = Some((&(*(*shape)), h));
             }
+            // No-op
+            None => {}
         }
+    }
 
-        match hit_shape {
-            Some(s) => Some((s, modified_t_max)),
-            None => None,
+    return hit_shape;
+}
+
+impl Aggregate for List {
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _: &mut Workspace,
+    ) -> Option<(&SyncShape, Hit<'_>)> {
+        hit_shapes(self, r, t_min, t_max)
+    }
+
+    fn info(&self) -> String {
+        // A list has nothing interesting to say about itself, so also report
+        // what a BVH over the same shapes would look like for comparison.
+        let bvh = new_bvh(self.clone(), &BVHSettings::new());
+        format!(
+            "List aggregate with {} shapes.\nA BVH over the same shapes would have:\n{}",
+            self.len(),
+            bvh.info()
+        )
+    }
+
+    // The list is a single leaf
+    fn node_bounds(&self, _depth: Option<usize>) -> Vec<AABB> {
+        let mut bounds = AABB::new_empty();
+        for shape in self {
+            bounds = AABB::union(&bounds, &shape.get_bounding_box());
         }
+        return vec![bounds];
+    }
+
+    fn shapes(&self) -> Vec<&SyncShape> {
+        return self.iter().map(|s| &(**s)).collect();
     }
 }
 
 // Bounding Volume Hierarchy
-type BVH = Vec<BVHTypes>;
+// The shapes of every leaf sit next to each other in one list owned by the
+// tree, in the order traversal reaches the leaves, and a leaf is just a
+// range of that list. Leaves need no allocation of their own, and the
+// shapes of nearby leaves are near each other in memory.
+struct BVH {
+    nodes: Vec<BVHTypes>,
+    shapes: List,
+}
 enum BVHTypes {
     Leaf(BVHLeaf),
     Node(BVHNode),
 }
 struct BVHLeaf {
     bounding_box: AABB,
-    shapes: List, // Just uses the simple shape list aggregate
+    first_shape: usize,
+    shape_count: usize,
 }
 struct BVHNode {
     bounding_box: AABB,
@@ -183,33 +734,246 @@ struct BVHNode {
     right_offset: usize,
 }
 
-// Constructs a new BVH using the Surface Area Heuristic (SAH).
-pub fn new_bvh(shapes: Vec<Arc<SyncShape>>) -> Box<SyncAggregate> {
-    let mut bvh = Box::new(Vec::new());
-    new_bvh_helper(&mut (*bvh), shapes);
+// A shape as seen by BVH construction. Normally the bounding box is just the
+// shape's own, but when splitting large shapes, a shape may be referenced
+// several times with each reference covering a different piece of it.
+struct BVHReference {
+    shape: Arc<SyncShape>,
+    bounding_box: AABB,
+}
+
+impl BVHReference {
+    fn new(shape: Arc<SyncShape>) -> BVHReference {
+        BVHReference {
+            bounding_box: shape.get_bounding_box(),
+            shape: shape,
+        }
+    }
+}
+
+// Controls how much effort goes into BVH construction.
+pub enum BuildQuality {
+    // Splits every node at the median shape, skipping SAH entirely. Fast to
+    // build, but slower to trace.
+    Low,
+    // Full sweep of every split point with SAH.
+    Medium,
+    // SAH, plus pre-splitting of large shapes in the style of an SBVH.
+    High,
+}
+
+pub struct BVHSettings {
+    // Nodes with this many shapes or fewer always become leaves.
+    pub leaf_size: usize,
+    // Nodes with more shapes than this are always split, even if SAH
+    // would prefer a leaf.
+    pub max_leaf_size: usize,
+    // SAH cost of testing a ray against a node's bounding box.
+    pub traversal_cost: f32,
+    // SAH cost of testing a ray against a single shape.
+    pub intersection_cost: f32,
+    pub quality: BuildQuality,
+}
+
+impl BVHSettings {
+    pub fn new() -> BVHSettings {
+        BVHSettings {
+            leaf_size: 2,
+            max_leaf_size: std::usize::MAX,
+            traversal_cost: 1.0_f32,
+            intersection_cost: 1.0_f32,
+            quality: BuildQuality::Medium,
+        }
+    }
+}
+
+// Constructs a new BVH, using the Surface Area Heuristic (SAH) unless
+// settings ask for a low quality build.
+pub fn new_bvh(shapes: Vec<Arc<SyncShape>>, settings: &BVHSettings) -> Box<SyncAggregate> {
+    Box::new(build_bvh(shapes, settings))
+}
+
+fn build_bvh(shapes: Vec<Arc<SyncShape>>, settings: &BVHSettings) -> BVH {
+    let references = match settings.quality {
+        BuildQuality::High => pre_split(shapes),
+        _ => shapes.into_iter().map(BVHReference::new).collect(),
+    };
+
+    let mut bvh = BVH::new();
+    new_bvh_helper(&mut bvh, references, settings);
     return bvh;
 }
+
+impl BVH {
+    fn new() -> BVH {
+        BVH {
+            nodes: Vec::new(),
+            shapes: Vec::new(),
+        }
+    }
+
+    fn leaf_shapes(&self, leaf: &BVHLeaf) -> &[Arc<SyncShape>] {
+        &self.shapes[leaf.first_shape..leaf.first_shape + leaf.shape_count]
+    }
+}
+
+// Same as new_bvh, but first tries to read an already built BVH from the
+// cache file, and writes the BVH it builds to the cache file otherwise.
+// The cache only stores node bounds and indices into the shape list, so the
+// shapes must be given in the same order every time. A key derived from
+// the shapes' bounds and the build settings is stored alongside it, and a
+// cache with a different key is rebuilt rather than trusted.
+pub fn new_cached_bvh(
+    shapes: Vec<Arc<SyncShape>>,
+    settings: &BVHSettings,
+    cache_path: &Path,
+) -> Box<SyncAggregate> {
+    let key = cache_key(&shapes, settings);
+    if cache_path.exists() {
+        match read_bvh_cache(cache_path, &shapes, key) {
+            Ok(bvh) => return Box::new(bvh),
+            Err(e) => eprintln!(
+                "Rebuilding BVH, could not use cache {}: {}",
+                cache_path.display(),
+                e
+            ),
+        }
+    }
+
+    let indices = shape_indices(&shapes);
+    let bvh = build_bvh(shapes, settings);
+    match write_bvh_cache(cache_path, &bvh, &indices, key) {
+        Ok(()) => {}
+        Err(e) => eprintln!("Failed to write BVH cache {}: {}", cache_path.display(), e),
+    }
+    return Box::new(bvh);
+}
+
+// Pre-splitting is used by high quality builds in place of the full spatial
+// splits of an SBVH. Shapes with unusually large bounding boxes (big floor
+// and wall triangles, for example) are split up front into several
+// references with tighter, clipped bounds. The result is less overlap
+// between sibling nodes at the cost of a longer build and some duplicated
+// references in leaves.
+//
+// References with a surface area larger than this many times the median
+// get split.
+const SPLIT_AREA_FACTOR: f32 = 4.0_f32;
+// Limits a single shape to at most 2^MAX_SPLIT_DEPTH references.
+const MAX_SPLIT_DEPTH: u32 = 6;
+
+fn pre_split(shapes: Vec<Arc<SyncShape>>) -> Vec<BVHReference> {
+    let references: Vec<BVHReference> = shapes.into_iter().map(BVHReference::new).collect();
+    if references.is_empty() {
+        return references;
+    }
+
+    // Use the median rather than the mean, so that a handful of very large
+    // shapes can not raise the threshold above themselves.
+    let mut areas: Vec<f32> = references
+        .iter()
+        .map(|r| r.bounding_box.surface_area())
+        .collect();
+    areas.sort_by(|a, b| a.partial_cmp(b).unwrap_or(cmp::Ordering::Equal));
+    let split_threshold = SPLIT_AREA_FACTOR * areas[areas.len() / 2];
+
+    let mut split_references = Vec::with_capacity(references.len());
+    for reference in references {
+        pre_split_helper(reference, split_threshold, 0, &mut split_references);
+    }
+    return split_references;
+}
+
+fn pre_split_helper(
+    reference: BVHReference,
+    split_threshold: f32,
+    depth: u32,
+    out: &mut Vec<BVHReference>,
+) {
+    if depth >= MAX_SPLIT_DEPTH || reference.bounding_box.surface_area() <= split_threshold {
+        out.push(reference);
+        return;
+    }
+
+    // Cut the box in half along its largest axis, and clip the shape
+    // against each half to find the bounds of each piece.
+    let axis = reference.bounding_box.largest_axis();
+    let center = reference.bounding_box.center();
+    let mut left_max = reference.bounding_box.max;
+    let mut right_min = reference.bounding_box.min;
+    match axis {
+        Axis::X => {
+            left_max = Point3::new(center.x(), left_max.y(), left_max.z());
+            right_min = Point3::new(center.x(), right_min.y(), right_min.z());
+        }
+        Axis::Y => {
+            left_max = Point3::new(left_max.x(), center.y(), left_max.z());
+            right_min = Point3::new(right_min.x(), center.y(), right_min.z());
+        }
+        Axis::Z => {
+            left_max = Point3::new(left_max.x(), left_max.y(), center.z());
+            right_min = Point3::new(right_min.x(), right_min.y(), center.z());
+        }
+    }
+
+    let halves = [
+        AABB::new(reference.bounding_box.min, left_max),
+        AABB::new(right_min, reference.bounding_box.max),
+    ];
+    for half in halves.iter() {
+        match reference.shape.get_clipped_bounding_box(half) {
+            Some(clipped) => pre_split_helper(
+                BVHReference {
+                    shape: Arc::clone(&reference.shape),
+                    bounding_box: clipped,
+                },
+                split_threshold,
+                depth + 1,
+                out,
+            ),
+            // The shape does not pass through this half at all
+            None => {}
+        }
+    }
+}
+
+// Leaves only need one copy of each shape, even if several split
+// references to the same shape ended up in the same leaf.
+fn push_leaf(bvh: &mut BVH, bounding_box: AABB, references: Vec<BVHReference>) {
+    let first_shape = bvh.shapes.len();
+    for reference in references {
+        if !bvh.shapes[first_shape..]
+            .iter()
+            .any(|s| Arc::ptr_eq(s, &reference.shape))
+        {
+            bvh.shapes.push(reference.shape);
+        }
+    }
+    bvh.nodes.push(BVHTypes::Leaf(BVHLeaf {
+        bounding_box: bounding_box,
+        first_shape: first_shape,
+        shape_count: bvh.shapes.len() - first_shape,
+    }));
+}
+
 // Helper for recursive case of BVH construction.
-fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<Arc<SyncShape>>) {
+fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<BVHReference>, settings: &BVHSettings) {
     // Calculate total bounds for this iteration
     let mut total_bounds = AABB::new_empty();
     for shape in &shapes {
-        total_bounds = AABB::union(&total_bounds, &shape.get_bounding_box());
+        total_bounds = AABB::union(&total_bounds, &shape.bounding_box);
     }
 
     // If we only have a couple shapes, just make a leaf
-    if (&shapes).len() <= 2 {
-        bvh.push(BVHTypes::Leaf(BVHLeaf {
-            bounding_box: total_bounds,
-            shapes: shapes,
-        }));
+    if (&shapes).len() <= settings.leaf_size {
+        push_leaf(bvh, total_bounds, shapes);
         return;
     }
 
     // Compute centroid (center of bounding boxes) bounds
     let mut centroid_bounds = AABB::new_empty();
     for shape in &shapes {
-        centroid_bounds = AABB::union_point(&centroid_bounds, &shape.get_bounding_box().center());
+        centroid_bounds = AABB::union_point(&centroid_bounds, &shape.bounding_box.center());
     }
     // We will cut over the dimension for which bounding box centers cover the
     // largest area
@@ -217,10 +981,7 @@ fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<Arc<SyncShape>>) {
 
     // If we have zero area to split over, just make a leaf
     if centroid_bounds.max[cut_axis] == centroid_bounds.min[cut_axis] {
-        bvh.push(BVHTypes::Leaf(BVHLeaf {
-            bounding_box: total_bounds,
-            shapes: shapes,
-        }));
+        push_leaf(bvh, total_bounds, shapes);
         return;
     }
 
@@ -231,8 +992,8 @@ fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<Arc<SyncShape>>) {
     // the program. Should it become an issue, I can consider slightly less
     // optimal, but linear time, alternatives, such as partitioning with buckets.
     shapes.sort_by(|a, b| {
-        let a_c = a.get_bounding_box().center()[cut_axis];
-        let b_c = b.get_bounding_box().center()[cut_axis];
+        let a_c = a.bounding_box.center()[cut_axis];
+        let b_c = b.bounding_box.center()[cut_axis];
         if a_c < b_c {
             cmp::Ordering::Less
         } else if a_c > b_c {
@@ -242,43 +1003,17 @@ fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<Arc<SyncShape>>) {
         }
     });
 
-    // Apply SAH:
-    // Start by calculating bounds at each possible split point in reverse,
-    // a linear operation.
-    let mut reverse_bounds = Vec::with_capacity(shapes.len());
-    reverse_bounds.resize_with(shapes.len(), AABB::new_empty);
-    for reverse_idx in (0..(shapes.len() - 1)).rev() {
-        reverse_bounds[reverse_idx] = shapes[reverse_idx].get_bounding_box();
-        if reverse_idx + 1 < shapes.len() {
-            reverse_bounds[reverse_idx] = AABB::union(
-                &reverse_bounds[reverse_idx],
-                &reverse_bounds[reverse_idx + 1],
-            );
-        }
-    }
-    // Then iterate forward, applying SAH at each split point.
-    let mut forward_bounds = AABB::new_empty();
-    let mut min_cost = std::f32::MAX;
-    let mut min_cost_index = 0;
-    for idx in 0..shapes.len() - 1 {
-        forward_bounds = AABB::union(&forward_bounds, &shapes[idx].get_bounding_box());
-        let cost =
-        // Extra cost incurred by the ray to bounding box intersection should we make a node
-        1_f32 +
-        // (Probability of going through A) * (Cost to iterate A (1 per element in A))
-        ((forward_bounds.surface_area() / total_bounds.surface_area()) * (idx + 1) as f32) +
-        // (Probability of going through B) * (Cost to iterate B (1 per element in B))
-        ((reverse_bounds[idx + 1].surface_area() / total_bounds.surface_area()) * (shapes.len() - (idx + 1)) as f32);
-        // Pick min cost
-        if cost < min_cost {
-            min_cost = cost;
-            min_cost_index = idx;
-        }
-    }
+    // Low quality builds just split down the middle
+    let (min_cost, min_cost_index) = match settings.quality {
+        BuildQuality::Low => (0.0_f32, (shapes.len() / 2) - 1),
+        _ => sah_split(&shapes, &total_bounds, settings),
+    };
 
     // Compare split cost to cost of creating a leaf,
-    // which is 1 per element.
-    if min_cost < shapes.len() as f32 {
+    // which is the intersection cost per element.
+    if min_cost < shapes.len() as f32 * settings.intersection_cost
+        || shapes.len() > settings.max_leaf_size
+    {
         // Split the shape vector into two pieces at our split index
         let second_half = shapes.split_off(min_cost_index + 1);
 
@@ -286,19 +1021,19 @@ fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<Arc<SyncShape>>) {
         // but also maintain the readability of just pushing to "bvh" most
         // of the time. I push a placeholder node that gets overwritten in
         // a moment when I know what my real right_offset value should be.
-        bvh.push(BVHTypes::Node(BVHNode {
+        bvh.nodes.push(BVHTypes::Node(BVHNode {
             bounding_box: AABB::new_empty(),
             cut_axis: cut_axis,
             right_offset: 0,
         }));
-        let node_idx = bvh.len() - 1;
+        let node_idx = bvh.nodes.len() - 1;
 
         // Add the left branch
-        new_bvh_helper(bvh, shapes);
+        new_bvh_helper(bvh, shapes, settings);
 
         // Now do the replacement of the node with
         // a correct right_offset
-        bvh[node_idx] = BVHTypes::Node(BVHNode {
+        bvh.nodes[node_idx] = BVHTypes::Node(BVHNode {
             bounding_box: total_bounds,
             cut_axis: cut_axis,
             // Offset is current length minus this node's index,
@@ -306,21 +1041,62 @@ fn new_bvh_helper(bvh: &mut BVH, mut shapes: Vec<Arc<SyncShape>>) {
             // leaf to represent the right branch, and this leaf
             // will reside at the index currently represented by
             // bvh's length
-            right_offset: bvh.len() - node_idx,
+            right_offset: bvh.nodes.len() - node_idx,
         });
 
         // Last, add the right branch
-        new_bvh_helper(bvh, second_half);
+        new_bvh_helper(bvh, second_half, settings);
         return;
     }
     // If it's cheap enough, just make the leaf
-    bvh.push(BVHTypes::Leaf(BVHLeaf {
-        bounding_box: total_bounds,
-        shapes: shapes,
-    }));
+    push_leaf(bvh, total_bounds, shapes);
     return;
 }
 
+// Finds the cheapest place to split a list of shapes, already sorted along
+// the cut axis, returning the cost and the index of the last shape in the
+// first half.
+fn sah_split(
+    shapes: &Vec<BVHReference>,
+    total_bounds: &AABB,
+    settings: &BVHSettings,
+) -> (f32, usize) {
+    // Apply SAH:
+    // Start by calculating bounds at each possible split point in reverse,
+    // a linear operation.
+    let mut reverse_bounds = Vec::with_capacity(shapes.len());
+    reverse_bounds.resize_with(shapes.len(), AABB::new_empty);
+    for reverse_idx in (0..shapes.len()).rev() {
+        reverse_bounds[reverse_idx] = AABB::clone(&shapes[reverse_idx].bounding_box);
+        if reverse_idx + 1 < shapes.len() {
+            reverse_bounds[reverse_idx] = AABB::union(
+                &reverse_bounds[reverse_idx],
+                &reverse_bounds[reverse_idx + 1],
+            );
+        }
+    }
+    // Then iterate forward, applying SAH at each split point.
+    let mut forward_bounds = AABB::new_empty();
+    let mut min_cost = std::f32::MAX;
+    let mut min_cost_index = 0;
+    for idx in 0..shapes.len() - 1 {
+        forward_bounds = AABB::union(&forward_bounds, &shapes[idx].bounding_box);
+        let cost =
+        // Extra cost incurred by the ray to bounding box intersection should we make a node
+        settings.traversal_cost +
+        // (Probability of going through A) * (Cost to iterate A)
+        ((forward_bounds.surface_area() / total_bounds.surface_area()) * (idx + 1) as f32 * settings.intersection_cost) +
+        // (Probability of going through B) * (Cost to iterate B)
+        ((reverse_bounds[idx + 1].surface_area() / total_bounds.surface_area()) * (shapes.len() - (idx + 1)) as f32 * settings.intersection_cost);
+        // Pick min cost
+        if cost < min_cost {
+            min_cost = cost;
+            min_cost_index = idx;
+        }
+    }
+    return (min_cost, min_cost_index);
+}
+
 impl Aggregate for BVH {
     fn hit(
         &self,
@@ -328,7 +1104,7 @@ impl Aggregate for BVH {
         t_min: f32,
         t_max: f32,
         workspace: &mut Workspace,
-    ) -> Option<(&SyncShape, f32)> {
+    ) -> Option<(&SyncShape, Hit<'_>)> {
         // Grab the workspace as the pre-allocated vector
         // we expect it to be.
         let to_explore = match workspace {
@@ -336,12 +1112,12 @@ impl Aggregate for BVH {
             _ => panic!("BVH Aggregate was given a bad workspace!"),
         };
 
-        if self.is_empty() {
+        if self.nodes.is_empty() {
             return None;
         }
 
         let mut modified_t_max = t_max;
-        let mut hit_shape: Option<&SyncShape> = None;
+        let mut hit_shape: Option<(&SyncShape, Hit)> = None;
 
         let mut to_explore_count = 1;
         to_explore[0] = 0;
@@ -351,18 +1127,15 @@ impl Aggregate for BVH {
             to_explore_count -= 1;
             let cur_idx = to_explore[to_explore_count];
 
-            match &self[cur_idx] {
+            match &self.nodes[cur_idx] {
                 BVHTypes::Leaf(leaf) => {
                     if !leaf.bounding_box.intersect(r, t_min, modified_t_max) {
                         continue;
                     }
-                    match leaf
-                        .shapes
-                        .hit(r, t_min, modified_t_max, &mut Workspace::Void)
-                    {
-                        Some((s, t)) => {
-                            modified_t_max = t;
-                            hit_shape = Some(s);
+                    match hit_shapes(self.leaf_shapes(leaf), r, t_min, modified_t_max) {
+                        Some((s, h)) => {
+                            modified_t_max = h.t;
+                            hit_shape = Some((s, h));
                         }
                         None => {}
                     }
@@ -394,19 +1167,384 @@ impl Aggregate for BVH {
             }
         }
 
-        match hit_shape {
-            Some(s) => Some((s, modified_t_max)),
-            None => None,
-        }
+        return hit_shape;
     }
 
     // Allocate this conservatively, so that we never
     // have to allocate more space in our hit loop
     fn get_workspace(&self) -> Workspace {
-        let mut v = Vec::with_capacity(self.len());
-        v.resize(self.len(), 0_usize);
+        let mut v = Vec::with_capacity(self.nodes.len());
+        v.resize(self.nodes.len(), 0_usize);
         return Workspace::BVH(v);
     }
+
+    fn info(&self) -> String {
+        let stats = BVHStats::new(self);
+        let mut report = String::new();
+        report.push_str(&format!(
+            "BVH aggregate with {} nodes ({} interior, {} leaves).\n",
+            self.nodes.len(),
+            stats.interior_count,
+            stats.leaf_count
+        ));
+        report.push_str(&format!(
+            "Leaf size: average {:.2}, max {} ({} shape references total)\n",
+            stats.shape_count as f32 / utils::float_max(stats.leaf_count as f32, 1.0_f32),
+            stats.max_leaf_size,
+            stats.shape_count
+        ));
+        report.push_str(&format!(
+            "Leaf depth: average {:.2}, max {}\n",
+            stats.total_leaf_depth as f32 / utils::float_max(stats.leaf_count as f32, 1.0_f32),
+            stats.leaf_depths.len().saturating_sub(1)
+        ));
+        report.push_str("Leaf depth distribution:\n");
+        for (depth, count) in stats.leaf_depths.iter().enumerate() {
+            if *count > 0 {
+                report.push_str(&format!("    {:>4}: {}\n", depth, count));
+            }
+        }
+        report.push_str(&format!("SAH cost: {:.3}\n", stats.sah_cost));
+        report.push_str(&format!(
+            "Sibling overlap (as a fraction of parent surface area): average {:.3}, max {:.3}, {} of {} interior nodes overlap\n",
+            stats.total_overlap / utils::float_max(stats.interior_count as f32, 1.0_f32),
+            stats.max_overlap,
+            stats.overlapping_count,
+            stats.interior_count
+        ));
+        return report;
+    }
+
+    fn node_bounds(&self, depth: Option<usize>) -> Vec<AABB> {
+        let mut bounds = Vec::new();
+        if !self.nodes.is_empty() {
+            collect_node_bounds(self, 0, 0, depth, &mut bounds);
+        }
+        return bounds;
+    }
+
+    fn shapes(&self) -> Vec<&SyncShape> {
+        return self.shapes.shapes();
+    }
+}
+
+fn collect_node_bounds(
+    bvh: &BVH,
+    idx: usize,
+    depth: usize,
+    target_depth: Option<usize>,
+    bounds: &mut Vec<AABB>,
+) {
+    match &bvh.nodes[idx] {
+        BVHTypes::Leaf(leaf) => bounds.push(leaf.bounding_box.clone()),
+        BVHTypes::Node(node) => {
+            if target_depth == Some(depth) {
+                bounds.push(node.bounding_box.clone());
+                return;
+            }
+            collect_node_bounds(bvh, idx + 1, depth + 1, target_depth, bounds);
+            collect_node_bounds(
+                bvh,
+                idx + node.right_offset,
+                depth + 1,
+                target_depth,
+                bounds,
+            );
+        }
+    }
+}
+
+// BVH cache file format, all values little endian:
+// magic, version (u32), key (u64), shape count (u64), node count (u64),
+// then per node a tag (u8, 0 for leaves and 1 for nodes), min and max
+// (3 x f32 each), followed by either the cut axis (u8) and right offset
+// (u64) for nodes, or a shape count (u64) and that many shape indices
+// (u64 each) for leaves.
+const BVH_CACHE_MAGIC: &[u8; 6] = b"RTBVH\0";
+const BVH_CACHE_VERSION: u32 = 1;
+
+// Maps shapes, by address, to their index in the shape list.
+fn shape_indices(shapes: &Vec<Arc<SyncShape>>) -> HashMap<usize, u64> {
+    let mut indices = HashMap::with_capacity(shapes.len());
+    for (idx, shape) in shapes.iter().enumerate() {
+        indices.insert(Arc::as_ptr(shape) as *const () as usize, idx as u64);
+    }
+    return indices;
+}
+
+fn cache_key(shapes: &Vec<Arc<SyncShape>>, settings: &BVHSettings) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for shape in shapes {
+        let bounds = shape.get_bounding_box();
+        for v in [bounds.min, bounds.max].iter() {
+            v.x().to_bits().hash(&mut hasher);
+            v.y().to_bits().hash(&mut hasher);
+            v.z().to_bits().hash(&mut hasher);
+        }
+    }
+    settings.leaf_size.hash(&mut hasher);
+    settings.max_leaf_size.hash(&mut hasher);
+    settings.traversal_cost.to_bits().hash(&mut hasher);
+    settings.intersection_cost.to_bits().hash(&mut hasher);
+    match settings.quality {
+        BuildQuality::Low => 0_u8,
+        BuildQuality::Medium => 1_u8,
+        BuildQuality::High => 2_u8,
+    }
+    .hash(&mut hasher);
+    return hasher.finish();
+}
+
+fn write_bvh_cache(
+    cache_path: &Path,
+    bvh: &BVH,
+    indices: &HashMap<usize, u64>,
+    key: u64,
+) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(cache_path)?);
+    out.write_all(BVH_CACHE_MAGIC)?;
+    out.write_all(&BVH_CACHE_VERSION.to_le_bytes())?;
+    out.write_all(&key.to_le_bytes())?;
+    out.write_all(&(indices.len() as u64).to_le_bytes())?;
+    out.write_all(&(bvh.nodes.len() as u64).to_le_bytes())?;
+
+    for bvh_type in &bvh.nodes {
+        let (tag, bounding_box) = match bvh_type {
+            BVHTypes::Leaf(leaf) => (0_u8, &leaf.bounding_box),
+            BVHTypes::Node(node) => (1_u8, &node.bounding_box),
+        };
+        out.write_all(&[tag])?;
+        for v in [bounding_box.min, bounding_box.max].iter() {
+            out.write_all(&v.x().to_le_bytes())?;
+            out.write_all(&v.y().to_le_bytes())?;
+            out.write_all(&v.z().to_le_bytes())?;
+        }
+
+        match bvh_type {
+            BVHTypes::Leaf(leaf) => {
+                out.write_all(&(leaf.shape_count as u64).to_le_bytes())?;
+                for shape in bvh.leaf_shapes(leaf) {
+                    let idx = indices[&(Arc::as_ptr(shape) as *const () as usize)];
+                    out.write_all(&idx.to_le_bytes())?;
+                }
+            }
+            BVHTypes::Node(node) => {
+                out.write_all(&[match node.cut_axis {
+                    Axis::X => 0_u8,
+                    Axis::Y => 1_u8,
+                    Axis::Z => 2_u8,
+                }])?;
+                out.write_all(&(node.right_offset as u64).to_le_bytes())?;
+            }
+        }
+    }
+    return out.flush();
+}
+
+fn read_bvh_cache(
+    cache_path: &Path,
+    shapes: &Vec<Arc<SyncShape>>,
+    key: u64,
+) -> Result<BVH, String> {
+    let mut input = io::BufReader::new(match fs::File::open(cache_path) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("{}", e)),
+    });
+    // Any read error is just reported as a malformed cache
+    match read_bvh_cache_helper(&mut input, shapes, key) {
+        Ok(bvh) => bvh,
+        Err(e) => Err(format!("Malformed BVH cache: {}", e)),
+    }
+}
+
+fn read_bvh_cache_helper(
+    input: &mut dyn Read,
+    shapes: &Vec<Arc<SyncShape>>,
+    key: u64,
+) -> io::Result<Result<BVH, String>> {
+    let mut magic = [0_u8; 6];
+    input.read_exact(&mut magic)?;
+    if &magic != BVH_CACHE_MAGIC {
+        return Ok(Err(String::from("Not a BVH cache file.")));
+    }
+    let version = read_u32(input)?;
+    if version != BVH_CACHE_VERSION {
+        return Ok(Err(format!(
+            "Cache version {} is not supported, expected {}.",
+            version, BVH_CACHE_VERSION
+        )));
+    }
+    if read_u64(input)? != key {
+        return Ok(Err(String::from(
+            "Cache was built from different shapes or settings.",
+        )));
+    }
+    let shape_count = read_u64(input)? as usize;
+    if shape_count != shapes.len() {
+        return Ok(Err(format!(
+            "Cache has {} shapes, but the scene has {}.",
+            shape_count,
+            shapes.len()
+        )));
+    }
+
+    let node_count = read_u64(input)? as usize;
+    let mut bvh = BVH::new();
+    bvh.nodes.reserve(node_count);
+    for node_idx in 0..node_count {
+        let mut tag = [0_u8; 1];
+        input.read_exact(&mut tag)?;
+        let min = Point3::new(read_f32(input)?, read_f32(input)?, read_f32(input)?);
+        let max = Point3::new(read_f32(input)?, read_f32(input)?, read_f32(input)?);
+
+        match tag[0] {
+            0 => {
+                let leaf_count = read_u64(input)? as usize;
+                let first_shape = bvh.shapes.len();
+                for _ in 0..leaf_count {
+                    let idx = read_u64(input)? as usize;
+                    if idx >= shapes.len() {
+                        return Ok(Err(format!("Shape index {} is out of bounds.", idx)));
+                    }
+                    bvh.shapes.push(Arc::clone(&shapes[idx]));
+                }
+                bvh.nodes.push(BVHTypes::Leaf(BVHLeaf {
+                    bounding_box: AABB::new(min, max),
+                    first_shape: first_shape,
+                    shape_count: leaf_count,
+                }));
+            }
+            1 => {
+                let mut axis = [0_u8; 1];
+                input.read_exact(&mut axis)?;
+                let right_offset = read_u64(input)? as usize;
+                // Both children need to exist for traversal to be safe
+                if right_offset < 2 || node_idx + right_offset >= node_count {
+                    return Ok(Err(format!("Node {} has a bad right offset.", node_idx)));
+                }
+                bvh.nodes.push(BVHTypes::Node(BVHNode {
+                    bounding_box: AABB::new(min, max),
+                    cut_axis: match axis[0] {
+                        0 => Axis::X,
+                        1 => Axis::Y,
+                        2 => Axis::Z,
+                        a => return Ok(Err(format!("Unknown axis {}.", a))),
+                    },
+                    right_offset: right_offset,
+                }));
+            }
+            t => return Ok(Err(format!("Unknown node tag {}.", t))),
+        }
+    }
+    return Ok(Ok(bvh));
+}
+
+fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0_u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0_u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(input: &mut dyn Read) -> io::Result<f32> {
+    let mut bytes = [0_u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+// Statistics gathered by walking a constructed BVH.
+struct BVHStats {
+    interior_count: usize,
+    leaf_count: usize,
+    shape_count: usize,
+    max_leaf_size: usize,
+    total_leaf_depth: usize,
+    // Index is the depth, value is the number of leaves at that depth
+    leaf_depths: Vec<usize>,
+    // Expected cost of a random ray, with unit traversal and intersection costs
+    sah_cost: f32,
+    total_overlap: f32,
+    max_overlap: f32,
+    overlapping_count: usize,
+}
+
+impl BVHStats {
+    fn new(bvh: &BVH) -> BVHStats {
+        let mut stats = BVHStats {
+            interior_count: 0,
+            leaf_count: 0,
+            shape_count: 0,
+            max_leaf_size: 0,
+            total_leaf_depth: 0,
+            leaf_depths: Vec::new(),
+            sah_cost: 0.0_f32,
+            total_overlap: 0.0_f32,
+            max_overlap: 0.0_f32,
+            overlapping_count: 0,
+        };
+        if bvh.nodes.is_empty() {
+            return stats;
+        }
+
+        let root_area = BVHStats::bounding_box(bvh, 0).surface_area();
+        stats.walk(bvh, 0, 0, root_area);
+        return stats;
+    }
+
+    fn bounding_box(bvh: &BVH, idx: usize) -> &AABB {
+        match &bvh.nodes[idx] {
+            BVHTypes::Leaf(leaf) => &leaf.bounding_box,
+            BVHTypes::Node(node) => &node.bounding_box,
+        }
+    }
+
+    fn walk(&mut self, bvh: &BVH, idx: usize, depth: usize, root_area: f32) {
+        // Probability that a ray hitting the root also hits this node
+        let probability = if root_area > 0.0_f32 {
+            BVHStats::bounding_box(bvh, idx).surface_area() / root_area
+        } else {
+            1.0_f32
+        };
+
+        match &bvh.nodes[idx] {
+            BVHTypes::Leaf(leaf) => {
+                self.leaf_count += 1;
+                self.shape_count += leaf.shape_count;
+                self.max_leaf_size = cmp::max(self.max_leaf_size, leaf.shape_count);
+                self.total_leaf_depth += depth;
+                if self.leaf_depths.len() <= depth {
+                    self.leaf_depths.resize(depth + 1, 0);
+                }
+                self.leaf_depths[depth] += 1;
+                self.sah_cost += probability * leaf.shape_count as f32;
+            }
+            BVHTypes::Node(node) => {
+                self.interior_count += 1;
+                self.sah_cost += probability;
+
+                let left = BVHStats::bounding_box(bvh, idx + 1);
+                let right = BVHStats::bounding_box(bvh, idx + node.right_offset);
+                let node_area = node.bounding_box.surface_area();
+                let overlap = match AABB::intersection(left, right) {
+                    Some(b) if node_area > 0.0_f32 => b.surface_area() / node_area,
+                    _ => 0.0_f32,
+                };
+                if overlap > 0.0_f32 {
+                    self.overlapping_count += 1;
+                }
+                self.total_overlap += overlap;
+                self.max_overlap = utils::float_max(self.max_overlap, overlap);
+
+                self.walk(bvh, idx + 1, depth + 1, root_area);
+                self.walk(bvh, idx + node.right_offset, depth + 1, root_area);
+            }
+        }
+    }
 }
 
 // Axis Aligned Bounding Box
@@ -415,25 +1553,47 @@ pub struct AABB {
     pub max: Point3,
 }
 
+impl Clone for AABB {
+    fn clone(&self) -> AABB {
+        AABB::new(self.min, self.max)
+    }
+}
+
 impl AABB {
     pub fn new(min: Point3, max: Point3) -> AABB {
         AABB { min: min, max: max }
     }
 
-    fn new_empty() -> AABB {
+    // An inverted box, so that a union with it is always the other box.
+    pub fn new_empty() -> AABB {
         AABB {
-            min: Point3::origin(),
-            max: Point3::origin(),
+            min: Point3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX),
+            max: Point3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN),
         }
     }
 
-    fn union(box1: &AABB, box2: &AABB) -> AABB {
+    pub fn union(box1: &AABB, box2: &AABB) -> AABB {
         AABB {
             min: Point3::min(box1.min, box2.min),
             max: Point3::max(box1.max, box2.max),
         }
     }
 
+    // Returns None if the boxes do not overlap.
+    pub fn intersection(box1: &AABB, box2: &AABB) -> Option<AABB> {
+        let overlap = AABB {
+            min: Point3::max(box1.min, box2.min),
+            max: Point3::min(box1.max, box2.max),
+        };
+        if overlap.min.x() > overlap.max.x()
+            || overlap.min.y() > overlap.max.y()
+            || overlap.min.z() > overlap.max.z()
+        {
+            return None;
+        }
+        Some(overlap)
+    }
+
     fn union_point(box1: &AABB, point: &Point3) -> AABB {
         AABB {
             min: Point3::min(box1.min, *point),
@@ -0,0 +1,330 @@
+use crate::resources::Resources;
+use crate::scene;
+use crate::stats::Statistics;
+use crate::version;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+use std::fmt::Write as FmtWrite;
+use std::{env, fs, io, path, sync::Arc, sync::Mutex, time::Instant};
+
+// Benchmark scenes are laid out from a fixed seed so every run (and every
+// version) traces the same geometry. Per sample noise still comes from the
+// thread RNG, which only changes the image, not the amount of work.
+const BENCH_SEED: u64 = 0x5EED_u64;
+const BENCH_RESOLUTION: u32 = 96;
+const BENCH_SAMPLES: u32 = 16;
+// Subdivisions of the generated sphere mesh
+const MESH_SEGMENTS: u32 = 64;
+const MESH_RINGS: u32 = 32;
+
+struct BenchScene {
+    name: &'static str,
+    generate: fn(&mut StdRng, &path::Path) -> io::Result<serde_json::Value>,
+}
+
+const BENCH_SCENES: [BenchScene; 4] = [
+    BenchScene {
+        name: "sphere field",
+        generate: sphere_field,
+    },
+    BenchScene {
+        name: "mesh heavy",
+        generate: mesh_heavy,
+    },
+    BenchScene {
+        name: "glass heavy",
+        generate: glass_heavy,
+    },
+    BenchScene {
+        name: "volume",
+        generate: volume,
+    },
+];
+
+// Renders each of the bundled scenes and prints the time spent in each
+// stage along with the rays traced per second.
+pub fn run(num_threads: u32) {
+    // Generated assets (the mesh for now) need a directory to live in
+    let bench_dir = env::temp_dir().join("rust-raytracer-bench");
+    fs::create_dir_all(&bench_dir).expect("Failed to create a directory for benchmark assets.");
+
+    println!(
+        "Benchmarking {} scenes at {}x{}, {} samples per pixel, {} threads",
+        BENCH_SCENES.len(),
+        BENCH_RESOLUTION,
+        BENCH_RESOLUTION,
+        BENCH_SAMPLES,
+        num_threads
+    );
+    let mut total_rays = 0_u64;
+    let mut total_render_secs = 0.0_f64;
+    for bench_scene in BENCH_SCENES.iter() {
+        let mut rng = StdRng::seed_from_u64(BENCH_SEED);
+
+        let generate_start = Instant::now();
+        let scene_json = (bench_scene.generate)(&mut rng, &bench_dir)
+            .expect("Failed to generate benchmark scene.");
+        let generate_secs = generate_start.elapsed().as_secs_f64();
+
+        // Loading covers parsing, reading meshes, and building the aggregate
+        let load_start = Instant::now();
+        let mut res = Resources::new();
+        let scene_spec = scene::deserialize(&scene_json.to_string(), &bench_dir, &mut res)
+            .expect("Failed to load benchmark scene.");
+        let load_secs = load_start.elapsed().as_secs_f64();
+
+        let render_start = Instant::now();
+        let (_, _, stats) = crate::render::render(
+            &scene_spec,
+            num_threads,
+            Arc::new(Mutex::new(io::sink())),
+            None,
+        )
+        .expect("Failed to render benchmark scene.");
+        let render_secs = render_start.elapsed().as_secs_f64();
+
+        println!(
+            "{}",
+            bench_report(
+                bench_scene.name,
+                generate_secs,
+                load_secs,
+                render_secs,
+                &stats
+            )
+        );
+        total_rays += stats.rays();
+        total_render_secs += render_secs;
+    }
+    println!(
+        "Total: {} rays in {:.3} s, {:.0} rays/s",
+        total_rays,
+        total_render_secs,
+        total_rays as f64 / total_render_secs
+    );
+}
+
+fn bench_report(
+    name: &str,
+    generate_secs: f64,
+    load_secs: f64,
+    render_secs: f64,
+    stats: &Statistics,
+) -> String {
+    let mut report = String::new();
+    write!(
+        report,
+        "{:<14} generate {:.3} s, load {:.3} s, render {:.3} s, {} rays, {:.0} rays/s",
+        name,
+        generate_secs,
+        load_secs,
+        render_secs,
+        stats.rays(),
+        stats.rays() as f64 / render_secs
+    )
+    .expect("Failed to format benchmark report.");
+    return report;
+}
+
+// Shared pieces of every benchmark scene: logistics, a camera looking at
+// the origin from above and in front, and a big light overhead.
+fn base_scene(
+    textures: serde_json::Value,
+    materials: serde_json::Value,
+    shapes: Vec<serde_json::Value>,
+) -> serde_json::Value {
+    let mut textures = textures;
+    let mut materials = materials;
+    textures["BenchLight"] = json!({"type": "Constant", "color": [8, 8, 8]});
+    textures["BenchGround"] = json!({"type": "Constant", "color": [0.5, 0.5, 0.5]});
+    materials["BenchLight"] = json!({"type": "DiffuseLight", "emission": "BenchLight"});
+    materials["BenchGround"] = json!({"type": "Lambert", "albedo": "BenchGround"});
+
+    let mut all_shapes = vec![
+        json!({
+            "type": "Sphere",
+            "name": "Light",
+            "radius": 3,
+            "material": "BenchLight",
+            "transform": {"translate": [0, 10, 0]}
+        }),
+        json!({
+            "type": "Sphere",
+            "name": "Ground",
+            "radius": 1000,
+            "material": "BenchGround",
+            "transform": {"translate": [0, -1000, 0]}
+        }),
+    ];
+    all_shapes.extend(shapes);
+
+    return json!({
+        "Version": version::CURRENT_VERSION,
+        "Logistics": {
+            "resolution_x": BENCH_RESOLUTION,
+            "resolution_y": BENCH_RESOLUTION,
+            "samples": BENCH_SAMPLES
+        },
+        "Camera": {
+            "position": [0, 4, 12],
+            "look_at": [0, 0.5, 0],
+            "up": [0, 1, 0],
+            "fov": 45,
+            "aspect_ratio": 1,
+            "aperture": 0,
+            "focus_distance": 12
+        },
+        "Textures": textures,
+        "Materials": materials,
+        "Aggregate": "BVH",
+        "Shapes": all_shapes
+    });
+}
+
+// Random point on the ground within the given half extent
+fn ground_position(rng: &mut StdRng, extent: f32, height: f32) -> [f32; 3] {
+    return [
+        rng.gen_range(-extent, extent),
+        height,
+        rng.gen_range(-extent, extent),
+    ];
+}
+
+// Many small diffuse and metal spheres, dominated by BVH traversal
+fn sphere_field(rng: &mut StdRng, _: &path::Path) -> io::Result<serde_json::Value> {
+    let mut textures = json!({});
+    let mut materials = json!({});
+    let mut shapes = Vec::new();
+    for i in 0..400 {
+        let name = format!("Field{}", i);
+        textures[&name] = json!({
+            "type": "Constant",
+            "color": [rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()]
+        });
+        materials[&name] = if rng.gen::<f32>() < 0.75_f32 {
+            json!({"type": "Lambert", "albedo": name})
+        } else {
+            json!({"type": "Metal", "albedo": name, "roughness": rng.gen_range(0.0_f32, 0.5_f32)})
+        };
+        let radius = rng.gen_range(0.1_f32, 0.3_f32);
+        shapes.push(json!({
+            "type": "Sphere",
+            "radius": radius,
+            "material": name,
+            "transform": {"translate": ground_position(rng, 6.0_f32, radius)}
+        }));
+    }
+    return Ok(base_scene(textures, materials, shapes));
+}
+
+// A handful of finely tessellated meshes, dominated by Triangle tests
+fn mesh_heavy(rng: &mut StdRng, bench_dir: &path::Path) -> io::Result<serde_json::Value> {
+    let mesh_file = "bench_sphere.obj";
+    fs::write(
+        bench_dir.join(mesh_file),
+        sphere_obj(MESH_SEGMENTS, MESH_RINGS),
+    )?;
+
+    let textures = json!({"Mesh": {"type": "Constant", "color": [0.7, 0.6, 0.4]}});
+    let materials = json!({"Mesh": {"type": "Lambert", "albedo": "Mesh"}});
+    let mut shapes = Vec::new();
+    for _ in 0..16 {
+        let scale = rng.gen_range(0.4_f32, 1.0_f32);
+        shapes.push(json!({
+            "type": "Mesh",
+            "file_path": mesh_file,
+            "enable_backface_culling": false,
+            "material": "Mesh",
+            "transform": {
+                "translate": ground_position(rng, 4.0_f32, scale),
+                "scale": [scale, scale, scale]
+            }
+        }));
+    }
+    return Ok(base_scene(textures, materials, shapes));
+}
+
+// Solid and hollow glass spheres, dominated by long specular paths
+fn glass_heavy(rng: &mut StdRng, _: &path::Path) -> io::Result<serde_json::Value> {
+    let textures = json!({});
+    let materials = json!({"Glass": {"type": "Dielectric", "refractive_index": 1.5}});
+    let mut shapes = Vec::new();
+    for _ in 0..40 {
+        let radius = rng.gen_range(0.2_f32, 0.6_f32);
+        let position = ground_position(rng, 4.0_f32, radius);
+        shapes.push(json!({
+            "type": "Sphere",
+            "radius": radius,
+            "material": "Glass",
+            "transform": {"translate": position}
+        }));
+        if rng.gen::<f32>() < 0.5_f32 {
+            shapes.push(json!({
+                "type": "Sphere",
+                "radius": -0.9_f32 * radius,
+                "material": "Glass",
+                "transform": {"translate": position}
+            }));
+        }
+    }
+    return Ok(base_scene(textures, materials, shapes));
+}
+
+// Overlapping participating media, dominated by volume scattering
+fn volume(rng: &mut StdRng, _: &path::Path) -> io::Result<serde_json::Value> {
+    let textures = json!({"Smoke": {"type": "Constant", "color": [0.8, 0.8, 0.8]}});
+    let materials = json!({"Smoke": {"type": "Isotropic", "albedo": "Smoke"}});
+    let mut shapes = Vec::new();
+    for _ in 0..8 {
+        let radius = rng.gen_range(0.5_f32, 1.5_f32);
+        shapes.push(json!({
+            "type": "ConstantMedium",
+            "density": rng.gen_range(0.2_f32, 2.0_f32),
+            "phase_func": "Smoke",
+            "boundary": {
+                "type": "Sphere",
+                "radius": radius,
+                "material": "Smoke",
+                "transform": {"translate": ground_position(rng, 3.0_f32, radius)}
+            }
+        }));
+    }
+    return Ok(base_scene(textures, materials, shapes));
+}
+
+// Writes a UV sphere of unit radius as OBJ text
+fn sphere_obj(segments: u32, rings: u32) -> String {
+    let mut obj = String::new();
+    for ring in 0..(rings + 1) {
+        let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+        for segment in 0..segments {
+            let phi = 2.0_f32 * std::f32::consts::PI * segment as f32 / segments as f32;
+            writeln!(
+                obj,
+                "v {} {} {}",
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin()
+            )
+            .expect("Failed to format benchmark mesh.");
+        }
+    }
+    // OBJ indices start at 1
+    let index = |ring: u32, segment: u32| ring * segments + (segment % segments) + 1;
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let (a, b) = (index(ring, segment), index(ring, segment + 1));
+            let (c, d) = (index(ring + 1, segment), index(ring + 1, segment + 1));
+            // Skip the degenerate halves of the quads touching the poles
+            if ring != 0 {
+                writeln!(obj, "f {} {} {}", a, b, c).expect("Failed to format benchmark mesh.");
+            }
+            if ring != rings - 1 {
+                writeln!(obj, "f {} {} {}", b, d, c).expect("Failed to format benchmark mesh.");
+            }
+        }
+    }
+    return obj;
+}
@@ -0,0 +1,58 @@
+use crate::aggregate::AABB;
+
+use std::io;
+use std::io::Write;
+
+// Writes bounding boxes to an OBJ file for inspection in a modeling tool,
+// each as a named object made of six quads. Viewing them as a wireframe
+// over the scene's own meshes shows where bounds are loose or wrong.
+pub fn write_obj<W: Write>(out: &mut W, boxes: &[(String, AABB)]) -> io::Result<()> {
+    for (i, (name, bounds)) in boxes.iter().enumerate() {
+        writeln!(out, "o {}", name)?;
+        for corner in 0..8 {
+            writeln!(
+                out,
+                "v {} {} {}",
+                if corner & 1 == 0 {
+                    bounds.min.x()
+                } else {
+                    bounds.max.x()
+                },
+                if corner & 2 == 0 {
+                    bounds.min.y()
+                } else {
+                    bounds.max.y()
+                },
+                if corner & 4 == 0 {
+                    bounds.min.z()
+                } else {
+                    bounds.max.z()
+                },
+            )?;
+        }
+        // OBJ indices start at one and count every vertex in the file
+        let first = i * 8 + 1;
+        for face in BOX_FACES.iter() {
+            writeln!(
+                out,
+                "f {} {} {} {}",
+                first + face[0],
+                first + face[1],
+                first + face[2],
+                first + face[3]
+            )?;
+        }
+    }
+    return Ok(());
+}
+
+// Corners of each face, counter-clockwise from outside, where bit 0 of a
+// corner's index picks the max x, bit 1 the max y, and bit 2 the max z.
+const BOX_FACES: [[usize; 4]; 6] = [
+    [0, 4, 6, 2],
+    [1, 3, 7, 5],
+    [0, 1, 5, 4],
+    [2, 6, 7, 3],
+    [0, 2, 3, 1],
+    [4, 5, 7, 6],
+];
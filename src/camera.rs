@@ -1,24 +1,136 @@
 use crate::point::Point3;
 use crate::ray::Ray;
+use crate::rng;
+use crate::sampling;
 use crate::utils;
 use crate::vector::Vector3;
 
 use serde::Deserialize;
 use std::convert;
+use std::f32;
 
 #[derive(Deserialize)]
-#[serde(from = "CameraDescription")]
-pub struct Camera {
+#[serde(try_from = "CameraDescription")]
+pub enum Camera {
+    Perspective(Perspective),
+    Fisheye(Fisheye),
+    Equirectangular(Equirectangular),
+}
+
+impl Camera {
+    // Returns the ray through the point (s, t) on the image, both in [0, 1]
+    // from the bottom left. None is returned for points the camera does not
+    // see, like the corners outside of a fisheye's circle.
+    pub fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        match self {
+            Camera::Perspective(p) => Some(p.get_ray(s, t)),
+            Camera::Fisheye(f) => f.get_ray(s, t),
+            Camera::Equirectangular(e) => Some(e.get_ray(s, t)),
+        }
+    }
+
+    // About how many radians apart the rays through neighboring pixels are,
+    // at the center of the image, for texture filtering
+    pub fn pixel_spread(&self, resolution_y: u32) -> f32 {
+        let view_height = match self {
+            Camera::Perspective(p) => {
+                let center = p.lower_left_corner + p.horizontal * 0.5_f32 + p.vertical * 0.5_f32;
+                p.vertical.length() / (center - p.frame.origin).length()
+            }
+            // The circle's radius is half the image's height
+            Camera::Fisheye(f) => 2.0_f32 * f.half_fov,
+            Camera::Equirectangular(_) => f32::consts::PI,
+        };
+        return view_height / resolution_y as f32;
+    }
+
+    // Range of times along a camera ray between the clipping planes
+    pub fn clip_range(&self, r: &Ray) -> (f32, f32) {
+        match self {
+            Camera::Perspective(p) => p.clip_range(r),
+            Camera::Fisheye(f) => f.frame.clip_range(r),
+            Camera::Equirectangular(e) => e.frame.clip_range(r),
+        }
+    }
+}
+
+// Where a camera is and which way it faces
+struct Frame {
     origin: Point3,
+    // Unit vectors to the right of, above, and in front of the camera
+    u: Vector3,
+    v: Vector3,
+    forward: Vector3,
+    // Clipping distances. Nothing nearer or farther is seen by camera rays.
+    near: f32,
+    far: f32,
+    // Camera rays are cast at random times while the shutter is open
+    shutter_open: f32,
+    shutter_close: f32,
+    // Fraction of the shutter interval spent reading rows out, top to
+    // bottom, as a CMOS sensor does. Each row is exposed for the rest of
+    // the interval, starting as the row above it does, so moving things
+    // skew and the camera's own motion wobbles. Zero is a global shutter,
+    // every row exposed for the whole interval.
+    rolling_shutter: f32,
+}
+
+// Options every projection shares
+pub struct CameraSettings {
+    pub near: f32,
+    pub far: f32,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    pub rolling_shutter: f32,
+}
+
+impl Frame {
+    fn new(pos: &Point3, look_at: &Point3, up: &Vector3, settings: &CameraSettings) -> Frame {
+        let w = (*pos - *look_at).normalized();
+        let u = up.cross(w).normalized();
+        let v = w.cross(u);
+
+        Frame {
+            origin: *pos,
+            u: u,
+            v: v,
+            forward: -w,
+            near: settings.near,
+            far: settings.far,
+            shutter_open: settings.shutter_open,
+            shutter_close: settings.shutter_close,
+            rolling_shutter: settings.rolling_shutter,
+        }
+    }
+
+    // A random time while the row at t, from 0 at the bottom of the image
+    // to 1 at the top, is exposed
+    fn random_time(&self, t: f32) -> f32 {
+        let interval = self.shutter_close - self.shutter_open;
+        let row_open = self.shutter_open + self.rolling_shutter * interval * (1.0_f32 - t);
+        let exposure = (1.0_f32 - self.rolling_shutter) * interval;
+        return row_open + rng::random() * exposure;
+    }
+
+    // Panoramic cameras see in every direction, so they clip by distance
+    // from the camera rather than by planes.
+    fn clip_range(&self, r: &Ray) -> (f32, f32) {
+        let speed = r.dir.length();
+        return (self.near / speed, self.far / speed);
+    }
+}
+
+// Perspective
+// A thin lens camera, with depth of field from a non-zero aperture.
+pub struct Perspective {
+    frame: Frame,
     lower_left_corner: Point3,
     horizontal: Vector3,
     vertical: Vector3,
-    u: Vector3,
-    v: Vector3,
     lens_radius: f32,
 }
 
-impl Camera {
+impl Perspective {
     pub fn new(
         pos: &Point3,
         look_at: &Point3,
@@ -27,63 +139,216 @@ impl Camera {
         aspect: f32,
         aperture: f32,
         focus_dist: f32,
-    ) -> Camera {
+        settings: &CameraSettings,
+    ) -> Perspective {
         let theta = vertical_fov.to_radians();
         let half_height = (theta / 2.0_f32).tan();
         let half_width = aspect * half_height;
 
-        let w = (*pos - *look_at).normalized();
-        let u = up.cross(w).normalized();
-        let v = w.cross(u);
-
-        Camera {
-            origin: *pos,
+        let frame = Frame::new(pos, look_at, up, settings);
+        Perspective {
             lower_left_corner: *pos
-                - (half_width * focus_dist * u)
-                - (half_height * focus_dist * v)
-                - (w * focus_dist),
-            horizontal: 2.0_f32 * half_width * focus_dist * u,
-            vertical: 2.0_f32 * half_height * focus_dist * v,
-            u: u,
-            v: v,
+                - (half_width * focus_dist * frame.u)
+                - (half_height * focus_dist * frame.v)
+                + (frame.forward * focus_dist),
+            horizontal: 2.0_f32 * half_width * focus_dist * frame.u,
+            vertical: 2.0_f32 * half_height * focus_dist * frame.v,
             lens_radius: aperture / 2.0_f32,
+            frame: frame,
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let ray_disk = self.lens_radius * utils::random_unit_disk();
-        let offset = self.u * ray_disk.x() + self.v * ray_disk.y();
+    fn get_ray(&self, s: f32, t: f32) -> Ray {
+        let ray_disk = self.lens_radius * sampling::random_unit_disk();
+        let offset = self.frame.u * ray_disk.x() + self.frame.v * ray_disk.y();
 
         Ray::new(
-            self.origin + offset,
+            self.frame.origin + offset,
             self.lower_left_corner + (self.horizontal * s) + (self.vertical * t)
-                - self.origin
+                - self.frame.origin
                 - offset,
+            self.frame.random_time(t),
         )
     }
+
+    // The clipping planes are perpendicular to the view direction
+    fn clip_range(&self, r: &Ray) -> (f32, f32) {
+        let speed = r.dir.dot(self.frame.forward);
+        return (self.frame.near / speed, self.frame.far / speed);
+    }
+}
+
+// Fisheye
+// An equidistant fisheye, where the angle away from the view direction grows
+// linearly with the distance from the center of the image. The field of view
+// spans a circle touching the top and bottom of the image.
+pub struct Fisheye {
+    frame: Frame,
+    half_fov: f32,
+    aspect: f32,
+}
+
+impl Fisheye {
+    pub fn new(
+        pos: &Point3,
+        look_at: &Point3,
+        up: &Vector3,
+        fov: f32,
+        aspect: f32,
+        settings: &CameraSettings,
+    ) -> Fisheye {
+        Fisheye {
+            frame: Frame::new(pos, look_at, up, settings),
+            half_fov: utils::clamp(fov, 0.0_f32, 180.0_f32).to_radians() / 2.0_f32,
+            aspect: aspect,
+        }
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        // Position on the image relative to its center, where the circle has
+        // a radius of one
+        let x = (2.0_f32 * s - 1.0_f32) * self.aspect;
+        let y = 2.0_f32 * t - 1.0_f32;
+        let radius = (x * x + y * y).sqrt();
+        if radius > 1.0_f32 {
+            return None;
+        }
+
+        let theta = radius * self.half_fov;
+        let phi = y.atan2(x);
+        return Some(Ray::new(
+            self.frame.origin,
+            theta.sin() * (phi.cos() * self.frame.u + phi.sin() * self.frame.v)
+                + theta.cos() * self.frame.forward,
+            self.frame.random_time(t),
+        ));
+    }
+}
+
+// Equirectangular
+// A full 360 by 180 degree panorama, with longitude across the image and
+// latitude up it. The view direction is at the center of the image. Images
+// are usually twice as wide as they are tall.
+pub struct Equirectangular {
+    frame: Frame,
+}
+
+impl Equirectangular {
+    pub fn new(
+        pos: &Point3,
+        look_at: &Point3,
+        up: &Vector3,
+        settings: &CameraSettings,
+    ) -> Equirectangular {
+        Equirectangular {
+            frame: Frame::new(pos, look_at, up, settings),
+        }
+    }
+
+    fn get_ray(&self, s: f32, t: f32) -> Ray {
+        let longitude = (s - 0.5_f32) * 2.0_f32 * f32::consts::PI;
+        let latitude = (t - 0.5_f32) * f32::consts::PI;
+        return Ray::new(
+            self.frame.origin,
+            latitude.cos()
+                * (longitude.sin() * self.frame.u + longitude.cos() * self.frame.forward)
+                + latitude.sin() * self.frame.v,
+            self.frame.random_time(t),
+        );
+    }
+}
+
+#[derive(Deserialize)]
+enum Projection {
+    Perspective,
+    Fisheye,
+    Equirectangular,
 }
 
 #[derive(Deserialize)]
 struct CameraDescription {
+    // Perspective when not given
+    projection: Option<Projection>,
     position: Point3,
     look_at: Point3,
     up: Vector3,
-    fov: f32,
-    aspect_ratio: f32,
-    aperture: f32,
-    focus_distance: f32,
+    // Vertical field of view for perspective cameras, or the angle across
+    // a fisheye's circle. Panoramas always see everything, so they need
+    // neither this nor the aspect ratio.
+    fov: Option<f32>,
+    aspect_ratio: Option<f32>,
+    // Only perspective cameras have a lens
+    aperture: Option<f32>,
+    focus_distance: Option<f32>,
+    near: Option<f32>,
+    far: Option<f32>,
+    // Times the shutter opens and closes, for motion blur. Both default to
+    // zero, which freezes moving shapes where they start.
+    #[serde(default)]
+    shutter_open: f32,
+    #[serde(default)]
+    shutter_close: f32,
+    // Rolling shutter readout, zero for a global shutter by default
+    #[serde(default)]
+    rolling_shutter: f32,
 }
 
-impl convert::From<CameraDescription> for Camera {
-    fn from(camera_desc: CameraDescription) -> Self {
-        Camera::new(
-            &camera_desc.position,
-            &camera_desc.look_at,
-            &camera_desc.up,
-            camera_desc.fov,
-            camera_desc.aspect_ratio,
-            camera_desc.aperture,
-            camera_desc.focus_distance,
-        )
+impl convert::TryFrom<CameraDescription> for Camera {
+    type Error = &'static str;
+    fn try_from(camera_desc: CameraDescription) -> Result<Self, Self::Error> {
+        if camera_desc.shutter_close < camera_desc.shutter_open {
+            return Err("Camera shutter_close can not be before shutter_open.");
+        }
+        if !(camera_desc.rolling_shutter >= 0.0_f32 && camera_desc.rolling_shutter <= 1.0_f32) {
+            return Err("Camera rolling_shutter must be between 0 and 1.");
+        }
+        let settings = CameraSettings {
+            near: camera_desc.near.unwrap_or(0.0_f32),
+            far: camera_desc.far.unwrap_or(utils::T_MAX),
+            shutter_open: camera_desc.shutter_open,
+            shutter_close: camera_desc.shutter_close,
+            rolling_shutter: camera_desc.rolling_shutter,
+        };
+        match camera_desc.projection.unwrap_or(Projection::Perspective) {
+            Projection::Perspective => match (
+                camera_desc.fov,
+                camera_desc.aspect_ratio,
+                camera_desc.aperture,
+                camera_desc.focus_distance,
+            ) {
+                (Some(fov), Some(aspect_ratio), Some(aperture), Some(focus_distance)) => {
+                    Ok(Camera::Perspective(Perspective::new(
+                        &camera_desc.position,
+                        &camera_desc.look_at,
+                        &camera_desc.up,
+                        fov,
+                        aspect_ratio,
+                        aperture,
+                        focus_distance,
+                        &settings,
+                    )))
+                }
+                _ => Err(
+                    "Perspective cameras need a fov, aspect_ratio, aperture, and focus_distance.",
+                ),
+            },
+            Projection::Fisheye => match (camera_desc.fov, camera_desc.aspect_ratio) {
+                (Some(fov), Some(aspect_ratio)) => Ok(Camera::Fisheye(Fisheye::new(
+                    &camera_desc.position,
+                    &camera_desc.look_at,
+                    &camera_desc.up,
+                    fov,
+                    aspect_ratio,
+                    &settings,
+                ))),
+                _ => Err("Fisheye cameras need a fov and aspect_ratio."),
+            },
+            Projection::Equirectangular => Ok(Camera::Equirectangular(Equirectangular::new(
+                &camera_desc.position,
+                &camera_desc.look_at,
+                &camera_desc.up,
+                &settings,
+            ))),
+        }
     }
 }
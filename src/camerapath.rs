@@ -0,0 +1,216 @@
+use crate::point::Point3;
+use crate::utils;
+
+use serde::Deserialize;
+use serde_json::json;
+
+// Moves a camera along a curve over the frames of an animation, so a
+// flythrough is a handful of points in the scene rather than a position
+// and look_at keyed into the spec of every frame. The frame being rendered
+// comes from the Logistics, or the --frame command line flag, and the
+// camera's position and look_at are filled in from the path before it is
+// read like any other.
+//
+// The camera moves at an even speed along the curve, however unevenly its
+// points are spread, and the ease curve then shapes its speed over the
+// frames. Targets for look_at follow a curve of their own, with the nth
+// target looked at as the camera passes the nth point.
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum Curve {
+    // Passes through every point, with each segment's tangents aimed from
+    // the point before it to the point after it
+    CatmullRom,
+    // Cubic Bézier segments, each an anchor, two control points, and the
+    // next anchor, which starts the following segment
+    Bezier,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum Ease {
+    Linear,
+    // Starts slowly
+    EaseIn,
+    // Stops slowly
+    EaseOut,
+    // Starts and stops slowly
+    EaseInOut,
+}
+
+#[derive(Deserialize)]
+struct CameraPath {
+    curve: Curve,
+    points: Vec<Point3>,
+    // The camera's own look_at is kept when not given
+    look_at: Option<Vec<Point3>>,
+    // How many frames the path takes, from the first frame at its start to
+    // the last at its end. Later frames stay at the end.
+    frames: u32,
+    #[serde(default = "default_ease")]
+    ease: Ease,
+}
+
+fn default_ease() -> Ease {
+    Ease::Linear
+}
+
+// Samples taken along each segment to measure its length
+const LENGTH_SAMPLES: usize = 64;
+
+// Replaces the camera spec's path, if it has one, with the position and
+// look_at of the given frame
+pub fn follow(camera_json: &mut serde_json::Value, frame: u32) -> Result<(), String> {
+    let path_value = match camera_json.as_object_mut().and_then(|c| c.remove("path")) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let path: CameraPath = match serde_json::from_value(path_value) {
+        Ok(p) => p,
+        Err(e) => return Err(format!("Camera path is malformed: {}", e)),
+    };
+    let segments = segment_count(path.curve, path.points.len())?;
+    match &path.look_at {
+        Some(targets) if targets.len() != path.points.len() => {
+            return Err(format!(
+                "Camera path has {} points but {} look_at targets, it needs as many of each.",
+                path.points.len(),
+                targets.len()
+            ))
+        }
+        _ => {}
+    }
+    if path.frames < 2 {
+        return Err(String::from("Camera path needs at least 2 frames."));
+    }
+
+    let progress = ease(
+        path.ease,
+        (frame as f32 / (path.frames - 1) as f32).min(1.0_f32),
+    );
+    let (segment, t) = even_speed(path.curve, &path.points, segments, progress);
+    camera_json["position"] = point_json(evaluate(path.curve, &path.points, segment, t));
+    match &path.look_at {
+        Some(targets) => {
+            camera_json["look_at"] = point_json(evaluate(path.curve, targets, segment, t))
+        }
+        None => {}
+    }
+    return Ok(());
+}
+
+fn segment_count(curve: Curve, point_count: usize) -> Result<usize, String> {
+    match curve {
+        Curve::CatmullRom if point_count >= 2 => return Ok(point_count - 1),
+        Curve::CatmullRom => {
+            return Err(String::from(
+                "CatmullRom camera paths need at least 2 points.",
+            ))
+        }
+        Curve::Bezier if point_count >= 4 && (point_count - 1) % 3 == 0 => {
+            return Ok((point_count - 1) / 3)
+        }
+        Curve::Bezier => {
+            return Err(format!(
+                "Bezier camera paths need 3 points a segment plus 1, but have {}.",
+                point_count
+            ))
+        }
+    }
+}
+
+fn ease(ease: Ease, t: f32) -> f32 {
+    return match ease {
+        Ease::Linear => t,
+        Ease::EaseIn => t * t,
+        Ease::EaseOut => t * (2.0_f32 - t),
+        Ease::EaseInOut => t * t * (3.0_f32 - 2.0_f32 * t),
+    };
+}
+
+// The segment, and the parameter along it, that lies the given fraction of
+// the whole path's length from its start
+fn even_speed(curve: Curve, points: &[Point3], segments: usize, fraction: f32) -> (usize, f32) {
+    // Running lengths at each sample along the path, the first at 0
+    let mut lengths = Vec::with_capacity(segments * LENGTH_SAMPLES + 1);
+    lengths.push(0.0_f32);
+    let mut previous = points[0];
+    for segment in 0..segments {
+        for i in 1..=LENGTH_SAMPLES {
+            let p = evaluate(curve, points, segment, i as f32 / LENGTH_SAMPLES as f32);
+            let total = lengths[lengths.len() - 1] + (p - previous).length();
+            lengths.push(total);
+            previous = p;
+        }
+    }
+    let total = lengths[lengths.len() - 1];
+    if total == 0.0_f32 {
+        // The camera doesn't move, so only the targets matter
+        return parameter_at(segments, fraction * segments as f32);
+    }
+
+    let distance = fraction * total;
+    let after = lengths
+        .iter()
+        .position(|l| *l >= distance)
+        .unwrap_or(lengths.len() - 1)
+        .max(1);
+    let step = lengths[after] - lengths[after - 1];
+    let within = if step > 0.0_f32 {
+        (distance - lengths[after - 1]) / step
+    } else {
+        0.0_f32
+    };
+    return parameter_at(
+        segments,
+        (after as f32 - 1.0_f32 + within) / LENGTH_SAMPLES as f32,
+    );
+}
+
+// Splits a parameter running from 0 to the number of segments into a
+// segment and the parameter along it
+fn parameter_at(segments: usize, parameter: f32) -> (usize, f32) {
+    let segment = (parameter.floor() as usize).min(segments - 1);
+    return (
+        segment,
+        utils::clamp(parameter - segment as f32, 0.0_f32, 1.0_f32),
+    );
+}
+
+fn evaluate(curve: Curve, points: &[Point3], segment: usize, t: f32) -> Point3 {
+    match curve {
+        Curve::CatmullRom => {
+            // The ends are continued straight on
+            let p1 = points[segment];
+            let p2 = points[segment + 1];
+            let p0 = if segment > 0 {
+                points[segment - 1]
+            } else {
+                p1 - (p2 - p1)
+            };
+            let p3 = if segment + 2 < points.len() {
+                points[segment + 2]
+            } else {
+                p2 + (p2 - p1)
+            };
+            let t2 = t * t;
+            let t3 = t2 * t;
+            return p1
+                + 0.5_f32
+                    * ((p2 - p0) * t
+                        + (2.0_f32 * (p0 - p1) + 4.0_f32 * (p2 - p1) - (p3 - p1)) * t2
+                        + (3.0_f32 * (p1 - p2) + (p3 - p0)) * t3);
+        }
+        Curve::Bezier => {
+            let p = &points[segment * 3..segment * 3 + 4];
+            let s = 1.0_f32 - t;
+            return p[0]
+                + 3.0_f32 * s * s * t * (p[1] - p[0])
+                + 3.0_f32 * s * t * t * (p[2] - p[0])
+                + t * t * t * (p[3] - p[0]);
+        }
+    }
+}
+
+fn point_json(p: Point3) -> serde_json::Value {
+    return json!([p.x(), p.y(), p.z()]);
+}
@@ -0,0 +1,152 @@
+use crate::aggregate::AABB;
+use crate::material::SyncMaterial;
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::shape::{Hit, HitProperties, Shape, SyncShape};
+use crate::texture::TexCoord;
+use crate::utils;
+use crate::vector::Vector3;
+
+use std::sync::Arc;
+
+// A plane that removes the geometry on the side its normal points to, for
+// section renders. Clipped shapes are left open, there are no caps over the
+// cut.
+pub struct ClipPlane {
+    point: Point3,
+    normal: Vector3,
+}
+
+impl Copy for ClipPlane {}
+impl Clone for ClipPlane {
+    fn clone(&self) -> ClipPlane {
+        *self
+    }
+}
+
+impl ClipPlane {
+    pub fn new(point: Point3, normal: Vector3) -> ClipPlane {
+        ClipPlane {
+            point: point,
+            normal: normal,
+        }
+    }
+
+    // Whether all of the box is on the removed side of the plane
+    pub fn removes(&self, bounds: &AABB) -> bool {
+        // The corner of the box farthest behind the plane
+        let nearest = Point3::new(
+            if self.normal.x() > 0.0_f32 {
+                bounds.min.x()
+            } else {
+                bounds.max.x()
+            },
+            if self.normal.y() > 0.0_f32 {
+                bounds.min.y()
+            } else {
+                bounds.max.y()
+            },
+            if self.normal.z() > 0.0_f32 {
+                bounds.min.z()
+            } else {
+                bounds.max.z()
+            },
+        );
+        return self.normal.dot(nearest - self.point) > 0.0_f32;
+    }
+}
+
+// Clipped
+// Wraps a shape so that only hits on the kept side of every plane count.
+// The kept part of a ray is a single span, since each plane keeps a convex
+// half-space, so the wrapped shape only needs to be hit once within it.
+pub struct Clipped {
+    shape: Arc<SyncShape>,
+    planes: Vec<ClipPlane>,
+}
+
+impl Clipped {
+    pub fn new(shape: Arc<SyncShape>, planes: Vec<ClipPlane>) -> Clipped {
+        Clipped {
+            shape: shape,
+            planes: planes,
+        }
+    }
+}
+
+impl Shape for Clipped {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let mut kept_min = t_min;
+        let mut kept_max = t_max;
+        for plane in &self.planes {
+            // How far in front of the plane the ray starts, and how quickly
+            // it moves further in front
+            let start = plane.normal.dot(r.origin - plane.point);
+            let speed = plane.normal.dot(r.dir);
+            if speed == 0.0_f32 {
+                if start > 0.0_f32 {
+                    return None;
+                }
+                continue;
+            }
+
+            let t_plane = -start / speed;
+            if speed > 0.0_f32 {
+                kept_max = utils::float_min(kept_max, t_plane);
+            } else {
+                kept_min = utils::float_max(kept_min, t_plane);
+            }
+            if kept_min >= kept_max {
+                return None;
+            }
+        }
+        return self.shape.hit(r, kept_min, kept_max);
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        self.shape.get_hit_properties(r, hit)
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        self.shape.get_material()
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        self.shape.get_bounding_box()
+    }
+
+    fn get_clipped_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        self.shape.get_clipped_bounding_box(clip)
+    }
+
+    // Sampling still covers the whole of the wrapped shape. Directions
+    // toward the removed part just see past it, which is unbiased, if a
+    // little wasteful.
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        self.shape.pdf(r, t_min)
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        self.shape.random_dir_towards(from_origin)
+    }
+
+    fn edge_distance(&self, r: &Ray, hit: &Hit) -> Option<f32> {
+        self.shape.edge_distance(r, hit)
+    }
+
+    fn is_bounded(&self) -> bool {
+        self.shape.is_bounded()
+    }
+
+    fn is_front_face(&self, r: &Ray, hit: &Hit) -> bool {
+        self.shape.is_front_face(r, hit)
+    }
+
+    fn instance_depth(&self) -> usize {
+        self.shape.instance_depth()
+    }
+
+    fn corner_uvs(&self) -> Option<[TexCoord; 3]> {
+        self.shape.corner_uvs()
+    }
+}
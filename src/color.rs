@@ -34,12 +34,25 @@ impl RGB {
         self.0.z
     }
 
-    pub fn gamma_correct(&self) -> RGB {
-        RGB::new(self.r().sqrt(), self.g().sqrt(), self.b().sqrt())
+    // Perceived brightness, with the Rec. 709 weights
+    pub fn luminance(&self) -> f32 {
+        0.2126_f32 * self.r() + 0.7152_f32 * self.g() + 0.0722_f32 * self.b()
     }
 
-    pub fn inverse_gamma_correct(self) -> RGB {
-        self * self
+    // The color turned about the grey axis by the angle in degrees, which
+    // shifts its hue and keeps greys as they are. Channels that would go
+    // negative are clamped to 0.
+    pub fn hue_rotated(&self, degrees: f32) -> RGB {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        // Rodrigues' rotation about (1, 1, 1) / sqrt(3)
+        let third = (1.0_f32 - cos) / 3.0_f32;
+        let root = sin / 3.0_f32.sqrt();
+        let (same, next, previous) = (cos + third, third - root, third + root);
+        return RGB::new(
+            (same * self.r() + next * self.g() + previous * self.b()).max(0.0_f32),
+            (previous * self.r() + same * self.g() + next * self.b()).max(0.0_f32),
+            (next * self.r() + previous * self.g() + same * self.b()).max(0.0_f32),
+        );
     }
 }
 
@@ -57,6 +70,13 @@ impl ops::Add for RGB {
     }
 }
 
+impl ops::Sub for RGB {
+    type Output = RGB;
+    fn sub(self, rhs: RGB) -> RGB {
+        RGB(self.0.sub(rhs.0))
+    }
+}
+
 impl ops::Mul<f32> for RGB {
     type Output = RGB;
     fn mul(self, rhs: f32) -> RGB {
@@ -0,0 +1,126 @@
+use crate::color::RGB;
+
+use serde::Deserialize;
+
+// Color spaces a scene can be rendered in and written out to. Colors in the
+// scene specification and in image textures are authored with the sRGB (Rec.
+// 709) primaries, and are converted into the working space as the scene is
+// loaded. Rendering happens in the working space, which is always linear,
+// and the result is converted and encoded for the output space at the end.
+#[derive(Deserialize)]
+pub enum ColorSpace {
+    #[serde(rename = "sRGB")]
+    SRGB,
+    Rec709,
+    ACEScg,
+}
+
+impl Copy for ColorSpace {}
+impl Clone for ColorSpace {
+    fn clone(&self) -> ColorSpace {
+        *self
+    }
+}
+
+// Rec. 709 primaries with a D65 white to the ACES AP1 primaries with a D60
+// white, including a Bradford chromatic adaptation, and back again.
+const REC709_TO_AP1: [[f32; 3]; 3] = [
+    [0.6130974_f32, 0.3395231_f32, 0.0473795_f32],
+    [0.0701937_f32, 0.9163539_f32, 0.0134524_f32],
+    [0.0206156_f32, 0.1095698_f32, 0.8698147_f32],
+];
+const AP1_TO_REC709: [[f32; 3]; 3] = [
+    [1.7050510_f32, -0.6217921_f32, -0.0832590_f32],
+    [-0.1302564_f32, 1.1408048_f32, -0.0105485_f32],
+    [-0.0240033_f32, -0.1289690_f32, 1.1529720_f32],
+];
+
+impl ColorSpace {
+    // Converts a linear color with the Rec. 709 primaries into this space's
+    // primaries. sRGB and Rec. 709 share primaries, so only ACEScg changes.
+    pub fn from_rec709(&self, c: RGB) -> RGB {
+        match self {
+            ColorSpace::SRGB | ColorSpace::Rec709 => c,
+            ColorSpace::ACEScg => transform(&REC709_TO_AP1, c),
+        }
+    }
+
+    // Inverse of from_rec709
+    pub fn to_rec709(&self, c: RGB) -> RGB {
+        match self {
+            ColorSpace::SRGB | ColorSpace::Rec709 => c,
+            ColorSpace::ACEScg => transform(&AP1_TO_REC709, c),
+        }
+    }
+
+    // Converts a linear color in this space's primaries into another's
+    pub fn convert_to(&self, other: ColorSpace, c: RGB) -> RGB {
+        return other.from_rec709(self.to_rec709(c));
+    }
+
+    pub fn has_rec709_primaries(&self) -> bool {
+        match self {
+            ColorSpace::SRGB | ColorSpace::Rec709 => true,
+            ColorSpace::ACEScg => false,
+        }
+    }
+
+    // CIE xy coordinates of the red, green, and blue primaries and the white
+    // point, in that order
+    pub fn chromaticities(&self) -> [f32; 8] {
+        match self {
+            ColorSpace::SRGB | ColorSpace::Rec709 => [
+                0.64_f32, 0.33_f32, 0.30_f32, 0.60_f32, 0.15_f32, 0.06_f32, 0.3127_f32, 0.3290_f32,
+            ],
+            ColorSpace::ACEScg => [
+                0.713_f32,
+                0.293_f32,
+                0.165_f32,
+                0.830_f32,
+                0.128_f32,
+                0.044_f32,
+                0.32168_f32,
+                0.33767_f32,
+            ],
+        }
+    }
+
+    // Applies this space's transfer function to a linear value in [0, 1],
+    // giving the value to store in an image. ACEScg is stored linearly.
+    pub fn encode(&self, v: f32) -> f32 {
+        match self {
+            ColorSpace::SRGB => {
+                if v <= 0.0031308_f32 {
+                    12.92_f32 * v
+                } else {
+                    1.055_f32 * v.powf(1.0_f32 / 2.4_f32) - 0.055_f32
+                }
+            }
+            ColorSpace::Rec709 => {
+                if v < 0.018_f32 {
+                    4.5_f32 * v
+                } else {
+                    1.099_f32 * v.powf(0.45_f32) - 0.099_f32
+                }
+            }
+            ColorSpace::ACEScg => v,
+        }
+    }
+}
+
+// Inverse of the sRGB transfer function, for decoding 8 bit images
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045_f32 {
+        v / 12.92_f32
+    } else {
+        ((v + 0.055_f32) / 1.055_f32).powf(2.4_f32)
+    }
+}
+
+fn transform(m: &[[f32; 3]; 3], c: RGB) -> RGB {
+    RGB::new(
+        m[0][0] * c.r() + m[0][1] * c.g() + m[0][2] * c.b(),
+        m[1][0] * c.r() + m[1][1] * c.g() + m[1][2] * c.b(),
+        m[2][0] * c.r() + m[2][1] * c.g() + m[2][2] * c.b(),
+    )
+}
@@ -0,0 +1,209 @@
+use crate::aggregate::AABB;
+use crate::material::SyncMaterial;
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::rng;
+use crate::shape::{Hit, HitProperties, Shape, SyncShape};
+
+use serde::Deserialize;
+use std::sync::Arc;
+
+// How far past a crossing, relative to its distance along the ray, the
+// search for the next crossing of the same child starts
+const CROSSING_EPSILON: f32 = 0.0001_f32;
+// Closed shapes cross a ray only a few times, this just keeps a shape that
+// keeps reporting the same hit from stalling the walk
+const MAX_CROSSINGS: u32 = 64_u32;
+
+#[derive(Deserialize)]
+pub enum CSGOperation {
+    // Inside either child
+    Union,
+    // Inside both children
+    Intersection,
+    // Inside the left child but not the right
+    Difference,
+}
+
+impl Copy for CSGOperation {}
+impl Clone for CSGOperation {
+    fn clone(&self) -> CSGOperation {
+        *self
+    }
+}
+
+impl CSGOperation {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CSGOperation::Union => in_left || in_right,
+            CSGOperation::Intersection => in_left && in_right,
+            CSGOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// CSG
+// Combines two shapes into one solid. Hits are found by walking each
+// child's crossings along the ray in order, tracking whether the ray is
+// inside each, and stopping where the combined solid is entered or left.
+// Children need to be closed for inside and outside to mean anything, so
+// open shapes like disks and uncapped cylinders give odd results. Planes
+// work, as the half-space behind them.
+pub struct CSG {
+    operation: CSGOperation,
+    left: Arc<SyncShape>,
+    right: Arc<SyncShape>,
+    material: Arc<SyncMaterial>,
+}
+
+impl CSG {
+    pub fn new(
+        operation: CSGOperation,
+        left: Arc<SyncShape>,
+        right: Arc<SyncShape>,
+        material: Arc<SyncMaterial>,
+    ) -> CSG {
+        CSG {
+            operation: operation,
+            left: left,
+            right: right,
+            material: material,
+        }
+    }
+
+    // The child a hit made by CSG::hit is on, the child's own hit, and
+    // whether its surface faces the other way on the combined solid, as the
+    // right child's does when it is cut out of the left
+    fn child_at<'a>(&self, hit: &Hit<'a>) -> (&Arc<SyncShape>, Hit<'a>, bool) {
+        let (right, child_hit) = hit.child();
+        if !right {
+            return (&self.left, child_hit, false);
+        }
+        return (
+            &self.right,
+            child_hit,
+            match self.operation {
+                CSGOperation::Difference => true,
+                _ => false,
+            },
+        );
+    }
+}
+
+impl Shape for CSG {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        // Whether the ray starts inside a child shows in its first crossing,
+        // which leaves the child if so. Crossings past t_max still count.
+        let mut left_next = self.left.hit(r, t_min, f32::INFINITY);
+        let mut right_next = self.right.hit(r, t_min, f32::INFINITY);
+        let mut in_left = match &left_next {
+            Some(h) => !self.left.is_front_face(r, h),
+            None => false,
+        };
+        let mut in_right = match &right_next {
+            Some(h) => !self.right.is_front_face(r, h),
+            None => false,
+        };
+
+        for _ in 0..MAX_CROSSINGS {
+            let was_inside = self.operation.contains(in_left, in_right);
+            let (hit, from_left) = match (left_next, right_next) {
+                (Some(l), Some(r)) => {
+                    if l.t <= r.t {
+                        (l, true)
+                    } else {
+                        (r, false)
+                    }
+                }
+                (Some(l), None) => (l, true),
+                (None, Some(r)) => (r, false),
+                (None, None) => return None,
+            };
+            let t = hit.t;
+            if t >= t_max {
+                return None;
+            }
+
+            let search_from = t + CROSSING_EPSILON * t.abs();
+            if from_left {
+                in_left = self.left.is_front_face(r, &hit);
+                left_next = self.left.hit(r, search_from, f32::INFINITY);
+            } else {
+                in_right = self.right.is_front_face(r, &hit);
+                right_next = self.right.hit(r, search_from, f32::INFINITY);
+            }
+            if self.operation.contains(in_left, in_right) != was_inside {
+                return Some(Hit::on_child(!from_left, hit));
+            }
+        }
+        return None;
+    }
+
+    // The normal points out of the combined solid, whichever way the child
+    // has its own facing
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let (child, child_hit, flipped) = self.child_at(hit);
+        let mut hit_props = child.get_hit_properties(r, &child_hit);
+        let faces_ray = r.dir.dot(hit_props.normal) < 0.0_f32;
+        if faces_ray != (child.is_front_face(r, &child_hit) != flipped) {
+            hit_props.normal = -hit_props.normal;
+        }
+        return hit_props;
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        &self.material
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        let left = self.left.get_bounding_box();
+        match self.operation {
+            CSGOperation::Union => AABB::union(&left, &self.right.get_bounding_box()),
+            CSGOperation::Intersection => {
+                match AABB::intersection(&left, &self.right.get_bounding_box()) {
+                    Some(b) => b,
+                    // Nothing is left, so any empty box will do
+                    None => AABB::new(left.min, left.min),
+                }
+            }
+            CSGOperation::Difference => left,
+        }
+    }
+
+    // Every surface of the combined solid is part of one child or the other,
+    // so sampling both children evenly covers it. Directions toward parts
+    // of the children that were removed just see past them, like clipped
+    // shapes do.
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        return 0.5_f32 * (self.left.pdf(r, t_min) + self.right.pdf(r, t_min));
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> crate::vector::Vector3 {
+        if rng::random() < 0.5_f32 {
+            return self.left.random_dir_towards(from_origin);
+        }
+        return self.right.random_dir_towards(from_origin);
+    }
+
+    fn edge_distance(&self, r: &Ray, hit: &Hit) -> Option<f32> {
+        let (child, child_hit, _) = self.child_at(hit);
+        return child.edge_distance(r, &child_hit);
+    }
+
+    fn is_bounded(&self) -> bool {
+        match self.operation {
+            CSGOperation::Union => self.left.is_bounded() && self.right.is_bounded(),
+            CSGOperation::Intersection => self.left.is_bounded() || self.right.is_bounded(),
+            CSGOperation::Difference => self.left.is_bounded(),
+        }
+    }
+
+    fn is_front_face(&self, r: &Ray, hit: &Hit) -> bool {
+        let (child, child_hit, flipped) = self.child_at(hit);
+        return child.is_front_face(r, &child_hit) != flipped;
+    }
+
+    fn instance_depth(&self) -> usize {
+        return std::cmp::max(self.left.instance_depth(), self.right.instance_depth());
+    }
+}
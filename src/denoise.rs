@@ -0,0 +1,196 @@
+// Denoising of the finished image with Intel Open Image Denoise
+// (https://www.openimagedenoise.org), compiled in with the `denoise`
+// feature, which links against the OpenImageDenoise library through its C
+// API. The image is denoised in the working space, before it is converted
+// and quantized for output, by the library's generic ray tracing filter,
+// guided by the image's albedo and normal passes so that texture detail and
+// edges survive. Low sample renders come out smooth enough for previews,
+// though the filter invents detail where there were too few samples to
+// find it.
+//
+// Without the feature, asking for denoising is an error.
+
+use crate::framebuffer::Framebuffer;
+use crate::passes::PassImages;
+use crate::scene::Logistics;
+
+#[cfg(feature = "denoise")]
+use crate::color::RGB;
+#[cfg(feature = "denoise")]
+use crate::passes::Pass;
+#[cfg(feature = "denoise")]
+use std::ffi::CStr;
+#[cfg(feature = "denoise")]
+use std::os::raw::{c_char, c_int, c_void};
+
+// Whether this build can denoise
+pub const AVAILABLE: bool = cfg!(feature = "denoise");
+
+#[cfg(feature = "denoise")]
+#[allow(non_camel_case_types)]
+type OIDNDevice = *mut c_void;
+#[cfg(feature = "denoise")]
+#[allow(non_camel_case_types)]
+type OIDNFilter = *mut c_void;
+
+#[cfg(feature = "denoise")]
+const OIDN_DEVICE_TYPE_DEFAULT: c_int = 0;
+#[cfg(feature = "denoise")]
+const OIDN_FORMAT_FLOAT3: c_int = 3;
+#[cfg(feature = "denoise")]
+const OIDN_ERROR_NONE: c_int = 0;
+
+#[cfg(feature = "denoise")]
+#[link(name = "OpenImageDenoise")]
+extern "C" {
+    fn oidnNewDevice(device_type: c_int) -> OIDNDevice;
+    fn oidnCommitDevice(device: OIDNDevice);
+    fn oidnGetDeviceError(device: OIDNDevice, out_message: *mut *const c_char) -> c_int;
+    fn oidnReleaseDevice(device: OIDNDevice);
+    fn oidnNewFilter(device: OIDNDevice, filter_type: *const c_char) -> OIDNFilter;
+    fn oidnSetSharedFilterImage(
+        filter: OIDNFilter,
+        name: *const c_char,
+        ptr: *mut c_void,
+        format: c_int,
+        width: usize,
+        height: usize,
+        byte_offset: usize,
+        byte_pixel_stride: usize,
+        byte_row_stride: usize,
+    );
+    fn oidnSetFilter1b(filter: OIDNFilter, name: *const c_char, value: bool);
+    fn oidnCommitFilter(filter: OIDNFilter);
+    fn oidnExecuteFilter(filter: OIDNFilter);
+    fn oidnReleaseFilter(filter: OIDNFilter);
+}
+
+// A pass averaged over the samples, in rows from the top left, three floats
+// a pixel, as the filter reads images
+#[cfg(feature = "denoise")]
+fn rows(pixel: impl Fn(usize) -> RGB, res_x: u32, res_y: u32, samples: u32) -> Vec<f32> {
+    let mut values = Vec::with_capacity(res_x as usize * res_y as usize * 3);
+    for y in 0..res_y {
+        for x in 0..res_x {
+            let c = pixel((x * res_y + y) as usize) / samples as f32;
+            values.push(c.r());
+            values.push(c.g());
+            values.push(c.b());
+        }
+    }
+    return values;
+}
+
+// The device's last error, if any
+#[cfg(feature = "denoise")]
+fn device_error(device: OIDNDevice) -> Option<String> {
+    let mut message: *const c_char = std::ptr::null();
+    let error = unsafe { oidnGetDeviceError(device, &mut message) };
+    if error == OIDN_ERROR_NONE {
+        return None;
+    }
+    if message.is_null() {
+        return Some(format!("Open Image Denoise failed with error {}.", error));
+    }
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    return Some(format!("Open Image Denoise failed: {}", message));
+}
+
+// Replaces the summed colors of the framebuffer with denoised ones, using
+// the image's albedo and normal passes, which the scene traces whenever it
+// denoises
+#[cfg(feature = "denoise")]
+pub fn denoise(
+    frame: &mut Framebuffer,
+    passes: &PassImages,
+    logistics: &Logistics,
+) -> Result<(), String> {
+    let res_x = logistics.resolution_x;
+    let res_y = logistics.resolution_y;
+    let samples = logistics.samples;
+    let (albedo, normal) = match (passes.sums(Pass::Albedo), passes.sums(Pass::Normal)) {
+        (Some(a), Some(n)) => (a, n),
+        _ => {
+            return Err(String::from(
+                "Denoising needs the albedo and normal passes.",
+            ))
+        }
+    };
+    let mut color = rows(|i| frame.get(i), res_x, res_y, samples);
+    let mut albedo = rows(|i| albedo[i], res_x, res_y, samples);
+    let mut normal = rows(|i| normal[i], res_x, res_y, samples);
+    let mut output = vec![0.0_f32; color.len()];
+
+    let device = unsafe { oidnNewDevice(OIDN_DEVICE_TYPE_DEFAULT) };
+    if device.is_null() {
+        return Err(String::from(
+            "Open Image Denoise could not create a device.",
+        ));
+    }
+    unsafe { oidnCommitDevice(device) };
+    match device_error(device) {
+        Some(e) => {
+            unsafe { oidnReleaseDevice(device) };
+            return Err(e);
+        }
+        None => {}
+    }
+    let filter = unsafe { oidnNewFilter(device, b"RT\0".as_ptr() as *const c_char) };
+    let mut images: [(&[u8], &mut Vec<f32>); 4] = [
+        (b"color\0", &mut color),
+        (b"albedo\0", &mut albedo),
+        (b"normal\0", &mut normal),
+        (b"output\0", &mut output),
+    ];
+    for (name, values) in images.iter_mut() {
+        unsafe {
+            oidnSetSharedFilterImage(
+                filter,
+                name.as_ptr() as *const c_char,
+                values.as_mut_ptr() as *mut c_void,
+                OIDN_FORMAT_FLOAT3,
+                res_x as usize,
+                res_y as usize,
+                0,
+                0,
+                0,
+            )
+        };
+    }
+    // Colors are radiance, not limited to [0, 1]
+    unsafe {
+        oidnSetFilter1b(filter, b"hdr\0".as_ptr() as *const c_char, true);
+        oidnCommitFilter(filter);
+        oidnExecuteFilter(filter);
+    }
+    let result = device_error(device);
+    unsafe {
+        oidnReleaseFilter(filter);
+        oidnReleaseDevice(device);
+    }
+    match result {
+        Some(e) => return Err(e),
+        None => {}
+    }
+
+    // Back into the framebuffer as sums over the samples
+    for y in 0..res_y {
+        for x in 0..res_x {
+            let i = (y * res_x + x) as usize * 3;
+            let c = RGB::new(output[i], output[i + 1], output[i + 2]);
+            frame.set((x * res_y + y) as usize, c * samples as f32);
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(not(feature = "denoise"))]
+pub fn denoise(
+    _frame: &mut Framebuffer,
+    _passes: &PassImages,
+    _logistics: &Logistics,
+) -> Result<(), String> {
+    Err(String::from(
+        "Denoising needs a build with the denoise feature, see the Readme.",
+    ))
+}
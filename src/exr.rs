@@ -0,0 +1,121 @@
+use crate::colorspace::ColorSpace;
+
+use std::io;
+use std::io::Write;
+
+// Writes linear floating point RGB data as an uncompressed, single part,
+// scanline OpenEXR image, a row at a time from top to bottom. rows fills in
+// the pixels of the given row, three floats each from left to right. The
+// chromaticities of the given color space are stored in the header so that
+// other tools know how to interpret the colors, along with each pair of text
+// as a string attribute, for metadata like the scene's fingerprint.
+pub fn write_rgb<W, F>(
+    out: &mut W,
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+    text: &[(&str, &str)],
+    mut rows: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(u32, &mut Vec<f32>) -> io::Result<()>,
+{
+    let mut header = Vec::new();
+    // Magic number, then version 2 with no flags set (single part scanline)
+    header.extend_from_slice(&[0x76_u8, 0x2f_u8, 0x31_u8, 0x01_u8]);
+    header.extend_from_slice(&2_u32.to_le_bytes());
+
+    // Channels must be listed in alphabetical order, each stored as a 32 bit
+    // float (pixel type 2), not perceptually linear, and not subsampled.
+    let mut channels = Vec::new();
+    for name in CHANNEL_NAMES.iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0_u8);
+        channels.extend_from_slice(&2_i32.to_le_bytes());
+        channels.extend_from_slice(&[0_u8; 4]);
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    channels.push(0_u8);
+    write_attribute(&mut header, "channels", "chlist", &channels);
+
+    write_attribute(&mut header, "compression", "compression", &[0_u8]);
+    let mut window = Vec::new();
+    for v in [0_i32, 0_i32, width as i32 - 1_i32, height as i32 - 1_i32].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y, top to bottom
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0_u8]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0_u8; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    let mut chromaticities = Vec::new();
+    for v in color_space.chromaticities().iter() {
+        chromaticities.extend_from_slice(&v.to_le_bytes());
+    }
+    write_attribute(
+        &mut header,
+        "chromaticities",
+        "chromaticities",
+        &chromaticities,
+    );
+    for (name, value) in text {
+        write_attribute(&mut header, name, "string", value.as_bytes());
+    }
+    header.push(0_u8);
+    out.write_all(&header)?;
+
+    // Uncompressed files store one scanline per block, each of which starts
+    // with its y coordinate and the size of its data. The offset table
+    // points at every block from the start of the file.
+    let line_size = width as u64 * CHANNEL_NAMES.len() as u64 * 4_u64;
+    let first_block = header.len() as u64 + height as u64 * 8_u64;
+    for y in 0..(height as u64) {
+        out.write_all(&(first_block + y * (line_size + 8_u64)).to_le_bytes())?;
+    }
+
+    let mut data = Vec::with_capacity(width as usize * 3_usize);
+    let mut line = Vec::with_capacity(line_size as usize + 8_usize);
+    for y in 0..height {
+        data.clear();
+        rows(y, &mut data)?;
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        // Channels are stored one after another, each across the whole line
+        for channel in CHANNEL_OFFSETS.iter() {
+            for x in 0..width {
+                let idx = (x * 3_u32) as usize + channel;
+                line.extend_from_slice(&data[idx].to_le_bytes());
+            }
+        }
+        out.write_all(&line)?;
+    }
+    return Ok(());
+}
+
+const CHANNEL_NAMES: [&str; 3] = ["B", "G", "R"];
+// Where each of the channels above is found in an RGB pixel
+const CHANNEL_OFFSETS: [usize; 3] = [2, 1, 0];
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, attribute_type: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0_u8);
+    header.extend_from_slice(attribute_type.as_bytes());
+    header.push(0_u8);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
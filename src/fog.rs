@@ -0,0 +1,66 @@
+use crate::color::RGB;
+use crate::ray::Ray;
+
+// Below this vertical direction, a ray is treated as horizontal so the
+// optical depth integral does not divide by zero.
+const HORIZONTAL_EPSILON: f32 = 0.00001_f32;
+
+// Exponential height fog, a cheap analytic stand in for atmosphere. Density
+// falls off exponentially with height above a base:
+//     density(y) = density * e^(-falloff * (y - base_height))
+// The fog integrated along a ray segment blends what is seen toward the fog
+// color, so distant surfaces and escaping rays fade out with depth. The fog
+// only attenuates and tints, it does not scatter rays like a volume does.
+pub struct HeightFog {
+    density: f32,
+    falloff: f32,
+    base_height: f32,
+    color: RGB,
+}
+
+impl HeightFog {
+    pub fn new(density: f32, falloff: f32, base_height: f32, color: RGB) -> HeightFog {
+        HeightFog {
+            density: density,
+            falloff: falloff,
+            base_height: base_height,
+            color: color,
+        }
+    }
+
+    // Blends the radiance arriving along the ray from time 't' with the fog
+    // color. An infinite 't' is used for rays that escape the scene.
+    pub fn apply(&self, r: &Ray, t: f32, radiance: RGB) -> RGB {
+        let transmittance = self.transmittance(r, t);
+        return radiance * transmittance + self.color * (1.0_f32 - transmittance);
+    }
+
+    // Fraction of light that makes it through the fog along the ray to time
+    // 't', without the fog's own color. Used for shadow rays.
+    pub fn transmittance(&self, r: &Ray, t: f32) -> f32 {
+        return (-self.optical_depth(r, t)).exp();
+    }
+
+    // Integral of density along the ray from its origin out to time 't'
+    fn optical_depth(&self, r: &Ray, t: f32) -> f32 {
+        let dir_length = r.dir.length();
+        let distance = t * dir_length;
+        let dir_y = r.dir.y() / dir_length;
+        let origin_density =
+            self.density * (-self.falloff * (r.origin.y() - self.base_height)).exp();
+
+        // Far enough above the base, there is effectively no fog. This also
+        // keeps escaping rays from multiplying zero by infinity.
+        if origin_density == 0.0_f32 {
+            return 0.0_f32;
+        }
+        // With no falloff or a horizontal ray, density is constant along it
+        let rate = self.falloff * dir_y;
+        if rate.abs() < HORIZONTAL_EPSILON {
+            return origin_density * distance;
+        }
+        // Otherwise, the integral of origin_density * e^(-rate * s) from 0 to
+        // distance. For escaping rays going up, e^(-rate * s) goes to zero.
+        return origin_density * (1.0_f32 - (-rate * distance).exp()) / rate;
+    }
+}
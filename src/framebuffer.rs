@@ -0,0 +1,302 @@
+use crate::color::RGB;
+
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// How the rendered image is held in memory until it is written out
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum Precision {
+    // 32 bit floats, 12 bytes a pixel
+    Float,
+    // 16 bit floats, 6 bytes a pixel, for panoramas and other resolutions
+    // too large to hold otherwise. Half floats keep about three significant
+    // digits, well past what an 8 bit image shows, and reach 65504.
+    Half,
+    // 32 bit floats kept in a temporary file rather than memory, for
+    // poster-size images too large for either. Only the tiles being traced
+    // and one row of the image at a time are in memory. The file goes in
+    // the system's temporary directory, TMPDIR on Unix, which should be on
+    // a disk rather than in memory for this to help.
+    Disk,
+}
+
+// The image render fills in, with each pixel's summed color indexed by
+// x * resolution_y + y. Half precision stores averages rather than sums, so
+// high sample counts can not overflow it, and scales them back up when read.
+// Disk keeps it in a file, which is best read and written a row at a time.
+pub enum Framebuffer {
+    Float(Vec<RGB>),
+    Half { pixels: Vec<[u16; 3]>, samples: u32 },
+    Disk(DiskImage),
+}
+
+impl Framebuffer {
+    // A black image of the given resolution
+    pub fn new(
+        precision: Precision,
+        res_x: u32,
+        res_y: u32,
+        samples: u32,
+    ) -> Result<Framebuffer, String> {
+        let pixel_count = res_x as usize * res_y as usize;
+        return match precision {
+            Precision::Float => {
+                let mut pixels = Vec::new();
+                pixels.resize_with(pixel_count, RGB::black);
+                Ok(Framebuffer::Float(pixels))
+            }
+            Precision::Half => Ok(Framebuffer::Half {
+                pixels: vec![[0_u16; 3]; pixel_count],
+                samples: samples.max(1),
+            }),
+            Precision::Disk => match DiskImage::create(res_x, res_y) {
+                Ok(d) => Ok(Framebuffer::Disk(d)),
+                Err(e) => Err(format!("Could not create the image on disk: {}", e)),
+            },
+        };
+    }
+
+    pub fn get(&self, index: usize) -> RGB {
+        match self {
+            Framebuffer::Float(pixels) => pixels[index],
+            Framebuffer::Half { pixels, samples } => {
+                let p = &pixels[index];
+                RGB::new(from_half(p[0]), from_half(p[1]), from_half(p[2])) * (*samples as f32)
+            }
+            Framebuffer::Disk(d) => {
+                let (x, y) = d.position(index);
+                let mut color = [RGB::black()];
+                d.read(x, y, &mut color)
+                    .expect("Failed to read the image on disk.");
+                color[0]
+            }
+        }
+    }
+
+    pub fn set(&mut self, index: usize, color: RGB) {
+        match self {
+            Framebuffer::Float(pixels) => pixels[index] = color,
+            Framebuffer::Half { pixels, samples } => {
+                let average = color / *samples as f32;
+                pixels[index] = [
+                    to_half(average.r()),
+                    to_half(average.g()),
+                    to_half(average.b()),
+                ];
+            }
+            Framebuffer::Disk(d) => {
+                let (x, y) = d.position(index);
+                d.write(x, y, &[color])
+                    .expect("Failed to write the image on disk.");
+            }
+        }
+    }
+
+    // Sets a run of pixels along row y, starting at column x
+    pub fn set_row(&mut self, x: u32, y: u32, colors: &[RGB], res_y: u32) -> io::Result<()> {
+        match self {
+            Framebuffer::Disk(d) => return d.write(x, y, colors),
+            _ => {
+                for (i, c) in colors.iter().enumerate() {
+                    self.set(((x + i as u32) * res_y + y) as usize, *c);
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    // Every pixel of row y, from left to right
+    pub fn row(&self, y: u32, res_x: u32, res_y: u32) -> io::Result<Vec<RGB>> {
+        let mut colors = vec![RGB::black(); res_x as usize];
+        match self {
+            Framebuffer::Disk(d) => d.read(0, y, &mut colors)?,
+            _ => {
+                for x in 0..res_x {
+                    colors[x as usize] = self.get((x * res_y + y) as usize);
+                }
+            }
+        }
+        return Ok(colors);
+    }
+}
+
+// A temporary file of summed pixel colors, each three little endian 32 bit
+// floats, stored by row so finished tiles and the rows of the output are
+// each a handful of contiguous reads and writes. It is removed when dropped.
+pub struct DiskImage {
+    file: File,
+    path: PathBuf,
+    res_x: u32,
+    res_y: u32,
+}
+
+const DISK_PIXEL_SIZE: u64 = 12;
+
+// Tells apart the images of renders in the same process
+static DISK_IMAGE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl DiskImage {
+    fn create(res_x: u32, res_y: u32) -> io::Result<DiskImage> {
+        let path = std::env::temp_dir().join(format!(
+            "rust-raytracer-{}-{}.image",
+            process::id(),
+            DISK_IMAGE_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let image = DiskImage {
+            file: file,
+            path: path,
+            res_x: res_x,
+            res_y: res_y,
+        };
+        // Unwritten parts of the file read as zeros, which are black
+        image
+            .file
+            .set_len(res_x as u64 * res_y as u64 * DISK_PIXEL_SIZE)?;
+        return Ok(image);
+    }
+
+    // The column and row of an index into the framebuffer
+    fn position(&self, index: usize) -> (u32, u32) {
+        return (
+            (index / self.res_y as usize) as u32,
+            (index % self.res_y as usize) as u32,
+        );
+    }
+
+    fn offset(&self, x: u32, y: u32) -> u64 {
+        return (y as u64 * self.res_x as u64 + x as u64) * DISK_PIXEL_SIZE;
+    }
+
+    fn read(&self, x: u32, y: u32, colors: &mut [RGB]) -> io::Result<()> {
+        let mut bytes = vec![0_u8; colors.len() * DISK_PIXEL_SIZE as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset(x, y)))?;
+        file.read_exact(&mut bytes)?;
+        for (c, b) in colors
+            .iter_mut()
+            .zip(bytes.chunks(DISK_PIXEL_SIZE as usize))
+        {
+            let channel = |i: usize| f32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+            *c = RGB::new(channel(0), channel(4), channel(8));
+        }
+        return Ok(());
+    }
+
+    fn write(&self, x: u32, y: u32, colors: &[RGB]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(colors.len() * DISK_PIXEL_SIZE as usize);
+        for c in colors {
+            bytes.extend_from_slice(&c.r().to_le_bytes());
+            bytes.extend_from_slice(&c.g().to_le_bytes());
+            bytes.extend_from_slice(&c.b().to_le_bytes());
+        }
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset(x, y)))?;
+        return file.write_all(&bytes);
+    }
+}
+
+impl Drop for DiskImage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Sums samples, when compensated with Kahan's summation, which carries the
+// low bits each addition rounds away into the next, so thousands of small
+// samples added to a large running total are not lost
+pub struct CompensatedSum {
+    sum: RGB,
+    compensation: RGB,
+    compensated: bool,
+}
+
+impl CompensatedSum {
+    pub fn new(compensated: bool) -> CompensatedSum {
+        CompensatedSum {
+            sum: RGB::black(),
+            compensation: RGB::black(),
+            compensated: compensated,
+        }
+    }
+
+    pub fn add(&mut self, value: RGB) {
+        if !self.compensated {
+            self.sum = self.sum + value;
+            return;
+        }
+        let y = value - self.compensation;
+        let t = self.sum + y;
+        self.compensation = (t - self.sum) - y;
+        self.sum = t;
+    }
+
+    pub fn sum(&self) -> RGB {
+        return self.sum;
+    }
+}
+
+// The nearest half float, rounding ties to even. Values too large for a
+// half become the largest one rather than infinity.
+fn to_half(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000_u32) as u16;
+    let exponent = ((bits >> 23) & 0xff_u32) as i32;
+    let mantissa = bits & 0x7fffff_u32;
+    if exponent == 0xff {
+        // Infinity stays infinity, and NaN stays NaN
+        return sign | 0x7c00_u16 | if mantissa != 0 { 0x200_u16 } else { 0_u16 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7bff_u16;
+    }
+    if half_exponent <= 0 {
+        // Subnormal halves, in steps of 2^-24, and zero below half a step
+        if half_exponent < -10 {
+            return sign;
+        }
+        let full_mantissa = mantissa | 0x800000_u32;
+        let shift = (14 - half_exponent) as u32;
+        return sign | round_shifted(full_mantissa, shift) as u16;
+    }
+    let rounded = ((half_exponent as u32) << 10) + round_shifted(mantissa, 13);
+    // Rounding up can carry into the exponent, which is still right, unless
+    // it carries into infinity
+    return sign | rounded.min(0x7bff_u32) as u16;
+}
+
+// Shifts right, rounding to the nearest and ties to even
+fn round_shifted(value: u32, shift: u32) -> u32 {
+    let shifted = value >> shift;
+    let remainder = value & ((1_u32 << shift) - 1);
+    let halfway = 1_u32 << (shift - 1);
+    if remainder > halfway || (remainder == halfway && shifted & 1 == 1) {
+        return shifted + 1;
+    }
+    return shifted;
+}
+
+fn from_half(h: u16) -> f32 {
+    let sign = if h & 0x8000_u16 != 0 {
+        -1.0_f32
+    } else {
+        1.0_f32
+    };
+    let exponent = ((h >> 10) & 0x1f_u16) as i32;
+    let mantissa = (h & 0x3ff_u16) as f32;
+    return match exponent {
+        0 => sign * mantissa * 2.0_f32.powi(-24),
+        0x1f if mantissa == 0.0_f32 => sign * std::f32::INFINITY,
+        0x1f => std::f32::NAN,
+        _ => sign * (1.0_f32 + mantissa / 1024.0_f32) * 2.0_f32.powi(exponent - 15),
+    };
+}
@@ -0,0 +1,871 @@
+use crate::aggregate;
+use crate::aggregate::{FirstHit, FirstScattering};
+use crate::color::RGB;
+use crate::framebuffer::Framebuffer;
+use crate::passes::TilePasses;
+use crate::pdf;
+use crate::point::Point3;
+use crate::ray::{Ray, RayKind};
+use crate::render;
+use crate::rng;
+use crate::scene::Scene;
+use crate::shape::{Hit, SyncShape};
+use crate::stats::Statistics;
+use crate::tile::Tile;
+use crate::utils;
+use crate::vector::Vector3;
+
+use serde::Deserialize;
+
+// Gradient domain path tracing, after "Gradient-Domain Path Tracing"
+// (Kettunen et al. 2015). Each camera sample is shifted onto the pixels to
+// its left, right, above and below: the neighbor's camera ray is cast with
+// the same random numbers, and where both rays hit surfaces that scatter
+// by a PDF, the neighbor's path is reconnected to the second vertex of
+// the pixel's own, when that vertex is diffuse or emits. Past it, the two
+// paths are the same, so in diffuse interiors the difference between them
+// is only in their first bounce, and far less noisy than either color.
+// What the first hits emit or get from the scene's lights is shifted by
+// replaying its random numbers. Once rendering is done, the image is solved
+// for as the one that best fits both the gradients and the colors, a
+// screened Poisson problem.
+//
+// Each pair of neighboring pixels is sampled from both sides, and the two
+// are weighed against each other by the balance heuristic, on how likely
+// each pixel was to trace the path the other was shifted onto. A path that
+// can not be reconnected, at a mirror or a glossy second vertex, gives its
+// whole color to the gradient from its own side, so the shifts never bias
+// the gradients. Where a pair's camera rays land on different surfaces,
+// across the edges of objects and of emitters the camera sees, the
+// difference between them is as noisy as either color, and the pair's
+// gradient is left out of the solve. Scenes with fog, alpha masks, hidden
+// materials or emitter links, and the debug modes, shift whole samples by
+// replaying them.
+//
+// A sample takes about three times as long as a normal one. In the same
+// time it leaves less than half the noise from pixel to pixel, and in
+// diffuse interiors lit mostly by bounced light, less over regions up to
+// about eight pixels across. Over larger regions, the noise left is that of
+// the light arriving at the second vertices, which the shifts share rather
+// than trace again, and a normal render of the same length does as well or
+// better.
+#[derive(Deserialize)]
+pub struct GradientDomain {
+    // How strongly the solved image is held to the colors, against the
+    // gradients. Lower values trust the gradients more, smoothing out more
+    // noise, but let errors in them spread further.
+    #[serde(default = "default_alpha")]
+    pub alpha: f32,
+    // Conjugate gradient steps taken to solve for the image
+    #[serde(default = "default_iterations")]
+    pub iterations: u32,
+}
+
+fn default_alpha() -> f32 {
+    0.2_f32
+}
+
+fn default_iterations() -> u32 {
+    50
+}
+
+// Neighbors each sample is shifted onto, as offsets from its pixel
+const NEIGHBORS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+// How closely a shifted first hit has to face the same way as the base's,
+// and lie in its plane, relative to the distance between them, to be taken
+// as on the same surface
+const SAME_SURFACE_COS: f32 = 0.99_f32;
+const SAME_SURFACE_OFFSET: f32 = 0.05_f32;
+
+// Traces one sample of the pixel, shifted onto each of its neighbors, into
+// the tile's sums, and returns its color. Samples the camera has no ray for
+// are black, so gradients still reach to the edge of what it sees.
+pub fn trace_sample(
+    scene: &Scene,
+    x: u32,
+    y: u32,
+    workspace: &mut aggregate::Workspace,
+    passes: Option<&mut TilePasses>,
+    stats: &mut Statistics,
+    sums: &mut TileSums,
+) -> RGB {
+    let seeds = Seeds {
+        camera: rng::new_seed(),
+        scatter: rng::new_seed(),
+        direct: rng::new_seed(),
+        path: rng::new_seed(),
+    };
+    let reconnects = reconnects(scene);
+    let base = if reconnects {
+        trace_base(scene, x, y, &seeds, workspace, passes, stats)
+    } else {
+        rng::replay(seeds.camera);
+        let direct = render::trace_sample(scene, x, y, workspace, None, passes, stats)
+            .unwrap_or(RGB::black());
+        Base {
+            emitted: RGB::black(),
+            direct: direct,
+            indirect: RGB::black(),
+            surface: seen_surface(scene, x, y, seeds.camera, workspace),
+            vertex: None,
+        }
+    };
+    let color = base.emitted + base.direct + base.indirect;
+    sums.add_emitted(x, y, base.emitted);
+
+    let (res_x, res_y) = (sums.res_x as i32, sums.res_y as i32);
+    let mut paired = false;
+    for (step_x, step_y) in &NEIGHBORS {
+        let (nx, ny) = (x as i32 + step_x, y as i32 + step_y);
+        if nx < 0 || ny < 0 || nx >= res_x || ny >= res_y {
+            continue;
+        }
+        let (nx, ny) = (nx as u32, ny as u32);
+        if reconnects {
+            let shifted = shift(scene, &base, nx, ny, &seeds, workspace, stats);
+            sums.add(x, y, nx, ny, shifted.own, shifted.neighbor);
+            sums.add_emitted(nx, ny, shifted.emitted);
+            if shifted.crossed {
+                sums.split(x, y, nx, ny);
+            }
+        } else {
+            rng::replay(seeds.camera);
+            let c = render::trace_sample(scene, nx, ny, workspace, None, None, stats)
+                .unwrap_or(RGB::black());
+            sums.add(x, y, nx, ny, base.direct * 0.5_f32, c * 0.5_f32);
+            let seen = seen_surface(scene, nx, ny, seeds.camera, workspace);
+            if !same_surface(&base.surface, &seen) {
+                sums.split(x, y, nx, ny);
+            }
+        }
+        paired = true;
+    }
+    // A pixel with no neighbors is only its own samples
+    if !paired {
+        sums.add_alone(x, y, base.direct + base.indirect);
+    }
+    return color;
+}
+
+// Whether samples can be reconnected in the scene, rather than replayed
+fn reconnects(scene: &Scene) -> bool {
+    let logistics = &scene.logistics;
+    return scene.fog.is_none()
+        && scene.alpha_masks.is_empty()
+        && scene.material_visibility.is_empty()
+        && scene.emitter_links.is_empty()
+        && logistics.wireframe.is_none()
+        && !logistics.check_normals.unwrap_or(false)
+        && !logistics.lobes.unwrap_or(false);
+}
+
+// The random numbers of a sample, each part drawing from its own seed so
+// that its shifts draw the same numbers for the same things
+struct Seeds {
+    camera: u64,
+    scatter: u64,
+    direct: u64,
+    path: u64,
+}
+
+// A sample's color, split into what its first hit emits, what it gets from
+// the scene's lights, and the light it scatters from further along its
+// path, with what it takes to reconnect to that path
+struct Base<'a> {
+    emitted: RGB,
+    direct: RGB,
+    indirect: RGB,
+    surface: Surface,
+    vertex: Option<Reconnection<'a>>,
+}
+
+// What a camera ray's first hit is, to tell whether a shift stays on the
+// same surface
+enum Surface {
+    // Nothing that adds to the solved image: a miss, an emitter, whose
+    // light is added apart, or a surface that absorbs the ray
+    Dark,
+    // A surface, with the point hit and its normal there
+    Facing(Point3, Vector3),
+    Other,
+}
+
+fn surface(first: &FirstHit) -> Surface {
+    return match &first.scattering {
+        FirstScattering::Rough {
+            hit_point, normal, ..
+        } => Surface::Facing(*hit_point, *normal),
+        FirstScattering::Emits(_) => Surface::Dark,
+        FirstScattering::None if first.direct.luminance() <= 0.0_f32 => Surface::Dark,
+        _ => Surface::Other,
+    };
+}
+
+// What the camera ray through the pixel hits first, for samples that are
+// replayed whole, told apart only by where it is and which way it faces.
+// Emitters are never taken as the same surface, as their light is solved
+// for with the rest.
+fn seen_surface(
+    scene: &Scene,
+    x: u32,
+    y: u32,
+    camera_seed: u64,
+    workspace: &mut aggregate::Workspace,
+) -> Surface {
+    rng::replay(camera_seed);
+    let (r, t_min, far) = match render::camera_ray(scene, x, y) {
+        Some(ray_range) => ray_range,
+        None => return Surface::Dark,
+    };
+    let (s, h) = match scene.shape_aggregate.hit(&r, t_min, far, workspace) {
+        Some(hit_shape) => hit_shape,
+        None => return Surface::Dark,
+    };
+    let mut hit_props = s.get_hit_properties(&r, &h);
+    hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+    if s.get_material().emit(&r, &hit_props).is_some() {
+        return Surface::Other;
+    }
+    return Surface::Facing(hit_props.hit_point, hit_props.normal);
+}
+
+// The first two vertices of a path that neighbors can reconnect to, the
+// second with its normal turned toward the first
+struct Reconnection<'a> {
+    first: Point3,
+    // The density the direction to the second vertex was sampled with
+    pdf_val: f32,
+    second: Point3,
+    normal: Vector3,
+    time: f32,
+    to_second: SecondVertex<'a>,
+}
+
+enum SecondVertex<'a> {
+    // The light leaving a diffuse surface, the same toward any point in
+    // front of it
+    Diffuse(RGB),
+    // An emitter and the hit on it, whose light is found again for each
+    // point it is seen from
+    Emitter(&'a SyncShape, Hit<'a>),
+}
+
+fn trace_base<'a>(
+    scene: &'a Scene,
+    x: u32,
+    y: u32,
+    seeds: &Seeds,
+    workspace: &mut aggregate::Workspace,
+    passes: Option<&mut TilePasses>,
+    stats: &mut Statistics,
+) -> Base<'a> {
+    let mut base = Base {
+        emitted: RGB::black(),
+        direct: RGB::black(),
+        indirect: RGB::black(),
+        surface: Surface::Dark,
+        vertex: None,
+    };
+    rng::replay(seeds.camera);
+    let (r, t_min, far) = match render::camera_ray(scene, x, y) {
+        Some(ray_range) => ray_range,
+        None => return base,
+    };
+    let first = aggregate::first_hit(
+        &r,
+        t_min,
+        far,
+        scene,
+        workspace,
+        (seeds.scatter, seeds.direct),
+        stats,
+    );
+    base.direct = first.direct;
+    base.surface = surface(&first);
+    rng::replay(seeds.path);
+    match first.scattering {
+        FirstScattering::None => {}
+        FirstScattering::Emits(e) => base.emitted = e,
+        FirstScattering::Specular(attenuation, scattered) => {
+            base.indirect = attenuation
+                * aggregate::trace(
+                    &scattered,
+                    scene,
+                    workspace,
+                    &render::black_background,
+                    1,
+                    RayKind::Glossy,
+                    None,
+                    stats,
+                );
+        }
+        FirstScattering::Rough {
+            hit_point,
+            shape_key,
+            attenuation,
+            pdf,
+            ..
+        } => {
+            // Scattered as in the aggregate module's shading
+            let important_samples = &(*scene.important_samples);
+            let scattered = if important_samples.is_valid() {
+                Ray::new(
+                    hit_point,
+                    pdf::pair_generate(important_samples, &pdf, &hit_point),
+                    r.time,
+                )
+            } else {
+                Ray::new(hit_point, pdf.generate(&hit_point), r.time)
+            }
+            .continuing(&r);
+            let pdf_val = scattered_value(scene, &pdf, &scattered);
+            if pdf_val > 0.0_f32 {
+                let hit_shape = scene.shape_aggregate.hit(
+                    &scattered,
+                    scene.ray_epsilon,
+                    utils::T_MAX,
+                    workspace,
+                );
+                let arriving = aggregate::trace_hit(
+                    &scattered,
+                    hit_shape,
+                    scene,
+                    workspace,
+                    &render::black_background,
+                    1,
+                    Some(shape_key),
+                    stats,
+                );
+                base.indirect = attenuation * pdf.scattering(&scattered) * arriving / pdf_val;
+                base.vertex = match hit_shape {
+                    Some((s, h)) => reconnection(&scattered, s, h, arriving, pdf_val),
+                    None => None,
+                };
+            }
+        }
+    }
+    // Along the same ray, so the passes line up with the image
+    match passes {
+        Some(p) => p.add(&r, t_min, far, scene, workspace),
+        None => {}
+    }
+    return base;
+}
+
+// The density of a scattered direction, as in the aggregate module's
+// shading
+fn scattered_value(scene: &Scene, pdf: &pdf::PDF, r: &Ray) -> f32 {
+    let important_samples = &(*scene.important_samples);
+    if important_samples.is_valid() {
+        return pdf::pair_value(important_samples, pdf, r);
+    }
+    return pdf.value(r);
+}
+
+// What it takes to reconnect to the second vertex of a path, where the
+// scattered ray hit the shape, or None where it can not be
+fn reconnection<'a>(
+    scattered: &Ray,
+    s: &'a SyncShape,
+    h: Hit<'a>,
+    arriving: RGB,
+    pdf_val: f32,
+) -> Option<Reconnection<'a>> {
+    let mut hit_props = s.get_hit_properties(scattered, &h);
+    hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+    let to_second = match s.get_material().emit(scattered, &hit_props) {
+        Some(_) => SecondVertex::Emitter(s, h),
+        None if s.get_material().is_diffuse() => SecondVertex::Diffuse(arriving),
+        None => return None,
+    };
+    let normal = if hit_props.normal.dot(scattered.dir) > 0.0_f32 {
+        -hit_props.normal
+    } else {
+        hit_props.normal
+    };
+    return Some(Reconnection {
+        first: scattered.origin,
+        pdf_val: pdf_val,
+        second: hit_props.hit_point,
+        normal: normal,
+        time: scattered.time,
+        to_second: to_second,
+    });
+}
+
+// A base sample shifted onto a neighbor: its share of the colors of its
+// pixel and of the neighbor, from the pair of them, what the shifted camera
+// ray sees emitted, one more sample of the neighbor's, and whether it
+// landed on another surface than the base
+struct Shifted {
+    own: RGB,
+    neighbor: RGB,
+    emitted: RGB,
+    crossed: bool,
+}
+
+// Shifts the base sample onto the neighbor. Light straight from the first
+// hit is split evenly between the pair's two sides, as replay shifts each
+// way as likely as the other. Reconnected light is weighed by the balance
+// heuristic, and light that could not be reconnected is the base's alone.
+fn shift(
+    scene: &Scene,
+    base: &Base,
+    x: u32,
+    y: u32,
+    seeds: &Seeds,
+    workspace: &mut aggregate::Workspace,
+    stats: &mut Statistics,
+) -> Shifted {
+    rng::replay(seeds.camera);
+    let first = match render::camera_ray(scene, x, y) {
+        Some((r, t_min, far)) => Some((
+            aggregate::first_hit(
+                &r,
+                t_min,
+                far,
+                scene,
+                workspace,
+                (seeds.scatter, seeds.direct),
+                stats,
+            ),
+            r,
+        )),
+        None => None,
+    };
+    let own = base.direct * 0.5_f32;
+    let (first, r) = match first {
+        Some(first_ray) => first_ray,
+        None => {
+            return Shifted {
+                own: own + base.indirect,
+                neighbor: RGB::black(),
+                emitted: RGB::black(),
+                crossed: !same_surface(&base.surface, &Surface::Dark),
+            }
+        }
+    };
+    let mut shifted = Shifted {
+        own: own + base.indirect,
+        neighbor: first.direct * 0.5_f32,
+        emitted: RGB::black(),
+        crossed: !same_surface(&base.surface, &surface(&first)),
+    };
+    match &first.scattering {
+        FirstScattering::Emits(e) => shifted.emitted = *e,
+        _ => {}
+    }
+    match reconnect(scene, base, &first, &r, workspace) {
+        Some((indirect, weight)) => {
+            shifted.own = own + base.indirect * weight;
+            shifted.neighbor = shifted.neighbor + indirect * weight;
+        }
+        None => {}
+    }
+    return shifted;
+}
+
+// Whether a shifted first hit is on the same surface as the base's, facing
+// the same way and in its plane, or both add nothing to the solved image
+fn same_surface(base: &Surface, shifted: &Surface) -> bool {
+    return match (base, shifted) {
+        (Surface::Dark, Surface::Dark) => true,
+        (Surface::Facing(base_point, base_normal), Surface::Facing(point, normal)) => {
+            let offset = *point - *base_point;
+            base_normal.dot(*normal) >= SAME_SURFACE_COS
+                && base_normal.dot(offset).abs() <= SAME_SURFACE_OFFSET * offset.length()
+        }
+        _ => false,
+    };
+}
+
+// The light the shifted path's first hit scatters from the base's second
+// vertex, in proportion to the base's density, and the balance heuristic
+// weight of the base's side of the pair, or None where the paths can not
+// be reconnected
+fn reconnect(
+    scene: &Scene,
+    base: &Base,
+    first: &FirstHit,
+    r: &Ray,
+    workspace: &mut aggregate::Workspace,
+) -> Option<(RGB, f32)> {
+    let vertex = match &base.vertex {
+        Some(v) => v,
+        None => return None,
+    };
+    let (hit_point, attenuation, pdf) = match &first.scattering {
+        FirstScattering::Rough {
+            hit_point,
+            attenuation,
+            pdf,
+            ..
+        } => (hit_point, attenuation, pdf),
+        _ => return None,
+    };
+    let to_base = vertex.first - vertex.second;
+    let to_shifted = *hit_point - vertex.second;
+    let (base_distance, distance) = (to_base.length(), to_shifted.length());
+    let cos_base = vertex.normal.dot(to_base) / base_distance;
+    let cos_shifted = vertex.normal.dot(to_shifted) / distance;
+    // Both first vertices have to be in front of the second
+    if !(distance > scene.ray_epsilon) || !(cos_shifted > 0.0_f32) {
+        return None;
+    }
+    // How much more of the solid angle around the shifted first vertex
+    // the second takes up than around the base's
+    let jacobian = (cos_shifted * base_distance * base_distance) / (cos_base * distance * distance);
+    let scattered = Ray::new(*hit_point, -to_shifted / distance, vertex.time).continuing(r);
+    // A neighbor could never trace a path that is blocked, so the base has
+    // no other side to share it with
+    let occluded = scene
+        .shape_aggregate
+        .hit(
+            &scattered,
+            scene.ray_epsilon,
+            distance - scene.ray_epsilon,
+            workspace,
+        )
+        .is_some();
+    if occluded {
+        return None;
+    }
+    let shifted_pdf_val = scattered_value(scene, pdf, &scattered);
+    let weight = vertex.pdf_val / (vertex.pdf_val + shifted_pdf_val * jacobian);
+
+    let arriving = match &vertex.to_second {
+        SecondVertex::Diffuse(arriving) => *arriving,
+        SecondVertex::Emitter(s, h) => {
+            // The same point of the same shape, from the shifted vertex
+            let shifted_hit = h.at(distance);
+            let mut hit_props = s.get_hit_properties(&scattered, &shifted_hit);
+            hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+            hit_props.set_footprint(&scattered, distance);
+            let from_behind =
+                !s.get_material().is_two_sided() && !s.is_front_face(&scattered, &shifted_hit);
+            match s.get_material().emit(&scattered, &hit_props) {
+                Some(e) if !from_behind => e,
+                _ => RGB::black(),
+            }
+        }
+    };
+    let indirect = *attenuation * pdf.scattering(&scattered) * arriving * jacobian / vertex.pdf_val;
+    return Some((indirect, weight));
+}
+
+// Every sample traced for the image, indexed by y * resolution_x + x. Each
+// pair of neighboring pixels gives both an estimate of each of their colors
+// and the difference between them: the colors sum each pixel's estimates
+// from every pair it is in, and the gradients the differences across each
+// pixel's pair with the pixel to its right and the one below. The last
+// column has no horizontal gradients and the last row no vertical ones.
+// Emitters the camera sees are summed apart and added back after solving,
+// so that every camera ray that lands in a pixel, shifted or not, samples
+// how much of it they cover. Pairs split by an edge are marked, to leave
+// their gradients out.
+pub struct Gradients {
+    res_x: u32,
+    res_y: u32,
+    emitted: Vec<RGB>,
+    colors: Vec<RGB>,
+    dx: Vec<RGB>,
+    dy: Vec<RGB>,
+    split_x: Vec<bool>,
+    split_y: Vec<bool>,
+}
+
+impl Gradients {
+    pub fn new(res_x: u32, res_y: u32) -> Gradients {
+        let pixel_count = res_x as usize * res_y as usize;
+        return Gradients {
+            res_x: res_x,
+            res_y: res_y,
+            emitted: vec![RGB::black(); pixel_count],
+            colors: vec![RGB::black(); pixel_count],
+            dx: vec![RGB::black(); pixel_count],
+            dy: vec![RGB::black(); pixel_count],
+            split_x: vec![false; pixel_count],
+            split_y: vec![false; pixel_count],
+        };
+    }
+
+    // Adds in what a tile traced, which reaches a pixel past each of its
+    // edges
+    pub fn add(&mut self, sums: &TileSums) {
+        for y in sums.y_min..sums.y_max {
+            for x in sums.x_min..sums.x_max {
+                let from = sums.index(x, y);
+                let to = (y * self.res_x + x) as usize;
+                self.emitted[to] = self.emitted[to] + sums.emitted[from];
+                self.colors[to] = self.colors[to] + sums.colors[from];
+                self.dx[to] = self.dx[to] + sums.dx[from];
+                self.dy[to] = self.dy[to] + sums.dy[from];
+                self.split_x[to] |= sums.split_x[from];
+                self.split_y[to] |= sums.split_y[from];
+            }
+        }
+    }
+
+    fn neighbor_count(&self, x: usize, y: usize) -> u32 {
+        let (res_x, res_y) = (self.res_x as usize, self.res_y as usize);
+        return (x > 0) as u32 + (x + 1 < res_x) as u32 + (y > 0) as u32 + (y + 1 < res_y) as u32;
+    }
+
+    // How many pairs the pixel is in, each of which gives one estimate of
+    // its color per sample, or 1 for a pixel with no neighbors
+    fn pair_count(&self, x: usize, y: usize) -> u32 {
+        return self.neighbor_count(x, y).max(1);
+    }
+
+    // How many camera rays land in the pixel per sample, its own and one
+    // shifted from each neighbor
+    fn camera_ray_count(&self, x: usize, y: usize) -> u32 {
+        return 1 + self.neighbor_count(x, y);
+    }
+}
+
+// What one tile traced, over the tile and the pixels just past its edges
+// that its samples were shifted onto
+pub struct TileSums {
+    res_x: u32,
+    res_y: u32,
+    x_min: u32,
+    y_min: u32,
+    x_max: u32,
+    y_max: u32,
+    emitted: Vec<RGB>,
+    colors: Vec<RGB>,
+    dx: Vec<RGB>,
+    dy: Vec<RGB>,
+    split_x: Vec<bool>,
+    split_y: Vec<bool>,
+}
+
+impl TileSums {
+    pub fn new(t: &Tile, res_x: u32, res_y: u32) -> TileSums {
+        let x_min = t.x_min.saturating_sub(1);
+        let y_min = t.y_min.saturating_sub(1);
+        let x_max = (t.x_max + 1).min(res_x);
+        let y_max = (t.y_max + 1).min(res_y);
+        let pixel_count = ((x_max - x_min) * (y_max - y_min)) as usize;
+        return TileSums {
+            res_x: res_x,
+            res_y: res_y,
+            x_min: x_min,
+            y_min: y_min,
+            x_max: x_max,
+            y_max: y_max,
+            emitted: vec![RGB::black(); pixel_count],
+            colors: vec![RGB::black(); pixel_count],
+            dx: vec![RGB::black(); pixel_count],
+            dy: vec![RGB::black(); pixel_count],
+            split_x: vec![false; pixel_count],
+            split_y: vec![false; pixel_count],
+        };
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        return ((y - self.y_min) * (self.x_max - self.x_min) + (x - self.x_min)) as usize;
+    }
+
+    // Adds the estimates of the colors of a pixel and its neighbor from a
+    // sample of the pixel, and their difference to the gradient between
+    // them
+    fn add(&mut self, x: u32, y: u32, nx: u32, ny: u32, own: RGB, shifted: RGB) {
+        let i = self.index(x, y);
+        let n = self.index(nx, ny);
+        self.colors[i] = self.colors[i] + own;
+        self.colors[n] = self.colors[n] + shifted;
+        // Gradients are stored at the left or upper pixel of the pair,
+        // from it to the other
+        if nx > x {
+            self.dx[i] = self.dx[i] + (shifted - own);
+        } else if nx < x {
+            self.dx[n] = self.dx[n] + (own - shifted);
+        } else if ny > y {
+            self.dy[i] = self.dy[i] + (shifted - own);
+        } else {
+            self.dy[n] = self.dy[n] + (own - shifted);
+        }
+    }
+
+    // Marks the pixel's pair with the neighbor as split by an edge
+    fn split(&mut self, x: u32, y: u32, nx: u32, ny: u32) {
+        let i = self.index(x.min(nx), y.min(ny));
+        if nx != x {
+            self.split_x[i] = true;
+        } else {
+            self.split_y[i] = true;
+        }
+    }
+
+    fn add_alone(&mut self, x: u32, y: u32, color: RGB) {
+        let i = self.index(x, y);
+        self.colors[i] = self.colors[i] + color;
+    }
+
+    fn add_emitted(&mut self, x: u32, y: u32, emitted: RGB) {
+        let i = self.index(x, y);
+        self.emitted[i] = self.emitted[i] + emitted;
+    }
+}
+
+// Writes the image that best fits the colors and gradients into the frame,
+// minimizing
+//     alpha^2 |I - colors|^2 + |dx(I) - gradients_x|^2 + |dy(I) - gradients_y|^2
+// over the averaged image I, by conjugate gradients started from the colors,
+// with the gradients of pairs split by an edge left out. Each channel is
+// solved on its own.
+pub fn reconstruct(
+    frame: &mut Framebuffer,
+    gradients: &Gradients,
+    settings: &GradientDomain,
+    samples: u32,
+) -> Result<(), String> {
+    let (res_x, res_y) = (gradients.res_x as usize, gradients.res_y as usize);
+    let pixel_count = res_x * res_y;
+    // Averages of each pixel's estimates from its pairs, and of the
+    // gradients, over the samples
+    let mut colors = gradients.colors.clone();
+    for y in 0..res_y {
+        for x in 0..res_x {
+            let i = y * res_x + x;
+            colors[i] = colors[i] * (1.0_f32 / gradients.pair_count(x, y) as f32);
+        }
+    }
+    let scale = 1.0_f64 / samples.max(1) as f64;
+
+    let mut solved = vec![[0.0_f64; 3]; pixel_count];
+    for channel in 0..3 {
+        let value = |c: &RGB| -> f64 {
+            (match channel {
+                0 => c.r(),
+                1 => c.g(),
+                _ => c.b(),
+            }) as f64
+                * scale
+        };
+        let problem = Problem {
+            res_x: res_x,
+            res_y: res_y,
+            alpha_squared: settings.alpha as f64 * settings.alpha as f64,
+            primal: colors.iter().map(value).collect(),
+            dx: gradients.dx.iter().map(value).collect(),
+            dy: gradients.dy.iter().map(value).collect(),
+            split_x: &gradients.split_x,
+            split_y: &gradients.split_y,
+        };
+        let image = problem.solve(problem.primal.clone(), settings.iterations);
+        for (s, v) in solved.iter_mut().zip(image) {
+            s[channel] = v;
+        }
+    }
+
+    // Back to sums, as the framebuffer holds them, with the emitters the
+    // camera sees. The solve can dip a little below 0 beside sharp edges.
+    let samples = samples.max(1) as f64;
+    for y in 0..res_y {
+        let row: Vec<RGB> = solved[y * res_x..(y + 1) * res_x]
+            .iter()
+            .zip(&gradients.emitted[y * res_x..(y + 1) * res_x])
+            .enumerate()
+            .map(|(x, (c, e))| {
+                RGB::new(
+                    (c[0].max(0.0) * samples) as f32,
+                    (c[1].max(0.0) * samples) as f32,
+                    (c[2].max(0.0) * samples) as f32,
+                ) + *e * (1.0_f32 / gradients.camera_ray_count(x, y) as f32)
+            })
+            .collect();
+        match frame.set_row(0, y as u32, &row, res_y as u32) {
+            Ok(()) => {}
+            Err(e) => return Err(format!("Failed to store the reconstructed image: {}", e)),
+        }
+    }
+    return Ok(());
+}
+
+// One channel of the image to solve for, indexed by y * res_x + x
+struct Problem<'a> {
+    res_x: usize,
+    res_y: usize,
+    alpha_squared: f64,
+    primal: Vec<f64>,
+    dx: Vec<f64>,
+    dy: Vec<f64>,
+    // Pairs whose gradients are left out
+    split_x: &'a [bool],
+    split_y: &'a [bool],
+}
+
+impl<'a> Problem<'a> {
+    // Minimizes the least squares problem by conjugate gradients on its
+    // normal equations, A x = b, where A is alpha^2 plus the image Laplacian
+    // built from the same differences as the gradients
+    fn solve(&self, mut x: Vec<f64>, iterations: u32) -> Vec<f64> {
+        let (res_x, res_y) = (self.res_x, self.res_y);
+        let apply = |v: &[f64], out: &mut [f64]| {
+            for i in 0..v.len() {
+                out[i] = self.alpha_squared * v[i];
+            }
+            for y in 0..res_y {
+                for x in 0..res_x {
+                    let i = y * res_x + x;
+                    if x + 1 < res_x && !self.split_x[i] {
+                        let difference = v[i + 1] - v[i];
+                        out[i] -= difference;
+                        out[i + 1] += difference;
+                    }
+                    if y + 1 < res_y && !self.split_y[i] {
+                        let difference = v[i + res_x] - v[i];
+                        out[i] -= difference;
+                        out[i + res_x] += difference;
+                    }
+                }
+            }
+        };
+        let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f64>();
+
+        // alpha^2 colors + dx^T gradients_x + dy^T gradients_y
+        let mut b = vec![0.0_f64; x.len()];
+        for y in 0..res_y {
+            for x in 0..res_x {
+                let i = y * res_x + x;
+                b[i] += self.alpha_squared * self.primal[i];
+                if x + 1 < res_x && !self.split_x[i] {
+                    b[i] -= self.dx[i];
+                    b[i + 1] += self.dx[i];
+                }
+                if y + 1 < res_y && !self.split_y[i] {
+                    b[i] -= self.dy[i];
+                    b[i + res_x] += self.dy[i];
+                }
+            }
+        }
+
+        let mut product = vec![0.0_f64; b.len()];
+        apply(&x, &mut product);
+        let mut residual: Vec<f64> = b.iter().zip(&product).map(|(b, p)| b - p).collect();
+        let mut direction = residual.clone();
+        let mut residual_squared = dot(&residual, &residual);
+        for _ in 0..iterations {
+            if residual_squared <= 0.0 {
+                break;
+            }
+            apply(&direction, &mut product);
+            let step = residual_squared / dot(&direction, &product);
+            for i in 0..x.len() {
+                x[i] += step * direction[i];
+                residual[i] -= step * product[i];
+            }
+            let next_squared = dot(&residual, &residual);
+            let keep = next_squared / residual_squared;
+            for i in 0..direction.len() {
+                direction[i] = residual[i] + keep * direction[i];
+            }
+            residual_squared = next_squared;
+        }
+        return x;
+    }
+}
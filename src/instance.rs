@@ -0,0 +1,338 @@
+use crate::aggregate::{new_bvh, BVHSettings, SyncAggregate, Workspace, AABB};
+use crate::color::RGB;
+use crate::material::{Material, ScatterProperties, SyncMaterial};
+use crate::matrix::Matrix4;
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::rng;
+use crate::shape::{Hit, HitProperties, Shape, SyncShape, MAX_INSTANCE_DEPTH};
+use crate::texture::SyncTexture;
+use crate::vector::Vector3;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static NEXT_PROTOTYPE_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Traversal stacks for each prototype's BVH, kept between rays so that
+    // hitting an instance does not allocate
+    static WORKSPACES: RefCell<HashMap<usize, Workspace>> = RefCell::new(HashMap::new());
+    // The variation of the instance being shaded, for textures to read, see
+    // Varied
+    static VARIATIONS: RefCell<Vec<Arc<Variation>>> = RefCell::new(Vec::new());
+}
+
+// Prototype
+// Shapes shared by every Instance of them, along with a BVH over them that
+// is built once.
+pub struct Prototype {
+    id: usize,
+    shapes: Vec<Arc<SyncShape>>,
+    aggregate: Box<SyncAggregate>,
+    bounds: AABB,
+    // How many instances of the prototype have been made, see Variation
+    instance_count: AtomicUsize,
+    // How deep the instances among its shapes go
+    depth: usize,
+}
+
+impl Prototype {
+    pub fn new(shapes: Vec<Arc<SyncShape>>) -> Prototype {
+        let mut bounds = AABB::new_empty();
+        for shape in &shapes {
+            bounds = AABB::union(&bounds, &shape.get_bounding_box());
+        }
+        let depth = shapes.iter().map(|s| s.instance_depth()).max().unwrap_or(0);
+        Prototype {
+            id: NEXT_PROTOTYPE_ID.fetch_add(1, Ordering::Relaxed),
+            aggregate: new_bvh(shapes.clone(), &BVHSettings::new()),
+            shapes: shapes,
+            bounds: bounds,
+            instance_count: AtomicUsize::new(0),
+            depth: depth,
+        }
+    }
+
+    // The index of an instance being made among all of the prototype's
+    // instances, in the order they are made
+    pub fn next_instance_index(&self) -> usize {
+        return self.instance_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The material every shape of the prototype shares, if there is one
+    pub fn shared_material(&self) -> Option<&Arc<SyncMaterial>> {
+        let first = self.shapes.first()?.get_material();
+        if self
+            .shapes
+            .iter()
+            .all(|s| Arc::ptr_eq(s.get_material(), first))
+        {
+            return Some(first);
+        }
+        return None;
+    }
+
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(&SyncShape, Hit<'_>)> {
+        // The workspace is taken out while in use, so an instance reached
+        // while tracing another instance of the same prototype gets its own
+        let mut workspace = match WORKSPACES.with(|w| w.borrow_mut().remove(&self.id)) {
+            Some(w) => w,
+            None => self.aggregate.get_workspace(),
+        };
+        let hit = self.aggregate.hit(r, t_min, t_max, &mut workspace);
+        WORKSPACES.with(|w| w.borrow_mut().insert(self.id, workspace));
+        return hit;
+    }
+}
+
+// Instance
+// Places a prototype in the scene with its own transform and material,
+// without copying its shapes. Normals are carried through the inverse
+// transpose of the transform, so they stay correct under non-uniform scale.
+pub struct Instance {
+    prototype: Arc<Prototype>,
+    local_to_world: Matrix4,
+    world_to_local: Matrix4,
+    normal_to_world: Matrix4,
+    material: Arc<SyncMaterial>,
+}
+
+impl Instance {
+    pub fn new(
+        prototype: Arc<Prototype>,
+        local_to_world: &Matrix4,
+        material: Arc<SyncMaterial>,
+    ) -> Result<Instance, &'static str> {
+        // The hit on an instance keeps the shape of each prototype it went
+        // through, see Hit
+        if prototype.depth >= MAX_INSTANCE_DEPTH {
+            return Err("Instances can only be nested 4 deep.");
+        }
+        let world_to_local = local_to_world.inverse()?;
+        Ok(Instance {
+            prototype: prototype,
+            local_to_world: local_to_world.clone(),
+            normal_to_world: world_to_local.transpose(),
+            world_to_local: world_to_local,
+            material: material,
+        })
+    }
+
+    // The shape of the prototype that a hit made by Instance::hit is on, and
+    // the hit on it
+    fn part<'a>(hit: &Hit<'a>) -> (&'a SyncShape, Hit<'a>) {
+        return hit
+            .part()
+            .expect("Instance was given a hit that was not on it.");
+    }
+}
+
+impl Shape for Instance {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        return match self
+            .prototype
+            .hit(&(&self.world_to_local * r), t_min, t_max)
+        {
+            Some((shape, shape_hit)) => Some(Hit::through(shape, shape_hit)),
+            None => None,
+        };
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let local_ray = &self.world_to_local * r;
+        let (shape, shape_hit) = Instance::part(hit);
+        let local_props = shape.get_hit_properties(&local_ray, &shape_hit);
+        HitProperties {
+            hit_point: r.point_at(hit.t),
+            normal: (&self.normal_to_world * local_props.normal).normalized(),
+            uv: local_props.uv,
+            pu: &self.local_to_world * local_props.pu,
+            pv: &self.local_to_world * local_props.pv,
+            uv_footprint: 0.0_f32,
+        }
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        &self.material
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        let local = &self.prototype.bounds;
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(-f32::MAX, -f32::MAX, -f32::MAX);
+        for i in 0..8 {
+            let corner = &self.local_to_world
+                * Point3::new(
+                    if i & 1 == 0 {
+                        local.min.x()
+                    } else {
+                        local.max.x()
+                    },
+                    if i & 2 == 0 {
+                        local.min.y()
+                    } else {
+                        local.max.y()
+                    },
+                    if i & 4 == 0 {
+                        local.min.z()
+                    } else {
+                        local.max.z()
+                    },
+                );
+            min = Point3::min(min, corner);
+            max = Point3::max(max, corner);
+        }
+        return AABB::new(min, max);
+    }
+
+    // Every shape of the prototype is sampled evenly, like the shapes of a
+    // CSG. This visits all of them, so emissive prototypes are best kept
+    // small.
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        let local_ray = &self.world_to_local * r;
+        let mut total = 0.0_f32;
+        for shape in &self.prototype.shapes {
+            total += shape.pdf(&local_ray, t_min);
+        }
+        return total / self.prototype.shapes.len() as f32;
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        let shapes = &self.prototype.shapes;
+        let shape = &shapes[rng::below(shapes.len())];
+        let local_dir = shape.random_dir_towards(&(&self.world_to_local * (*from_origin)));
+        return &self.local_to_world * local_dir;
+    }
+
+    // Measured in the prototype's space, which is exact for rigid transforms
+    fn edge_distance(&self, r: &Ray, hit: &Hit) -> Option<f32> {
+        let (shape, shape_hit) = Instance::part(hit);
+        return shape.edge_distance(&(&self.world_to_local * r), &shape_hit);
+    }
+
+    fn is_bounded(&self) -> bool {
+        return self.prototype.shapes.iter().all(|s| s.is_bounded());
+    }
+
+    fn is_front_face(&self, r: &Ray, hit: &Hit) -> bool {
+        let (shape, shape_hit) = Instance::part(hit);
+        return shape.is_front_face(&(&self.world_to_local * r), &shape_hit);
+    }
+
+    fn instance_depth(&self) -> usize {
+        return self.prototype.depth + 1;
+    }
+}
+
+// Variation
+// Sets an instance apart from the other instances of its prototype, so that
+// a forest scattered from one tree does not look like clones. Each instance
+// draws random numbers from its seed, by default its index among the
+// instances of its prototype in the order the scene lists them: one turns
+// the hue of everything its material scatters and emits by up to hue_shift
+// degrees either way, and one is its "random" attribute. Textures read the
+// attributes of the instance being shaded, its "random", its "index", and any
+// given to it, with texture::InstanceAttribute.
+pub struct Variation {
+    hue_shift: f32,
+    attributes: Vec<(String, f32)>,
+}
+
+impl Variation {
+    pub fn new(
+        index: usize,
+        seed: Option<u64>,
+        hue_shift: f32,
+        attributes: &HashMap<String, f32>,
+    ) -> Variation {
+        let random = rng::seeded(2, seed.unwrap_or(index as u64));
+        // Attributes given by name come first, so they win over the builtins
+        let mut all: Vec<(String, f32)> = attributes
+            .iter()
+            .map(|(name, value)| (String::clone(name), *value))
+            .collect();
+        all.push((String::from("random"), random[1]));
+        all.push((String::from("index"), index as f32));
+        Variation {
+            hue_shift: hue_shift * (2.0_f32 * random[0] - 1.0_f32),
+            attributes: all,
+        }
+    }
+}
+
+// The named attribute of the instance being shaded, None outside of varied
+// instances and for attributes it was not given
+pub fn attribute(name: &str) -> Option<f32> {
+    return VARIATIONS.with(|v| {
+        let variations = v.borrow();
+        let variation = variations.last()?;
+        return variation
+            .attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| *value);
+    });
+}
+
+// Varied
+// The material of an instance with a Variation. It shades with the scene's
+// material, with the variation where textures can read it, and turns the hue
+// of what comes out.
+pub struct Varied {
+    material: Arc<SyncMaterial>,
+    variation: Arc<Variation>,
+}
+
+impl Varied {
+    pub fn new(material: Arc<SyncMaterial>, variation: Variation) -> Varied {
+        Varied {
+            material: material,
+            variation: Arc::new(variation),
+        }
+    }
+
+    fn with_variation<T>(&self, shade: impl FnOnce() -> T) -> T {
+        VARIATIONS.with(|v| v.borrow_mut().push(Arc::clone(&self.variation)));
+        let result = shade();
+        VARIATIONS.with(|v| v.borrow_mut().pop());
+        return result;
+    }
+}
+
+impl Material for Varied {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        let mut scattered = self.with_variation(|| self.material.scatter(in_ray, hit_props))?;
+        scattered.attenuation = scattered.attenuation.hue_rotated(self.variation.hue_shift);
+        return Some(scattered);
+    }
+
+    fn emit(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<RGB> {
+        let emitted = self.with_variation(|| self.material.emit(in_ray, hit_props))?;
+        return Some(emitted.hue_rotated(self.variation.hue_shift));
+    }
+
+    fn is_important(&self) -> bool {
+        self.material.is_important()
+    }
+
+    // Sampling by the texture's brightness, without the variation, only
+    // costs noise where the variation changes it
+    fn emission_texture(&self) -> Option<&Arc<SyncTexture>> {
+        self.material.emission_texture()
+    }
+
+    fn is_two_sided(&self) -> bool {
+        self.material.is_two_sided()
+    }
+
+    fn is_diffuse(&self) -> bool {
+        self.material.is_diffuse()
+    }
+
+    fn varies(&self) -> Option<&SyncMaterial> {
+        Some(&*self.material)
+    }
+}
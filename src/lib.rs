@@ -0,0 +1,63 @@
+// The renderer as a library, which the command line program in main.rs
+// drives, and which rendering tests can drive too, see src/testing.rs
+
+pub mod aggregate;
+pub mod base;
+pub mod bench;
+pub mod bounds;
+pub mod camera;
+pub mod camerapath;
+pub mod clip;
+pub mod color;
+pub mod colorspace;
+pub mod csg;
+pub mod denoise;
+pub mod exr;
+pub mod fog;
+pub mod framebuffer;
+pub mod gradient;
+pub mod instance;
+pub mod light;
+pub mod live;
+pub mod lobes;
+pub mod material;
+pub mod matrix;
+pub mod measured;
+pub mod meshcache;
+pub mod motion;
+pub mod mtl;
+pub mod normals;
+pub mod numa;
+pub mod objstream;
+pub mod outline;
+pub mod passes;
+pub mod pdf;
+pub mod png;
+pub mod point;
+pub mod presets;
+pub mod profile;
+pub mod progress;
+pub mod projection;
+pub mod ray;
+pub mod render;
+pub mod resampling;
+pub mod resources;
+pub mod rng;
+pub mod sampling;
+pub mod scene;
+pub mod schema;
+pub mod shape;
+pub mod spectrum;
+pub mod stats;
+pub mod sun;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod texture;
+pub mod tile;
+pub mod transform;
+pub mod utils;
+pub mod uvs;
+pub mod vector;
+pub mod version;
+pub mod volume;
+pub mod wireframe;
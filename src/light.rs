@@ -0,0 +1,210 @@
+use crate::color::RGB;
+use crate::point::Point3;
+use crate::utils;
+use crate::vector::Vector3;
+
+use std::collections::HashSet;
+use std::f32;
+
+// Lights that exist apart from the scene's geometry, and so can never be hit
+// by a ray. They only contribute by being sampled directly from a surface,
+// with a shadow ray to check that nothing is in the way.
+pub trait Light {
+    // Returns the light arriving at the point, or None if this light can not
+    // reach it. Lights with an area pick a point on it by the pair of numbers
+    // in [0, 1), so the same sample can be taken again from another point.
+    fn sample(&self, point: &Point3, u: (f32, f32)) -> Option<LightSample>;
+}
+pub type SyncLight = dyn Light + Send + Sync;
+
+// Light linking, which shapes a light lights, keyed like the stats module
+// keys them. A light lights every shape unless it lists the shapes it
+// includes, and never lights the shapes it excludes.
+pub struct LightLinks {
+    include: Option<HashSet<usize>>,
+    exclude: HashSet<usize>,
+}
+
+impl LightLinks {
+    pub fn new(include: Option<HashSet<usize>>, exclude: HashSet<usize>) -> LightLinks {
+        LightLinks {
+            include: include,
+            exclude: exclude,
+        }
+    }
+
+    pub fn lights(&self, shape: usize) -> bool {
+        if self.exclude.contains(&shape) {
+            return false;
+        }
+        return match &self.include {
+            Some(i) => i.contains(&shape),
+            None => true,
+        };
+    }
+}
+
+// Shadow linking and styling for a light. The excluded shapes cast no
+// shadows from it, and the shadows of the rest let some of its light
+// through, tinted, rather than none of it.
+pub struct Shadows {
+    exclude: HashSet<usize>,
+    // Share of the light let through a shadow, black for solid shadows
+    transmission: RGB,
+}
+
+impl Shadows {
+    pub fn new(exclude: HashSet<usize>, transmission: RGB) -> Shadows {
+        Shadows {
+            exclude: exclude,
+            transmission: transmission,
+        }
+    }
+
+    pub fn solid() -> Shadows {
+        Shadows::new(HashSet::new(), RGB::black())
+    }
+
+    pub fn casts(&self, shape: usize) -> bool {
+        !self.exclude.contains(&shape)
+    }
+
+    pub fn transmission(&self) -> RGB {
+        self.transmission
+    }
+}
+
+// One of the scene's lights, with what it lights and how it is shadowed
+pub struct SceneLight {
+    pub light: Box<SyncLight>,
+    pub links: Option<LightLinks>,
+    pub shadows: Shadows,
+}
+
+pub struct LightSample {
+    // Unit direction from the point toward the light
+    pub dir: Vector3,
+    // Distance to the light, which shadow rays must reach without a hit
+    pub distance: f32,
+    // Radiance arriving at the point, before any shadowing
+    pub radiance: RGB,
+}
+
+// Point
+// An infinitely small light emitting equally in all directions, falling off
+// with the square of the distance.
+pub struct Point {
+    position: Point3,
+    intensity: f32,
+    color: RGB,
+}
+
+impl Point {
+    pub fn new(position: Point3, intensity: f32, color: RGB) -> Point {
+        Point {
+            position: position,
+            intensity: intensity,
+            color: color,
+        }
+    }
+}
+
+impl Light for Point {
+    fn sample(&self, point: &Point3, _u: (f32, f32)) -> Option<LightSample> {
+        let to_light = self.position - *point;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0_f32 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        return Some(LightSample {
+            dir: to_light / distance,
+            distance: distance,
+            radiance: self.color * (self.intensity / distance_squared),
+        });
+    }
+}
+
+// Directional
+// A light infinitely far away shining in one direction, like the sun. A
+// non-zero angular size gives it a disk or rectangle in the sky, which
+// softens shadows into penumbras. Radiance is what arrives at a surface
+// facing the light, so there is no falloff, and shadow rays are traced all
+// the way out of the scene.
+pub struct Directional {
+    // Frame around the direction toward the light. Rectangles are kept
+    // level, with their width along the horizon.
+    toward: Vector3,
+    right: Vector3,
+    up: Vector3,
+    shape: SunShape,
+    radiance: RGB,
+}
+
+pub enum SunShape {
+    // Cosine of the disk's angular radius
+    Disk(f32),
+    // Tangents of half the rectangle's angular width and height
+    Rectangle(f32, f32),
+}
+
+impl Directional {
+    // The direction is the one the light travels in.
+    pub fn new(direction: &Vector3, shape: SunShape, radiance: RGB) -> Directional {
+        let toward = -direction.normalized();
+        let world_up = if toward.y().abs() > 0.999_f32 {
+            Vector3::new(0.0_f32, 0.0_f32, 1.0_f32)
+        } else {
+            Vector3::new(0.0_f32, 1.0_f32, 0.0_f32)
+        };
+        let right = world_up.cross(toward).normalized();
+        Directional {
+            toward: toward,
+            right: right,
+            up: toward.cross(right),
+            shape: shape,
+            radiance: radiance,
+        }
+    }
+
+    // Sun shapes from angular sizes in degrees, kept under a hemisphere
+    pub fn disk(angular_diameter: f32) -> SunShape {
+        let radius = utils::clamp(angular_diameter * 0.5_f32, 0.0_f32, 90.0_f32);
+        return SunShape::Disk(radius.to_radians().cos());
+    }
+
+    pub fn rectangle(angular_width: f32, angular_height: f32) -> SunShape {
+        let half_width = utils::clamp(angular_width * 0.5_f32, 0.0_f32, 89.9_f32);
+        let half_height = utils::clamp(angular_height * 0.5_f32, 0.0_f32, 89.9_f32);
+        return SunShape::Rectangle(
+            half_width.to_radians().tan(),
+            half_height.to_radians().tan(),
+        );
+    }
+}
+
+impl Light for Directional {
+    fn sample(&self, _point: &Point3, (r1, r2): (f32, f32)) -> Option<LightSample> {
+        let (x, y, z) = match self.shape {
+            // Uniform over the cone of directions toward the disk
+            SunShape::Disk(cos_radius) => {
+                let z = 1.0_f32 - r2 * (1.0_f32 - cos_radius);
+                let sin_z = utils::float_max(1.0_f32 - z * z, 0.0_f32).sqrt();
+                let phi = 2.0_f32 * f32::consts::PI * r1;
+                (phi.cos() * sin_z, phi.sin() * sin_z, z)
+            }
+            // Uniform over the rectangle, placed a unit distance away
+            SunShape::Rectangle(tan_width, tan_height) => (
+                (2.0_f32 * r1 - 1.0_f32) * tan_width,
+                (2.0_f32 * r2 - 1.0_f32) * tan_height,
+                1.0_f32,
+            ),
+        };
+        let dir = x * self.right + y * self.up + z * self.toward;
+        return Some(LightSample {
+            dir: dir.normalized(),
+            distance: utils::T_MAX,
+            radiance: self.radiance,
+        });
+    }
+}
@@ -0,0 +1,298 @@
+use crate::color::RGB;
+use crate::scene::Logistics;
+use crate::tile::Tile;
+
+use serde_json::{json, Value};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::Mutex;
+
+// Sends the progress of a render, and the pixels of each tile as it is
+// finished, to another program listening on a local socket, so a frontend
+// can show the image coming together without the raytracer needing a window
+// of its own. On Unix the path is a Unix domain socket or a named pipe
+// (FIFO), elsewhere a named pipe like \\.\pipe\raytracer. Messages are JSON
+// objects, one per line:
+//   {"type": "start", "width", "height", "samples", "tiles", "fingerprint"}
+//   {"type": "tile", "x", "y", "width", "height", "progress", "pixels"}
+//   {"type": "done"} or {"type": "failed", "message"}
+// Tile pixels are 8 bit RGB in row-major order, encoded for the output space
+// like the PNG output. Outlines are only drawn once every tile is finished,
+// so they are in the output file but not in any tile.
+//
+// An interactive frontend, on a socket rather than a pipe, can then ask for
+// regions of the finished image to be traced again with more samples, which
+// are merged into what the image already has, until it closes:
+//   {"type": "refine", "x", "y", "width", "height", "samples"}
+//   {"type": "close"}, or the frontend hanging up
+// Refine requests may also give the fingerprint of the scene they were
+// meant for, from its start message or an image's metadata, and are refused
+// unless it is this render's.
+// Each region is answered with its own start and tiles, then one of:
+//   {"type": "refining", "x", "y", "width", "height", "samples", "tiles"}
+//   {"type": "tile", ...} for each of the region's tiles, merged
+//   {"type": "refined"} or {"type": "refused", "message"}
+pub struct LiveOutput {
+    // None once the listener has gone away, so the render carries on
+    // without it
+    stream: Mutex<Option<Box<dyn Write + Send>>>,
+    // Requests from an interactive frontend, None otherwise
+    requests: Mutex<Option<BufReader<Box<dyn Read + Send>>>>,
+    // Pixels sent and the pixels there are to send, of the image or the
+    // region being refined
+    progress: Mutex<(u64, u64)>,
+}
+
+// A region of the finished image to trace again, with how many more samples
+// each of its pixels takes
+pub struct Refinement {
+    pub region: Tile,
+    pub samples: u32,
+}
+
+impl LiveOutput {
+    // Connects to the frontend, taking its requests too when interactive
+    pub fn connect(path: &str, interactive: bool) -> Result<LiveOutput, String> {
+        let (stream, reader) = match open(path) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(format!(
+                    "Could not connect to the progress socket {}: {}",
+                    path, e
+                ))
+            }
+        };
+        let requests = match (interactive, reader) {
+            (true, Some(r)) => Some(BufReader::new(r)),
+            (true, None) => {
+                return Err(format!(
+                    "Can not take requests from {}, interactive frontends need a socket rather \
+                     than a named pipe",
+                    path
+                ))
+            }
+            (false, _) => None,
+        };
+        return Ok(LiveOutput {
+            stream: Mutex::new(Some(stream)),
+            requests: Mutex::new(requests),
+            progress: Mutex::new((0, 0)),
+        });
+    }
+
+    pub fn interactive(&self) -> bool {
+        return self
+            .requests
+            .lock()
+            .expect("Failed to lock live output requests.")
+            .is_some();
+    }
+
+    pub fn start(&self, logistics: &Logistics, tile_count: usize, fingerprint: &str) {
+        *self
+            .progress
+            .lock()
+            .expect("Failed to lock live output progress.") = (
+            0,
+            logistics.resolution_x as u64 * logistics.resolution_y as u64,
+        );
+        self.send(json!({
+            "type": "start",
+            "width": logistics.resolution_x,
+            "height": logistics.resolution_y,
+            "samples": logistics.samples,
+            "tiles": tile_count,
+            "fingerprint": fingerprint,
+        }));
+    }
+
+    // Sends a finished tile, with its summed colors from render_tile in
+    // row-major order
+    pub fn tile(&self, t: &Tile, tile_colors: &Vec<RGB>, logistics: &Logistics) {
+        let mut pixels = Vec::with_capacity(tile_colors.len() * 3);
+        for c in tile_colors {
+            pixels.extend_from_slice(&crate::render::pixel_rgb8(*c, logistics));
+        }
+        let progress = {
+            let mut progress = self
+                .progress
+                .lock()
+                .expect("Thread failed to lock live output progress.");
+            progress.0 += t.pixel_count() as u64;
+            progress.0 as f64 / progress.1 as f64
+        };
+        self.send(json!({
+            "type": "tile",
+            "x": t.x_min,
+            "y": t.y_min,
+            "width": t.width(),
+            "height": t.height(),
+            "progress": progress,
+            "pixels": pixels,
+        }));
+    }
+
+    pub fn finish(&self, failure: Option<&String>) {
+        match failure {
+            Some(message) => self.send(json!({"type": "failed", "message": message})),
+            None => self.send(json!({"type": "done"})),
+        }
+    }
+
+    // Waits for the frontend's next region to refine, clipped to the image,
+    // or None once it closes. Requests that make no sense are refused and
+    // passed over.
+    pub fn next_refinement(&self, res_x: u32, res_y: u32, fingerprint: &str) -> Option<Refinement> {
+        loop {
+            let mut line = String::new();
+            let read = match &mut *self
+                .requests
+                .lock()
+                .expect("Failed to lock live output requests.")
+            {
+                Some(r) => r.read_line(&mut line),
+                None => return None,
+            };
+            match read {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!(
+                        "\nStopped taking requests, the progress socket failed: {}",
+                        e
+                    );
+                    return None;
+                }
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_request(&line, res_x, res_y, fingerprint) {
+                Ok(Some(r)) => return Some(r),
+                Ok(None) => return None,
+                Err(e) => self.refuse(&e),
+            }
+        }
+    }
+
+    // Starts sending a region's tiles, with progress through the region
+    pub fn refining(&self, refinement: &Refinement, tile_count: usize) {
+        let region = &refinement.region;
+        *self
+            .progress
+            .lock()
+            .expect("Failed to lock live output progress.") = (0, region.pixel_count() as u64);
+        self.send(json!({
+            "type": "refining",
+            "x": region.x_min,
+            "y": region.y_min,
+            "width": region.width(),
+            "height": region.height(),
+            "samples": refinement.samples,
+            "tiles": tile_count,
+        }));
+    }
+
+    pub fn refined(&self) {
+        self.send(json!({"type": "refined"}));
+    }
+
+    pub fn refuse(&self, message: &str) {
+        self.send(json!({"type": "refused", "message": message}));
+    }
+
+    fn send(&self, message: Value) {
+        let mut stream = self
+            .stream
+            .lock()
+            .expect("Thread failed to lock the progress socket.");
+        let result = match &mut *stream {
+            Some(s) => writeln!(s, "{}", message).and_then(|_| s.flush()),
+            None => return,
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!(
+                    "\nStopped sending progress, the progress socket failed: {}",
+                    e
+                );
+                *stream = None;
+            }
+        }
+    }
+}
+
+// A refine request as a region and sample count, None for a close, or why
+// the request was refused
+fn parse_request(
+    line: &str,
+    res_x: u32,
+    res_y: u32,
+    fingerprint: &str,
+) -> Result<Option<Refinement>, String> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Could not read the request: {}", e)),
+    };
+    match request["type"].as_str() {
+        Some("refine") => {}
+        Some("close") => return Ok(None),
+        _ => return Err(String::from("Requests are either refine or close")),
+    }
+    match request["fingerprint"].as_str() {
+        Some(f) if f != fingerprint => {
+            return Err(format!(
+                "The request is for the scene with fingerprint {}, not this one, {}",
+                f, fingerprint
+            ))
+        }
+        _ => {}
+    }
+    let number = |field: &str| match request[field].as_u64() {
+        Some(n) => Ok(n.min(std::u32::MAX as u64) as u32),
+        None => Err(format!("A refine request needs a whole number {}", field)),
+    };
+    let (x, y) = (number("x")?, number("y")?);
+    let (width, height) = (number("width")?, number("height")?);
+    let samples = number("samples")?;
+    let region = Tile {
+        x_min: x.min(res_x),
+        y_min: y.min(res_y),
+        x_max: x.saturating_add(width).min(res_x),
+        y_max: y.saturating_add(height).min(res_y),
+    };
+    if region.pixel_count() == 0 {
+        return Err(String::from("The region to refine is outside of the image"));
+    }
+    if samples == 0 {
+        return Err(String::from("A refine request needs at least one sample"));
+    }
+    return Ok(Some(Refinement {
+        region: region,
+        samples: samples,
+    }));
+}
+
+// The stream to send on, and one to read requests from when the path is a
+// socket
+#[cfg(unix)]
+fn open(path: &str) -> std::io::Result<(Box<dyn Write + Send>, Option<Box<dyn Read + Send>>)> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    if std::fs::metadata(path)?.file_type().is_fifo() {
+        return Ok((Box::new(OpenOptions::new().write(true).open(path)?), None));
+    }
+    let stream = UnixStream::connect(path)?;
+    let reader = stream.try_clone()?;
+    return Ok((Box::new(stream), Some(Box::new(reader))));
+}
+
+// Named pipes on Windows are duplex, so requests come back on the same one
+#[cfg(not(unix))]
+fn open(path: &str) -> std::io::Result<(Box<dyn Write + Send>, Option<Box<dyn Read + Send>>)> {
+    let pipe = OpenOptions::new().read(true).write(true).open(path)?;
+    let reader = pipe.try_clone()?;
+    return Ok((Box::new(pipe), Some(Box::new(reader))));
+}
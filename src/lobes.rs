@@ -0,0 +1,56 @@
+use crate::aggregate::Workspace;
+use crate::color::RGB;
+use crate::material::Reflectance;
+use crate::pdf::PDF;
+use crate::ray::Ray;
+use crate::scene::Scene;
+
+// Color for a camera ray in the lobes debug mode, showing which kind of
+// scattering the material at its first hit chose: red for diffuse, green
+// for glossy, blue for transmission, and white for emission. Materials
+// that pick between lobes at random, like Blends and Clearcoats, pick one
+// per sample as they do while rendering, so averaged over a pixel's
+// samples each channel is the fraction that took that lobe. Noisy regions
+// can then be traced to the lobes behind them, like a small share of
+// glossy samples catching a bright light. Absorbed samples and misses are
+// black. The image is written like any render, through the output's
+// transfer curve, so small shares show up brighter than they are.
+pub fn lobe_color(
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+    scene: &Scene,
+    workspace: &mut Workspace,
+) -> RGB {
+    let (shape, hit) = match scene.shape_aggregate.hit(r, t_min, t_max, workspace) {
+        Some(h) => h,
+        None => return RGB::black(),
+    };
+    let t_hit = hit.t;
+    let mut hit_props = shape.get_hit_properties(r, &hit);
+    hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+    hit_props.set_footprint(r, t_hit);
+    let material = shape.get_material();
+    if material.emit(r, &hit_props).is_some() {
+        return RGB::new(1.0_f32, 1.0_f32, 1.0_f32);
+    }
+    let scattered = match material.scatter(r, &hit_props) {
+        Some(s) => s,
+        None => return RGB::black(),
+    };
+    let diffuse = RGB::new(1.0_f32, 0.0_f32, 0.0_f32);
+    let glossy = RGB::new(0.0_f32, 1.0_f32, 0.0_f32);
+    match scattered.reflectance {
+        // Specular rays that leave through the other side of the surface
+        // were transmitted
+        Reflectance::Specular(s) => {
+            if s.dir.dot(hit_props.normal) * r.dir.dot(hit_props.normal) > 0.0_f32 {
+                return RGB::new(0.0_f32, 0.0_f32, 1.0_f32);
+            }
+            return glossy;
+        }
+        // As rays are kinded while tracing
+        Reflectance::PDF(PDF::GGX(_)) => return glossy,
+        Reflectance::PDF(_) | Reflectance::Toon(_) => return diffuse,
+    }
+}
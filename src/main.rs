@@ -1,51 +1,168 @@
-// Local modules
-mod aggregate;
-mod base;
-mod camera;
-mod color;
-mod material;
-mod matrix;
-mod pdf;
-mod point;
-mod progress;
-mod ray;
-mod resources;
-mod scene;
-mod shape;
-mod texture;
-mod transform;
-mod utils;
-mod vector;
-mod volume;
-
 // External/std libraries for main
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use image::png::PNGEncoder;
 use image::ColorType;
-use rand;
 use std::{
-    fs, fs::OpenOptions, io, path, process, sync::mpsc, sync::Arc, sync::Mutex, thread,
+    collections::{BTreeMap, HashSet},
+    fs,
+    fs::OpenOptions,
+    io, panic, path, process,
+    sync::Arc,
+    sync::Mutex,
+    thread,
     time::Instant,
 };
 
 // Use statements for local modules
-use crate::color::{COLOR_SPACE, RGB};
-use crate::progress::Progress;
-use crate::ray::Ray;
-use crate::resources::Resources;
-use crate::scene::Scene;
+use rust_raytracer::aggregate::AABB;
+use rust_raytracer::colorspace::ColorSpace;
+use rust_raytracer::live::LiveOutput;
+use rust_raytracer::render::{linear_row, render, rgb8_row};
+use rust_raytracer::resources::Resources;
+use rust_raytracer::scene::Scene;
+use rust_raytracer::{bench, bounds, exr, numa, passes, png, profile, scene, schema, stats, uvs};
 
 fn main() {
     // Define command line args
     let matches = App::new("Raytracer")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Loads a scene specification and reports on its acceleration structure")
+                .arg(
+                    Arg::with_name("IN_SCENE_FILE")
+                        .help("The scene specification to inspect")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("bounds")
+                        .long("bounds")
+                        .value_name("OBJ_FILE")
+                        .help("Writes bounding boxes to an OBJ file, the BVH's leaves by default")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("depth")
+                        .long("depth")
+                        .value_name("DEPTH")
+                        .help("Writes the BVH nodes at this depth to the bounds file instead")
+                        .takes_value(true)
+                        .requires("bounds"),
+                )
+                .arg(
+                    Arg::with_name("shape")
+                        .long("shape")
+                        .value_name("NAME")
+                        .help("Writes the bounds of the shapes with this name instead, may be repeated")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("bounds")
+                        .conflicts_with("depth"),
+                )
+                .arg(
+                    Arg::with_name("uvs")
+                        .long("uvs")
+                        .value_name("DIRECTORY")
+                        .help("Reports on the UV layout of each mesh and writes its UV occupancy there")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Renders a fixed set of generated scenes and reports performance")
+                .arg(thread_count_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .about("Writes a JSON Schema of the scene specification format, for editors")
+                .arg(
+                    Arg::with_name("OUT_FILE")
+                        .help("The file to write the schema to, standard output when not given")
+                        .index(1),
+                ),
+        )
+        .arg(thread_count_arg())
+        .arg(
+            Arg::with_name("check-normals")
+                .long("check-normals")
+                .help("Colors front faces green and back faces red instead of shading the scene"),
+        )
+        .arg(
+            Arg::with_name("lobes")
+                .long("lobes")
+                .conflicts_with("check-normals")
+                .help(
+                    "Colors each pixel by the share of its samples that scattered diffusely (red), \
+                     glossily (green), by transmission (blue), or hit an emitter (white)",
+                ),
+        )
+        .arg(
+            Arg::with_name("override-material")
+                .long("override-material")
+                .value_name("MATERIAL")
+                .help(
+                    "Renders every surface but lights with the named material, or the builtin \
+                     grey Clay",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("frame")
+                .long("frame")
+                .value_name("FRAME")
+                .help("Renders this frame of an animation, for cameras that follow a path")
+                .takes_value(true),
+        )
         .arg(
-            Arg::with_name("thread-count")
-                .short("t")
-                .long("thread-count")
-                .value_name("THREAD_COUNT")
-                .help("Number of threads to use while tracing")
+            Arg::with_name("denoise")
+                .long("denoise")
+                .help(
+                    "Denoises the finished image with Intel Open Image Denoise, guided by its \
+                     albedo and normals (needs a build with the denoise feature)",
+                ),
+        )
+        .arg(
+            Arg::with_name("missing-asset")
+                .long("missing-asset")
+                .value_name("MODE")
+                .help(
+                    "What to do about images and meshes that can't be found: fail, or warn and \
+                     stand in a magenta checkerboard texture or unit cube for them",
+                )
+                .possible_values(&["fail", "placeholder"])
+                .default_value("fail")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("numa")
+                .long("numa")
+                .help(
+                    "Interleaves the scene's memory across NUMA nodes and pins each tracing thread \
+                     to its own CPU, spread across the nodes (Linux only)",
+                ),
+        )
+        .arg(
+            Arg::with_name("progress-socket")
+                .long("progress-socket")
+                .value_name("PATH")
+                .help(
+                    "Also sends progress and each finished tile's pixels to a frontend listening \
+                     on this Unix socket or named pipe",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("interactive")
+                .long("interactive")
+                .help(
+                    "Keeps the finished image open for the frontend to ask for regions of it to be \
+                     traced again with more samples, writing it once the frontend closes",
+                )
+                .requires("progress-socket"),
+        )
         .arg(
             Arg::with_name("IN_SCENE_FILE")
                 .help("The scene specification to render")
@@ -63,36 +180,89 @@ fn main() {
     // Grab a stamp for the start of the run
     let program_start = Instant::now();
 
-    // Grab the number of threads we want to use from arguments,
-    // default to 2
-    let num_threads = matches
-        .value_of("thread-count")
-        .unwrap_or("2")
-        .parse::<u32>()
-        .expect("thread-count requires a valid positive integer");
-    if num_threads == 0_u32 {
-        panic!("Need a thread count greater than zero");
+    if let Some(info_matches) = matches.subcommand_matches("info") {
+        let mut res = Resources::new();
+        let scene_spec = load_scene(
+            info_matches
+                .value_of("IN_SCENE_FILE")
+                .expect("Need to specify an IN_SCENE_FILE argument"),
+            None,
+            &mut res,
+        );
+        print_info(&scene_spec);
+        match info_matches.value_of("bounds") {
+            Some(obj_file) => write_bounds(&scene_spec, info_matches, obj_file),
+            None => {}
+        }
+        match info_matches.value_of("uvs") {
+            Some(dir) => write_uvs(&scene_spec, dir),
+            None => {}
+        }
+        println!(
+            "Loaded in {} seconds",
+            program_start.elapsed().as_secs_f64()
+        );
+        return;
     }
 
-    // Read the scene spec file
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        bench::run(parse_thread_count(bench_matches));
+        return;
+    }
+
+    if let Some(schema_matches) = matches.subcommand_matches("schema") {
+        let schema = serde_json::to_string_pretty(&schema::scene_schema())
+            .expect("Failed to serialize the scene schema.");
+        match schema_matches.value_of("OUT_FILE") {
+            Some(out_file) => {
+                fs::write(out_file, schema).expect("Failed to write the scene schema.")
+            }
+            None => println!("{}", schema),
+        }
+        return;
+    }
+
+    let num_threads = parse_thread_count(&matches);
+
+    // Read the scene spec file. With --numa its memory, the BVH and meshes
+    // above all, is spread across every node rather than left on this
+    // thread's.
+    let numa = matches.is_present("numa");
+    if numa {
+        match numa::interleave_memory() {
+            Ok(()) => {}
+            Err(e) => eprintln!("Could not interleave the scene across NUMA nodes: {}", e),
+        }
+    }
     let mut res = Resources::new();
-    let scene_spec_path = path::Path::new(
+    res.set_placeholders(matches.value_of("missing-asset") == Some("placeholder"));
+    let mut scene_spec = load_scene(
         matches
             .value_of("IN_SCENE_FILE")
             .expect("Need to specify an IN_SCENE_FILE argument"),
+        Some(&matches),
+        &mut res,
     );
-    let scene_str = fs::read_to_string(&scene_spec_path).expect("Failed to read scene spec file.");
-    let scene_spec = Arc::new(
-        scene::deserialize(
-            &scene_str,
-            match scene_spec_path.parent() {
-                Some(p) => p,
-                None => path::Path::new("/"),
-            },
-            &mut res,
-        )
-        .expect("Failed to parse scene spec JSON."),
-    );
+    if numa {
+        match numa::default_memory() {
+            Ok(()) => {}
+            Err(e) => eprintln!("Could not restore the default NUMA memory policy: {}", e),
+        }
+        scene_spec.logistics.pin_threads = Some(true);
+    }
+
+    // Connect to a frontend's socket, if there is one, before the render's
+    // long work starts
+    let live = match matches.value_of("progress-socket") {
+        Some(path) => match LiveOutput::connect(path, matches.is_present("interactive")) {
+            Ok(l) => Some(l),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
 
     // Create the output file according to input path
     let out_file = OpenOptions::new()
@@ -104,167 +274,320 @@ fn main() {
                 .expect("Need to specify an OUT_FILEPATH argument"),
         )
         .expect("Failed to create new file");
-    let png_encoder = PNGEncoder::new(out_file);
+    let out_path = path::Path::new(
+        matches
+            .value_of("OUT_FILEPATH")
+            .expect("Need to specify an OUT_FILEPATH argument"),
+    );
+    let pass_list = scene_spec.logistics.passes();
+    let pass_files = match passes::create_files(out_path, &pass_list) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{}", e);
+            drop(out_file);
+            let _ = fs::remove_file(out_path);
+            process::exit(1);
+        }
+    };
 
     // Specifications
     let res_x = scene_spec.logistics.resolution_x;
     let res_y = scene_spec.logistics.resolution_y;
-    let samples = scene_spec.logistics.samples;
 
-    // Init output color float data with empty values.
-    let colors = Arc::new(Mutex::new(Vec::new()));
-    {
-        (*colors
-            .lock()
-            .expect("Failed to acquire output data lock for resizing."))
-        .resize_with((res_x * res_y) as usize, RGB::black);
+    let (colors, pass_images, stats) = match render(
+        &scene_spec,
+        num_threads,
+        Arc::new(Mutex::new(io::stdout())),
+        live.as_ref(),
+    ) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            // Don't leave the empty output files behind
+            drop(out_file);
+            drop(pass_files);
+            let _ = fs::remove_file(out_path);
+            passes::remove_files(out_path, &pass_list);
+            process::exit(1);
+        }
+    };
+    print!(
+        "{}",
+        stats.report(&scene_spec.shape_names, &scene_spec.material_names)
+    );
+    match profile::report() {
+        Some(r) => print!("{}", r),
+        None => {}
     }
 
-    // Set up a queue of input pixels + samples for threads to process
-    let (tx, rx) = {
-        let (temp_tx, temp_rx) = mpsc::channel();
-        (temp_tx, Arc::new(Mutex::new(temp_rx)))
+    // Once all tracing has been done, finalize data and write the image to
+    // disk a row at a time, as a linear EXR for ACEScg or an 8 bit PNG
+    // otherwise, noting the scene's fingerprint in its metadata
+    let output_space = scene_spec.logistics.output_space();
+    let metadata = [(FINGERPRINT_KEY, scene_spec.fingerprint.as_str())];
+    let write_result = match output_space {
+        ColorSpace::ACEScg => exr::write_rgb(
+            &mut io::BufWriter::new(out_file),
+            res_x,
+            res_y,
+            output_space,
+            &metadata,
+            |y, line| linear_row(&colors, &scene_spec.logistics, y, line),
+        ),
+        ColorSpace::SRGB | ColorSpace::Rec709 => png::write_rgb8(
+            io::BufWriter::new(out_file),
+            res_x,
+            res_y,
+            &metadata,
+            |y, line| rgb8_row(&colors, &scene_spec.logistics, y, line),
+        ),
     };
+    match write_result {
+        Ok(()) => {}
+        Err(e) => {
+            eprintln!("Failed to encode the image for output: {}", e);
+            process::exit(1);
+        }
+    }
+    match pass_images {
+        Some(p) => match p.write(pass_files, &scene_spec.logistics, &metadata) {
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("Failed to encode the passes for output: {}", e);
+                process::exit(1);
+            }
+        },
+        None => {}
+    }
+    println!(
+        "Success! Took {} seconds",
+        program_start.elapsed().as_secs_f64()
+    );
+}
 
-    // Set up a structure to track progress and print to standard out
-    let progress_tracker = Arc::new(Mutex::new(Progress::new(
-        res_x as u64 * res_y as u64 * samples as u64,
-        Arc::new(Mutex::new(io::stdout())),
-        20_u32,
-    )));
+// The metadata key images are given their scene's fingerprint under
+const FINGERPRINT_KEY: &str = "fingerprint";
 
-    // Spawn threads up to the desired amount (minus one,
-    // because the main thread is a thread too)
-    let mut threads = Vec::new();
-    for _ in 0..(num_threads - 1_u32) {
-        let thread_scene = Arc::clone(&scene_spec);
-        let thread_rx = Arc::clone(&rx);
-        let thread_colors = Arc::clone(&colors);
-        let thread_progress = Arc::clone(&progress_tracker);
-        threads.push(thread::spawn(move || {
-            thread_work(&thread_scene, &thread_rx, &thread_colors, &thread_progress)
-        }))
-    }
+fn thread_count_arg() -> Arg<'static, 'static> {
+    return Arg::with_name("thread-count")
+        .short("t")
+        .long("thread-count")
+        .visible_alias("threads")
+        .value_name("THREAD_COUNT")
+        .help("Number of threads to use while tracing, 0 (the default) uses all logical cores")
+        .takes_value(true);
+}
 
-    // Fill queue with data
-    for x in 0..res_x {
-        for y in 0..res_y {
-            for _ in 0..samples {
-                tx.send((x, y))
-                    .expect("Main thread failed to send pixel data into queue.");
+// Grab the number of threads we want to use from arguments,
+// default to one per logical core
+fn parse_thread_count(matches: &ArgMatches) -> u32 {
+    let num_threads = match matches.value_of("thread-count") {
+        Some(t) => t
+            .parse::<u32>()
+            .expect("thread-count requires a valid non-negative integer"),
+        None => 0_u32,
+    };
+    if num_threads == 0_u32 {
+        return match thread::available_parallelism() {
+            Ok(n) => n.get() as u32,
+            Err(e) => {
+                eprintln!(
+                    "Failed to detect the number of logical cores, using one thread: {}",
+                    e
+                );
+                1_u32
             }
+        };
+    }
+    return num_threads;
+}
+
+// Loads the scene, with the overrides given on the command line when there
+// are any
+fn load_scene(scene_spec_file: &str, matches: Option<&ArgMatches>, res: &mut Resources) -> Scene {
+    let scene_spec_path = path::Path::new(scene_spec_file);
+    let mut scene_str =
+        fs::read_to_string(&scene_spec_path).expect("Failed to read scene spec file.");
+    // Materials are bound to shapes, and cameras placed on their paths, as
+    // the scene loads, so overrides from the command line go into the spec
+    // itself, where they count toward the scene's fingerprint too
+    let mut overrides = serde_json::Map::new();
+    let override_material = matches.and_then(|m| m.value_of("override-material"));
+    let frame = matches.and_then(|m| m.value_of("frame"));
+    for flag in ["check-normals", "lobes", "denoise"].iter() {
+        if matches.map_or(false, |m| m.is_present(flag)) {
+            overrides.insert(flag.replace("-", "_"), serde_json::Value::from(true));
         }
     }
-    // Drop Sender so threads can close on their own
-    drop(tx);
-    // Start having the main thread do some work too
-    thread_work(&scene_spec, &rx, &colors, &progress_tracker);
-    // Wait for tracing threads to complete if the main thread completes early
-    for t in threads {
-        t.join().expect("Failed to finalize a tracing thread.");
+    match override_material {
+        Some(name) => {
+            overrides.insert(
+                String::from("override_material"),
+                serde_json::Value::from(name),
+            );
+        }
+        None => {}
     }
-    (*progress_tracker)
-        .lock()
-        .expect("Failed to lock the command line progress tracker from the main thread")
-        .done();
-
-    // Once all tracing has been done, finalize data and convert to
-    // 8 bit unsigned integer
-    let mut data = Vec::with_capacity((res_x * res_y * 3_u32) as usize);
-    let locked_colors = &mut (*colors
-        .lock()
-        .expect("Main thread failed to lock output color data for writing to image."));
-    for y in 0..res_y {
-        for x in 0..res_x {
-            let mut col = locked_colors[((x * res_y) + y) as usize] / samples as f32;
-            col = col.gamma_correct();
-
-            data.push((col.r() * COLOR_SPACE) as u8);
-            data.push((col.g() * COLOR_SPACE) as u8);
-            data.push((col.b() * COLOR_SPACE) as u8);
+    match frame {
+        Some(f) => {
+            let frame = match f.parse::<u32>() {
+                Ok(n) => n,
+                Err(_) => {
+                    eprintln!("--frame must be a whole number, not {}.", f);
+                    process::exit(1);
+                }
+            };
+            overrides.insert(String::from("frame"), serde_json::Value::from(frame));
         }
+        None => {}
     }
-    // Write the image to disk
-    match png_encoder.encode(&data, res_x, res_y, ColorType::RGB(8)) {
-        Ok(()) => println!(
-            "Success! Took {} seconds",
-            program_start.elapsed().as_secs_f64()
-        ),
+    if !overrides.is_empty() {
+        let mut json: serde_json::Value =
+            serde_json::from_str(&scene_str).expect("Failed to parse scene spec JSON.");
+        match json.get_mut("Logistics").and_then(|l| l.as_object_mut()) {
+            Some(logistics) => logistics.extend(overrides),
+            None => panic!("Scene spec is missing 'Logistics'."),
+        }
+        scene_str = json.to_string();
+    }
+    match scene::deserialize(
+        &scene_str,
+        match scene_spec_path.parent() {
+            Some(p) => p,
+            None => path::Path::new("/"),
+        },
+        res,
+    ) {
+        Ok(s) => return s,
         Err(e) => {
-            eprintln!("Failed to encode the png for output: {}", e);
+            eprintln!("Failed to load {}: {}", scene_spec_file, e);
             process::exit(1);
         }
     }
 }
 
-fn thread_work(
-    thread_scene: &Scene,
-    thread_rx: &Mutex<mpsc::Receiver<(u32, u32)>>,
-    thread_colors: &Mutex<Vec<RGB>>,
-    thread_progress: &Mutex<Progress>,
-) {
-    let res_x = thread_scene.logistics.resolution_x;
-    let res_y = thread_scene.logistics.resolution_y;
-    let mut aggregate_workspace = thread_scene.shape_aggregate.get_workspace();
-
-    loop {
-        let (x, y) = {
-            match thread_rx
-                .lock()
-                .expect("Thread failed acquiring lock on input data queue.")
-                .iter()
-                .next()
-            {
-                Some((x, y)) => (x, y),
-                None => break,
-            }
-        };
-
-        // Note the use of rand::random. Consider switching to an explicit
-        // use of SmallRng, which is a non-secure, but fast, pseudo-RNG.
-        // The default implementation may not be as performant, and this
-        // program does not need the extra security benefits.
-        let u = (x as f32 + rand::random::<f32>()) / res_x as f32;
-        let v = ((res_y - y) as f32 + rand::random::<f32>()) / res_y as f32;
-        let r = thread_scene.camera.get_ray(u, v);
-
-        let pixel_color = aggregate::trace(
-            &r,
-            &(*thread_scene.shape_aggregate),
-            &thread_scene.important_samples,
-            &mut aggregate_workspace,
-            &black_background,
-            0,
-        );
+fn print_info(scene_spec: &Scene) {
+    println!(
+        "Resolution: {}x{}, {} samples per pixel",
+        scene_spec.logistics.resolution_x,
+        scene_spec.logistics.resolution_y,
+        scene_spec.logistics.samples
+    );
+    println!("Ray epsilon: {}", scene_spec.ray_epsilon);
+    println!("Fingerprint: {}", scene_spec.fingerprint);
+    print!("{}", scene_spec.shape_aggregate.info());
+}
 
-        {
-            let out_colors = &mut (*thread_colors
-                .lock()
-                .expect("Thread failed to acquire output data lock."));
-            out_colors[((x * res_y) + y) as usize] =
-                out_colors[((x * res_y) + y) as usize] + pixel_color;
+// Writes the bounds asked for on the command line to an OBJ file
+fn write_bounds(scene_spec: &Scene, matches: &ArgMatches, obj_file: &str) {
+    let boxes: Vec<(String, AABB)> = match matches.values_of("shape") {
+        // Every Shape from one entry in the scene shares its name, so the
+        // bounds of all of them together are written as one box
+        Some(names) => names
+            .map(|name| {
+                let mut bounds = AABB::new_empty();
+                let mut found = false;
+                for shape in scene_spec.shape_aggregate.shapes() {
+                    match scene_spec.shape_names.get(&stats::shape_key(shape)) {
+                        Some(n) if n == name => {
+                            bounds = AABB::union(&bounds, &shape.get_bounding_box());
+                            found = true;
+                        }
+                        _ => {}
+                    }
+                }
+                if !found {
+                    eprintln!("No shape named {} in the scene", name);
+                    process::exit(1);
+                }
+                (String::from(name), bounds)
+            })
+            .collect(),
+        None => {
+            let depth = match matches.value_of("depth") {
+                Some(d) => Some(
+                    d.parse::<usize>()
+                        .expect("depth requires a valid non-negative integer"),
+                ),
+                None => None,
+            };
+            scene_spec
+                .shape_aggregate
+                .node_bounds(depth)
+                .into_iter()
+                .enumerate()
+                .map(|(i, b)| (format!("node{}", i), b))
+                .collect()
         }
+    };
 
-        {
-            thread_progress
-                .lock()
-                .expect(
-                    "Failed to lock command line progress tracker from worker thread for update",
-                )
-                .update(1);
+    let out_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(obj_file)
+        .expect("Failed to create new bounds file");
+    match bounds::write_obj(&mut io::BufWriter::new(out_file), &boxes) {
+        Ok(()) => println!("Wrote {} bounding boxes to {}", boxes.len(), obj_file),
+        Err(e) => {
+            eprintln!("Failed to write the bounds file: {}", e);
+            process::exit(1);
         }
     }
 }
 
-/*
-fn background(r: &Ray) -> RGB {
-    // Sky blend
-    let dir_normal = r.dir.normalized();
-    let t = 0.5_f32 * (dir_normal.y() + 1.0_f32);
+// Reports on the UV layout of every mesh in the scene, and writes an image
+// of each one's occupancy of the unit UV square to the directory
+fn write_uvs(scene_spec: &Scene, dir: &str) {
+    // Split shapes are listed once per piece, so each triangle is only taken
+    // the first time it is seen
+    let mut seen = HashSet::new();
+    let mut meshes: BTreeMap<&String, Vec<_>> = BTreeMap::new();
+    for shape in scene_spec.shape_aggregate.shapes() {
+        let key = stats::shape_key(shape);
+        let uvs = match shape.corner_uvs() {
+            Some(uvs) => uvs,
+            None => continue,
+        };
+        if !seen.insert(key) {
+            continue;
+        }
+        match scene_spec.shape_names.get(&key) {
+            Some(name) => meshes.entry(name).or_insert_with(Vec::new).push(uvs),
+            None => {}
+        }
+    }
+    if meshes.is_empty() {
+        println!("No meshes with texture coordinates in the scene");
+        return;
+    }
+
+    fs::create_dir_all(dir).expect("Failed to create the UV directory");
+    for (name, triangles) in meshes {
+        let report = uvs::inspect(&triangles);
+        println!("UVs of {}: {}", name, report.summary());
 
-    RGB::new(1.0_f32, 1.0_f32, 1.0_f32) * (1.0_f32 - t) + RGB::new(0.5_f32, 0.7_f32, 1.0_f32) * t
-}
-*/
-fn black_background(_: &Ray) -> RGB {
-    RGB::black()
+        // Mesh names include their file paths
+        let file_name: String = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let out_path = path::Path::new(dir).join(format!("{}.png", file_name));
+        let out_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&out_path)
+            .expect("Failed to create new UV image file");
+        match PNGEncoder::new(out_file).encode(
+            &report.image,
+            uvs::UV_RESOLUTION,
+            uvs::UV_RESOLUTION,
+            ColorType::RGB(8),
+        ) {
+            Ok(()) => println!("Wrote UV occupancy to {}", out_path.display()),
+            Err(e) => {
+                eprintln!("Failed to write the UV image: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 }
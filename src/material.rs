@@ -1,15 +1,22 @@
 use crate::color::RGB;
+use crate::colorspace::ColorSpace;
+use crate::measured::MeasuredBRDF;
 use crate::pdf;
 use crate::pdf::PDF;
-use crate::ray::Ray;
+use crate::point::Point3;
+use crate::ray::{Ray, RayKind};
+use crate::rng;
+use crate::sampling;
+use crate::sampling::OrthonormalBasis;
 use crate::shape::HitProperties;
+use crate::spectrum;
 use crate::texture::SyncTexture;
 use crate::texture::TexCoord;
 use crate::utils;
 use crate::vector::Vector3;
 
-use rand;
 use serde::Deserialize;
+use std::f32;
 use std::sync::Arc;
 
 fn reflect(v: Vector3, n: Vector3) -> Vector3 {
@@ -29,20 +36,177 @@ fn schlick(cosine: f32, index: f32) -> f32 {
     r0 + (1.0_f32 - r0) * (1.0_f32 - cosine).powi(5)
 }
 
+// Thin film
+// A clear film a few hundred nanometers thick on a surface, like soap, oil
+// on water, or the coating on a lens. Light reflecting off the top and the
+// bottom of the film interferes, so some wavelengths reflect more than
+// others, and the colors shift with the angle and the thickness.
+#[derive(Deserialize, Clone, Copy)]
+pub struct ThinFilm {
+    // In nanometers
+    pub thickness: f32,
+    pub refractive_index: f32,
+}
+
+// What is under a thin film: a clear material with a refractive index, or a
+// mirror reflecting the given share of the light's amplitude
+#[derive(Clone, Copy)]
+enum FilmBase {
+    Clear(f32),
+    Mirror(f32),
+}
+
+impl ThinFilm {
+    // The reflectance at a wavelength for light arriving at the cosine to the
+    // normal from a medium of the outer refractive index, averaged over
+    // polarizations, from the Airy summation of the reflections in the film
+    fn reflectance(&self, cos_theta: f32, outer: f32, base: FilmBase, wavelength: f32) -> f32 {
+        let film = self.refractive_index;
+        let sin2_outer = (1.0_f32 - cos_theta * cos_theta).max(0.0_f32);
+        let sin2_film = (outer / film) * (outer / film) * sin2_outer;
+        if sin2_film >= 1.0_f32 {
+            return 1.0_f32;
+        }
+        let cos_film = (1.0_f32 - sin2_film).sqrt();
+        let (top_s, top_p) = fresnel_amplitudes(outer, cos_theta, film, cos_film);
+        let (bottom_s, bottom_p) = match base {
+            FilmBase::Clear(index) => {
+                let sin2_base = (film / index) * (film / index) * sin2_film;
+                if sin2_base >= 1.0_f32 {
+                    (1.0_f32, 1.0_f32)
+                } else {
+                    fresnel_amplitudes(film, cos_film, index, (1.0_f32 - sin2_base).sqrt())
+                }
+            }
+            // Reflecting off a denser medium flips the phase
+            FilmBase::Mirror(amplitude) => (-amplitude, -amplitude),
+        };
+        // The phase the light gains going down through the film and back up
+        let cos_phase =
+            (4.0_f32 * f32::consts::PI * film * self.thickness * cos_film / wavelength).cos();
+        let airy = |top: f32, bottom: f32| {
+            let cross = 2.0_f32 * top * bottom * cos_phase;
+            (top * top + bottom * bottom + cross) / (1.0_f32 + top * top * bottom * bottom + cross)
+        };
+        return utils::clamp(
+            0.5_f32 * (airy(top_s, bottom_s) + airy(top_p, bottom_p)),
+            0.0_f32,
+            1.0_f32,
+        );
+    }
+
+    // The reflectance for a path at a single wavelength, or the linear
+    // Rec. 709 color of the reflectance for one without
+    fn color(&self, cos_theta: f32, outer: f32, base: FilmBase, wavelength: Option<f32>) -> RGB {
+        match wavelength {
+            Some(w) => {
+                let r = self.reflectance(cos_theta, outer, base, w);
+                RGB::new(r, r, r)
+            }
+            None => spectrum::reflectance_color(|w| self.reflectance(cos_theta, outer, base, w)),
+        }
+    }
+}
+
+// The Fresnel amplitude reflection coefficients, for s and p polarized
+// light, going from one refractive index into another at the cosines to the
+// normal on either side
+fn fresnel_amplitudes(from: f32, cos_from: f32, to: f32, cos_to: f32) -> (f32, f32) {
+    let s = (from * cos_from - to * cos_to) / (from * cos_from + to * cos_to);
+    let p = (to * cos_from - from * cos_to) / (to * cos_from + from * cos_to);
+    return (s, p);
+}
+
+// Bump and normal maps
+// Detail that moves the shading normal without changing the shape. A bump
+// map's brightness is a height the surface is displaced by, and a normal
+// map's color is a normal in tangent space, with red along the u texture
+// direction, green along v, and blue out of the surface, each stored from 0
+// for -1 to 1 for 1. Normal map colors are taken as written, undoing the
+// conversion into the working space that all textures get.
+#[derive(Clone)]
+pub enum Perturbation {
+    Bump(Arc<SyncTexture>),
+    NormalMap(Arc<SyncTexture>, ColorSpace),
+}
+
+impl Perturbation {
+    fn normal(&self, hit_props: &HitProperties) -> Vector3 {
+        match self {
+            Perturbation::Bump(b) => bump_modify(hit_props, &(*(*b))),
+            Perturbation::NormalMap(n, working_space) => {
+                normal_map_modify(hit_props, &(*(*n)), *working_space)
+            }
+        }
+    }
+}
+
+// The normal to shade with, moved by the bump or normal map if there is one
+fn shading_normal(hit_props: &HitProperties, perturbation: &Option<Perturbation>) -> Vector3 {
+    match perturbation {
+        None => hit_props.normal,
+        Some(p) => p.normal(hit_props),
+    }
+}
+
+// The tangent space follows pu and pv, which is the texture's u and v
+// directions on shapes with texture coordinates
+fn normal_map_modify(
+    hit_props: &HitProperties,
+    normal_map: &SyncTexture,
+    working_space: ColorSpace,
+) -> Vector3 {
+    let color = working_space.to_rec709(normal_map.filtered_value(
+        &hit_props.uv,
+        &hit_props.hit_point,
+        &hit_props.normal,
+        hit_props.uv_footprint,
+    ));
+    let tangent_normal = Vector3::new(
+        2.0_f32 * color.r() - 1.0_f32,
+        2.0_f32 * color.g() - 1.0_f32,
+        2.0_f32 * color.b() - 1.0_f32,
+    );
+    if tangent_normal.squared_length() == 0.0_f32 {
+        return hit_props.normal;
+    }
+    let normal = hit_props.normal.normalized();
+    let basis = OrthonormalBasis::with_tangents(&normal, &hit_props.pu, &hit_props.pv);
+    // Where the texture is mirrored, v runs against the basis
+    let flip = if basis
+        .local(&Vector3::new(0.0_f32, 1.0_f32, 0.0_f32))
+        .dot(hit_props.pv)
+        < 0.0_f32
+    {
+        -1.0_f32
+    } else {
+        1.0_f32
+    };
+    return basis
+        .local(&Vector3::new(
+            tangent_normal.x(),
+            flip * tangent_normal.y(),
+            tangent_normal.z(),
+        ))
+        .normalized();
+}
+
 // https://www.microsoft.com/en-us/research/wp-content/uploads/1978/01/p286-blinn.pdf
 const BUMP_DELTA: f32 = 0.005_f32; // TODO: Make bump delta dynamic
 fn bump_modify(hit_props: &HitProperties, bump_map: &SyncTexture) -> Vector3 {
     // Get base value of bump at u, v, p
-    let displacement = bump_map.bump_value(&hit_props.uv, &hit_props.hit_point);
+    let displacement = bump_map.bump_value(&hit_props.uv, &hit_props.hit_point, &hit_props.normal);
     // Create partial derivatives for bump
     // by shifting u, v, and p
     let displacement_u = bump_map.bump_value(
         &TexCoord::new(hit_props.uv.u() + BUMP_DELTA, hit_props.uv.v()),
         &(hit_props.hit_point + BUMP_DELTA * hit_props.pu),
+        &hit_props.normal,
     );
     let displacement_v = bump_map.bump_value(
         &TexCoord::new(hit_props.uv.u(), hit_props.uv.v() + BUMP_DELTA),
         &(hit_props.hit_point + BUMP_DELTA * hit_props.pv),
+        &hit_props.normal,
     );
 
     // Determine new Pu and Pv
@@ -53,9 +217,25 @@ fn bump_modify(hit_props: &HitProperties, bump_map: &SyncTexture) -> Vector3 {
     new_pu.cross(new_pv).normalized()
 }
 
+// A material input like roughness read from the brightness of a texture,
+// which is a constant one when the scene gives a number
+fn scalar_input(texture: &SyncTexture, hit_props: &HitProperties) -> f32 {
+    let value = texture
+        .filtered_value(
+            &hit_props.uv,
+            &hit_props.hit_point,
+            &hit_props.normal,
+            hit_props.uv_footprint,
+        )
+        .luminance();
+    return utils::clamp(value, 0.0_f32, 1.0_f32);
+}
+
 pub enum Reflectance {
     Specular(Ray),
     PDF(PDF),
+    // Shaded from direct light alone, see Toon
+    Toon(ToonShading),
 }
 pub struct ScatterProperties {
     pub reflectance: Reflectance,
@@ -78,84 +258,313 @@ pub trait Material {
     // other sources of light. If true, more rays will be sent in this Material's
     // direction during tracing.
     fn is_important(&self) -> bool;
+
+    // The texture emission is read from by surface UVs, if any. Emitters
+    // with one can be sampled in proportion to the texture's brightness.
+    fn emission_texture(&self) -> Option<&Arc<SyncTexture>> {
+        None
+    }
+
+    // Whether emission leaves the back of a surface as well as the front,
+    // the side its normal points out of
+    fn is_two_sided(&self) -> bool {
+        true
+    }
+
+    // Whether the material scatters light the same way at whatever angle it
+    // is seen from, as Lambert does, so the light it sends toward one point
+    // it sends toward any other on the same side. Gradient domain rendering
+    // reconnects neighboring paths at such surfaces.
+    fn is_diffuse(&self) -> bool {
+        false
+    }
+
+    // The material this one varies, for materials made per instance from a
+    // scene's material, see instance::Varied. Settings given to a material
+    // by name, and its statistics, go to the one it varies.
+    fn varies(&self) -> Option<&SyncMaterial> {
+        None
+    }
 }
 pub type SyncMaterial = dyn Material + Send + Sync;
 
+// Which kinds of ray see a material. Rays that do not see it pass through
+// as if it was not there, so a light seen only by camera rays lights nothing,
+// and a backdrop hidden from diffuse rays casts no color onto the scene.
+pub struct Visibility {
+    camera: bool,
+    diffuse: bool,
+    glossy: bool,
+}
+
+impl Visibility {
+    pub fn new(camera: bool, diffuse: bool, glossy: bool) -> Visibility {
+        Visibility {
+            camera: camera,
+            diffuse: diffuse,
+            glossy: glossy,
+        }
+    }
+
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Diffuse => self.diffuse,
+            RayKind::Glossy => self.glossy,
+        }
+    }
+}
+
 pub struct Lambert {
     albedo: Arc<SyncTexture>,
-    bump_map: Option<Arc<SyncTexture>>,
+    perturbation: Option<Perturbation>,
 }
 
 impl Lambert {
-    pub fn new(albedo: Arc<SyncTexture>, bump_map: Option<Arc<SyncTexture>>) -> Lambert {
+    pub fn new(albedo: Arc<SyncTexture>, perturbation: Option<Perturbation>) -> Lambert {
         Lambert {
             albedo: albedo,
-            bump_map: bump_map,
+            perturbation: perturbation,
         }
     }
 }
 
 impl Material for Lambert {
     fn scatter(&self, _in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
-        // Apply bump map if present
-        let bump_modified_normal = match &self.bump_map {
-            None => hit_props.normal,
-            Some(b) => bump_modify(hit_props, &(*(*b))),
-        };
+        // Apply bump or normal map if present
+        let bump_modified_normal = shading_normal(hit_props, &self.perturbation);
 
         Some(ScatterProperties {
             reflectance: Reflectance::PDF(PDF::Cosine(pdf::Cosine::new(bump_modified_normal))),
-            attenuation: self.albedo.value(&hit_props.uv, &hit_props.hit_point),
+            attenuation: self.albedo.filtered_value(
+                &hit_props.uv,
+                &hit_props.hit_point,
+                &hit_props.normal,
+                hit_props.uv_footprint,
+            ),
         })
     }
 
     fn is_important(&self) -> bool {
         false
     }
+
+    fn is_diffuse(&self) -> bool {
+        true
+    }
 }
 
 pub struct Metal {
     albedo: Arc<SyncTexture>,
-    roughness: f32,
-    bump_map: Option<Arc<SyncTexture>>,
+    roughness: Arc<SyncTexture>,
+    perturbation: Option<Perturbation>,
+    thin_film: Option<ThinFilm>,
+    working_space: ColorSpace,
 }
 
 impl Metal {
     pub fn new(
         albedo: Arc<SyncTexture>,
-        roughness: f32,
-        bump_map: Option<Arc<SyncTexture>>,
+        roughness: Arc<SyncTexture>,
+        perturbation: Option<Perturbation>,
     ) -> Metal {
-        // Clamp roughness
-        let mut r = roughness;
-        if r < 0_f32 {
-            r = 0_f32;
-        } else if r > 1_f32 {
-            r = 1_f32;
+        Metal {
+            albedo: albedo,
+            roughness: roughness,
+            perturbation: perturbation,
+            thin_film: None,
+            working_space: ColorSpace::Rec709,
         }
+    }
 
+    pub fn with_thin_film(self, thin_film: Option<ThinFilm>, working_space: ColorSpace) -> Metal {
         Metal {
-            albedo: albedo,
-            roughness: r,
-            bump_map: bump_map,
+            thin_film: thin_film,
+            working_space: working_space,
+            ..self
         }
     }
 }
 
 impl Material for Metal {
     fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
-        // Apply bump map if present
-        let bump_modified_normal = match &self.bump_map {
-            None => hit_props.normal,
-            Some(b) => bump_modify(hit_props, &(*(*b))),
+        // Apply bump or normal map if present
+        let bump_modified_normal = shading_normal(hit_props, &self.perturbation);
+
+        let unit_direction = in_ray.dir.normalized();
+        let reflected = reflect(unit_direction, bump_modified_normal);
+        let roughness = scalar_input(&*self.roughness, hit_props);
+        let out_ray_dir = reflected + roughness * sampling::unit_sphere_random();
+        let albedo = self.albedo.filtered_value(
+            &hit_props.uv,
+            &hit_props.hit_point,
+            &hit_props.normal,
+            hit_props.uv_footprint,
+        );
+
+        let attenuation = match &self.thin_film {
+            None => albedo,
+            Some(film) => {
+                // The albedo is the metal's reflectance under the film, which
+                // the film's interference then tints
+                let base = (albedo.r() + albedo.g() + albedo.b()) / 3.0_f32;
+                if base <= 0.0_f32 {
+                    RGB::black()
+                } else {
+                    let out_unit = out_ray_dir.normalized();
+                    let cos_theta = utils::clamp(
+                        out_unit.dot((out_unit - unit_direction).normalized()),
+                        0.0_f32,
+                        1.0_f32,
+                    );
+                    let tint = self.working_space.from_rec709(film.color(
+                        cos_theta,
+                        1.0_f32,
+                        FilmBase::Mirror(base.sqrt()),
+                        in_ray.wavelength,
+                    ));
+                    let tinted = albedo * tint / base;
+                    RGB::new(
+                        tinted.r().min(1.0_f32),
+                        tinted.g().min(1.0_f32),
+                        tinted.b().min(1.0_f32),
+                    )
+                }
+            }
         };
 
-        let reflected = reflect(in_ray.dir.normalized(), bump_modified_normal);
-        let out_ray_dir = reflected + self.roughness * utils::unit_sphere_random();
+        Some(ScatterProperties {
+            reflectance: Reflectance::Specular(Ray::new(
+                hit_props.hit_point,
+                out_ray_dir,
+                in_ray.time,
+            )),
+            attenuation: attenuation,
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        true
+    }
+}
+
+// Measured metals, with the real (eta) and imaginary (k) parts of their
+// complex refractive indices at the red, green, and blue primaries of
+// Rec. 709
+#[derive(Deserialize, Clone, Copy)]
+pub enum ConductorPreset {
+    Gold,
+    Silver,
+    Copper,
+    Aluminum,
+}
+
+impl ConductorPreset {
+    pub fn eta_k(&self) -> (RGB, RGB) {
+        match self {
+            ConductorPreset::Gold => (
+                RGB::new(0.1431190_f32, 0.3749570_f32, 1.4424786_f32),
+                RGB::new(3.9831604_f32, 2.3857207_f32, 1.6032153_f32),
+            ),
+            ConductorPreset::Silver => (
+                RGB::new(0.1552646_f32, 0.1167233_f32, 0.1383807_f32),
+                RGB::new(4.8283433_f32, 3.1222459_f32, 2.1469504_f32),
+            ),
+            ConductorPreset::Copper => (
+                RGB::new(0.2004377_f32, 0.9240334_f32, 1.1022120_f32),
+                RGB::new(3.9129485_f32, 2.4528477_f32, 2.1421880_f32),
+            ),
+            ConductorPreset::Aluminum => (
+                RGB::new(1.6574600_f32, 0.8803690_f32, 0.5212287_f32),
+                RGB::new(9.2238692_f32, 6.2695232_f32, 4.8370012_f32),
+            ),
+        }
+    }
+}
+
+// Fresnel reflectance of unpolarized light off a conductor with the given
+// complex refractive index, for the cosine between the light and the normal
+// https://seblagarde.wordpress.com/2013/04/29/memo-on-fresnel-equations/
+fn conductor_fresnel(cos_theta: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0_f32 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0_f32 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5_f32 * (a2_plus_b2 + t0)).max(0.0_f32).sqrt();
+    let t2 = 2.0_f32 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    return 0.5_f32 * (rp + rs);
+}
+
+// Conductor
+// A metal colored by its complex refractive index rather than by an albedo,
+// so its reflections are tinted head on and whiten towards grazing angles
+// the way real metals do. Roughness blurs reflections like it does for
+// Metal.
+pub struct Conductor {
+    eta: RGB,
+    k: RGB,
+    roughness: Arc<SyncTexture>,
+    perturbation: Option<Perturbation>,
+    working_space: ColorSpace,
+}
+
+impl Conductor {
+    // Eta and k are for the Rec. 709 primaries, like colors in the scene
+    pub fn new(
+        eta: RGB,
+        k: RGB,
+        roughness: Arc<SyncTexture>,
+        perturbation: Option<Perturbation>,
+        working_space: ColorSpace,
+    ) -> Conductor {
+        Conductor {
+            eta: eta,
+            k: k,
+            roughness: roughness,
+            perturbation: perturbation,
+            working_space: working_space,
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        // Apply bump or normal map if present
+        let bump_modified_normal = shading_normal(hit_props, &self.perturbation);
+
+        let unit_direction = in_ray.dir.normalized();
+        let reflected = reflect(unit_direction, bump_modified_normal);
+        let roughness = scalar_input(&*self.roughness, hit_props);
+        let out_ray_dir = reflected + roughness * sampling::unit_sphere_random();
+
+        // Fresnel is taken about the normal that would reflect the ray into
+        // the direction it went, which roughness moves off the surface normal
+        let out_unit = out_ray_dir.normalized();
+        let cos_theta = utils::clamp(
+            out_unit.dot((out_unit - unit_direction).normalized()),
+            0.0_f32,
+            1.0_f32,
+        );
+        let fresnel = RGB::new(
+            conductor_fresnel(cos_theta, self.eta.r(), self.k.r()),
+            conductor_fresnel(cos_theta, self.eta.g(), self.k.g()),
+            conductor_fresnel(cos_theta, self.eta.b(), self.k.b()),
+        );
 
         Some(ScatterProperties {
-            reflectance: Reflectance::Specular(Ray::new(hit_props.hit_point, out_ray_dir)),
-            attenuation: self.albedo.value(&hit_props.uv, &hit_props.hit_point),
+            reflectance: Reflectance::Specular(Ray::new(
+                hit_props.hit_point,
+                out_ray_dir,
+                in_ray.time,
+            )),
+            attenuation: self.working_space.from_rec709(fresnel),
         })
     }
 
@@ -164,18 +573,729 @@ impl Material for Metal {
     }
 }
 
-#[derive(Deserialize)]
+// Clearcoat
+// A thin clear layer, like varnish or car paint lacquer, over another
+// material. Each scatter reflects off the coat as often as its Fresnel
+// reflectance towards the viewer says, and otherwise goes on to the base,
+// so the coat shows most at grazing angles. The base is seen through the
+// coat unchanged. A bump or normal map moves only the coat, for orange peel,
+// and the base keeps its own.
+pub struct Clearcoat {
+    base: Arc<SyncMaterial>,
+    // Squared roughness of the coat
+    alpha: f32,
+    refractive_index: f32,
+    perturbation: Option<Perturbation>,
+}
+
+impl Clearcoat {
+    pub fn new(
+        base: Arc<SyncMaterial>,
+        roughness: f32,
+        refractive_index: f32,
+        perturbation: Option<Perturbation>,
+    ) -> Clearcoat {
+        let r = utils::clamp(roughness, MIN_GGX_ROUGHNESS, 1.0_f32);
+        Clearcoat {
+            base: base,
+            alpha: r * r,
+            refractive_index: refractive_index,
+            perturbation: perturbation,
+        }
+    }
+}
+
+impl Material for Clearcoat {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        let normal = shading_normal(hit_props, &self.perturbation);
+        let cos_theta = in_ray.dir.normalized().dot(normal).abs();
+        if rng::random() >= schlick(cos_theta, self.refractive_index) {
+            return self.base.scatter(in_ray, hit_props);
+        }
+        // How often the coat is picked already accounts for its Fresnel
+        // reflectance, so the lobe leaves it out
+        Some(ScatterProperties {
+            reflectance: Reflectance::PDF(PDF::GGX(pdf::GGX::new(
+                normal,
+                hit_props.pu,
+                hit_props.pv,
+                in_ray.dir,
+                self.alpha,
+                self.alpha,
+                1.0_f32,
+            ))),
+            attenuation: RGB::new(1.0_f32, 1.0_f32, 1.0_f32),
+        })
+    }
+
+    fn emit(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<RGB> {
+        self.base.emit(in_ray, hit_props)
+    }
+
+    fn is_important(&self) -> bool {
+        self.base.is_important()
+    }
+}
+
+// Metallic flakes
+// The base layer of metallic car paint: glossy metal in the paint's color,
+// sprinkled with tiny mirror-like flakes that each face a slightly
+// different way, so they sparkle in and out as the view or light moves.
+// Flakes fill cells of a grid in world space, and each cell holds a flake
+// or not at random. A paint with a flop color shifts from its color head on
+// to the flop color at grazing angles, like pearlescent and color shifting
+// paints. Car paint is this under a Clearcoat.
+pub struct MetallicFlakes {
+    color: Arc<SyncTexture>,
+    flop_color: Option<Arc<SyncTexture>>,
+    flake_color: Arc<SyncTexture>,
+    // Squared roughness of the paint and of the flakes
+    alpha: f32,
+    flake_alpha: f32,
+    // Size of a grid cell, the share of cells with a flake, and how far
+    // flakes tilt from the surface, as the length of the offset to the normal
+    flake_size: f32,
+    flake_density: f32,
+    flake_tilt: f32,
+    perturbation: Option<Perturbation>,
+}
+
+impl MetallicFlakes {
+    pub fn new(
+        color: Arc<SyncTexture>,
+        flop_color: Option<Arc<SyncTexture>>,
+        flake_color: Arc<SyncTexture>,
+        roughness: f32,
+        flake_roughness: f32,
+        flake_size: f32,
+        flake_density: f32,
+        flake_tilt: f32,
+        perturbation: Option<Perturbation>,
+    ) -> MetallicFlakes {
+        let r = utils::clamp(roughness, MIN_GGX_ROUGHNESS, 1.0_f32);
+        let flake_r = utils::clamp(flake_roughness, MIN_GGX_ROUGHNESS, 1.0_f32);
+        MetallicFlakes {
+            color: color,
+            flop_color: flop_color,
+            flake_color: flake_color,
+            alpha: r * r,
+            flake_alpha: flake_r * flake_r,
+            flake_size: flake_size,
+            flake_density: utils::clamp(flake_density, 0.0_f32, 1.0_f32),
+            flake_tilt: flake_tilt,
+            perturbation: perturbation,
+        }
+    }
+
+    // The offset to the normal of the flake in the cell around the point, if
+    // the cell has one
+    fn flake(&self, p: &Point3) -> Option<Vector3> {
+        let cell = [
+            (p.x() / self.flake_size).floor() as i32,
+            (p.y() / self.flake_size).floor() as i32,
+            (p.z() / self.flake_size).floor() as i32,
+        ];
+        if cell_random(cell, 0) >= self.flake_density {
+            return None;
+        }
+        let offset = Vector3::new(
+            2.0_f32 * cell_random(cell, 1) - 1.0_f32,
+            2.0_f32 * cell_random(cell, 2) - 1.0_f32,
+            2.0_f32 * cell_random(cell, 3) - 1.0_f32,
+        );
+        return Some(offset * self.flake_tilt);
+    }
+}
+
+// A number in [0, 1) that is the same every time for a grid cell and a
+// stream, from an integer hash of them
+fn cell_random(cell: [i32; 3], stream: u32) -> f32 {
+    let mut h = stream.wrapping_mul(0x27d4eb2d);
+    for c in cell.iter() {
+        h = (h ^ (*c as u32)).wrapping_mul(0x9e3779b1);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x85ebca6b);
+        h ^= h >> 13;
+    }
+    return (h >> 8) as f32 / (1 << 24) as f32;
+}
+
+impl Material for MetallicFlakes {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        let point = &hit_props.hit_point;
+        let surface_normal = shading_normal(hit_props, &self.perturbation);
+        let (normal, alpha, color) = match self.flake(point) {
+            Some(offset) => {
+                let normal = (surface_normal + offset).normalized();
+                (
+                    normal,
+                    self.flake_alpha,
+                    self.flake_color.filtered_value(
+                        &hit_props.uv,
+                        point,
+                        &hit_props.normal,
+                        hit_props.uv_footprint,
+                    ),
+                )
+            }
+            None => {
+                let color = self.color.filtered_value(
+                    &hit_props.uv,
+                    point,
+                    &hit_props.normal,
+                    hit_props.uv_footprint,
+                );
+                let color = match &self.flop_color {
+                    Some(flop) => {
+                        let facing = in_ray.dir.normalized().dot(surface_normal).abs();
+                        flop.filtered_value(
+                            &hit_props.uv,
+                            point,
+                            &hit_props.normal,
+                            hit_props.uv_footprint,
+                        ) * (1.0_f32 - facing)
+                            + color * facing
+                    }
+                    None => color,
+                };
+                (surface_normal, self.alpha, color)
+            }
+        };
+        // Metal, which reflects fully at every angle
+        Some(ScatterProperties {
+            reflectance: Reflectance::PDF(PDF::GGX(pdf::GGX::new(
+                normal,
+                hit_props.pu,
+                hit_props.pv,
+                in_ray.dir,
+                alpha,
+                alpha,
+                1.0_f32,
+            ))),
+            attenuation: color,
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        false
+    }
+}
+
+// Measured
+// Reflectance measured off a real material, see the measured module. Half
+// of the directions are sampled from the measurements and half by cosine,
+// and each carries its own color, so lights are only found by the
+// directions sampled, like for Metal.
+pub struct Measured {
+    brdf: Arc<MeasuredBRDF>,
+    perturbation: Option<Perturbation>,
+    working_space: ColorSpace,
+}
+
+impl Measured {
+    pub fn new(
+        brdf: Arc<MeasuredBRDF>,
+        perturbation: Option<Perturbation>,
+        working_space: ColorSpace,
+    ) -> Measured {
+        Measured {
+            brdf: brdf,
+            perturbation: perturbation,
+            working_space: working_space,
+        }
+    }
+}
+
+impl Material for Measured {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        let to_viewer = -in_ray.dir.normalized();
+        let surface_normal = shading_normal(hit_props, &self.perturbation);
+        // Both sides of a surface reflect
+        let normal = if to_viewer.dot(surface_normal) < 0.0_f32 {
+            -surface_normal
+        } else {
+            surface_normal
+        };
+        let basis = OrthonormalBasis::new(&normal);
+        let viewer = basis.to_local(&to_viewer);
+        let out = if rng::random() < 0.5_f32 {
+            sampling::random_cosine_direction()
+        } else {
+            let half = self.brdf.sample_half();
+            2.0_f32 * viewer.dot(half) * half - viewer
+        };
+        if out.z() <= 0.0_f32 {
+            return None;
+        }
+
+        let half = (viewer + out).normalized();
+        let pdf = 0.5_f32 * out.z() / f32::consts::PI
+            + 0.5_f32 * self.brdf.half_pdf(&half) / (4.0_f32 * viewer.dot(half));
+        if !(pdf > 0.0_f32) {
+            return None;
+        }
+        let reflectance = self.brdf.value(&viewer, &out) * (out.z() / pdf);
+        Some(ScatterProperties {
+            reflectance: Reflectance::Specular(Ray::new(
+                hit_props.hit_point,
+                basis.local(&out),
+                in_ray.time,
+            )),
+            attenuation: self.working_space.from_rec709(reflectance),
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        false
+    }
+}
+
+// Smallest roughness Sheen allows, below it the lobe is a sliver at grazing
+// angles that directions sampled by cosine almost never find
+const MIN_SHEEN_ROUGHNESS: f32 = 0.07_f32;
+
+// Sheen
+// Velvet and other cloth, a sheen lobe that brightens towards grazing angles
+// over an optional diffuse base. The two are added together, by picking one
+// of them at random for each scatter and doubling it.
+pub struct Sheen {
+    albedo: Option<Arc<SyncTexture>>,
+    sheen: Arc<SyncTexture>,
+    alpha: f32,
+    perturbation: Option<Perturbation>,
+}
+
+impl Sheen {
+    pub fn new(
+        albedo: Option<Arc<SyncTexture>>,
+        sheen: Arc<SyncTexture>,
+        roughness: f32,
+        perturbation: Option<Perturbation>,
+    ) -> Sheen {
+        let r = utils::clamp(roughness, MIN_SHEEN_ROUGHNESS, 1.0_f32);
+        Sheen {
+            albedo: albedo,
+            sheen: sheen,
+            alpha: r * r,
+            perturbation: perturbation,
+        }
+    }
+}
+
+impl Material for Sheen {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        // Apply bump or normal map if present
+        let bump_modified_normal = shading_normal(hit_props, &self.perturbation);
+
+        match &self.albedo {
+            Some(albedo) if rng::random() < 0.5_f32 => {
+                return Some(ScatterProperties {
+                    reflectance: Reflectance::PDF(PDF::Cosine(pdf::Cosine::new(
+                        bump_modified_normal,
+                    ))),
+                    attenuation: albedo.filtered_value(
+                        &hit_props.uv,
+                        &hit_props.hit_point,
+                        &hit_props.normal,
+                        hit_props.uv_footprint,
+                    ) * 2.0_f32,
+                });
+            }
+            _ => {}
+        }
+        let sheen = self.sheen.filtered_value(
+            &hit_props.uv,
+            &hit_props.hit_point,
+            &hit_props.normal,
+            hit_props.uv_footprint,
+        );
+        Some(ScatterProperties {
+            reflectance: Reflectance::PDF(PDF::Sheen(pdf::Sheen::new(
+                bump_modified_normal,
+                in_ray.dir,
+                self.alpha,
+            ))),
+            attenuation: match &self.albedo {
+                Some(_) => sheen * 2.0_f32,
+                None => sheen,
+            },
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        false
+    }
+}
+
+// Toon
+// Cel shading, for stylized renders of the same scenes. Direct light from
+// the scene's Lights list is cut into a few flat bands of brightness, and
+// surfaces seen edge on get a hard rim of color. Light from emissive shapes
+// and from other surfaces is left out, so only the Lights list lights it.
+pub struct Toon {
+    albedo: Arc<SyncTexture>,
+    bands: u32,
+    rim_color: RGB,
+    // How edge on a surface must be seen for the rim, from the cosine
+    // between the normal and the viewer
+    rim_width: f32,
+    perturbation: Option<Perturbation>,
+}
+
+impl Toon {
+    pub fn new(
+        albedo: Arc<SyncTexture>,
+        bands: u32,
+        rim_color: RGB,
+        rim_width: f32,
+        perturbation: Option<Perturbation>,
+    ) -> Toon {
+        Toon {
+            albedo: albedo,
+            bands: bands.max(1),
+            rim_color: rim_color,
+            rim_width: rim_width,
+            perturbation: perturbation,
+        }
+    }
+}
+
+impl Material for Toon {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        let to_viewer = -in_ray.dir.normalized();
+        let surface_normal = shading_normal(hit_props, &self.perturbation);
+        let normal = if to_viewer.dot(surface_normal) < 0.0_f32 {
+            -surface_normal
+        } else {
+            surface_normal
+        };
+        let rim = if to_viewer.dot(normal) < self.rim_width {
+            self.rim_color
+        } else {
+            RGB::black()
+        };
+        Some(ScatterProperties {
+            reflectance: Reflectance::Toon(ToonShading {
+                normal: normal,
+                bands: self.bands,
+                rim: rim,
+            }),
+            attenuation: self.albedo.filtered_value(
+                &hit_props.uv,
+                &hit_props.hit_point,
+                &hit_props.normal,
+                hit_props.uv_footprint,
+            ),
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        false
+    }
+}
+
+pub struct ToonShading {
+    // Facing the viewer
+    pub normal: Vector3,
+    bands: u32,
+    // Added over the banded light
+    pub rim: RGB,
+}
+
+impl ToonShading {
+    // The light with its brightness rounded up to the next band, keeping its
+    // color. A brightness of 1, which a Lambert of the same albedo would
+    // show at full albedo, is the brightest band.
+    pub fn banded(&self, light: RGB) -> RGB {
+        let brightness = light.luminance();
+        if brightness <= 0.0_f32 {
+            return RGB::black();
+        }
+        let bands = self.bands as f32;
+        let band = (brightness * bands).ceil().min(bands) / bands;
+        return light * (band / brightness);
+    }
+}
+
+// Smallest roughness GGX allows, below it the distribution is too sharp
+// for single precision
+const MIN_GGX_ROUGHNESS: f32 = 0.02_f32;
+
+// GGX
+// Glossy reflection off microscopic facets, with a roughness and an index
+// of refraction for the Fresnel term. Unlike Metal, reflected directions are
+// importance sampled from the GGX distribution and weighted by the BRDF, so
+// lights and the sky are blurred consistently by the roughness. The
+// roughness can differ along the surface's u and v texture directions,
+// which streaks highlights across the direction that is rougher, like
+// brushed metal.
+pub struct GGX {
+    albedo: Arc<SyncTexture>,
+    // Roughness along u and v, squared for the alphas of the distribution
+    roughness_u: Arc<SyncTexture>,
+    roughness_v: Arc<SyncTexture>,
+    f0: f32,
+    perturbation: Option<Perturbation>,
+}
+
+impl GGX {
+    pub fn new(
+        albedo: Arc<SyncTexture>,
+        roughness_u: Arc<SyncTexture>,
+        roughness_v: Arc<SyncTexture>,
+        refractive_index: f32,
+        perturbation: Option<Perturbation>,
+    ) -> GGX {
+        let f0 = (refractive_index - 1.0_f32) / (refractive_index + 1.0_f32);
+        GGX {
+            albedo: albedo,
+            roughness_u: roughness_u,
+            roughness_v: roughness_v,
+            f0: f0 * f0,
+            perturbation: perturbation,
+        }
+    }
+}
+
+impl Material for GGX {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        // Apply bump or normal map if present
+        let bump_modified_normal = shading_normal(hit_props, &self.perturbation);
+        let r_u = scalar_input(&*self.roughness_u, hit_props).max(MIN_GGX_ROUGHNESS);
+        let r_v = scalar_input(&*self.roughness_v, hit_props).max(MIN_GGX_ROUGHNESS);
+
+        Some(ScatterProperties {
+            reflectance: Reflectance::PDF(PDF::GGX(pdf::GGX::new(
+                bump_modified_normal,
+                hit_props.pu,
+                hit_props.pv,
+                in_ray.dir,
+                r_u * r_u,
+                r_v * r_v,
+                self.f0,
+            ))),
+            attenuation: self.albedo.filtered_value(
+                &hit_props.uv,
+                &hit_props.hit_point,
+                &hit_props.normal,
+                hit_props.uv_footprint,
+            ),
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        false
+    }
+}
+
+// Metallic roughness
+// The metallic roughness model of glTF and most texture painting tools, in
+// which metalness and roughness textures say how each part of one surface
+// reflects. Each scatter is metallic as often as the metalness says, a GGX
+// reflection tinted by the base color. Otherwise it is dielectric: an
+// untinted GGX reflection as often as its Fresnel reflectance says, like
+// Clearcoat, and diffuse reflection in the base color the rest of the time.
+pub struct MetallicRoughness {
+    base_color: Arc<SyncTexture>,
+    metalness: Arc<SyncTexture>,
+    roughness: Arc<SyncTexture>,
+    refractive_index: f32,
+    perturbation: Option<Perturbation>,
+}
+
+impl MetallicRoughness {
+    pub fn new(
+        base_color: Arc<SyncTexture>,
+        metalness: Arc<SyncTexture>,
+        roughness: Arc<SyncTexture>,
+        refractive_index: f32,
+        perturbation: Option<Perturbation>,
+    ) -> MetallicRoughness {
+        MetallicRoughness {
+            base_color: base_color,
+            metalness: metalness,
+            roughness: roughness,
+            refractive_index: refractive_index,
+            perturbation: perturbation,
+        }
+    }
+}
+
+impl Material for MetallicRoughness {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        let normal = shading_normal(hit_props, &self.perturbation);
+        let base_color = self.base_color.filtered_value(
+            &hit_props.uv,
+            &hit_props.hit_point,
+            &hit_props.normal,
+            hit_props.uv_footprint,
+        );
+        let r = scalar_input(&*self.roughness, hit_props).max(MIN_GGX_ROUGHNESS);
+        // How often each lobe is picked already accounts for its Fresnel
+        // reflectance, so the lobes leave it out
+        let glossy = |attenuation: RGB| {
+            Some(ScatterProperties {
+                reflectance: Reflectance::PDF(PDF::GGX(pdf::GGX::new(
+                    normal,
+                    hit_props.pu,
+                    hit_props.pv,
+                    in_ray.dir,
+                    r * r,
+                    r * r,
+                    1.0_f32,
+                ))),
+                attenuation: attenuation,
+            })
+        };
+
+        if rng::random() < scalar_input(&*self.metalness, hit_props) {
+            return glossy(base_color);
+        }
+        let cos_theta = in_ray.dir.normalized().dot(normal).abs();
+        if rng::random() < schlick(cos_theta, self.refractive_index) {
+            return glossy(RGB::new(1.0_f32, 1.0_f32, 1.0_f32));
+        }
+        Some(ScatterProperties {
+            reflectance: Reflectance::PDF(PDF::Cosine(pdf::Cosine::new(normal))),
+            attenuation: base_color,
+        })
+    }
+
+    fn is_important(&self) -> bool {
+        false
+    }
+}
+
+// How a refractive index changes with the wavelength of light, with
+// wavelengths in micrometers as the coefficients are usually given
+pub enum Dispersion {
+    // n = A + B / λ^2 + C / λ^4 + ..., from the coefficients A, B, C, ...
+    Cauchy(Vec<f32>),
+    // n^2 = 1 + Σ B_i λ^2 / (λ^2 - C_i), from the B and C coefficients
+    Sellmeier(Vec<f32>, Vec<f32>),
+}
+
+impl Dispersion {
+    pub fn refractive_index(&self, wavelength: f32) -> f32 {
+        let micrometers = wavelength * 0.001_f32;
+        let squared = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy(coefficients) => {
+                let mut n = 0.0_f32;
+                let mut power = 1.0_f32;
+                for coefficient in coefficients {
+                    n += coefficient / power;
+                    power *= squared;
+                }
+                return n;
+            }
+            Dispersion::Sellmeier(b, c) => {
+                let mut n_squared = 1.0_f32;
+                for (b_i, c_i) in b.iter().zip(c.iter()) {
+                    n_squared += b_i * squared / (squared - c_i);
+                }
+                return n_squared.max(1.0_f32).sqrt();
+            }
+        }
+    }
+}
+
+// Dielectric
+// Glass and other clear materials. A dispersive one bends each wavelength
+// by a different amount, and picks a wavelength for paths through it that
+// do not have one, weighting them by the color of that wavelength, so
+// prisms and gemstones split white light into its colors.
 pub struct Dielectric {
     refractive_index: f32,
+    dispersion: Option<Dispersion>,
+    thin_film: Option<ThinFilm>,
+    perturbation: Option<Perturbation>,
+    working_space: ColorSpace,
+}
+
+impl Dielectric {
+    pub fn new(refractive_index: f32) -> Dielectric {
+        Dielectric {
+            refractive_index: refractive_index,
+            dispersion: None,
+            thin_film: None,
+            perturbation: None,
+            working_space: ColorSpace::Rec709,
+        }
+    }
+
+    pub fn dispersive(dispersion: Dispersion, working_space: ColorSpace) -> Dielectric {
+        Dielectric {
+            refractive_index: dispersion
+                .refractive_index(0.5_f32 * (spectrum::MIN_WAVELENGTH + spectrum::MAX_WAVELENGTH)),
+            dispersion: Some(dispersion),
+            thin_film: None,
+            perturbation: None,
+            working_space: working_space,
+        }
+    }
+
+    pub fn with_thin_film(
+        self,
+        thin_film: Option<ThinFilm>,
+        working_space: ColorSpace,
+    ) -> Dielectric {
+        Dielectric {
+            thin_film: thin_film,
+            working_space: working_space,
+            ..self
+        }
+    }
+
+    pub fn with_perturbation(self, perturbation: Option<Perturbation>) -> Dielectric {
+        Dielectric {
+            perturbation: perturbation,
+            ..self
+        }
+    }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
-        let attenuation = RGB::new(1.0_f32, 1.0_f32, 1.0_f32); // Attenuation is perfect
-        let (etai_over_etat, normal_for_use) = if in_ray.dir.dot(hit_props.normal) < 0.0_f32 {
-            (1.0 / self.refractive_index, hit_props.normal)
+        let (refractive_index, wavelength, attenuation) =
+            match (&self.dispersion, in_ray.wavelength) {
+                (None, w) => (
+                    self.refractive_index,
+                    w,
+                    RGB::new(1.0_f32, 1.0_f32, 1.0_f32),
+                ),
+                (Some(d), Some(w)) => (
+                    d.refractive_index(w),
+                    Some(w),
+                    RGB::new(1.0_f32, 1.0_f32, 1.0_f32),
+                ),
+                (Some(d), None) => {
+                    let w = spectrum::sample_wavelength();
+                    (
+                        d.refractive_index(w),
+                        Some(w),
+                        self.working_space
+                            .from_rec709(spectrum::wavelength_weight(w)),
+                    )
+                }
+            };
+        let (etai_over_etat, geometric_normal) = if in_ray.dir.dot(hit_props.normal) < 0.0_f32 {
+            (1.0 / refractive_index, hit_props.normal)
+        } else {
+            (refractive_index, -hit_props.normal)
+        };
+        // A bump or normal map turns the normal on the side the ray is on,
+        // unless it would turn it away from the ray
+        let shading = shading_normal(hit_props, &self.perturbation);
+        let shading = if shading.dot(geometric_normal) < 0.0_f32 {
+            -shading
         } else {
-            (self.refractive_index, -hit_props.normal)
+            shading
+        };
+        let normal_for_use = if in_ray.dir.dot(shading) < 0.0_f32 {
+            shading
+        } else {
+            geometric_normal
         };
 
         let unit_direction = in_ray.dir.normalized();
@@ -185,24 +1305,66 @@ impl Material for Dielectric {
         if etai_over_etat * sin_theta > 1.0_f32 {
             let reflected = reflect(unit_direction, normal_for_use);
             return Some(ScatterProperties {
-                reflectance: Reflectance::Specular(Ray::new(hit_props.hit_point, reflected)),
+                reflectance: Reflectance::Specular(
+                    Ray::new(hit_props.hit_point, reflected, in_ray.time)
+                        .with_wavelength(wavelength),
+                ),
                 attenuation: attenuation,
             });
         }
 
-        let reflect_prob = schlick(cos_theta, etai_over_etat);
-        if rand::random::<f32>() < reflect_prob {
+        // A thin film reflects each color by a different amount, so the
+        // chance of reflecting is the average, and each color is weighted by
+        // how much more or less likely it was than that
+        let (reflect_prob, reflect_weight, refract_weight) = match &self.thin_film {
+            None => (
+                schlick(cos_theta, etai_over_etat),
+                RGB::new(1.0_f32, 1.0_f32, 1.0_f32),
+                RGB::new(1.0_f32, 1.0_f32, 1.0_f32),
+            ),
+            Some(film) => {
+                // The film is on the outside of the surface
+                let (outer, base) = if etai_over_etat < 1.0_f32 {
+                    (1.0_f32, refractive_index)
+                } else {
+                    (refractive_index, 1.0_f32)
+                };
+                let reflectance = self.working_space.from_rec709(film.color(
+                    cos_theta,
+                    outer,
+                    FilmBase::Clear(base),
+                    wavelength,
+                ));
+                let prob = utils::clamp(
+                    (reflectance.r() + reflectance.g() + reflectance.b()) / 3.0_f32,
+                    0.001_f32,
+                    0.999_f32,
+                );
+                let transmittance = RGB::new(
+                    (1.0_f32 - reflectance.r()).max(0.0_f32),
+                    (1.0_f32 - reflectance.g()).max(0.0_f32),
+                    (1.0_f32 - reflectance.b()).max(0.0_f32),
+                );
+                (prob, reflectance / prob, transmittance / (1.0_f32 - prob))
+            }
+        };
+        if rng::random() < reflect_prob {
             let reflected = reflect(unit_direction, normal_for_use);
             return Some(ScatterProperties {
-                reflectance: Reflectance::Specular(Ray::new(hit_props.hit_point, reflected)),
-                attenuation: attenuation,
+                reflectance: Reflectance::Specular(
+                    Ray::new(hit_props.hit_point, reflected, in_ray.time)
+                        .with_wavelength(wavelength),
+                ),
+                attenuation: attenuation * reflect_weight,
             });
         }
 
         let refracted = refract(unit_direction, normal_for_use, etai_over_etat);
         return Some(ScatterProperties {
-            reflectance: Reflectance::Specular(Ray::new(hit_props.hit_point, refracted)),
-            attenuation: attenuation,
+            reflectance: Reflectance::Specular(
+                Ray::new(hit_props.hit_point, refracted, in_ray.time).with_wavelength(wavelength),
+            ),
+            attenuation: attenuation * refract_weight,
         });
     }
 
@@ -211,13 +1373,31 @@ impl Material for Dielectric {
     }
 }
 
+// Diffuse light
+// Emits the emission texture's color scaled by an intensity, so a light can
+// be made bright without HDR textures. A falloff above 0 dims the light
+// away from head on, by the cosine to the normal to that power, and a light
+// that is not two sided is dark from behind.
 pub struct DiffuseLight {
     emission: Arc<SyncTexture>,
+    intensity: f32,
+    two_sided: bool,
+    falloff: f32,
 }
 
 impl DiffuseLight {
-    pub fn new(emission: Arc<SyncTexture>) -> DiffuseLight {
-        DiffuseLight { emission: emission }
+    pub fn new(
+        emission: Arc<SyncTexture>,
+        intensity: f32,
+        two_sided: bool,
+        falloff: f32,
+    ) -> DiffuseLight {
+        DiffuseLight {
+            emission: emission,
+            intensity: intensity,
+            two_sided: two_sided,
+            falloff: falloff,
+        }
     }
 }
 
@@ -226,11 +1406,170 @@ impl Material for DiffuseLight {
         None
     }
 
-    fn emit(&self, _in_ray: &Ray, hit_props: &HitProperties) -> Option<RGB> {
-        Some(self.emission.value(&hit_props.uv, &hit_props.hit_point))
+    fn emit(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<RGB> {
+        let mut strength = self.intensity;
+        if self.falloff > 0.0_f32 {
+            let cos_theta = in_ray
+                .dir
+                .normalized()
+                .dot(hit_props.normal.normalized())
+                .abs();
+            strength *= cos_theta.powf(self.falloff);
+        }
+        Some(
+            self.emission.filtered_value(
+                &hit_props.uv,
+                &hit_props.hit_point,
+                &hit_props.normal,
+                hit_props.uv_footprint,
+            ) * strength,
+        )
+    }
+
+    fn is_important(&self) -> bool {
+        true
+    }
+
+    fn emission_texture(&self) -> Option<&Arc<SyncTexture>> {
+        Some(&self.emission)
+    }
+
+    fn is_two_sided(&self) -> bool {
+        self.two_sided
+    }
+}
+
+// A DiffuseLight that only emits within a cone around a direction. An optional
+// gobo (or cookie) texture is projected across the cone, and is evaluated by
+// the direction the light leaves the surface in, rather than by surface UVs.
+pub struct SpotLight {
+    emission: Arc<SyncTexture>,
+    basis: OrthonormalBasis,
+    cos_cone: f32,
+    cos_falloff: f32,
+    tan_cone: f32,
+    gobo: Option<Arc<SyncTexture>>,
+}
+
+impl SpotLight {
+    pub fn new(
+        emission: Arc<SyncTexture>,
+        direction: &Vector3,
+        cone_angle: f32,
+        falloff_angle: f32,
+        gobo: Option<Arc<SyncTexture>>,
+    ) -> SpotLight {
+        // Keep the cone within a hemisphere, and the falloff within the cone
+        let cone = utils::clamp(cone_angle, 0.0_f32, 89.9_f32).to_radians();
+        let falloff = utils::clamp(falloff_angle.to_radians(), 0.0_f32, cone);
+
+        SpotLight {
+            emission: emission,
+            basis: OrthonormalBasis::new(&direction.normalized()),
+            cos_cone: cone.cos(),
+            cos_falloff: falloff.cos(),
+            tan_cone: cone.tan(),
+            gobo: gobo,
+        }
+    }
+}
+
+impl Material for SpotLight {
+    fn scatter(&self, _in_ray: &Ray, _hit_props: &HitProperties) -> Option<ScatterProperties> {
+        None
+    }
+
+    fn emit(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<RGB> {
+        // Light travels back along the incoming ray
+        let local_dir = self.basis.to_local(&(-in_ray.dir.normalized()));
+        let cosine = local_dir.z();
+        if cosine <= self.cos_cone {
+            return Some(RGB::black());
+        }
+
+        let mut emitted = self.emission.filtered_value(
+            &hit_props.uv,
+            &hit_props.hit_point,
+            &hit_props.normal,
+            hit_props.uv_footprint,
+        );
+        if cosine < self.cos_falloff {
+            emitted = emitted * utils::smoothstep(self.cos_cone, self.cos_falloff, cosine);
+        }
+
+        match &self.gobo {
+            None => {}
+            Some(g) => {
+                // Project the direction onto the plane one unit along the spot
+                // direction, so the edges of the cone map to the edges of the
+                // texture.
+                let projected_scale = 1.0_f32 / (cosine * self.tan_cone);
+                let gobo_uv = TexCoord::new(
+                    0.5_f32 * (local_dir.x() * projected_scale + 1.0_f32),
+                    0.5_f32 * (local_dir.y() * projected_scale + 1.0_f32),
+                )
+                .clamp_to_valid_coords();
+                emitted = emitted * g.value(&gobo_uv, &hit_props.hit_point);
+            }
+        }
+
+        Some(emitted)
     }
 
     fn is_important(&self) -> bool {
         true
     }
 }
+
+// Blend
+// Combines two materials, picking one of them at random for each scatter,
+// the top one as often as the mask's brightness at the hit says, from 0 for
+// always the base to 1 for always the top. Rust over metal, worn paint and
+// decals are a base and a top with a mask painted for them. Emission, which
+// is not sampled, is mixed by the mask instead.
+pub struct Blend {
+    base: Arc<SyncMaterial>,
+    top: Arc<SyncMaterial>,
+    mask: Arc<SyncTexture>,
+}
+
+impl Blend {
+    pub fn new(base: Arc<SyncMaterial>, top: Arc<SyncMaterial>, mask: Arc<SyncTexture>) -> Blend {
+        Blend {
+            base: base,
+            top: top,
+            mask: mask,
+        }
+    }
+
+    fn top_share(&self, hit_props: &HitProperties) -> f32 {
+        return scalar_input(&*self.mask, hit_props);
+    }
+}
+
+impl Material for Blend {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+        if rng::random() < self.top_share(hit_props) {
+            return self.top.scatter(in_ray, hit_props);
+        }
+        return self.base.scatter(in_ray, hit_props);
+    }
+
+    fn emit(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<RGB> {
+        let share = self.top_share(hit_props);
+        let (base, top) = (
+            self.base.emit(in_ray, hit_props),
+            self.top.emit(in_ray, hit_props),
+        );
+        match (base, top) {
+            (None, None) => None,
+            (Some(b), None) => Some(b * (1.0_f32 - share)),
+            (None, Some(t)) => Some(t * share),
+            (Some(b), Some(t)) => Some(b * (1.0_f32 - share) + t * share),
+        }
+    }
+
+    fn is_important(&self) -> bool {
+        self.base.is_important() || self.top.is_important()
+    }
+}
@@ -1,7 +1,9 @@
 use crate::point::Point3;
 use crate::ray::Ray;
+use crate::utils;
 use crate::vector::Vector3;
 
+use std::hash::{Hash, Hasher};
 use std::ops;
 
 pub struct Matrix4 {
@@ -15,6 +17,18 @@ impl Clone for Matrix4 {
     }
 }
 
+// By the bits of each entry, so matrices that hash the same transform
+// points the same
+impl Hash for Matrix4 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for row in &self.data {
+            for v in row {
+                v.to_bits().hash(state);
+            }
+        }
+    }
+}
+
 impl Matrix4 {
     pub fn new() -> Matrix4 {
         Matrix4 {
@@ -83,6 +97,18 @@ impl Matrix4 {
         m
     }
 
+    // Interpolates each element between two matrices, so any point they
+    // transform moves along a straight line as 't' goes from 0 to 1.
+    pub fn lerp(t: f32, a: &Matrix4, b: &Matrix4) -> Matrix4 {
+        let mut m = Matrix4::new();
+        for row in 0..4 {
+            for col in 0..4 {
+                m.data[row][col] = utils::lerp(t, a.data[row][col], b.data[row][col]);
+            }
+        }
+        m
+    }
+
     // Gauss-Jordan Elimination
     // from https://www.scratchapixel.com/lessons/mathematics-physics-for-computer-graphics/matrix-inverse
     pub fn inverse(&self) -> Result<Matrix4, &'static str> {
@@ -132,6 +158,16 @@ impl Matrix4 {
         }
         return Ok(res);
     }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = Matrix4::new();
+        for row in 0..4 {
+            for col in 0..4 {
+                m.data[row][col] = self.data[col][row];
+            }
+        }
+        m
+    }
 }
 
 // TODO (performance): Use SIMD?
@@ -185,6 +221,6 @@ impl ops::Mul<Point3> for &Matrix4 {
 impl ops::Mul<&Ray> for &Matrix4 {
     type Output = Ray;
     fn mul(self, rhs: &Ray) -> Ray {
-        Ray::new(self * rhs.origin, self * rhs.dir)
+        Ray::new(self * rhs.origin, self * rhs.dir, rhs.time).continuing(rhs)
     }
 }
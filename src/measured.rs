@@ -0,0 +1,193 @@
+use crate::color::RGB;
+use crate::rng;
+use crate::utils;
+use crate::vector::Vector3;
+
+use std::{f32, fs, path::Path};
+
+// Measured isotropic BRDFs in the format of the MERL BRDF database
+// (Matusik, Pfister, Brand, and McMillan 2003). Reflectance is tabulated by
+// the half vector's angle to the normal, and by the angles of the light
+// about the half vector, with the half angle sampled more finely near the
+// normal where highlights are.
+// https://www.merl.com/brdf/
+pub struct MeasuredBRDF {
+    // Linear Rec. 709 reflectance for each table entry
+    values: Vec<RGB>,
+    // Running totals of the chance of sampling each half angle, for
+    // importance sampling half vectors from the table
+    half_angle_cdf: Vec<f32>,
+}
+
+const THETA_H_RES: usize = 90;
+const THETA_D_RES: usize = 90;
+const PHI_D_RES: usize = 180;
+const TABLE_SIZE: usize = THETA_H_RES * THETA_D_RES * PHI_D_RES;
+
+// The database scales each channel by these to store them
+const RED_SCALE: f64 = 1.0_f64 / 1500.0_f64;
+const GREEN_SCALE: f64 = 1.15_f64 / 1500.0_f64;
+const BLUE_SCALE: f64 = 1.66_f64 / 1500.0_f64;
+
+impl MeasuredBRDF {
+    pub fn load(path: &Path) -> Result<MeasuredBRDF, String> {
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read measured BRDF {}: {}",
+                    path.display(),
+                    e
+                ))
+            }
+        };
+        if bytes.starts_with(b"tensor_file") {
+            return Err(format!(
+                "Measured BRDF {} is an RGL tensor file, only MERL files are supported.",
+                path.display()
+            ));
+        }
+        if bytes.len() < 12 {
+            return Err(format!(
+                "Measured BRDF {} is too short to be a MERL file.",
+                path.display()
+            ));
+        }
+        let dims: Vec<usize> = bytes[0..12]
+            .chunks(4)
+            .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize)
+            .collect();
+        if dims[0] * dims[1] * dims[2] != TABLE_SIZE || bytes.len() != 12 + TABLE_SIZE * 3 * 8 {
+            return Err(format!(
+                "Measured BRDF {} does not have the dimensions of a MERL file.",
+                path.display()
+            ));
+        }
+        let channel = |c: usize, scale: f64| {
+            let start = 12 + c * TABLE_SIZE * 8;
+            bytes[start..start + TABLE_SIZE * 8]
+                .chunks(8)
+                .map(|b| {
+                    let v = f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+                    // Entries below the horizon are stored as negative
+                    (v * scale).max(0.0_f64) as f32
+                })
+                .collect::<Vec<f32>>()
+        };
+        let (red, green, blue) = (
+            channel(0, RED_SCALE),
+            channel(1, GREEN_SCALE),
+            channel(2, BLUE_SCALE),
+        );
+        let values: Vec<RGB> = (0..TABLE_SIZE)
+            .map(|i| RGB::new(red[i], green[i], blue[i]))
+            .collect();
+
+        // Each half angle is as likely as its average brightness over the
+        // light's angles, times the solid angle of its band of half vectors
+        let mut half_angle_cdf = Vec::with_capacity(THETA_H_RES);
+        let mut total = 0.0_f32;
+        let per_half_angle = THETA_D_RES * PHI_D_RES;
+        for i in 0..THETA_H_RES {
+            let slice = &values[i * per_half_angle..(i + 1) * per_half_angle];
+            let brightness =
+                slice.iter().map(|v| v.luminance()).sum::<f32>() / per_half_angle as f32;
+            total += brightness * band_solid_angle(i);
+            half_angle_cdf.push(total);
+        }
+        if total > 0.0_f32 {
+            for c in half_angle_cdf.iter_mut() {
+                *c /= total;
+            }
+        }
+        return Ok(MeasuredBRDF {
+            values: values,
+            half_angle_cdf: half_angle_cdf,
+        });
+    }
+
+    // The BRDF for directions towards the viewer and towards the light, in a
+    // frame with the normal along Z
+    pub fn value(&self, to_viewer: &Vector3, to_light: &Vector3) -> RGB {
+        if to_viewer.z() <= 0.0_f32 || to_light.z() <= 0.0_f32 {
+            return RGB::black();
+        }
+        let half = (*to_viewer + *to_light).normalized();
+        let theta_h = utils::clamp(half.z(), -1.0_f32, 1.0_f32).acos();
+        let phi_h = half.y().atan2(half.x());
+        // The light as seen from the half vector
+        let normal = Vector3::new(0.0_f32, 0.0_f32, 1.0_f32);
+        let bi_normal = Vector3::new(0.0_f32, 1.0_f32, 0.0_f32);
+        let diff = rotate(&rotate(to_light, &normal, -phi_h), &bi_normal, -theta_h);
+        let theta_d = utils::clamp(diff.z(), -1.0_f32, 1.0_f32).acos();
+        let mut phi_d = diff.y().atan2(diff.x());
+        // Reciprocity makes the table symmetric about the plane of incidence
+        if phi_d < 0.0_f32 {
+            phi_d += f32::consts::PI;
+        }
+
+        let theta_h_index = theta_half_index(theta_h);
+        let theta_d_index =
+            ((theta_d / f32::consts::FRAC_PI_2 * THETA_D_RES as f32) as usize).min(THETA_D_RES - 1);
+        let phi_d_index =
+            ((phi_d / f32::consts::PI * PHI_D_RES as f32) as usize).min(PHI_D_RES - 1);
+        return self.values
+            [(theta_h_index * THETA_D_RES + theta_d_index) * PHI_D_RES + phi_d_index];
+    }
+
+    // A half vector importance sampled from the table, in the frame with the
+    // normal along Z
+    pub fn sample_half(&self) -> Vector3 {
+        let r = rng::random();
+        let band = match self.half_angle_cdf.iter().position(|c| r < *c) {
+            Some(b) => b,
+            None => THETA_H_RES - 1,
+        };
+        // Uniform over the band's solid angle
+        let (cos_start, cos_end) = (band_start(band).cos(), band_start(band + 1).cos());
+        let cos_theta = utils::lerp(rng::random(), cos_start, cos_end);
+        let sin_theta = (1.0_f32 - cos_theta * cos_theta).max(0.0_f32).sqrt();
+        let phi = 2.0_f32 * f32::consts::PI * rng::random();
+        return Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+    }
+
+    // The solid angle density of sample_half giving the half vector
+    pub fn half_pdf(&self, half: &Vector3) -> f32 {
+        if half.z() <= 0.0_f32 {
+            return 0.0_f32;
+        }
+        let band = theta_half_index(half.z().min(1.0_f32).acos());
+        let chance = if band == 0 {
+            self.half_angle_cdf[0]
+        } else {
+            self.half_angle_cdf[band] - self.half_angle_cdf[band - 1]
+        };
+        return chance / band_solid_angle(band);
+    }
+}
+
+// Half angles are stored by the square root of their share of a right
+// angle
+fn theta_half_index(theta_h: f32) -> usize {
+    if theta_h <= 0.0_f32 {
+        return 0;
+    }
+    let index = (theta_h / f32::consts::FRAC_PI_2).sqrt() * THETA_H_RES as f32;
+    return (index as usize).min(THETA_H_RES - 1);
+}
+
+// The half angle where a band of the table starts
+fn band_start(band: usize) -> f32 {
+    let share = band as f32 / THETA_H_RES as f32;
+    return share * share * f32::consts::FRAC_PI_2;
+}
+
+fn band_solid_angle(band: usize) -> f32 {
+    return 2.0_f32 * f32::consts::PI * (band_start(band).cos() - band_start(band + 1).cos());
+}
+
+// Rotates the vector about the axis by the angle, with Rodrigues' formula
+fn rotate(v: &Vector3, axis: &Vector3, angle: f32) -> Vector3 {
+    let (sin, cos) = angle.sin_cos();
+    return *v * cos + *axis * (axis.dot(*v) * (1.0_f32 - cos)) + axis.cross(*v) * sin;
+}
@@ -0,0 +1,312 @@
+use crate::matrix::Matrix4;
+use crate::objstream::ObjObject;
+use crate::point::Point3;
+use crate::projection::UVProjection;
+use crate::texture::TexCoord;
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Keeps the objects of meshes on disk as they are once read, transformed,
+// and given projected texture coordinates, in files named by a hash of
+// everything that went into them: the contents of the OBJ file, the
+// transform, and the UV projection. Other scenes, and the other frames of
+// an animation, that use the same mesh the same way read the objects back
+// rather than redoing the work, and changing any of those makes a new file.
+// Materials are looked up again each time, so they can change freely.
+//
+// Cache file format, all values little endian:
+// magic, version (u32), key (u64), then objects, each a tag (u8, 1) then
+// its name, material library, vertices, texture coordinates, faces, and
+// material names, ending with a tag of 0. Strings are a length (u64) and
+// that many bytes of UTF-8, optional strings a u8 of 1 before those or just
+// a 0, and lists a count (u64) before their items. Vertices are 3 x f32,
+// texture coordinates 2 x f32, and faces three corners of a vertex index
+// (u64) and a texture coordinate index (u64, u64::MAX for none), then the
+// index (u64) of their material name.
+const MESH_CACHE_MAGIC: &[u8; 6] = b"RTMESH";
+const MESH_CACHE_VERSION: u32 = 1;
+
+const NO_TEX_COORD: u64 = std::u64::MAX;
+
+// Hashes the OBJ file a block at a time, along with the settings that
+// change how its objects come out
+pub fn key(
+    obj_path: &Path,
+    local_to_world: &Matrix4,
+    uv_projection: Option<UVProjection>,
+) -> io::Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    let mut file = File::open(obj_path)?;
+    let mut block = vec![0_u8; 1 << 16];
+    let mut length = 0_u64;
+    loop {
+        let read = file.read(&mut block)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&block[..read]);
+        length += read as u64;
+    }
+    length.hash(&mut hasher);
+    local_to_world.hash(&mut hasher);
+    uv_projection.hash(&mut hasher);
+    return Ok(hasher.finish());
+}
+
+// Where the objects for the key are kept in the cache directory
+pub fn file_path(cache_dir: &Path, key: u64) -> PathBuf {
+    return cache_dir.join(format!("{:016x}.mesh", key));
+}
+
+// Reads a cache file, calling on_object with each of its objects in order,
+// as objstream::read does. A cache that can not be used is an Err, for the
+// caller to throw away what it built from it and read the OBJ file, and
+// errors from on_object are passed on inside an Ok.
+pub fn read<E, F>(cache_path: &Path, key: u64, on_object: F) -> Result<Result<(), E>, String>
+where
+    F: FnMut(ObjObject, Option<&String>) -> Result<(), E>,
+{
+    let mut input = BufReader::new(match File::open(cache_path) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("{}", e)),
+    });
+    // Any read error is just reported as a malformed cache
+    match read_helper(&mut input, key, on_object) {
+        Ok(result) => result,
+        Err(e) => Err(format!("Malformed mesh cache: {}", e)),
+    }
+}
+
+fn read_helper<E, F>(
+    input: &mut dyn Read,
+    key: u64,
+    mut on_object: F,
+) -> io::Result<Result<Result<(), E>, String>>
+where
+    F: FnMut(ObjObject, Option<&String>) -> Result<(), E>,
+{
+    let mut magic = [0_u8; 6];
+    input.read_exact(&mut magic)?;
+    if &magic != MESH_CACHE_MAGIC {
+        return Ok(Err(String::from("Not a mesh cache file.")));
+    }
+    let version = read_u32(input)?;
+    if version != MESH_CACHE_VERSION {
+        return Ok(Err(format!(
+            "Cache version {} is not supported, expected {}.",
+            version, MESH_CACHE_VERSION
+        )));
+    }
+    if read_u64(input)? != key {
+        return Ok(Err(String::from(
+            "Cache was made from a different file or settings.",
+        )));
+    }
+
+    loop {
+        match read_u8(input)? {
+            0 => return Ok(Ok(Ok(()))),
+            1 => {}
+            tag => return Ok(Err(format!("Unknown tag {}.", tag))),
+        }
+        let name = read_string(input)?;
+        let material_library = read_optional_string(input)?;
+        let mut object = ObjObject::new(name);
+
+        let vertex_count = read_u64(input)? as usize;
+        object.vertices.reserve(vertex_count);
+        for _ in 0..vertex_count {
+            object.vertices.push(Point3::new(
+                read_f32(input)?,
+                read_f32(input)?,
+                read_f32(input)?,
+            ));
+        }
+        let tex_coord_count = read_u64(input)? as usize;
+        object.tex_coords.reserve(tex_coord_count);
+        for _ in 0..tex_coord_count {
+            object
+                .tex_coords
+                .push(TexCoord::new(read_f32(input)?, read_f32(input)?));
+        }
+        let face_count = read_u64(input)? as usize;
+        object.faces.reserve(face_count);
+        object.face_materials.reserve(face_count);
+        for _ in 0..face_count {
+            let mut face = [(0_usize, None); 3];
+            for corner in face.iter_mut() {
+                let vertex = read_u64(input)?;
+                if vertex >= vertex_count as u64 {
+                    return Ok(Err(format!("Vertex index {} is out of bounds.", vertex)));
+                }
+                let tex_coord = match read_u64(input)? {
+                    NO_TEX_COORD => None,
+                    t if t < tex_coord_count as u64 => Some(t as usize),
+                    t => {
+                        return Ok(Err(format!(
+                            "Texture coordinate index {} is out of bounds.",
+                            t
+                        )))
+                    }
+                };
+                *corner = (vertex as usize, tex_coord);
+            }
+            object.faces.push(face);
+            object.face_materials.push(read_u64(input)? as usize);
+        }
+        let material_count = read_u64(input)? as usize;
+        for _ in 0..material_count {
+            object.material_names.push(read_optional_string(input)?);
+        }
+        if object.face_materials.iter().any(|m| *m >= material_count) {
+            return Ok(Err(String::from("Material index is out of bounds.")));
+        }
+
+        match on_object(object, material_library.as_ref()) {
+            Ok(()) => {}
+            Err(e) => return Ok(Ok(Err(e))),
+        }
+    }
+}
+
+// Writes a cache file an object at a time, as the OBJ file is read. It is
+// written beside its final path, and only moved there by finish, so a
+// cache is never seen half written, even by another render making the same
+// one. If not finished, it is removed when dropped.
+pub struct CacheWriter {
+    out: BufWriter<File>,
+    temp_path: PathBuf,
+    cache_path: PathBuf,
+}
+
+impl CacheWriter {
+    pub fn create(cache_path: &Path, key: u64) -> io::Result<CacheWriter> {
+        match cache_path.parent() {
+            Some(dir) => fs::create_dir_all(dir)?,
+            None => {}
+        }
+        let temp_path = cache_path.with_extension(format!("mesh.{}", process::id()));
+        let mut writer = CacheWriter {
+            out: BufWriter::new(File::create(&temp_path)?),
+            temp_path: temp_path,
+            cache_path: cache_path.to_path_buf(),
+        };
+        writer.out.write_all(MESH_CACHE_MAGIC)?;
+        writer.out.write_all(&MESH_CACHE_VERSION.to_le_bytes())?;
+        writer.out.write_all(&key.to_le_bytes())?;
+        return Ok(writer);
+    }
+
+    pub fn add(&mut self, object: &ObjObject, material_library: Option<&String>) -> io::Result<()> {
+        let out = &mut self.out;
+        out.write_all(&[1_u8])?;
+        write_string(out, &object.name)?;
+        write_optional_string(out, material_library)?;
+
+        out.write_all(&(object.vertices.len() as u64).to_le_bytes())?;
+        for v in &object.vertices {
+            out.write_all(&v.x().to_le_bytes())?;
+            out.write_all(&v.y().to_le_bytes())?;
+            out.write_all(&v.z().to_le_bytes())?;
+        }
+        out.write_all(&(object.tex_coords.len() as u64).to_le_bytes())?;
+        for t in &object.tex_coords {
+            out.write_all(&t.u().to_le_bytes())?;
+            out.write_all(&t.v().to_le_bytes())?;
+        }
+        out.write_all(&(object.faces.len() as u64).to_le_bytes())?;
+        for (face, material) in object.faces.iter().zip(&object.face_materials) {
+            for (vertex, tex_coord) in face {
+                out.write_all(&(*vertex as u64).to_le_bytes())?;
+                let tex_coord = match tex_coord {
+                    Some(t) => *t as u64,
+                    None => NO_TEX_COORD,
+                };
+                out.write_all(&tex_coord.to_le_bytes())?;
+            }
+            out.write_all(&(*material as u64).to_le_bytes())?;
+        }
+        out.write_all(&(object.material_names.len() as u64).to_le_bytes())?;
+        for name in &object.material_names {
+            write_optional_string(out, name.as_ref())?;
+        }
+        return Ok(());
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.write_all(&[0_u8])?;
+        self.out.flush()?;
+        return fs::rename(&self.temp_path, &self.cache_path);
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        // Nothing is left to remove once finished
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+fn write_string(out: &mut dyn Write, s: &str) -> io::Result<()> {
+    out.write_all(&(s.len() as u64).to_le_bytes())?;
+    return out.write_all(s.as_bytes());
+}
+
+fn write_optional_string(out: &mut dyn Write, s: Option<&String>) -> io::Result<()> {
+    match s {
+        Some(s) => {
+            out.write_all(&[1_u8])?;
+            return write_string(out, s);
+        }
+        None => return out.write_all(&[0_u8]),
+    }
+}
+
+fn read_u8(input: &mut dyn Read) -> io::Result<u8> {
+    let mut bytes = [0_u8; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0_u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0_u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(input: &mut dyn Read) -> io::Result<f32> {
+    let mut bytes = [0_u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_string(input: &mut dyn Read) -> io::Result<String> {
+    let length = read_u64(input)? as usize;
+    let mut bytes = Vec::new();
+    input.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "string is cut short",
+        ));
+    }
+    return String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+}
+
+fn read_optional_string(input: &mut dyn Read) -> io::Result<Option<String>> {
+    return match read_u8(input)? {
+        0 => Ok(None),
+        _ => Ok(Some(read_string(input)?)),
+    };
+}
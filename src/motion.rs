@@ -0,0 +1,143 @@
+use crate::aggregate::AABB;
+use crate::material::SyncMaterial;
+use crate::matrix::Matrix4;
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::shape::{Hit, HitProperties, Shape, SyncShape};
+use crate::texture::TexCoord;
+use crate::vector::Vector3;
+
+use std::sync::Arc;
+
+// Moving
+// Wraps a shape to move it over time, for motion blur. The wrapped shape is
+// where it is at time 0, and the motion matrix takes it to where it is at
+// time 1. In between, the motion is interpolated element by element, so
+// every point on the shape moves in a straight line. That keeps bounds easy
+// but turns large rotations into shears, so they are best kept small.
+pub struct Moving {
+    shape: Arc<SyncShape>,
+    motion: Matrix4,
+    // Where the shape is halfway through its motion, which light sampling
+    // aims at so that sampling does not depend on a ray's time
+    middle_to_world: Matrix4,
+    world_to_middle: Matrix4,
+}
+
+impl Moving {
+    pub fn new(shape: Arc<SyncShape>, motion: &Matrix4) -> Result<Moving, &'static str> {
+        let middle_to_world = Matrix4::lerp(0.5_f32, &Matrix4::new_identity(), motion);
+        Ok(Moving {
+            shape: shape,
+            motion: motion.clone(),
+            world_to_middle: middle_to_world.inverse()?,
+            middle_to_world: middle_to_world,
+        })
+    }
+
+    // Transform from where the shape starts to where it is at the time
+    fn motion_at(&self, time: f32) -> Matrix4 {
+        return Matrix4::lerp(time, &Matrix4::new_identity(), &self.motion);
+    }
+}
+
+impl Shape for Moving {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let world_to_start = match self.motion_at(r.time).inverse() {
+            Ok(m) => m,
+            Err(_) => return None,
+        };
+        return self.shape.hit(&(&world_to_start * r), t_min, t_max);
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let start_to_world = self.motion_at(r.time);
+        let world_to_start = start_to_world
+            .inverse()
+            .expect("Moving shape was hit at a time its motion can not be undone.");
+        let start_props = self.shape.get_hit_properties(&(&world_to_start * r), hit);
+        HitProperties {
+            hit_point: r.point_at(hit.t),
+            normal: (&start_to_world * start_props.normal).normalized(),
+            uv: start_props.uv,
+            pu: &start_to_world * start_props.pu,
+            pv: &start_to_world * start_props.pv,
+            uv_footprint: 0.0_f32,
+        }
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        self.shape.get_material()
+    }
+
+    // Bounds covering the whole motion. Points move in straight lines, so
+    // the bounds at the start and end contain every point in between.
+    fn get_bounding_box(&self) -> AABB {
+        let start = self.shape.get_bounding_box();
+        let mut min = start.min;
+        let mut max = start.max;
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 {
+                    start.min.x()
+                } else {
+                    start.max.x()
+                },
+                if i & 2 == 0 {
+                    start.min.y()
+                } else {
+                    start.max.y()
+                },
+                if i & 4 == 0 {
+                    start.min.z()
+                } else {
+                    start.max.z()
+                },
+            );
+            let end_corner = &self.motion * corner;
+            min = Point3::min(min, end_corner);
+            max = Point3::max(max, end_corner);
+        }
+        return AABB::new(min, max);
+    }
+
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        return self.shape.pdf(&(&self.world_to_middle * r), t_min);
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        let middle_dir = self
+            .shape
+            .random_dir_towards(&(&self.world_to_middle * (*from_origin)));
+        return &self.middle_to_world * middle_dir;
+    }
+
+    // Measured where the shape starts, which is exact for rigid motion
+    fn edge_distance(&self, r: &Ray, hit: &Hit) -> Option<f32> {
+        let world_to_start = self
+            .motion_at(r.time)
+            .inverse()
+            .expect("Moving shape was hit at a time its motion can not be undone.");
+        return self.shape.edge_distance(&(&world_to_start * r), hit);
+    }
+
+    fn is_bounded(&self) -> bool {
+        return self.shape.is_bounded();
+    }
+
+    fn is_front_face(&self, r: &Ray, hit: &Hit) -> bool {
+        let world_to_start = self
+            .motion_at(r.time)
+            .inverse()
+            .expect("Moving shape was hit at a time its motion can not be undone.");
+        return self.shape.is_front_face(&(&world_to_start * r), hit);
+    }
+
+    fn instance_depth(&self) -> usize {
+        return self.shape.instance_depth();
+    }
+
+    fn corner_uvs(&self) -> Option<[TexCoord; 3]> {
+        return self.shape.corner_uvs();
+    }
+}
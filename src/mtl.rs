@@ -0,0 +1,331 @@
+use crate::color::RGB;
+use crate::colorspace::ColorSpace;
+use crate::material;
+use crate::material::SyncMaterial;
+use crate::objstream::ObjError;
+use crate::resources::Resources;
+use crate::texture;
+use crate::texture::SyncTexture;
+
+use std::{collections::HashMap, fs, path, sync::Arc};
+
+// Material libraries (.mtl files) referenced by OBJ meshes, each read once
+// however many meshes use it. Their materials are mapped onto the closest
+// materials the renderer has:
+// - An emissive color (Ke) that is not black becomes a DiffuseLight.
+// - A dissolve (d) below 1 with an optical density (Ni) becomes a Dielectric.
+// - A specular color (Ks) brighter than the diffuse color (Kd) becomes a
+//   Metal, with a roughness taken from the specular exponent (Ns), unless
+//   the illumination model (illum) is 0 or 1, which have no highlight.
+// - Everything else becomes a Lambert, using the diffuse map (map_Kd) if
+//   there is one.
+pub struct MaterialLibraries<'a> {
+    res: &'a mut Resources,
+    working_space: ColorSpace,
+    // Replaces every material that does not emit light, like the
+    // override_material setting does for the scene's own materials
+    override_material: Option<Arc<SyncMaterial>>,
+    loaded: HashMap<path::PathBuf, Arc<HashMap<String, Arc<SyncMaterial>>>>,
+}
+
+impl<'a> MaterialLibraries<'a> {
+    pub fn new(
+        res: &'a mut Resources,
+        working_space: ColorSpace,
+        override_material: Option<Arc<SyncMaterial>>,
+    ) -> MaterialLibraries<'a> {
+        MaterialLibraries {
+            res: res,
+            working_space: working_space,
+            override_material: override_material,
+            loaded: HashMap::new(),
+        }
+    }
+
+    // Notes a file the scene was made from, see Resources
+    pub fn depend_on(&mut self, path: &path::Path) {
+        self.res.depend_on(path);
+    }
+
+    // Where an asset is, searching the scene's search paths, see Resources
+    pub fn find(&self, dir: &path::Path, asset_path: &path::Path) -> path::PathBuf {
+        return self.res.find(dir, asset_path);
+    }
+
+    // Whether missing files are stood in for, see Resources
+    pub fn placeholders(&self) -> bool {
+        return self.res.placeholders();
+    }
+
+    // A diffuse checkerboard for meshes standing in for missing files. It is
+    // kept even with an override material, so placeholders stay easy to spot.
+    pub fn placeholder_material(&mut self) -> Arc<SyncMaterial> {
+        let image = self.res.placeholder_image();
+        let checkerboard = self.in_working_space(Arc::new(texture::Image::new(
+            image,
+            false,
+            texture::Filter::Nearest,
+        )));
+        return Arc::new(material::Lambert::new(checkerboard, None));
+    }
+
+    // The materials of the library at the given path, by name
+    pub fn load(
+        &mut self,
+        library_path: &path::Path,
+    ) -> Result<Arc<HashMap<String, Arc<SyncMaterial>>>, String> {
+        let absolute_path = match library_path.canonicalize() {
+            Ok(p) => p,
+            Err(e) => {
+                return Err(format!(
+                    "There was a problem finding material library {}: {}",
+                    library_path.display(),
+                    e
+                ))
+            }
+        };
+        self.res.depend_on(&absolute_path);
+        match self.loaded.get(&absolute_path) {
+            Some(l) => return Ok(Arc::clone(l)),
+            None => {}
+        }
+
+        let mtl_string = match fs::read_to_string(&absolute_path) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read material library {}: {}",
+                    absolute_path.display(),
+                    e
+                ))
+            }
+        };
+        let mtl_materials = match parse(&mtl_string) {
+            Ok(m) => m,
+            Err(e) => {
+                return Err(format!(
+                    "Could not parse material library {}, {}",
+                    absolute_path.display(),
+                    e
+                ))
+            }
+        };
+        // Texture paths are relative to the library
+        let library_dir = match absolute_path.parent() {
+            Some(d) => d.to_path_buf(),
+            None => path::PathBuf::new(),
+        };
+        let mut library = HashMap::new();
+        for mtl_material in &mtl_materials {
+            let converted = self.convert(mtl_material, &library_dir)?;
+            library.insert(String::clone(&mtl_material.name), converted);
+        }
+
+        let library = Arc::new(library);
+        self.loaded.insert(absolute_path, Arc::clone(&library));
+        return Ok(library);
+    }
+
+    // Every material loaded from a library so far, with its name there
+    pub fn materials(&self) -> Vec<(&String, &Arc<SyncMaterial>)> {
+        return self.loaded.values().flat_map(|l| l.iter()).collect();
+    }
+
+    fn convert(
+        &mut self,
+        mtl_material: &MtlMaterial,
+        library_dir: &path::Path,
+    ) -> Result<Arc<SyncMaterial>, String> {
+        match &mtl_material.color_emissive {
+            Some(ke) if ke.r() > 0.0 || ke.g() > 0.0 || ke.b() > 0.0 => {
+                return Ok(Arc::new(material::DiffuseLight::new(
+                    self.constant_texture(ke),
+                    1.0_f32,
+                    true,
+                    0.0_f32,
+                )));
+            }
+            _ => {}
+        }
+        match &self.override_material {
+            Some(m) => return Ok(Arc::clone(m)),
+            None => {}
+        }
+
+        match mtl_material.optical_density {
+            Some(ni) if mtl_material.alpha < 1.0 => {
+                return Ok(Arc::new(material::Dielectric::new(ni)));
+            }
+            _ => {}
+        }
+
+        let kd = &mtl_material.color_diffuse;
+        let ks = &mtl_material.color_specular;
+        let specular = match mtl_material.illumination {
+            Some(0) | Some(1) => 0.0,
+            _ => ks.r().max(ks.g()).max(ks.b()),
+        };
+        if specular > kd.r().max(kd.g()).max(kd.b()) {
+            // The usual mapping from a Phong exponent to a microfacet
+            // roughness, an exponent of 0 is fully rough
+            let roughness = (2.0_f32 / (mtl_material.specular_coefficient + 2.0_f32))
+                .sqrt()
+                .min(1.0_f32);
+            return Ok(Arc::new(material::Metal::new(
+                self.constant_texture(ks),
+                Arc::new(texture::Constant::new(RGB::new(
+                    roughness, roughness, roughness,
+                ))),
+                None,
+            )));
+        }
+
+        let albedo = match &mtl_material.uv_map {
+            Some(map) => {
+                let image = self
+                    .res
+                    .load_image(&self.res.find(library_dir, path::Path::new(map)))?;
+                self.in_working_space(Arc::new(texture::Image::new(
+                    image,
+                    false,
+                    texture::Filter::Bilinear,
+                )))
+            }
+            None => self.constant_texture(kd),
+        };
+        return Ok(Arc::new(material::Lambert::new(albedo, None)));
+    }
+
+    fn constant_texture(&self, color: &RGB) -> Arc<SyncTexture> {
+        return self.in_working_space(Arc::new(texture::Constant::new(*color)));
+    }
+
+    // Colors in material libraries are sRGB, like colors in the scene
+    fn in_working_space(&self, texture: Arc<SyncTexture>) -> Arc<SyncTexture> {
+        if self.working_space.has_rec709_primaries() {
+            return texture::profiled(texture);
+        }
+        return texture::profiled(texture::fold_constant(Arc::new(
+            texture::WorkingSpace::new(texture, self.working_space),
+        )));
+    }
+}
+
+// MtlMaterial
+// The statements of a material in a library that the conversion reads.
+// Statements may come in any order, and ones it does not read are skipped.
+struct MtlMaterial {
+    name: String,
+    // Ns
+    specular_coefficient: f32,
+    // Kd
+    color_diffuse: RGB,
+    // Ks
+    color_specular: RGB,
+    // Ke
+    color_emissive: Option<RGB>,
+    // Ni
+    optical_density: Option<f32>,
+    // d, or 1 - Tr
+    alpha: f32,
+    // illum, of which 0 and 1 have no specular highlight
+    illumination: Option<u32>,
+    // map_Kd
+    uv_map: Option<String>,
+}
+
+impl MtlMaterial {
+    fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name: name,
+            specular_coefficient: 0.0_f32,
+            color_diffuse: RGB::black(),
+            color_specular: RGB::black(),
+            color_emissive: None,
+            optical_density: None,
+            alpha: 1.0_f32,
+            illumination: None,
+            uv_map: None,
+        }
+    }
+}
+
+// The materials of a library, in the order they are given
+fn parse(mtl_string: &str) -> Result<Vec<MtlMaterial>, ObjError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+    for (index, line) in mtl_string.lines().enumerate() {
+        let error = |message: String| ObjError {
+            line_number: index + 1,
+            message: message,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let rest: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            match rest.first() {
+                Some(name) => materials.push(MtlMaterial::new(String::from(*name))),
+                None => return Err(error(String::from("Expected a material name."))),
+            }
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(m) => m,
+            None => return Err(error(format!("Expected newmtl before {}.", keyword))),
+        };
+        match keyword {
+            "Ns" => material.specular_coefficient = parse_number(rest.first()).map_err(error)?,
+            "Kd" => material.color_diffuse = parse_color(&rest).map_err(error)?,
+            "Ks" => material.color_specular = parse_color(&rest).map_err(error)?,
+            "Ke" => material.color_emissive = Some(parse_color(&rest).map_err(error)?),
+            "Ni" => material.optical_density = Some(parse_number(rest.first()).map_err(error)?),
+            "d" => material.alpha = parse_number(rest.first()).map_err(error)?,
+            "Tr" => material.alpha = 1.0_f32 - parse_number(rest.first()).map_err(error)?,
+            "illum" => {
+                material.illumination = match rest.first().map(|t| t.parse::<u32>()) {
+                    Some(Ok(i)) => Some(i),
+                    _ => {
+                        return Err(error(String::from(
+                            "Expected a whole number for the illumination model.",
+                        )))
+                    }
+                }
+            }
+            // The path is the last token, after any options
+            "map_Kd" => match rest.last() {
+                Some(map) => material.uv_map = Some(String::from(*map)),
+                None => return Err(error(String::from("Expected a texture path."))),
+            },
+            // Ambient colors, other maps, and the rest of the format don't
+            // change the converted material
+            _ => {}
+        }
+    }
+    return Ok(materials);
+}
+
+// Numbers are read in double precision, then rounded to single
+fn parse_number(token: Option<&&str>) -> Result<f32, String> {
+    match token {
+        Some(token) => match token.parse::<f64>() {
+            Ok(n) => return Ok(n as f32),
+            Err(_) => return Err(format!("Expected a number but got {}.", token)),
+        },
+        None => return Err(String::from("Expected a number.")),
+    }
+}
+
+// Colors give red, green, and blue, or one number for all three
+fn parse_color(tokens: &[&str]) -> Result<RGB, String> {
+    let mut channels = Vec::with_capacity(3);
+    for token in tokens.iter().take(3) {
+        channels.push(parse_number(Some(token))?);
+    }
+    match channels.len() {
+        1 => return Ok(RGB::new(channels[0], channels[0], channels[0])),
+        3 => return Ok(RGB::new(channels[0], channels[1], channels[2])),
+        _ => return Err(String::from("Expected a color of one or three numbers.")),
+    }
+}
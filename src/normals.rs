@@ -0,0 +1,32 @@
+use crate::aggregate::Workspace;
+use crate::color::RGB;
+use crate::ray::Ray;
+use crate::scene::Scene;
+
+// Ambient share of the shading, so surfaces seen edge on stay visible
+const AMBIENT: f32 = 0.2_f32;
+
+// Color for a camera ray in the check normals debug mode. Front faces are
+// green and back faces red, shaded by the angle they are seen at so the
+// shapes can still be made out. Inverted meshes stand out in red, which
+// with backface culling would otherwise be a hard to explain black render.
+// Culled faces are not hit at all, so they show whatever is behind them.
+pub fn check_color(
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+    scene: &Scene,
+    workspace: &mut Workspace,
+) -> RGB {
+    let (shape, hit) = match scene.shape_aggregate.hit(r, t_min, t_max, workspace) {
+        Some(h) => h,
+        None => return RGB::black(),
+    };
+    let normal = shape.get_hit_properties(r, &hit).normal;
+    let facing = (r.dir.dot(normal) / r.dir.length()).abs();
+    let brightness = AMBIENT + (1.0_f32 - AMBIENT) * facing;
+    if shape.is_front_face(r, &hit) {
+        return RGB::new(0.0_f32, brightness, 0.0_f32);
+    }
+    return RGB::new(brightness, 0.0_f32, 0.0_f32);
+}
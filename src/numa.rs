@@ -0,0 +1,176 @@
+// Memory placement and thread pinning for machines with more than one NUMA
+// node. By default Linux puts memory on the node of the thread that first
+// touches it, so a scene loaded on the main thread lands entirely on one
+// node, and every thread on the other nodes reads the BVH and meshes across
+// the interconnect. Interleaving the pages while the scene loads spreads
+// that traffic over every node, and pinning the tracing threads keeps the
+// scheduler from piling them onto one node.
+//
+// Only Linux is supported, elsewhere every call returns an error.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+
+#[cfg(target_os = "linux")]
+const MPOL_DEFAULT: libc::c_long = 0;
+#[cfg(target_os = "linux")]
+const MPOL_INTERLEAVE: libc::c_long = 3;
+
+// Parses a sysfs list like "0-3,8,10-11"
+#[cfg(target_os = "linux")]
+fn parse_list(list: &str) -> Vec<usize> {
+    let mut values = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let mut ends = range.splitn(2, '-').map(|v| v.parse::<usize>());
+        match (ends.next(), ends.next()) {
+            (Some(Ok(start)), Some(Ok(end))) => values.extend(start..=end),
+            (Some(Ok(single)), None) => values.push(single),
+            _ => {}
+        }
+    }
+    return values;
+}
+
+#[cfg(target_os = "linux")]
+fn online_nodes() -> Vec<usize> {
+    match fs::read_to_string("/sys/devices/system/node/online") {
+        Ok(list) => parse_list(&list),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_mempolicy(mode: libc::c_long, nodes: &Vec<usize>) -> Result<(), String> {
+    let bits = 8 * std::mem::size_of::<libc::c_ulong>();
+    let max_node = nodes.iter().max().map_or(0, |n| n + 1);
+    let mut mask: Vec<libc::c_ulong> = vec![0; max_node / bits + 1];
+    for node in nodes {
+        mask[node / bits] |= 1 << (node % bits);
+    }
+    let (mask_ptr, mask_bits) = if nodes.is_empty() {
+        (std::ptr::null(), 0)
+    } else {
+        (mask.as_ptr(), mask.len() * bits)
+    };
+    // The kernel reads one bit fewer than it is told to
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            mode,
+            mask_ptr,
+            (mask_bits + 1) as libc::c_ulong,
+        )
+    };
+    if result != 0 {
+        return Err(format!(
+            "set_mempolicy failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    return Ok(());
+}
+
+// Interleaves the pages of memory the calling thread allocates from now on
+// across every NUMA node
+#[cfg(target_os = "linux")]
+pub fn interleave_memory() -> Result<(), String> {
+    let nodes = online_nodes();
+    if nodes.is_empty() {
+        return Err(String::from(
+            "Found no NUMA nodes to interleave memory across.",
+        ));
+    }
+    return set_mempolicy(MPOL_INTERLEAVE, &nodes);
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn interleave_memory() -> Result<(), String> {
+    Err(String::from(
+        "Interleaving memory is only supported on Linux.",
+    ))
+}
+
+// Goes back to placing memory on the node that first touches it
+#[cfg(target_os = "linux")]
+pub fn default_memory() -> Result<(), String> {
+    return set_mempolicy(MPOL_DEFAULT, &Vec::new());
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn default_memory() -> Result<(), String> {
+    Err(String::from(
+        "Interleaving memory is only supported on Linux.",
+    ))
+}
+
+// The CPUs this process may run on, taking one from each node in turn so
+// that consecutive threads land on different nodes
+#[cfg(target_os = "linux")]
+fn spread_cpus() -> Vec<usize> {
+    let allowed = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Vec::new();
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+            .collect::<Vec<usize>>()
+    };
+
+    let mut by_node: Vec<Vec<usize>> = online_nodes()
+        .iter()
+        .map(|node| {
+            match fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", node)) {
+                Ok(list) => parse_list(&list)
+                    .into_iter()
+                    .filter(|cpu| allowed.contains(cpu))
+                    .collect(),
+                Err(_) => Vec::new(),
+            }
+        })
+        .filter(|cpus: &Vec<usize>| !cpus.is_empty())
+        .collect();
+    if by_node.is_empty() {
+        return allowed;
+    }
+
+    let mut spread = Vec::with_capacity(allowed.len());
+    let most = by_node.iter().map(|cpus| cpus.len()).max().unwrap_or(0);
+    for i in 0..most {
+        for cpus in &mut by_node {
+            if i < cpus.len() {
+                spread.push(cpus[i]);
+            }
+        }
+    }
+    return spread;
+}
+
+// Pins the calling thread to one CPU, chosen by its index among the tracing
+// threads
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(index: usize) -> Result<(), String> {
+    let cpus = spread_cpus();
+    if cpus.is_empty() {
+        return Err(String::from("Found no CPUs to pin the thread to."));
+    }
+    let cpu = cpus[index % cpus.len()];
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        return Err(format!(
+            "Failed to pin a thread to CPU {}: {}",
+            cpu,
+            std::io::Error::last_os_error()
+        ));
+    }
+    return Ok(());
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_index: usize) -> Result<(), String> {
+    Err(String::from("Pinning threads is only supported on Linux."))
+}
@@ -0,0 +1,280 @@
+use crate::point::Point3;
+use crate::shape::MeshCorner;
+use crate::texture::TexCoord;
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+
+// Reads Wavefront OBJ files a line at a time, handing over each object as
+// soon as it ends, so neither the file's text nor more than one object of
+// parsed data is ever held in memory at once. Vertices and texture
+// coordinates are converted to their single precision types as they are
+// read. Faces with more than three corners are split into fans around their
+// last corner, and normals, groups, and smoothing groups are skipped, as
+// meshes compute their own. Faces may only use the vertices of their own
+// object.
+
+// One object of the file, with its faces indexing its own vertices and
+// texture coordinates from zero
+pub struct ObjObject {
+    pub name: String,
+    pub vertices: Vec<Point3>,
+    pub tex_coords: Vec<TexCoord>,
+    pub faces: Vec<[MeshCorner; 3]>,
+    // The material group of each face, as an index into material_names
+    pub face_materials: Vec<usize>,
+    // The names given by usemtl, in the order faces first use them, None for
+    // faces before any usemtl
+    pub material_names: Vec<Option<String>>,
+}
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub line_number: usize,
+    pub message: String,
+}
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.message)
+    }
+}
+
+// Reads the OBJ file, calling on_object with each of its objects in order,
+// along with the material library (mtllib) named so far. Stops at the first
+// error, from reading the file or from on_object.
+pub fn read<E, F>(path: &Path, mut on_object: F) -> Result<(), E>
+where
+    E: From<io::Error> + From<ObjError>,
+    F: FnMut(ObjObject, Option<&String>) -> Result<(), E>,
+{
+    let mut reader = io::BufReader::new(File::open(path)?);
+    let mut material_library = None;
+    let mut object = ObjObject::new(String::new());
+    // Where the current object starts among all of the file's vertices and
+    // texture coordinates, as OBJ indices count from the start of the file
+    let (mut vertex_start, mut tex_start) = (0_usize, 0_usize);
+    let mut material = None;
+
+    let mut line = String::new();
+    let mut line_number = 0_usize;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+        let error = |message: String| ObjError {
+            line_number: line_number,
+            message: message,
+        };
+        let content = match line.find('#') {
+            Some(i) => &line[..i],
+            None => &line[..],
+        };
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        match keyword {
+            "v" => {
+                let v = parse_numbers(&mut tokens, 3, 3).map_err(error)?;
+                object.vertices.push(Point3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let t = parse_numbers(&mut tokens, 1, 2).map_err(error)?;
+                object
+                    .tex_coords
+                    .push(TexCoord::new(t[0], *t.get(1).unwrap_or(&0.0_f32)));
+            }
+            "f" => {
+                let mut corners = Vec::new();
+                for token in tokens {
+                    corners.push(
+                        parse_corner(
+                            token,
+                            (vertex_start, object.vertices.len()),
+                            (tex_start, object.tex_coords.len()),
+                        )
+                        .map_err(error)?,
+                    );
+                }
+                if corners.len() < 3 {
+                    return Err(E::from(error(format!(
+                        "Only triangles are allowed in meshes, but object {} has a face with {} corners.",
+                        object.name,
+                        corners.len()
+                    ))));
+                }
+                let material_index = match object.material_names.iter().position(|m| *m == material)
+                {
+                    Some(i) => i,
+                    None => {
+                        object.material_names.push(material.clone());
+                        object.material_names.len() - 1
+                    }
+                };
+                let last = corners[corners.len() - 1];
+                for pair in corners[..corners.len() - 1].windows(2) {
+                    object.faces.push([last, pair[0], pair[1]]);
+                    object.face_materials.push(material_index);
+                }
+            }
+            "l" | "p" => {
+                return Err(E::from(error(format!(
+                    "Only triangles are allowed in meshes, but object {} has a {}.",
+                    object.name,
+                    if keyword == "l" { "line" } else { "point" }
+                ))));
+            }
+            "o" => {
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                // Vertices given before the first object belong to an
+                // anonymous one
+                if !object.is_empty() {
+                    vertex_start += object.vertices.len();
+                    tex_start += object.tex_coords.len();
+                    on_object(object, material_library.as_ref())?;
+                }
+                object = ObjObject::new(name);
+                // Each object starts without a material
+                material = None;
+            }
+            "usemtl" => material = tokens.next().map(String::from),
+            "mtllib" => {
+                if material_library.is_none() {
+                    material_library = tokens.next().map(String::from);
+                }
+            }
+            // Normals, groups, smoothing groups, and the rest of the format
+            // don't change the mesh
+            _ => {}
+        }
+    }
+    if !object.is_empty() {
+        on_object(object, material_library.as_ref())?;
+    }
+    return Ok(());
+}
+
+impl ObjObject {
+    pub fn new(name: String) -> ObjObject {
+        return ObjObject {
+            name: name,
+            vertices: Vec::new(),
+            tex_coords: Vec::new(),
+            faces: Vec::new(),
+            face_materials: Vec::new(),
+            material_names: Vec::new(),
+        };
+    }
+
+    // A unit cube centered on the origin, standing in for a missing file.
+    // Each side is textured with the whole of [0, 1] and it has a single
+    // material group without a name.
+    pub fn placeholder_cube() -> ObjObject {
+        let mut cube = ObjObject::new(String::from("Placeholder"));
+        for &(x, y, z) in &[
+            (-0.5, -0.5, 0.5),
+            (-0.5, -0.5, -0.5),
+            (0.5, -0.5, -0.5),
+            (0.5, -0.5, 0.5),
+            (-0.5, 0.5, 0.5),
+            (-0.5, 0.5, -0.5),
+            (0.5, 0.5, -0.5),
+            (0.5, 0.5, 0.5),
+        ] {
+            cube.vertices.push(Point3::new(x, y, z));
+        }
+        for &(u, v) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+            cube.tex_coords.push(TexCoord::new(u, v));
+        }
+        for side in &[
+            [4, 5, 1, 0],
+            [5, 6, 2, 1],
+            [6, 7, 3, 2],
+            [7, 4, 0, 3],
+            [0, 1, 2, 3],
+            [7, 6, 5, 4],
+        ] {
+            let corner = |i: usize| (side[i], Some(i));
+            cube.faces.push([corner(0), corner(1), corner(2)]);
+            cube.faces.push([corner(0), corner(2), corner(3)]);
+            cube.face_materials.push(0);
+            cube.face_materials.push(0);
+        }
+        cube.material_names.push(None);
+        return cube;
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.vertices.is_empty() && self.tex_coords.is_empty() && self.faces.is_empty();
+    }
+}
+
+// Between min and max numbers, read in double precision, then rounded to
+// single
+fn parse_numbers<'a, I: Iterator<Item = &'a str>>(
+    tokens: &mut I,
+    min: usize,
+    max: usize,
+) -> Result<Vec<f32>, String> {
+    let mut numbers = Vec::with_capacity(max);
+    for token in tokens.take(max) {
+        match token.parse::<f64>() {
+            Ok(n) => numbers.push(n as f32),
+            Err(_) => return Err(format!("Expected a number but got {}.", token)),
+        }
+    }
+    if numbers.len() < min {
+        return Err(format!("Expected at least {} numbers.", min));
+    }
+    return Ok(numbers);
+}
+
+// A corner of a face, as v, v/vt, v//vn, or v/vt/vn. Each range is where
+// the current object starts among the file's elements and how many it has
+// so far.
+fn parse_corner(
+    token: &str,
+    vertices: (usize, usize),
+    tex_coords: (usize, usize),
+) -> Result<MeshCorner, String> {
+    let mut parts = token.split('/');
+    let vertex = match parts.next() {
+        Some(v) if !v.is_empty() => parse_index(v, vertices)?,
+        _ => return Err(format!("Expected a vertex index but got {}.", token)),
+    };
+    let tex_coord = match parts.next() {
+        Some(t) if !t.is_empty() => Some(parse_index(t, tex_coords)?),
+        _ => None,
+    };
+    return Ok((vertex, tex_coord));
+}
+
+// OBJ indices count from 1 at the start of the file, or back from the
+// latest element when negative. The result counts from 0 at the start of
+// the object.
+fn parse_index(token: &str, (start, count): (usize, usize)) -> Result<usize, String> {
+    let index = match token.parse::<i64>() {
+        Ok(i) => i,
+        Err(_) => return Err(format!("Expected an index but got {}.", token)),
+    };
+    let from_object_start = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1 - start as i64
+    };
+    if from_object_start < 0 || from_object_start >= count as i64 {
+        return Err(format!(
+            "Index {} is outside of the current object, which has {} to {}.",
+            index,
+            start + 1,
+            start + count
+        ));
+    }
+    return Ok(from_object_start as usize);
+}
@@ -0,0 +1,135 @@
+use crate::aggregate::Workspace;
+use crate::color::RGB;
+use crate::framebuffer::Framebuffer;
+use crate::scene::Scene;
+use crate::stats;
+use crate::utils;
+use crate::vector::Vector3;
+
+use serde::Deserialize;
+
+// Ink lines around Toon materials, drawn after rendering. One ray through
+// the center of each pixel finds the distance to and the normal of what the
+// pixel sees, and lines go where those jump between neighboring pixels: at
+// silhouettes, where one surface is in front of another, and at creases.
+// Each line is drawn on the nearer side, in the color of the material there.
+#[derive(Deserialize)]
+pub struct Outline {
+    #[serde(default = "default_color")]
+    color: RGB,
+    // How much farther, as a share of its distance, the surface in a
+    // neighboring pixel must be for a line
+    #[serde(default = "default_depth_threshold")]
+    depth_threshold: f32,
+    // The angle in degrees between the normals of neighboring pixels for a
+    // line at a crease
+    #[serde(default = "default_normal_threshold")]
+    normal_threshold: f32,
+}
+
+fn default_color() -> RGB {
+    RGB::black()
+}
+
+fn default_depth_threshold() -> f32 {
+    0.1_f32
+}
+
+fn default_normal_threshold() -> f32 {
+    60.0_f32
+}
+
+// What the center of a pixel sees
+struct Sample {
+    depth: f32,
+    normal: Vector3,
+    material: usize,
+}
+
+// Draws the outlines of the scene's outlined materials over the colors,
+// which are stored by column like the output of render
+pub fn draw(colors: &mut Framebuffer, scene: &Scene, workspace: &mut Workspace) {
+    if scene.outlines.is_empty() {
+        return;
+    }
+    let res_x = scene.logistics.resolution_x;
+    let res_y = scene.logistics.resolution_y;
+    let mut samples = Vec::with_capacity((res_x * res_y) as usize);
+    for x in 0..res_x {
+        for y in 0..res_y {
+            samples.push(sample(x, y, scene, workspace));
+        }
+    }
+
+    let index = |x: u32, y: u32| ((x * res_y) + y) as usize;
+    for x in 0..res_x {
+        for y in 0..res_y {
+            let here = match &samples[index(x, y)] {
+                Some(s) => s,
+                None => continue,
+            };
+            let outline = match scene.outlines.get(&here.material) {
+                Some(o) => o,
+                None => continue,
+            };
+            let neighbors = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (nx, ny) in neighbors.iter() {
+                if *nx >= res_x || *ny >= res_y {
+                    continue;
+                }
+                if outline.separates(here, &samples[index(*nx, *ny)]) {
+                    colors.set(index(x, y), outline.color);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn sample(x: u32, y: u32, scene: &Scene, workspace: &mut Workspace) -> Option<Sample> {
+    let res_x = scene.logistics.resolution_x;
+    let res_y = scene.logistics.resolution_y;
+    let u = (x as f32 + 0.5_f32) / res_x as f32;
+    let v = ((res_y - y) as f32 + 0.5_f32) / res_y as f32;
+    let r = scene.camera.get_ray(u, v)?;
+    let (near, far) = scene.camera.clip_range(&r);
+    let t_min = utils::float_max(near, scene.ray_epsilon);
+    let (shape, hit) = scene.shape_aggregate.hit(&r, t_min, far, workspace)?;
+    let t_hit = hit.t;
+    let normal = shape.get_hit_properties(&r, &hit).normal;
+    return Some(Sample {
+        depth: t_hit * r.dir.length(),
+        // Facing the camera, so both sides of a surface agree
+        normal: if normal.dot(r.dir) > 0.0_f32 {
+            -normal
+        } else {
+            normal
+        },
+        material: stats::material_key(&(**shape.get_material())),
+    });
+}
+
+impl Outline {
+    // Whether a line goes between a pixel and a neighbor, drawn on the
+    // pixel's side. Lines between surfaces are drawn on the nearer one.
+    fn separates(&self, here: &Sample, neighbor: &Option<Sample>) -> bool {
+        let there = match neighbor {
+            Some(s) => s,
+            // The edge of the scene against the background
+            None => return true,
+        };
+        if there.depth - here.depth > self.depth_threshold * here.depth {
+            return true;
+        }
+        if here.depth - there.depth > self.depth_threshold * there.depth {
+            return false;
+        }
+        return here.normal.normalized().dot(there.normal.normalized())
+            < self.normal_threshold.to_radians().cos();
+    }
+}
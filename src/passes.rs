@@ -0,0 +1,232 @@
+use crate::aggregate::Workspace;
+use crate::color::RGB;
+use crate::exr;
+use crate::ray::Ray;
+use crate::scene::{Logistics, Scene};
+use crate::tile::Tile;
+
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Images of what each pixel's camera rays hit first, written beside the
+// rendered image for external denoisers and compositing. Each is averaged
+// over the pixel's samples, taken along the same camera rays as the image,
+// so edges are antialiased alike. Rays that hit nothing count as zero.
+// Passes are always held as 32 bit floats in memory and written as linear
+// EXRs, named after the output file with the pass's name before the
+// extension, like image.normal.exr for image.png.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum Pass {
+    // The shading normal in world space, turned to face the camera
+    Normal,
+    // The distance from the camera, in every channel
+    Depth,
+    // The color the surface scatters light with, its texture color for
+    // diffuse surfaces. Emitters that do not scatter are their emission,
+    // clamped to 1. Materials that pick a lobe at random pick one per
+    // sample, so their albedo is the average of their lobes'.
+    Albedo,
+}
+
+impl Pass {
+    fn name(&self) -> &'static str {
+        match self {
+            Pass::Normal => "normal",
+            Pass::Depth => "depth",
+            Pass::Albedo => "albedo",
+        }
+    }
+}
+
+// The summed passes of a tile, filled in a pixel at a time as render_tile
+// traces it, in row-major order within the tile
+pub struct TilePasses {
+    x_min: u32,
+    y_min: u32,
+    width: u32,
+    passes: Vec<Pass>,
+    // The pixel being traced
+    pixel: usize,
+    sums: Vec<Vec<RGB>>,
+}
+
+impl TilePasses {
+    pub fn new(passes: &[Pass], t: &Tile) -> TilePasses {
+        return TilePasses {
+            x_min: t.x_min,
+            y_min: t.y_min,
+            width: t.width(),
+            passes: passes.to_vec(),
+            pixel: 0,
+            sums: vec![vec![RGB::black(); t.pixel_count() as usize]; passes.len()],
+        };
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32) {
+        self.pixel = ((y - self.y_min) * self.width + (x - self.x_min)) as usize;
+    }
+
+    // Adds the first hit of a camera ray through the pixel being traced
+    pub fn add(
+        &mut self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        scene: &Scene,
+        workspace: &mut Workspace,
+    ) {
+        let (shape, hit) = match scene.shape_aggregate.hit(r, t_min, t_max, workspace) {
+            Some(h) => h,
+            None => return,
+        };
+        let t_hit = hit.t;
+        let mut hit_props = shape.get_hit_properties(r, &hit);
+        hit_props.uv = hit_props.uv.clamp_to_valid_coords();
+        hit_props.set_footprint(r, t_hit);
+        for (pass, sums) in self.passes.iter().zip(self.sums.iter_mut()) {
+            let value = match pass {
+                Pass::Normal => {
+                    let mut normal = hit_props.normal.normalized();
+                    if normal.dot(r.dir) > 0.0_f32 {
+                        normal = -normal;
+                    }
+                    RGB::new(normal.x(), normal.y(), normal.z())
+                }
+                Pass::Depth => {
+                    let depth = t_hit * r.dir.length();
+                    RGB::new(depth, depth, depth)
+                }
+                Pass::Albedo => {
+                    let material = shape.get_material();
+                    match material.scatter(r, &hit_props) {
+                        Some(s) => s.attenuation,
+                        None => match material.emit(r, &hit_props) {
+                            Some(e) => {
+                                RGB::new(e.r().min(1.0_f32), e.g().min(1.0_f32), e.b().min(1.0_f32))
+                            }
+                            None => RGB::black(),
+                        },
+                    }
+                }
+            };
+            sums[self.pixel] = sums[self.pixel] + value;
+        }
+    }
+}
+
+// The summed passes of the whole image, each indexed like the framebuffer,
+// by x * resolution_y + y
+pub struct PassImages {
+    passes: Vec<Pass>,
+    res_y: u32,
+    sums: Vec<Vec<RGB>>,
+}
+
+impl PassImages {
+    pub fn new(passes: &[Pass], res_x: u32, res_y: u32) -> PassImages {
+        return PassImages {
+            passes: passes.to_vec(),
+            res_y: res_y,
+            sums: vec![vec![RGB::black(); res_x as usize * res_y as usize]; passes.len()],
+        };
+    }
+
+    pub fn add(&mut self, tile: &TilePasses) {
+        for (image, tile_sums) in self.sums.iter_mut().zip(tile.sums.iter()) {
+            for (i, sum) in tile_sums.iter().enumerate() {
+                let x = tile.x_min + i as u32 % tile.width;
+                let y = tile.y_min + i as u32 / tile.width;
+                image[(x * self.res_y + y) as usize] = *sum;
+            }
+        }
+    }
+
+    // The summed pass over the image, when it was traced
+    pub fn sums(&self, pass: Pass) -> Option<&Vec<RGB>> {
+        return self
+            .passes
+            .iter()
+            .position(|p| *p == pass)
+            .map(|i| &self.sums[i]);
+    }
+
+    // Writes each pass the scene asks for to its file from create_files, averaged over the
+    // samples, with the same metadata as the rendered image. Albedo is
+    // converted from the working space to the output space's primaries, to
+    // match the rendered image.
+    pub fn write(
+        &self,
+        files: Vec<File>,
+        logistics: &Logistics,
+        metadata: &[(&str, &str)],
+    ) -> io::Result<()> {
+        let res_x = logistics.resolution_x;
+        let output_space = logistics.output_space();
+        // Passes traced only for the denoiser are left out
+        let written = logistics.passes();
+        let traced = self.passes.iter().zip(self.sums.iter());
+        for ((pass, image), file) in traced.filter(|(p, _)| written.contains(p)).zip(files) {
+            exr::write_rgb(
+                &mut io::BufWriter::new(file),
+                res_x,
+                self.res_y,
+                output_space,
+                metadata,
+                |y, line| {
+                    for x in 0..res_x {
+                        let mut c = image[(x * self.res_y + y) as usize] / logistics.samples as f32;
+                        if *pass == Pass::Albedo {
+                            c = logistics.working_space().convert_to(output_space, c);
+                        }
+                        line.push(c.r());
+                        line.push(c.g());
+                        line.push(c.b());
+                    }
+                    return Ok(());
+                },
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+// Creates the file of each pass beside the output file, before rendering,
+// so an existing one stops the render before it starts rather than after
+pub fn create_files(out_path: &Path, passes: &[Pass]) -> Result<Vec<File>, String> {
+    let mut files = Vec::with_capacity(passes.len());
+    for pass in passes {
+        let path = pass_path(out_path, *pass);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(f) => files.push(f),
+            Err(e) => {
+                // Don't leave the passes already created behind
+                remove_files(out_path, &passes[..files.len()]);
+                return Err(format!(
+                    "Failed to create the {} pass file {}: {}",
+                    pass.name(),
+                    path.display(),
+                    e
+                ));
+            }
+        }
+    }
+    return Ok(files);
+}
+
+pub fn remove_files(out_path: &Path, passes: &[Pass]) {
+    for pass in passes {
+        let _ = fs::remove_file(pass_path(out_path, *pass));
+    }
+}
+
+// The output path with the pass's name and an EXR extension in place of
+// its own, image.normal.exr for image.png
+fn pass_path(out_path: &Path, pass: Pass) -> PathBuf {
+    let stem = match out_path.file_stem() {
+        Some(s) => s.to_string_lossy().into_owned(),
+        None => String::new(),
+    };
+    return out_path.with_file_name(format!("{}.{}.exr", stem, pass.name()));
+}
@@ -1,17 +1,24 @@
 use crate::point::Point3;
 use crate::ray::Ray;
+use crate::rng;
+use crate::sampling;
+use crate::sampling::OrthonormalBasis;
 use crate::shape;
+use crate::texture::SyncTexture;
 use crate::utils;
-use crate::utils::OrthonormalBasis;
 use crate::vector::Vector3;
 
-use rand::seq::SliceRandom;
+use std::cmp;
 use std::f32;
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub enum PDF {
     Cosine(Cosine),
+    GGX(GGX),
+    Sheen(Sheen),
     Shape(Shape),
+    TexturedEmitter(TexturedEmitter),
     Mixture(Mixture),
 }
 
@@ -19,26 +26,48 @@ impl PDF {
     pub fn value(&self, r: &Ray) -> f32 {
         match self {
             PDF::Cosine(c) => c.value(r),
+            PDF::GGX(g) => g.value(r),
+            PDF::Sheen(s) => s.cosine.value(r),
             PDF::Shape(s) => s.value(r),
+            PDF::TexturedEmitter(t) => t.value(r),
             PDF::Mixture(m) => m.value(r),
         }
     }
+    // How much light arriving along the ray a material's PDF scatters
+    // towards the viewer, as the BRDF times the cosine of the angle to the
+    // normal, leaving out the material's color. For diffuse surfaces this is
+    // just the PDF, but surfaces that can not sample their BRDF exactly
+    // differ.
+    pub fn scattering(&self, r: &Ray) -> f32 {
+        match self {
+            PDF::GGX(g) => g.scattering(r),
+            PDF::Sheen(s) => s.scattering(r),
+            _ => self.value(r),
+        }
+    }
     pub fn generate(&self, origin: &Point3) -> Vector3 {
         match self {
             PDF::Cosine(c) => c.generate(),
+            PDF::GGX(g) => g.generate(),
+            PDF::Sheen(s) => s.cosine.generate(),
             PDF::Shape(s) => s.generate(origin),
+            PDF::TexturedEmitter(t) => t.generate(origin),
             PDF::Mixture(m) => m.generate(origin),
         }
     }
     pub fn is_valid(&self) -> bool {
         match self {
             PDF::Cosine(_) => true,
+            PDF::GGX(_) => true,
+            PDF::Sheen(_) => true,
             PDF::Shape(_) => true,
+            PDF::TexturedEmitter(_) => true,
             PDF::Mixture(m) => !m.is_empty(),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Cosine {
     normal: Vector3,
 }
@@ -58,23 +87,189 @@ impl Cosine {
     }
 
     fn generate(&self) -> Vector3 {
-        OrthonormalBasis::new(&self.normal).local(&utils::random_cosine_direction())
+        OrthonormalBasis::new(&self.normal).local(&sampling::random_cosine_direction())
+    }
+}
+
+// Reflection off microscopic facets whose normals follow the GGX
+// (Trowbridge-Reitz) distribution. Directions are generated by picking a
+// facet normal from the distribution and reflecting the viewer about it.
+// The distribution can be stretched, with a different alpha along each
+// tangent of the surface, for brushed looks.
+// https://www.cs.cornell.edu/~srm/publications/EGSR07-btdf.pdf
+// http://jcgt.org/published/0003/02/03/paper.pdf
+#[derive(Clone)]
+pub struct GGX {
+    // The normal and the surface's tangents, which alpha_u and alpha_v are
+    // along
+    basis: OrthonormalBasis,
+    // The viewer in terms of the basis
+    to_viewer: Vector3,
+    alpha_u: f32,
+    alpha_v: f32,
+    // Fresnel reflectance looking straight down the normal
+    f0: f32,
+}
+
+impl GGX {
+    // Tangents u and v are only needed when the alphas differ
+    pub fn new(
+        normal: Vector3,
+        tangent_u: Vector3,
+        tangent_v: Vector3,
+        in_dir: Vector3,
+        alpha_u: f32,
+        alpha_v: f32,
+        f0: f32,
+    ) -> GGX {
+        let to_viewer = -in_dir.normalized();
+        // Both sides of a surface reflect
+        let normal = if to_viewer.dot(normal) < 0.0_f32 {
+            -normal
+        } else {
+            normal
+        };
+        let basis = if alpha_u == alpha_v {
+            OrthonormalBasis::new(&normal)
+        } else {
+            OrthonormalBasis::with_tangents(&normal, &tangent_u, &tangent_v)
+        };
+        GGX {
+            to_viewer: basis.to_local(&to_viewer),
+            basis: basis,
+            alpha_u: alpha_u,
+            alpha_v: alpha_v,
+            f0: f0,
+        }
+    }
+
+    // Takes a facet normal in terms of the basis
+    fn distribution(&self, half: &Vector3) -> f32 {
+        return sampling::ggx_distribution(half, self.alpha_u, self.alpha_v);
+    }
+
+    // Smith's shadowing and masking for one direction, in terms of the basis
+    fn masking(&self, dir: &Vector3) -> f32 {
+        let u = self.alpha_u * dir.x();
+        let v = self.alpha_v * dir.y();
+        let tan2 = (u * u + v * v) / (dir.z() * dir.z());
+        return 2.0_f32 / (1.0_f32 + (1.0_f32 + tan2).sqrt());
+    }
+
+    // The outgoing direction and the facet normal that reflects the viewer
+    // into it, in terms of the basis, or None if the direction is below the
+    // surface.
+    fn angles(&self, r: &Ray) -> Option<(Vector3, Vector3)> {
+        let out = self.basis.to_local(&r.dir.normalized());
+        if out.z() <= 0.0_f32 || self.to_viewer.z() <= 0.0_f32 {
+            return None;
+        }
+        return Some((out, (out + self.to_viewer).normalized()));
+    }
+
+    fn value(&self, r: &Ray) -> f32 {
+        match self.angles(r) {
+            Some((out, half)) if half.z() > 0.0_f32 && out.dot(half) > 0.0_f32 => {
+                sampling::ggx_normal_pdf(&half, self.alpha_u, self.alpha_v)
+                    / (4.0_f32 * out.dot(half))
+            }
+            _ => 0.0_f32,
+        }
+    }
+
+    fn scattering(&self, r: &Ray) -> f32 {
+        let (out, half) = match self.angles(r) {
+            Some(a) => a,
+            None => return 0.0_f32,
+        };
+        if half.z() <= 0.0_f32 {
+            return 0.0_f32;
+        }
+        let cos_oh = out.dot(half);
+        let fresnel = self.f0 + (1.0_f32 - self.f0) * (1.0_f32 - cos_oh).max(0.0_f32).powi(5);
+        // The BRDF's division by the outgoing cosine cancels with the cosine
+        // it is multiplied by
+        return self.distribution(&half)
+            * self.masking(&self.to_viewer)
+            * self.masking(&out)
+            * fresnel
+            / (4.0_f32 * self.to_viewer.z());
+    }
+
+    // The facet normal's PDF is the distribution times its cosine, and
+    // reflecting turns that into value's PDF
+    fn generate(&self) -> Vector3 {
+        let half = sampling::random_ggx_normal(self.alpha_u, self.alpha_v);
+        let reflected = 2.0_f32 * self.to_viewer.dot(half) * half - self.to_viewer;
+        return self.basis.local(&reflected);
     }
 }
 
+// The soft glow of cloth at grazing angles, from fibers that stand up off
+// the surface, with the "Charlie" distribution and the visibility term from
+// "Production Friendly Microfacet Sheen BRDF" (Estevez and Kulla 2017) and
+// "Crafting a Next-Gen Material Pipeline for The Order: 1886" (Neubelt and
+// Pettineo 2013). The lobe is spread too wide to be worth sampling exactly,
+// so directions are sampled by cosine.
+#[derive(Clone)]
+pub struct Sheen {
+    cosine: Cosine,
+    normal: Vector3,
+    to_viewer: Vector3,
+    // Squared roughness
+    alpha: f32,
+}
+
+impl Sheen {
+    pub fn new(normal: Vector3, in_dir: Vector3, alpha: f32) -> Sheen {
+        let to_viewer = -in_dir.normalized();
+        // Both sides of a surface reflect
+        let normal = if to_viewer.dot(normal) < 0.0_f32 {
+            -normal
+        } else {
+            normal
+        };
+        Sheen {
+            cosine: Cosine::new(normal),
+            normal: normal,
+            to_viewer: to_viewer,
+            alpha: alpha,
+        }
+    }
+
+    fn scattering(&self, r: &Ray) -> f32 {
+        let out = r.dir.normalized();
+        let cos_out = out.dot(self.normal);
+        let cos_view = self.to_viewer.dot(self.normal);
+        if cos_out <= 0.0_f32 || cos_view <= 0.0_f32 {
+            return 0.0_f32;
+        }
+        let cos_half = (out + self.to_viewer).normalized().dot(self.normal);
+        let sin_half = (1.0_f32 - cos_half * cos_half).max(0.0_f32).sqrt();
+        let inv_alpha = 1.0_f32 / self.alpha;
+        let distribution =
+            (2.0_f32 + inv_alpha) * sin_half.powf(inv_alpha) / (2.0_f32 * f32::consts::PI);
+        let visibility = 1.0_f32 / (4.0_f32 * (cos_out + cos_view - cos_out * cos_view));
+        return distribution * visibility * cos_out;
+    }
+}
+
+#[derive(Clone)]
 pub struct Shape {
     shape: Arc<shape::SyncShape>,
+    ray_epsilon: f32,
 }
 
 impl Shape {
-    pub fn new(shape: &Arc<shape::SyncShape>) -> Shape {
+    pub fn new(shape: &Arc<shape::SyncShape>, ray_epsilon: f32) -> Shape {
         Shape {
             shape: Arc::clone(shape),
+            ray_epsilon: ray_epsilon,
         }
     }
 
     fn value(&self, r: &Ray) -> f32 {
-        self.shape.pdf(r)
+        self.shape.pdf(r, self.ray_epsilon)
     }
 
     fn generate(&self, origin: &Point3) -> Vector3 {
@@ -82,13 +277,294 @@ impl Shape {
     }
 }
 
+// Samples points on an emitter's surfaces in proportion to the brightness
+// of its emission texture, so that small bright regions (a TV screen, a
+// window of stained glass) are not left to chance. An emitter is every shape
+// sharing one emissive material, like the triangles of a mesh. Shapes are
+// picked in proportion to the power they emit, their area times their
+// average luminance, and points on each from a CDF over a grid of its unit
+// square parameterization. A shape's grid has as many cells as its share of
+// the texture has detail for, growing with the square root of its area in
+// texture coordinates, so a triangle covering a sliver of the image is a
+// single cell, sampled by area, and costs a handful of texture lookups.
+#[derive(Clone)]
+pub struct TexturedEmitter {
+    shapes: Vec<EmitterShape>,
+    ray_epsilon: f32,
+    // Probability of picking each shape, and the running total of those
+    shape_probabilities: Vec<f32>,
+    cdf: Vec<f32>,
+}
+
+#[derive(Clone)]
+struct EmitterShape {
+    shape: Arc<shape::SyncShape>,
+    area: f32,
+    // Cells along each side of the shape's parameter grid
+    grid_size: usize,
+    // Probability of picking each cell, and the running total of those,
+    // both empty when the shape is sampled by area
+    cell_probabilities: Vec<f32>,
+    cdf: Vec<f32>,
+}
+
+// Most cells along each side of a shape's parameter grid
+const EMITTER_GRID_SIZE: usize = 32;
+// Cells along each side of the unit square of texture coordinates, which a
+// shape's grid is sized to match
+const EMITTER_TEXTURE_CELLS: f32 = 64.0_f32;
+// Texture lookups along each side of a cell when averaging its luminance
+const EMITTER_CELL_SAMPLES: usize = 2;
+// Every cell, and every shape for its area, keeps at least this fraction of
+// the average weight, so that bright texels missed by the lookups can still
+// be sampled.
+const EMITTER_MIN_WEIGHT: f32 = 0.01_f32;
+
+impl TexturedEmitter {
+    // Returns None if the texture is the same everywhere, any of the shapes
+    // has no parameterization, or the texture turns out uniform across
+    // them, in which case sampling by area is just as good.
+    pub fn new(
+        shapes: &[Arc<shape::SyncShape>],
+        emission: &SyncTexture,
+        ray_epsilon: f32,
+    ) -> Option<TexturedEmitter> {
+        if shapes.is_empty() || emission.constant_value().is_some() {
+            return None;
+        }
+        let mut emitter_shapes = Vec::with_capacity(shapes.len());
+        let mut powers = Vec::with_capacity(shapes.len());
+        let mut uniform = true;
+        let mut first_luminance = None;
+        for shape in shapes {
+            let grid_size = emitter_grid_size(shape.as_ref())?;
+            let weights = cell_luminances(shape.as_ref(), emission, grid_size)?;
+            let total_weight: f32 = weights.iter().sum();
+            let luminance = total_weight / weights.len() as f32;
+            let area = shape.surface_area();
+            powers.push(luminance * area);
+
+            let (cell_probabilities, cdf) = match uniform_or_cdf(&weights) {
+                Some(c) => {
+                    uniform = false;
+                    c
+                }
+                None => (Vec::new(), Vec::new()),
+            };
+            match first_luminance {
+                None => first_luminance = Some(luminance),
+                Some(l) if (l - luminance).abs() > utils::float_max(l, luminance) * 0.001_f32 => {
+                    uniform = false
+                }
+                Some(_) => {}
+            }
+            emitter_shapes.push(EmitterShape {
+                shape: Arc::clone(shape),
+                area: area,
+                grid_size: grid_size,
+                cell_probabilities: cell_probabilities,
+                cdf: cdf,
+            });
+        }
+        if uniform {
+            return None;
+        }
+
+        // Dark shapes keep a floor in proportion to their area
+        let total_power: f32 = powers.iter().sum();
+        let total_area: f32 = emitter_shapes.iter().map(|s| s.area).sum();
+        if !(total_area > 0.0_f32) {
+            return None;
+        }
+        let mut weights = Vec::with_capacity(powers.len());
+        for (power, emitter_shape) in powers.iter().zip(emitter_shapes.iter()) {
+            let floor = EMITTER_MIN_WEIGHT * total_power * emitter_shape.area / total_area;
+            weights.push(*power + floor);
+        }
+        let (shape_probabilities, cdf) = probabilities(&weights);
+
+        return Some(TexturedEmitter {
+            shapes: emitter_shapes,
+            ray_epsilon: ray_epsilon,
+            shape_probabilities: shape_probabilities,
+            cdf: cdf,
+        });
+    }
+
+    // The chance of generating the direction, from every shape the ray
+    // meets, as each is sampled apart from the others
+    fn value(&self, r: &Ray) -> f32 {
+        let mut sum = 0.0_f32;
+        for (emitter_shape, probability) in self.shapes.iter().zip(self.shape_probabilities.iter())
+        {
+            let shape = &emitter_shape.shape;
+            let hit = match shape.hit(r, self.ray_epsilon, utils::T_MAX) {
+                Some(h) => h,
+                None => continue,
+            };
+            let hit_props = shape.get_hit_properties(r, &hit);
+            let t_hit = hit.t;
+            let grid_size = emitter_shape.grid_size;
+
+            // Probability per unit area, converted to per unit solid angle
+            let area_pdf = if emitter_shape.cell_probabilities.is_empty() {
+                1.0_f32 / emitter_shape.area
+            } else {
+                let (s, t) = shape.surface_parameters(&hit_props.hit_point);
+                let cell = grid_cell(s, grid_size) * grid_size + grid_cell(t, grid_size);
+                let cell_area = emitter_shape.area / (grid_size * grid_size) as f32;
+                emitter_shape.cell_probabilities[cell] / cell_area
+            };
+            let dist_squared = t_hit * t_hit * r.dir.squared_length();
+            let cosine = (r.dir.dot(hit_props.normal) / r.dir.length()).abs();
+            sum += probability * area_pdf * dist_squared / cosine;
+        }
+        return sum;
+    }
+
+    fn generate(&self, origin: &Point3) -> Vector3 {
+        let emitter_shape = &self.shapes[pick(&self.cdf, rng::random())];
+        let grid_size = emitter_shape.grid_size;
+        let cell = if emitter_shape.cdf.is_empty() {
+            rng::below(grid_size * grid_size)
+        } else {
+            pick(&emitter_shape.cdf, rng::random())
+        };
+        let s = ((cell / grid_size) as f32 + rng::random()) / grid_size as f32;
+        let t = ((cell % grid_size) as f32 + rng::random()) / grid_size as f32;
+        match emitter_shape.shape.surface_point(s, t) {
+            Some((point, _)) => return point - *origin,
+            None => panic!("TexturedEmitter shape lost its surface parameterization!"),
+        }
+    }
+}
+
+// Cells along each side of the shape's grid, from the extent of its texture
+// coordinates, or None if it has no parameterization
+fn emitter_grid_size(shape: &shape::SyncShape) -> Option<usize> {
+    let mut min = (f32::MAX, f32::MAX);
+    let mut max = (-f32::MAX, -f32::MAX);
+    for a in 0..3 {
+        for b in 0..3 {
+            let (_, uv) = shape.surface_point(a as f32 * 0.5_f32, b as f32 * 0.5_f32)?;
+            min = (
+                utils::float_min(min.0, uv.u()),
+                utils::float_min(min.1, uv.v()),
+            );
+            max = (
+                utils::float_max(max.0, uv.u()),
+                utils::float_max(max.1, uv.v()),
+            );
+        }
+    }
+    let uv_area = (max.0 - min.0) * (max.1 - min.1);
+    let side = (uv_area.max(0.0_f32).sqrt() * EMITTER_TEXTURE_CELLS).ceil();
+    return Some(cmp::max(
+        cmp::min(side as usize, EMITTER_GRID_SIZE),
+        1_usize,
+    ));
+}
+
+// The average luminance of the texture in each cell of the shape's grid
+fn cell_luminances(
+    shape: &shape::SyncShape,
+    emission: &SyncTexture,
+    grid_size: usize,
+) -> Option<Vec<f32>> {
+    let cell_size = 1.0_f32 / grid_size as f32;
+    let lookup_size = cell_size / EMITTER_CELL_SAMPLES as f32;
+    let lookups = (EMITTER_CELL_SAMPLES * EMITTER_CELL_SAMPLES) as f32;
+    let mut luminances = Vec::with_capacity(grid_size * grid_size);
+    for i in 0..grid_size {
+        for j in 0..grid_size {
+            let mut luminance = 0.0_f32;
+            for a in 0..EMITTER_CELL_SAMPLES {
+                for b in 0..EMITTER_CELL_SAMPLES {
+                    let s = i as f32 * cell_size + (a as f32 + 0.5_f32) * lookup_size;
+                    let t = j as f32 * cell_size + (b as f32 + 0.5_f32) * lookup_size;
+                    let (point, uv) = shape.surface_point(s, t)?;
+                    luminance += emission
+                        .value(&uv.clamp_to_valid_coords(), &point)
+                        .luminance();
+                }
+            }
+            luminances.push(luminance / lookups);
+        }
+    }
+    return Some(luminances);
+}
+
+// The probabilities and CDF of the cells, each keeping a floor, or None if
+// their weights are all about the same
+fn uniform_or_cdf(weights: &[f32]) -> Option<(Vec<f32>, Vec<f32>)> {
+    let mut min_weight = f32::MAX;
+    let mut max_weight = 0.0_f32;
+    let mut total_weight = 0.0_f32;
+    for w in weights {
+        min_weight = utils::float_min(min_weight, *w);
+        max_weight = utils::float_max(max_weight, *w);
+        total_weight += *w;
+    }
+    if max_weight - min_weight <= max_weight * 0.001_f32 {
+        return None;
+    }
+    let floor = EMITTER_MIN_WEIGHT * total_weight / weights.len() as f32;
+    let floored: Vec<f32> = weights.iter().map(|w| *w + floor).collect();
+    return Some(probabilities(&floored));
+}
+
+// The weights scaled to sum to one, and their running total
+fn probabilities(weights: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    let total_weight: f32 = weights.iter().sum();
+    let mut probabilities = Vec::with_capacity(weights.len());
+    let mut cdf = Vec::with_capacity(weights.len());
+    let mut running_total = 0.0_f32;
+    for w in weights {
+        let probability = *w / total_weight;
+        running_total += probability;
+        probabilities.push(probability);
+        cdf.push(running_total);
+    }
+    return (probabilities, cdf);
+}
+
+// The index the CDF puts x in [0, 1) at
+fn pick(cdf: &[f32], x: f32) -> usize {
+    return cmp::min(cdf.partition_point(|c| *c <= x), cdf.len() - 1_usize);
+}
+
+fn grid_cell(parameter: f32, grid_size: usize) -> usize {
+    return cmp::min((parameter * grid_size as f32) as usize, grid_size - 1_usize);
+}
+
+// Picks one of its members at random to generate each direction, evenly or
+// in proportion to the members' weights
+#[derive(Clone)]
 pub struct Mixture {
     members: Vec<PDF>,
+    // Probability of picking each member, and the running total of those,
+    // empty when they are picked evenly
+    weights: Vec<f32>,
+    cdf: Vec<f32>,
 }
 
 impl Mixture {
     pub fn new(members: Vec<PDF>) -> Mixture {
-        Mixture { members: members }
+        let weight = 1.0_f32 / members.len() as f32;
+        Mixture {
+            weights: vec![weight; members.len()],
+            cdf: Vec::new(),
+            members: members,
+        }
+    }
+
+    pub fn weighted(members: Vec<PDF>, weights: &[f32]) -> Mixture {
+        let (weights, cdf) = probabilities(weights);
+        Mixture {
+            members: members,
+            weights: weights,
+            cdf: cdf,
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -96,10 +572,9 @@ impl Mixture {
     }
 
     fn value(&self, r: &Ray) -> f32 {
-        let weight = 1.0_f32 / self.members.len() as f32;
         let mut sum = 0.0_f32;
 
-        for pdf in &self.members {
+        for (pdf, weight) in self.members.iter().zip(self.weights.iter()) {
             sum += weight * pdf.value(r);
         }
 
@@ -107,7 +582,12 @@ impl Mixture {
     }
 
     fn generate(&self, origin: &Point3) -> Vector3 {
-        match self.members.choose(&mut rand::thread_rng()) {
+        let index = if self.cdf.is_empty() {
+            rng::below(self.members.len())
+        } else {
+            pick(&self.cdf, rng::random())
+        };
+        match self.members.get(index) {
             Some(m) => m.generate(origin),
             None => panic!("Mixture PDF had no members!"),
         }
@@ -119,7 +599,7 @@ pub fn pair_value(first: &PDF, second: &PDF, r: &Ray) -> f32 {
 }
 
 pub fn pair_generate(first: &PDF, second: &PDF, origin: &Point3) -> Vector3 {
-    let r = rand::random::<f32>();
+    let r = rng::random();
     if r < 0.5_f32 {
         first.generate(origin)
     } else {
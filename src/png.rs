@@ -0,0 +1,110 @@
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use std::io;
+use std::io::Write;
+
+// Writes 8 bit RGB as a PNG image one row at a time, from top to bottom, so
+// the whole image never has to be in memory at once. rows fills in the
+// pixels of the given row, three bytes each from left to right. Each pair of
+// text is stored as a keyword and its text, for metadata like the scene's
+// fingerprint.
+pub fn write_rgb8<W, F>(
+    out: W,
+    width: u32,
+    height: u32,
+    text: &[(&str, &str)],
+    mut rows: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(u32, &mut Vec<u8>) -> io::Result<()>,
+{
+    let mut out = out;
+    out.write_all(&[
+        0x89_u8, b'P', b'N', b'G', 0x0d_u8, 0x0a_u8, 0x1a_u8, 0x0a_u8,
+    ])?;
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits a channel, truecolor, deflate, adaptive filtering, and no
+    // interlacing
+    header.extend_from_slice(&[8_u8, 2_u8, 0_u8, 0_u8, 0_u8]);
+    write_chunk(&mut out, b"IHDR", &header)?;
+    for (keyword, value) in text {
+        let mut data = Vec::with_capacity(keyword.len() + 1 + value.len());
+        data.extend_from_slice(keyword.as_bytes());
+        data.push(0_u8);
+        data.extend_from_slice(value.as_bytes());
+        write_chunk(&mut out, b"tEXt", &data)?;
+    }
+
+    let mut encoder = ZlibEncoder::new(
+        ChunkWriter {
+            out: out,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        },
+        Compression::Default,
+    );
+    let mut line = Vec::with_capacity(width as usize * 3_usize);
+    let mut filtered = Vec::with_capacity(width as usize * 3_usize + 1_usize);
+    for y in 0..height {
+        line.clear();
+        rows(y, &mut line)?;
+        // Each byte is stored as its difference from the same channel of
+        // the pixel to its left (the Sub filter), which compresses rendered
+        // images, smooth for the most part, far better than raw bytes
+        filtered.clear();
+        filtered.push(1_u8);
+        for (i, b) in line.iter().enumerate() {
+            let left = if i >= 3 { line[i - 3] } else { 0_u8 };
+            filtered.push(b.wrapping_sub(left));
+        }
+        encoder.write_all(&filtered)?;
+    }
+    let mut chunks = encoder.finish()?;
+    chunks.flush()?;
+    write_chunk(&mut chunks.out, b"IEND", &[])?;
+    return chunks.out.flush();
+}
+
+// How much compressed data goes in each IDAT chunk
+const CHUNK_SIZE: usize = 1 << 16;
+
+// Splits the compressed image data into IDAT chunks as it comes
+struct ChunkWriter<W: Write> {
+    out: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let taken = data.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..taken]);
+        if self.buffer.len() == CHUNK_SIZE {
+            write_chunk(&mut self.out, b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        return Ok(taken);
+    }
+
+    // Writes whatever is left over as a last, shorter chunk
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.out, b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        return self.out.flush();
+    }
+}
+
+// Every chunk is its length, its type, its data, then a CRC of the type and
+// data
+fn write_chunk<W: Write>(out: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(chunk_type)?;
+    out.write_all(data)?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    return out.write_all(&crc.finalize().to_be_bytes());
+}
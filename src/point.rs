@@ -5,7 +5,6 @@ use crate::vector::Vector3;
 use serde::Deserialize;
 use std::convert;
 use std::ops;
-use wavefront_obj::obj;
 
 #[derive(Deserialize)]
 #[serde(try_from = "Vec<f32>")]
@@ -93,12 +92,6 @@ impl convert::TryFrom<Vec<f32>> for Point3 {
     }
 }
 
-impl convert::From<obj::Vertex> for Point3 {
-    fn from(vertex: obj::Vertex) -> Self {
-        Point3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32)
-    }
-}
-
 impl ops::Index<Axis> for Point3 {
     type Output = f32;
     fn index(&self, index: Axis) -> &f32 {
@@ -0,0 +1,217 @@
+use serde_json::{json, Map, Value};
+
+// Ready made materials, which a scene can use by name instead of describing
+// them, as `{"library": "Gold"}` in its Materials. Any other keys given with
+// the name replace the library material's own, so `{"library":
+// "CarPaintRed", "flake_density": 0.6}` is the red car paint with more
+// flakes. Library materials are expanded into ordinary materials before the
+// scene is read, and the textures and helper materials they need are added
+// alongside them, named after the material with their role, like
+// "Paint color" for a material named "Paint".
+pub const NAMES: [&str; 19] = [
+    "Gold",
+    "Silver",
+    "Copper",
+    "Aluminum",
+    "Glass",
+    "CrownGlass",
+    "Water",
+    "Diamond",
+    "WhitePlastic",
+    "RedPlastic",
+    "BlackRubber",
+    "RedVelvet",
+    "Chalk",
+    "CarPaintRed",
+    "CarPaintBlack",
+    "CarPaintSilver",
+    "CarPaintBlue",
+    "CarPaintPearl",
+    "CarPaintChameleon",
+];
+
+// A library material, with the textures and other materials it names
+struct Preset {
+    material: Value,
+    textures: Vec<(String, Value)>,
+    materials: Vec<(String, Value)>,
+}
+
+pub fn expand(top_level: &mut Value) -> Result<(), String> {
+    let mut expanded = Vec::new();
+    match top_level.get("Materials").and_then(|m| m.as_object()) {
+        Some(materials) => {
+            for (name, value) in materials.iter() {
+                let library_name = match value.get("library") {
+                    Some(l) => match l.as_str() {
+                        Some(s) => s,
+                        None => {
+                            return Err(format!(
+                                "Material {} has a 'library' name that is not a string.",
+                                name
+                            ))
+                        }
+                    },
+                    None => continue,
+                };
+                if value.get("type").is_some() {
+                    return Err(format!(
+                        "Material {} takes its type from library material {}, and can not give one.",
+                        name, library_name
+                    ));
+                }
+                let mut preset = match preset(library_name, name) {
+                    Some(p) => p,
+                    None => {
+                        return Err(format!(
+                            "Material {} names library material {}, which is not one of: {}",
+                            name,
+                            library_name,
+                            NAMES.join(", ")
+                        ))
+                    }
+                };
+                for (key, v) in value.as_object().into_iter().flatten() {
+                    if key != "library" {
+                        preset.material[key] = Value::clone(v);
+                    }
+                }
+                expanded.push((String::clone(name), preset));
+            }
+        }
+        // Left for the scene to report
+        None => return Ok(()),
+    }
+
+    for (name, preset) in expanded {
+        add_all(top_level, "Textures", preset.textures)?;
+        add_all(top_level, "Materials", preset.materials)?;
+        top_level["Materials"][name] = preset.material;
+    }
+    return Ok(());
+}
+
+fn add_all(
+    top_level: &mut Value,
+    section: &str,
+    entries: Vec<(String, Value)>,
+) -> Result<(), String> {
+    let map: &mut Map<String, Value> =
+        match top_level.get_mut(section).and_then(|s| s.as_object_mut()) {
+            Some(m) => m,
+            None => return Err(format!("'{}' is not a JSON object.", section)),
+        };
+    for (name, value) in entries {
+        if map.contains_key(&name) {
+            return Err(format!(
+                "A library material needs to add {} to {}, which already has one.",
+                name, section
+            ));
+        }
+        map.insert(name, value);
+    }
+    return Ok(());
+}
+
+fn preset(library_name: &str, name: &str) -> Option<Preset> {
+    let role = |r: &str| format!("{} {}", name, r);
+    let preset = match library_name {
+        "Gold" | "Silver" | "Copper" | "Aluminum" => Preset {
+            material: json!({"type": "Conductor", "preset": library_name}),
+            textures: vec![],
+            materials: vec![],
+        },
+        "Glass" => dielectric(json!({"refractive_index": 1.5})),
+        // Schott N-BK7
+        "CrownGlass" => dielectric(json!({"sellmeier": {
+            "b": [1.03961212, 0.231792344, 1.01046945],
+            "c": [0.00600069867, 0.0200179144, 103.560653]
+        }})),
+        "Water" => dielectric(json!({"refractive_index": 1.333})),
+        // Peter (1923)
+        "Diamond" => dielectric(json!({"sellmeier": {
+            "b": [0.3306, 4.3356],
+            "c": [0.030625, 0.011236]
+        }})),
+        "WhitePlastic" | "RedPlastic" => {
+            let color = if library_name == "WhitePlastic" {
+                [0.8, 0.8, 0.8]
+            } else {
+                [0.6, 0.03, 0.03]
+            };
+            Preset {
+                material: json!({"type": "Clearcoat", "base": role("base"), "roughness": 0.05}),
+                textures: vec![(role("color"), constant(color))],
+                materials: vec![(
+                    role("base"),
+                    json!({"type": "Lambert", "albedo": role("color")}),
+                )],
+            }
+        }
+        "BlackRubber" => Preset {
+            material: json!({"type": "GGX", "albedo": role("color"), "roughness": 0.6}),
+            textures: vec![(role("color"), constant([0.04, 0.04, 0.04]))],
+            materials: vec![],
+        },
+        "RedVelvet" => Preset {
+            material: json!({
+                "type": "Sheen",
+                "albedo": role("color"),
+                "sheen": role("sheen"),
+                "roughness": 0.3
+            }),
+            textures: vec![
+                (role("color"), constant([0.35, 0.01, 0.03])),
+                (role("sheen"), constant([0.9, 0.4, 0.45])),
+            ],
+            materials: vec![],
+        },
+        "Chalk" => Preset {
+            material: json!({"type": "Lambert", "albedo": role("color")}),
+            textures: vec![(role("color"), constant([0.85, 0.85, 0.82]))],
+            materials: vec![],
+        },
+        "CarPaintRed" => car_paint(name, [0.5, 0.02, 0.02], None),
+        "CarPaintBlack" => car_paint(name, [0.02, 0.02, 0.02], None),
+        "CarPaintSilver" => car_paint(name, [0.6, 0.6, 0.62], None),
+        "CarPaintBlue" => car_paint(name, [0.03, 0.1, 0.5], None),
+        // White that turns gold towards the edges
+        "CarPaintPearl" => car_paint(name, [0.8, 0.8, 0.78], Some([0.7, 0.5, 0.2])),
+        // Teal that turns purple towards the edges
+        "CarPaintChameleon" => car_paint(name, [0.02, 0.4, 0.35], Some([0.35, 0.05, 0.45])),
+        _ => return None,
+    };
+    return Some(preset);
+}
+
+fn constant(color: [f32; 3]) -> Value {
+    return json!({"type": "Constant", "color": color});
+}
+
+fn dielectric(properties: Value) -> Preset {
+    let mut material = properties;
+    material["type"] = json!("Dielectric");
+    return Preset {
+        material: material,
+        textures: vec![],
+        materials: vec![],
+    };
+}
+
+fn car_paint(name: &str, color: [f32; 3], flop_color: Option<[f32; 3]>) -> Preset {
+    let role = |r: &str| format!("{} {}", name, r);
+    let mut material = json!({"type": "CarPaint", "color": role("color")});
+    let mut textures = vec![(role("color"), constant(color))];
+    match flop_color {
+        Some(f) => {
+            material["flop_color"] = json!(role("flop color"));
+            textures.push((role("flop color"), constant(f)));
+        }
+        None => {}
+    }
+    return Preset {
+        material: material,
+        textures: textures,
+        materials: vec![],
+    };
+}
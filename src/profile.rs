@@ -0,0 +1,126 @@
+// Timers for the stages of the hot path, compiled in with the `profile`
+// feature. Each thread adds to its own totals, and the totals of every
+// thread are summed for the report at the end. Without the feature, timers
+// are empty and compile away, so release builds pay nothing for them.
+
+#[cfg(feature = "profile")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "profile")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "profile")]
+use std::time::Instant;
+
+pub enum Stage {
+    // Finding the closest hit for a ray, including shadow rays
+    Intersection,
+    // Hit properties, emission and scattering for a hit
+    Shading,
+    // Texture lookups, which happen as part of shading
+    Texture,
+    // Generating directions from and evaluating the scattering PDFs
+    PDF,
+}
+
+#[cfg(feature = "profile")]
+const STAGE_NAMES: [&str; 4] = [
+    "Intersection",
+    "Shading",
+    "Texture sampling (part of shading)",
+    "PDF evaluation",
+];
+
+// Only the owning thread writes its totals, so they are updated with plain
+// loads and stores rather than read-modify-writes
+#[cfg(feature = "profile")]
+struct Totals {
+    nanos: [AtomicU64; 4],
+    calls: [AtomicU64; 4],
+}
+
+#[cfg(feature = "profile")]
+static THREAD_TOTALS: Mutex<Vec<Arc<Totals>>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "profile")]
+thread_local! {
+    static TOTALS: Arc<Totals> = {
+        let totals = Arc::new(Totals {
+            nanos: Default::default(),
+            calls: Default::default(),
+        });
+        THREAD_TOTALS.lock().unwrap().push(Arc::clone(&totals));
+        totals
+    };
+}
+
+// Records the time from its creation to being dropped against a stage
+#[cfg(feature = "profile")]
+pub struct Timer {
+    stage: usize,
+    start: Instant,
+}
+
+#[cfg(not(feature = "profile"))]
+pub struct Timer;
+
+#[cfg(feature = "profile")]
+pub fn start(stage: Stage) -> Timer {
+    Timer {
+        stage: stage as usize,
+        start: Instant::now(),
+    }
+}
+
+#[cfg(not(feature = "profile"))]
+#[inline(always)]
+pub fn start(_stage: Stage) -> Timer {
+    Timer
+}
+
+#[cfg(feature = "profile")]
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_nanos() as u64;
+        TOTALS.with(|t| {
+            let (nanos, calls) = (&t.nanos[self.stage], &t.calls[self.stage]);
+            nanos.store(nanos.load(Ordering::Relaxed) + elapsed, Ordering::Relaxed);
+            calls.store(calls.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        });
+    }
+}
+
+pub fn enabled() -> bool {
+    cfg!(feature = "profile")
+}
+
+// Time per stage summed over every thread, or None without the feature
+#[cfg(feature = "profile")]
+pub fn report() -> Option<String> {
+    let threads = THREAD_TOTALS.lock().unwrap();
+    let mut report = format!(
+        "Hot path profile (summed over {} threads):\n",
+        threads.len()
+    );
+    for (stage, name) in STAGE_NAMES.iter().enumerate() {
+        let nanos: u64 = threads
+            .iter()
+            .map(|t| t.nanos[stage].load(Ordering::Relaxed))
+            .sum();
+        let calls: u64 = threads
+            .iter()
+            .map(|t| t.calls[stage].load(Ordering::Relaxed))
+            .sum();
+        report.push_str(&format!(
+            "    {}: {:.3} s in {} calls ({:.0} ns each)\n",
+            name,
+            nanos as f64 * 1e-9_f64,
+            calls,
+            nanos as f64 / (calls as f64).max(1.0_f64)
+        ));
+    }
+    return Some(report);
+}
+
+#[cfg(not(feature = "profile"))]
+pub fn report() -> Option<String> {
+    None
+}
@@ -0,0 +1,118 @@
+use crate::point::Point3;
+use crate::texture::TexCoord;
+use crate::vector::Vector3;
+
+use serde::Deserialize;
+use std::f32;
+
+// Ways of making up texture coordinates for mesh faces that have none, from
+// where their vertices are in the mesh's own space, so that textures stay
+// put on the mesh under its transform. Planar and cubic projections fit the
+// largest side of the mesh's bounds to the unit square, so the texture
+// covers the mesh once without stretching.
+#[derive(Deserialize, Clone, Copy, Hash)]
+pub enum UVProjection {
+    // Along the Z axis, with X and Y becoming u and v
+    Planar,
+    // Longitude and latitude around the center of the mesh's bounds
+    Spherical,
+    // Along whichever axis each face faces most, like the sides of a box
+    Cubic,
+}
+
+pub struct Projector {
+    projection: UVProjection,
+    min: Point3,
+    center: Point3,
+    size: f32,
+}
+
+impl Projector {
+    pub fn new(projection: UVProjection, vertices: &Vec<Point3>) -> Projector {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(-f32::MAX, -f32::MAX, -f32::MAX);
+        for v in vertices {
+            min = Point3::min(min, *v);
+            max = Point3::max(max, *v);
+        }
+        let extent = max - min;
+        Projector {
+            projection: projection,
+            min: min,
+            center: min + 0.5_f32 * extent,
+            size: extent
+                .x()
+                .max(extent.y())
+                .max(extent.z())
+                .max(f32::MIN_POSITIVE),
+        }
+    }
+
+    // Texture coordinates for the corners of the face with the given vertices
+    pub fn face_uvs(&self, corners: [Point3; 3]) -> [TexCoord; 3] {
+        match self.projection {
+            UVProjection::Planar => {
+                return [
+                    self.planar(corners[0], 0, 1),
+                    self.planar(corners[1], 0, 1),
+                    self.planar(corners[2], 0, 1),
+                ];
+            }
+            UVProjection::Cubic => {
+                let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+                let (x, y, z) = (normal.x().abs(), normal.y().abs(), normal.z().abs());
+                let (u_axis, v_axis) = if x >= y && x >= z {
+                    (2, 1)
+                } else if y >= z {
+                    (0, 2)
+                } else {
+                    (0, 1)
+                };
+                return [
+                    self.planar(corners[0], u_axis, v_axis),
+                    self.planar(corners[1], u_axis, v_axis),
+                    self.planar(corners[2], u_axis, v_axis),
+                ];
+            }
+            UVProjection::Spherical => {
+                let mut uvs = [
+                    self.spherical(corners[0] - self.center),
+                    self.spherical(corners[1] - self.center),
+                    self.spherical(corners[2] - self.center),
+                ];
+                // A face across the seam where longitude wraps around would
+                // otherwise stretch back over the whole texture
+                let min_u = uvs.iter().map(|uv| uv.u()).fold(f32::MAX, f32::min);
+                let max_u = uvs.iter().map(|uv| uv.u()).fold(f32::MIN, f32::max);
+                if max_u - min_u > 0.5_f32 {
+                    for uv in uvs.iter_mut() {
+                        if uv.u() < 0.5_f32 {
+                            *uv = TexCoord::new(uv.u() + 1.0_f32, uv.v());
+                        }
+                    }
+                }
+                return uvs;
+            }
+        }
+    }
+
+    fn planar(&self, p: Point3, u_axis: usize, v_axis: usize) -> TexCoord {
+        let offset = [
+            p.x() - self.min.x(),
+            p.y() - self.min.y(),
+            p.z() - self.min.z(),
+        ];
+        return TexCoord::new(offset[u_axis] / self.size, offset[v_axis] / self.size);
+    }
+
+    fn spherical(&self, d: Vector3) -> TexCoord {
+        let length = d.length();
+        if length == 0.0_f32 {
+            return TexCoord::new(0.5_f32, 0.5_f32);
+        }
+        return TexCoord::new(
+            0.5_f32 + d.z().atan2(d.x()) / (2.0_f32 * f32::consts::PI),
+            0.5_f32 + (d.y() / length).asin() / f32::consts::PI,
+        );
+    }
+}
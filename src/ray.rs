@@ -1,17 +1,60 @@
 use crate::point::Point3;
 use crate::vector::Vector3;
 
+// What a ray was cast for, which materials can hide from. Rays scattered
+// into a single direction, by mirrors, glass, metal and volumes, count as
+// glossy along with those importance sampled from GGX, and the rest of the
+// scattered rays count as diffuse.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RayKind {
+    Camera,
+    Diffuse,
+    Glossy,
+}
+
 pub struct Ray {
     pub origin: Point3,
     pub dir: Vector3,
+    // When the ray was cast, within the camera's shutter interval. Moving
+    // shapes are hit wherever they are at this time.
+    pub time: f32,
+    // The wavelength, in nanometers, of the path the ray is part of, once a
+    // dispersive material has picked one for it
+    pub wavelength: Option<f32>,
+    // How many radians wider the ray's footprint grows per unit of length,
+    // for filtering textures. Only camera rays are given one, everything
+    // else is 0.
+    pub spread: f32,
 }
 
 impl Ray {
-    pub fn new(origin: Point3, dir: Vector3) -> Ray {
+    pub fn new(origin: Point3, dir: Vector3, time: f32) -> Ray {
         Ray {
             origin: origin,
             dir: dir,
+            time: time,
+            wavelength: None,
+            spread: 0.0_f32,
+        }
+    }
+
+    pub fn with_spread(mut self, spread: f32) -> Ray {
+        self.spread = spread;
+        return self;
+    }
+
+    pub fn with_wavelength(mut self, wavelength: Option<f32>) -> Ray {
+        self.wavelength = wavelength;
+        return self;
+    }
+
+    // The ray on the same path as another, taking the path's wavelength
+    // unless it was given one of its own
+    pub fn continuing(mut self, from: &Ray) -> Ray {
+        if self.wavelength.is_none() {
+            self.wavelength = from.wavelength;
         }
+        return self;
     }
 
     pub fn point_at(&self, t: f32) -> Point3 {
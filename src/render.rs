@@ -0,0 +1,655 @@
+// Tracing a scene's samples into an image across threads, tile by tile,
+// and converting the summed colors for output
+
+use crate::aggregate;
+use crate::color::{COLOR_SPACE, RGB};
+use crate::denoise;
+use crate::framebuffer::{self, CompensatedSum, Framebuffer};
+use crate::gradient;
+use crate::live::LiveOutput;
+use crate::lobes;
+use crate::normals;
+use crate::numa;
+use crate::outline;
+use crate::passes;
+use crate::progress::Progress;
+use crate::ray::{Ray, RayKind};
+use crate::resampling;
+use crate::rng;
+use crate::scene::{Logistics, Scene};
+use crate::stats::{self, Statistics};
+use crate::tile;
+use crate::utils;
+use rayon::prelude::*;
+use std::{
+    io, panic,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    sync::Mutex,
+};
+
+// Traces every sample of the scene across the given number of threads,
+// returning the summed (not yet averaged) color of each pixel, indexed by
+// x * resolution_y + y, along with the scene's passes, if any, and the
+// render statistics. If tracing panics
+// on any thread, the other threads stop at their next pixel, and the error
+// says where the first panic happened. Each finished tile is also sent to the
+// live output, when given.
+pub fn render(
+    scene_spec: &Scene,
+    num_threads: u32,
+    progress_output: Arc<Mutex<dyn io::Write + Send + Sync>>,
+    live: Option<&LiveOutput>,
+) -> Result<(Framebuffer, Option<passes::PassImages>, Statistics), String> {
+    let res_x = scene_spec.logistics.resolution_x;
+    let res_y = scene_spec.logistics.resolution_y;
+    let samples = scene_spec.logistics.samples;
+    // Refuse before tracing rather than after
+    if scene_spec.logistics.denoise.unwrap_or(false) && !denoise::AVAILABLE {
+        return Err(String::from(
+            "Denoising needs a build with the denoise feature, see the Readme.",
+        ));
+    }
+
+    // Set up a structure to track progress and print to the given output
+    let progress_tracker = Mutex::new(Progress::new(
+        res_x as u64 * res_y as u64 * samples as u64,
+        progress_output,
+        20_u32,
+    ));
+
+    // Threads take tiles off of the tile queue, render all of each one's
+    // samples into a private buffer, and copy the finished buffer into the
+    // image.
+    let mut tiles = tile::split_into_tiles(res_x, res_y, tile::TILE_SIZE);
+    match scene_spec.logistics.tile_focus {
+        Some(focus) => tile::sort_by_focus(&mut tiles, res_x, res_y, focus),
+        None => {}
+    }
+    match live {
+        Some(l) => l.start(&scene_spec.logistics, tiles.len(), &scene_spec.fingerprint),
+        None => {}
+    }
+    let regions = match &scene_spec.logistics.priority_regions {
+        Some(r) => r.as_slice(),
+        None => &[],
+    };
+    let (queue, workers) = tile::TileQueue::new(tiles.len(), num_threads as usize, |i| {
+        regions
+            .iter()
+            .any(|r| tile::overlaps(&tiles[i], res_x, res_y, *r))
+    });
+    let frame = Mutex::new(Framebuffer::new(
+        scene_spec.logistics.framebuffer(),
+        res_x,
+        res_y,
+        samples,
+    )?);
+    // Gradients beside the image, for gradient domain rendering
+    let gradients = match scene_spec.logistics.gradient_domain {
+        Some(_) => Some(Mutex::new(gradient::Gradients::new(res_x, res_y))),
+        None => None,
+    };
+    let pass_list = scene_spec.logistics.traced_passes();
+    let pass_images = if pass_list.is_empty() {
+        None
+    } else {
+        Some(Mutex::new(passes::PassImages::new(
+            &pass_list, res_x, res_y,
+        )))
+    };
+    // Render statistics are gathered per thread, then merged here
+    let stats = Mutex::new(Statistics::new());
+    let cancelled = AtomicBool::new(false);
+    let failure: Mutex<Option<String>> = Mutex::new(None);
+
+    let mut pool_builder = rayon::ThreadPoolBuilder::new().num_threads(num_threads as usize);
+    if scene_spec.logistics.pin_threads.unwrap_or(false) {
+        pool_builder = pool_builder.start_handler(|index| match numa::pin_current_thread(index) {
+            Ok(()) => {}
+            Err(e) => eprintln!("{}", e),
+        });
+    }
+    let pool = pool_builder
+        .build()
+        .expect("Failed to create the tracing thread pool.");
+    pool.scope(|scope| {
+        for (thread, local) in workers.into_iter().enumerate() {
+            // Each thread owns its end of the tile queue, everything else is
+            // lent
+            let (
+                tiles,
+                queue,
+                frame,
+                gradients,
+                pass_images,
+                stats,
+                cancelled,
+                failure,
+                progress_tracker,
+            ) = (
+                &tiles,
+                &queue,
+                &frame,
+                gradients.as_ref(),
+                pass_images.as_ref(),
+                &stats,
+                &cancelled,
+                &failure,
+                &progress_tracker,
+            );
+            scope.spawn(move |_| {
+                let mut aggregate_workspace = scene_spec.shape_aggregate.get_workspace();
+                let mut local_stats = Statistics::new();
+                loop {
+                    if cancelled.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let tile_idx = match queue.next(thread, &local) {
+                        Some(t) => t,
+                        None => break,
+                    };
+                    // Nothing shared is locked while tracing, so a panic
+                    // leaves the rest of the render in a usable state
+                    let rendered = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                        render_tile(
+                            scene_spec,
+                            &tiles[tile_idx],
+                            samples,
+                            scene_spec.logistics.seed,
+                            &mut aggregate_workspace,
+                            &mut local_stats,
+                            &cancelled,
+                        )
+                    }));
+                    let (tile_colors, tile_sums, tile_passes) = match rendered {
+                        Ok(Some(c)) => c,
+                        Ok(None) => break,
+                        Err(payload) => {
+                            cancelled.store(true, Ordering::Relaxed);
+                            let mut failure =
+                                failure.lock().expect("Thread failed to lock render failure.");
+                            if failure.is_none() {
+                                *failure = Some(format!(
+                                    "Tracing failed at {}: {}",
+                                    local_stats.position(
+                                        &scene_spec.shape_names,
+                                        &scene_spec.material_names
+                                    ),
+                                    panic_message(&payload)
+                                ));
+                            }
+                            break;
+                        }
+                    };
+                    match live {
+                        Some(l) => l.tile(&tiles[tile_idx], &tile_colors, &scene_spec.logistics),
+                        None => {}
+                    }
+                    let stored = {
+                        let t = &tiles[tile_idx];
+                        let mut frame = frame.lock().expect("Thread failed to lock the image.");
+                        (t.y_min..t.y_max).try_for_each(|y| {
+                            let start = ((y - t.y_min) * t.width()) as usize;
+                            frame.set_row(
+                                t.x_min,
+                                y,
+                                &tile_colors[start..start + t.width() as usize],
+                                res_y,
+                            )
+                        })
+                    };
+                    match (gradients, &tile_sums) {
+                        (Some(g), Some(sums)) => g
+                            .lock()
+                            .expect("Thread failed to lock the gradients.")
+                            .add(sums),
+                        _ => {}
+                    }
+                    match (pass_images, &tile_passes) {
+                        (Some(images), Some(p)) => images
+                            .lock()
+                            .expect("Thread failed to lock the passes.")
+                            .add(p),
+                        _ => {}
+                    }
+                    match stored {
+                        Ok(()) => {}
+                        Err(e) => {
+                            cancelled.store(true, Ordering::Relaxed);
+                            let mut failure =
+                                failure.lock().expect("Thread failed to lock render failure.");
+                            if failure.is_none() {
+                                *failure = Some(format!("Failed to store a finished tile: {}", e));
+                            }
+                            break;
+                        }
+                    }
+                    progress_tracker
+                        .lock()
+                        .expect(
+                            "Failed to lock command line progress tracker from worker thread for update",
+                        )
+                        .update(tiles[tile_idx].pixel_count() as u64 * samples as u64);
+                }
+                stats
+                    .lock()
+                    .expect("Thread failed to lock render statistics for merging.")
+                    .merge(&local_stats);
+            });
+        }
+    });
+    progress_tracker
+        .lock()
+        .expect("Failed to lock the command line progress tracker from the main thread")
+        .done();
+    let failure = failure
+        .into_inner()
+        .expect("Main thread failed to take the render failure.");
+    match live {
+        Some(l) => l.finish(failure.as_ref()),
+        None => {}
+    }
+    match failure {
+        Some(e) => return Err(e),
+        None => {}
+    }
+
+    let mut out_colors = frame
+        .into_inner()
+        .expect("Main thread failed to take the image.");
+    match live {
+        Some(l) if l.interactive() => refine(scene_spec, &pool, &mut out_colors, l, &stats)?,
+        _ => {}
+    }
+    match (&scene_spec.logistics.gradient_domain, gradients) {
+        (Some(settings), Some(g)) => gradient::reconstruct(
+            &mut out_colors,
+            &g.into_inner()
+                .expect("Main thread failed to take the gradients."),
+            settings,
+            samples,
+        )?,
+        _ => {}
+    }
+    // Outlines are drawn after, so they stay crisp
+    let mut out_passes = pass_images.map(|p| {
+        p.into_inner()
+            .expect("Main thread failed to take the passes.")
+    });
+    match &mut out_passes {
+        Some(p) if scene_spec.logistics.denoise.unwrap_or(false) => {
+            denoise::denoise(&mut out_colors, p, &scene_spec.logistics)?
+        }
+        _ => {}
+    }
+    outline::draw(
+        &mut out_colors,
+        scene_spec,
+        &mut scene_spec.shape_aggregate.get_workspace(),
+    );
+    let out_stats = stats
+        .into_inner()
+        .expect("Main thread failed to lock render statistics for reporting.");
+    return Ok((out_colors, out_passes, out_stats));
+}
+
+// Traces the regions of the finished image that an interactive frontend
+// asks for again, until it closes, merging each pixel's new samples with
+// those it has. The image holds sums over the scene's sample count, so the
+// merged sums are scaled back to that count, which leaves them the average
+// of every sample the pixel has taken. Gradient domain renders have no way
+// to merge more samples into their gradients, so refuse.
+fn refine(
+    scene_spec: &Scene,
+    pool: &rayon::ThreadPool,
+    frame: &mut Framebuffer,
+    live: &LiveOutput,
+    stats: &Mutex<Statistics>,
+) -> Result<(), String> {
+    let res_x = scene_spec.logistics.resolution_x;
+    let res_y = scene_spec.logistics.resolution_y;
+    let samples = scene_spec.logistics.samples;
+    let mut sample_counts = vec![samples; res_x as usize * res_y as usize];
+    let cancelled = AtomicBool::new(false);
+    loop {
+        let refinement = match live.next_refinement(res_x, res_y, &scene_spec.fingerprint) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        if scene_spec.logistics.gradient_domain.is_some() {
+            live.refuse("Gradient domain renders can not have regions traced again");
+            continue;
+        }
+        let tiles = tile::split_region(&refinement.region, tile::TILE_SIZE);
+        live.refining(&refinement, tiles.len());
+        let merged = Mutex::new((&mut *frame, &mut sample_counts));
+        let traced = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            pool.install(|| {
+                tiles.par_iter().for_each_init(
+                    || scene_spec.shape_aggregate.get_workspace(),
+                    |aggregate_workspace, t| {
+                        let mut local_stats = Statistics::new();
+                        // Unseeded, so that the new samples add to the
+                        // seeded ones rather than repeating them
+                        let (tile_colors, _, _) = render_tile(
+                            scene_spec,
+                            t,
+                            refinement.samples,
+                            None,
+                            aggregate_workspace,
+                            &mut local_stats,
+                            &cancelled,
+                        )
+                        .expect("Refinement is never cancelled.");
+                        let mut merged_colors = Vec::with_capacity(tile_colors.len());
+                        {
+                            let mut merged =
+                                merged.lock().expect("Thread failed to lock the image.");
+                            let (frame, sample_counts) = &mut *merged;
+                            let mut colors = tile_colors.iter();
+                            for y in t.y_min..t.y_max {
+                                for x in t.x_min..t.x_max {
+                                    let index = (x * res_y + y) as usize;
+                                    let color =
+                                        colors.next().expect("Tile has fewer colors than pixels.");
+                                    let had = sample_counts[index];
+                                    sample_counts[index] = had.saturating_add(refinement.samples);
+                                    let sum =
+                                        frame.get(index) * (had as f32 / samples as f32) + *color;
+                                    let c = sum * (samples as f32 / sample_counts[index] as f32);
+                                    frame.set(index, c);
+                                    merged_colors.push(c);
+                                }
+                            }
+                        }
+                        live.tile(t, &merged_colors, &scene_spec.logistics);
+                        stats
+                            .lock()
+                            .expect("Thread failed to lock render statistics for merging.")
+                            .merge(&local_stats);
+                    },
+                )
+            })
+        }));
+        match traced {
+            Ok(()) => live.refined(),
+            Err(payload) => {
+                let message = format!("Tracing failed while refining: {}", panic_message(&payload));
+                live.finish(Some(&message));
+                return Err(message);
+            }
+        }
+    }
+}
+
+// The message a panic was raised with, when it has one
+fn panic_message(payload: &Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => return String::from(*s),
+        None => {}
+    }
+    match payload.downcast_ref::<String>() {
+        Some(s) => return String::clone(s),
+        None => {}
+    }
+    return String::from("(no message)");
+}
+
+// Averages the summed colors from render, converts them from the working
+// space to the output space, encodes them with the output space's transfer
+// function, and converts to 8 bit unsigned integer RGB in row-major order.
+pub fn to_rgb8(colors: &Framebuffer, logistics: &Logistics) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    for y in 0..logistics.resolution_y {
+        line.clear();
+        rgb8_row(colors, logistics, y, &mut line)?;
+        data.extend_from_slice(&line);
+    }
+    return Ok(data);
+}
+
+// One row of to_rgb8, appended to line
+pub fn rgb8_row(
+    colors: &Framebuffer,
+    logistics: &Logistics,
+    y: u32,
+    line: &mut Vec<u8>,
+) -> io::Result<()> {
+    for c in colors.row(y, logistics.resolution_x, logistics.resolution_y)? {
+        line.extend_from_slice(&pixel_rgb8(c, logistics));
+    }
+    return Ok(());
+}
+
+// One pixel of to_rgb8, from its summed color
+pub fn pixel_rgb8(color: RGB, logistics: &Logistics) -> [u8; 3] {
+    let output_space = logistics.output_space();
+    let col = pixel_linear(color, logistics);
+    let encode =
+        |v: f32| (output_space.encode(utils::clamp(v, 0.0_f32, 1.0_f32)) * COLOR_SPACE) as u8;
+    return [encode(col.r()), encode(col.g()), encode(col.b())];
+}
+
+// Averages the summed colors of row y from render and converts them from
+// the working space to the output space's primaries, without encoding them,
+// appending them to line as floating point RGB from left to right.
+pub fn linear_row(
+    colors: &Framebuffer,
+    logistics: &Logistics,
+    y: u32,
+    line: &mut Vec<f32>,
+) -> io::Result<()> {
+    for c in colors.row(y, logistics.resolution_x, logistics.resolution_y)? {
+        let col = pixel_linear(c, logistics);
+
+        line.push(col.r());
+        line.push(col.g());
+        line.push(col.b());
+    }
+    return Ok(());
+}
+
+// One pixel of linear_row, from its summed color
+pub fn pixel_linear(color: RGB, logistics: &Logistics) -> RGB {
+    return logistics
+        .working_space()
+        .convert_to(logistics.output_space(), color / logistics.samples as f32);
+}
+
+// Traces the given samples of every pixel in the tile, returning the summed
+// colors in row-major order within the tile, or None if the render was
+// cancelled part way through. For gradient domain rendering, everything
+// traced for the gradient module is returned too, and the tile's passes
+// when the scene has any. With a seed, each sample's random numbers depend
+// only on it and the pixel, not on the thread or the order of tiles.
+fn render_tile(
+    scene_spec: &Scene,
+    t: &tile::Tile,
+    samples: u32,
+    seed: Option<u64>,
+    aggregate_workspace: &mut aggregate::Workspace,
+    local_stats: &mut Statistics,
+    cancelled: &AtomicBool,
+) -> Option<(
+    Vec<RGB>,
+    Option<gradient::TileSums>,
+    Option<passes::TilePasses>,
+)> {
+    let res_x = scene_spec.logistics.resolution_x;
+    let res_y = scene_spec.logistics.resolution_y;
+    // Samples for half precision images are summed with compensation, as
+    // they would be most likely to lose their low bits
+    let compensated = scene_spec.logistics.framebuffer() == framebuffer::Precision::Half;
+
+    let mut tile_colors: Vec<CompensatedSum> = (0..t.pixel_count())
+        .map(|_| CompensatedSum::new(compensated))
+        .collect();
+    let mut tile_sums = match scene_spec.logistics.gradient_domain {
+        Some(_) => Some(gradient::TileSums::new(t, res_x, res_y)),
+        None => None,
+    };
+    // Light resampling reuses the light samples of pixels traced before
+    let mut reservoirs = match scene_spec.logistics.light_resampling {
+        Some(_) => Some(resampling::TileReservoirs::new(t)),
+        None => None,
+    };
+    let pass_list = scene_spec.logistics.traced_passes();
+    let mut tile_passes = if pass_list.is_empty() {
+        None
+    } else {
+        Some(passes::TilePasses::new(&pass_list, t))
+    };
+    let mut sample_count = 0_u64;
+    // Every pixel takes a sample before any takes its next, so that light
+    // resampling has neighbors on every side to draw on
+    for sample in 0..samples {
+        let mut pixel_colors = tile_colors.iter_mut();
+        for y in t.y_min..t.y_max {
+            for x in t.x_min..t.x_max {
+                if cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let pixel_color = pixel_colors
+                    .next()
+                    .expect("Tile has fewer colors than pixels.");
+                local_stats.pixel = (x, y);
+                match seed {
+                    Some(seed) => rng::reseed(rng::sample_seed(seed, x, y, sample)),
+                    None => {}
+                }
+                match &mut reservoirs {
+                    Some(r) => r.set_pixel(x, y),
+                    None => {}
+                }
+                match &mut tile_passes {
+                    Some(p) => p.set_pixel(x, y),
+                    None => {}
+                }
+                // Only time a fraction of samples to keep overhead low
+                local_stats.sampling = sample_count % stats::SAMPLE_RATE == 0_u64;
+                sample_count += 1_u64;
+
+                let sums = match &mut tile_sums {
+                    Some(s) => s,
+                    None => {
+                        match trace_sample(
+                            scene_spec,
+                            x,
+                            y,
+                            aggregate_workspace,
+                            reservoirs.as_mut(),
+                            tile_passes.as_mut(),
+                            local_stats,
+                        ) {
+                            Some(c) => pixel_color.add(c),
+                            None => {}
+                        }
+                        continue;
+                    }
+                };
+                // The same sample through this pixel and its neighbors, see
+                // the gradient module. Only the pixel's own sample goes into
+                // its passes.
+                pixel_color.add(gradient::trace_sample(
+                    scene_spec,
+                    x,
+                    y,
+                    aggregate_workspace,
+                    tile_passes.as_mut(),
+                    local_stats,
+                    sums,
+                ));
+            }
+        }
+    }
+    return Some((
+        tile_colors.iter().map(|c| c.sum()).collect(),
+        tile_sums,
+        tile_passes,
+    ));
+}
+
+// The color of one camera sample through the pixel, or None where the
+// camera has no ray. What the ray hits first is added to the passes, when
+// given.
+pub fn trace_sample(
+    scene_spec: &Scene,
+    x: u32,
+    y: u32,
+    aggregate_workspace: &mut aggregate::Workspace,
+    reservoirs: Option<&mut resampling::TileReservoirs>,
+    passes: Option<&mut passes::TilePasses>,
+    local_stats: &mut Statistics,
+) -> Option<RGB> {
+    let check_normals = scene_spec.logistics.check_normals.unwrap_or(false);
+    let lobes = scene_spec.logistics.lobes.unwrap_or(false);
+    let (r, t_min, far) = match camera_ray(scene_spec, x, y) {
+        Some(ray_range) => ray_range,
+        // Outside of what the camera sees, so left black
+        None => return None,
+    };
+    let wireframe = &scene_spec.logistics.wireframe;
+    let edge_color = match wireframe {
+        Some(w) => w.edge_color(&r, t_min, far, scene_spec, aggregate_workspace),
+        None => None,
+    };
+    let color = match (edge_color, wireframe) {
+        (Some(c), _) => c,
+        (None, Some(w)) if !w.shades() => RGB::black(),
+        _ if check_normals => normals::check_color(&r, t_min, far, scene_spec, aggregate_workspace),
+        _ if lobes => lobes::lobe_color(&r, t_min, far, scene_spec, aggregate_workspace),
+        _ => aggregate::trace_range(
+            &r,
+            t_min,
+            far,
+            scene_spec,
+            aggregate_workspace,
+            &black_background,
+            0,
+            RayKind::Camera,
+            None,
+            reservoirs,
+            local_stats,
+        ),
+    };
+    // Along the same ray, so the passes line up with the image
+    match passes {
+        Some(p) => p.add(&r, t_min, far, scene_spec, aggregate_workspace),
+        None => {}
+    }
+    return Some(color);
+}
+
+// A camera ray through a random point of the pixel, and the range along it
+// that hits count in, or None where the camera has no ray
+pub fn camera_ray(scene_spec: &Scene, x: u32, y: u32) -> Option<(Ray, f32, f32)> {
+    let res_x = scene_spec.logistics.resolution_x;
+    let res_y = scene_spec.logistics.resolution_y;
+    // Camera rays carry how quickly they widen, so textures can be filtered
+    // to what each pixel sees
+    let spread = scene_spec.camera.pixel_spread(res_y);
+
+    let u = (x as f32 + rng::random()) / res_x as f32;
+    let v = ((res_y - y) as f32 + rng::random()) / res_y as f32;
+    let r = match scene_spec.camera.get_ray(u, v) {
+        Some(r) => r.with_spread(spread),
+        None => return None,
+    };
+    let (near, far) = scene_spec.camera.clip_range(&r);
+    return Some((r, utils::float_max(near, scene_spec.ray_epsilon), far));
+}
+
+/*
+fn background(r: &Ray) -> RGB {
+    // Sky blend
+    let dir_normal = r.dir.normalized();
+    let t = 0.5_f32 * (dir_normal.y() + 1.0_f32);
+
+    RGB::new(1.0_f32, 1.0_f32, 1.0_f32) * (1.0_f32 - t) + RGB::new(0.5_f32, 0.7_f32, 1.0_f32) * t
+}
+*/
+pub fn black_background(_: &Ray) -> RGB {
+    RGB::black()
+}
@@ -0,0 +1,331 @@
+use crate::aggregate;
+use crate::aggregate::Workspace;
+use crate::color::RGB;
+use crate::pdf;
+use crate::point::Point3;
+use crate::ray::Ray;
+use crate::rng;
+use crate::scene::Scene;
+use crate::shape::HitProperties;
+use crate::stats::Statistics;
+use crate::tile::Tile;
+use crate::vector::Vector3;
+
+use serde::Deserialize;
+
+// Direct lighting by resampled importance sampling, after "Spatiotemporal
+// Reservoir Resampling for Real-Time Ray Tracing with Dynamic Direct
+// Lighting" (Bitterli et al. 2020). Rather than tracing a shadow ray toward
+// every light, each shading point draws a few candidate light samples,
+// uniformly over the lights, and keeps one in a reservoir with a chance in
+// proportion to how much light it would bring before shadowing. Only the
+// kept sample gets a shadow ray, so the cost no longer grows with the
+// number of lights.
+//
+// At camera hits the reservoirs kept by nearby pixels of the same tile, at
+// their latest samples, are merged in too, so each pixel draws on many
+// times its own candidates wherever the same lights matter to its
+// neighbors. Neighbors whose surfaces face too differently or lie at too
+// different a depth are passed over, and the merged reservoir is weighted
+// by only the candidates that could have produced its sample, which keeps
+// the result unbiased. Gradient domain renders leave this reuse out, as it
+// would keep their shifted paths from replaying exactly.
+//
+// A sample is a light and the pair of numbers that picks a point on it, so
+// the same sample can be weighed again from a neighbor's point.
+#[derive(Deserialize)]
+pub struct LightResampling {
+    // Light samples drawn at each shading point
+    #[serde(default = "default_candidates")]
+    pub candidates: u32,
+    // Reservoirs of nearby pixels merged in at camera hits, 0 for none
+    #[serde(default = "default_neighbors")]
+    pub neighbors: u32,
+    // How far away, in pixels, neighbors are taken from
+    #[serde(default = "default_radius")]
+    pub radius: u32,
+}
+
+fn default_candidates() -> u32 {
+    8
+}
+
+fn default_neighbors() -> u32 {
+    8
+}
+
+fn default_radius() -> u32 {
+    3
+}
+
+// Neighbors are merged in only when their normals are within about 25
+// degrees of the pixel's and their distances from the camera within 10%
+const MIN_NORMAL_COSINE: f32 = 0.9_f32;
+const MAX_DEPTH_DIFFERENCE: f32 = 0.1_f32;
+
+#[derive(Clone, Copy)]
+struct Candidate {
+    light: usize,
+    u: (f32, f32),
+}
+
+#[derive(Clone, Copy)]
+struct Reservoir {
+    sample: Option<Candidate>,
+    // The summed resampling weights of every candidate seen
+    weight_sum: f32,
+    // How many candidates were seen
+    count: u32,
+    // The light the kept sample brings, by luminance, at the point it was
+    // kept for
+    target: f32,
+}
+
+impl Reservoir {
+    fn new() -> Reservoir {
+        return Reservoir {
+            sample: None,
+            weight_sum: 0.0_f32,
+            count: 0,
+            target: 0.0_f32,
+        };
+    }
+
+    // Sees count more candidates, keeping the given one with a chance of its
+    // weight over all of theirs so far
+    fn update(&mut self, candidate: Candidate, weight: f32, target: f32, count: u32) {
+        self.count += count;
+        if !(weight > 0.0_f32) {
+            return;
+        }
+        self.weight_sum += weight;
+        if rng::random() * self.weight_sum < weight {
+            self.sample = Some(candidate);
+            self.target = target;
+        }
+    }
+
+    // What the kept sample's light is multiplied by for an unbiased estimate
+    // of all of the lights'
+    fn contribution_weight(&self) -> f32 {
+        if self.count == 0 || !(self.target > 0.0_f32) {
+            return 0.0_f32;
+        }
+        return self.weight_sum / (self.count as f32 * self.target);
+    }
+}
+
+// Where a camera ray hit, with what the surface there scatters, so
+// neighbors can tell whether it is alike and which samples it could keep
+#[derive(Clone)]
+struct Surface {
+    point: Point3,
+    normal: Vector3,
+    distance: f32,
+    time: f32,
+    shape_key: usize,
+    scatter_pdf: pdf::PDF,
+}
+
+impl Surface {
+    fn resembles(&self, other: &Surface) -> bool {
+        return self.normal.dot(other.normal) >= MIN_NORMAL_COSINE
+            && (self.distance - other.distance).abs() <= MAX_DEPTH_DIFFERENCE * self.distance;
+    }
+
+    // The light the candidate brings to the surface before shadowing, by
+    // luminance, which candidates are resampled in proportion to
+    fn target(&self, candidate: &Candidate, scene: &Scene) -> f32 {
+        match aggregate::unshadowed_light(
+            &self.point,
+            self.time,
+            self.shape_key,
+            &self.scatter_pdf,
+            &scene.lights[candidate.light],
+            candidate.u,
+        ) {
+            Some((_, light_sample, weight)) => return (light_sample.radiance * weight).luminance(),
+            None => return 0.0_f32,
+        }
+    }
+}
+
+// The latest camera hit of each pixel in a tile and the reservoir kept
+// there, for its neighbors to merge in
+pub struct TileReservoirs {
+    x_min: u32,
+    y_min: u32,
+    width: u32,
+    height: u32,
+    // The pixel being traced
+    pixel: (u32, u32),
+    kept: Vec<Option<(Reservoir, Surface)>>,
+}
+
+impl TileReservoirs {
+    pub fn new(t: &Tile) -> TileReservoirs {
+        let width = t.x_max - t.x_min;
+        let height = t.y_max - t.y_min;
+        return TileReservoirs {
+            x_min: t.x_min,
+            y_min: t.y_min,
+            width: width,
+            height: height,
+            pixel: (t.x_min, t.y_min),
+            kept: vec![None; (width * height) as usize],
+        };
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32) {
+        self.pixel = (x, y);
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        return ((y - self.y_min) * self.width + (x - self.x_min)) as usize;
+    }
+
+    // A pixel of the tile within the radius of the one being traced, other
+    // than it, or None when the one picked falls outside of the tile
+    fn random_neighbor(&self, radius: u32) -> Option<usize> {
+        let offset = || rng::below(2 * radius as usize + 1) as i64 - radius as i64;
+        let x = self.pixel.0 as i64 + offset();
+        let y = self.pixel.1 as i64 + offset();
+        if x < self.x_min as i64
+            || y < self.y_min as i64
+            || x >= (self.x_min + self.width) as i64
+            || y >= (self.y_min + self.height) as i64
+            || (x, y) == (self.pixel.0 as i64, self.pixel.1 as i64)
+        {
+            return None;
+        }
+        return Some(self.index(x as u32, y as u32));
+    }
+
+    // Merges the pixel's own reservoir with its neighbors', returning the
+    // sample kept and its contribution weight, and keeps the pixel's own for
+    // the neighbors still to come
+    fn merge(
+        &mut self,
+        own: Reservoir,
+        surface: Surface,
+        settings: &LightResampling,
+        scene: &Scene,
+    ) -> (Option<Candidate>, f32) {
+        let mut merged = Reservoir::new();
+        match own.sample {
+            Some(c) => merged.update(c, own.weight_sum, own.target, own.count),
+            None => merged.count += own.count,
+        }
+        let mut merged_from = Vec::with_capacity(settings.neighbors as usize);
+        for _ in 0..settings.neighbors {
+            let index = match self.random_neighbor(settings.radius) {
+                Some(i) if !merged_from.contains(&i) => i,
+                _ => continue,
+            };
+            let (reservoir, neighbor) = match &self.kept[index] {
+                Some(k) => k,
+                None => continue,
+            };
+            let candidate = match reservoir.sample {
+                Some(c) if surface.resembles(neighbor) => c,
+                _ => continue,
+            };
+            // Reweighed by how much the sample brings here
+            let brings = surface.target(&candidate, scene);
+            merged.update(
+                candidate,
+                brings * reservoir.contribution_weight() * reservoir.count as f32,
+                brings,
+                reservoir.count,
+            );
+            merged_from.push(index);
+        }
+
+        let own_index = self.index(self.pixel.0, self.pixel.1);
+        self.kept[own_index] = Some((own, surface));
+
+        let candidate = match merged.sample {
+            Some(c) => c,
+            None => return (None, 0.0_f32),
+        };
+        // Only the reservoirs that could have kept the sample count toward
+        // how many candidates it was picked from
+        let mut count = own.count;
+        for i in merged_from {
+            match &self.kept[i] {
+                Some((reservoir, neighbor)) if neighbor.target(&candidate, scene) > 0.0_f32 => {
+                    count += reservoir.count
+                }
+                _ => {}
+            }
+        }
+        if count == 0 || !(merged.target > 0.0_f32) {
+            return (None, 0.0_f32);
+        }
+        return (
+            Some(candidate),
+            merged.weight_sum / (count as f32 * merged.target),
+        );
+    }
+}
+
+// Direct lighting from one light sample, kept from the candidates drawn at
+// the hit and, when given the tile's reservoirs, those of its neighbors
+pub fn direct_lighting(
+    settings: &LightResampling,
+    hit_props: &HitProperties,
+    r: &Ray,
+    t: f32,
+    shape_key: usize,
+    scatter_pdf: &pdf::PDF,
+    reservoirs: Option<&mut TileReservoirs>,
+    scene: &Scene,
+    workspace: &mut Workspace,
+    stats: &mut Statistics,
+) -> RGB {
+    let light_count = scene.lights.len();
+    if light_count == 0 {
+        return RGB::black();
+    }
+    let surface = Surface {
+        point: hit_props.hit_point,
+        normal: hit_props.normal.normalized(),
+        distance: t * r.dir.length(),
+        time: r.time,
+        shape_key: shape_key,
+        scatter_pdf: scatter_pdf.clone(),
+    };
+
+    // Candidates are drawn uniformly over the lights, so each one's weight
+    // is its target over a PDF of one over the number of lights
+    let mut own = Reservoir::new();
+    for _ in 0..settings.candidates.max(1) {
+        let candidate = Candidate {
+            light: rng::below(light_count),
+            u: (rng::random(), rng::random()),
+        };
+        let brings = surface.target(&candidate, scene);
+        own.update(candidate, brings * light_count as f32, brings, 1);
+    }
+
+    let (kept, weight) = match reservoirs {
+        Some(tile) => tile.merge(own, surface, settings, scene),
+        None => (own.sample, own.contribution_weight()),
+    };
+    match kept {
+        Some(c) => {
+            return aggregate::light_contribution(
+                &hit_props.hit_point,
+                r.time,
+                shape_key,
+                scatter_pdf,
+                &scene.lights[c.light],
+                c.u,
+                scene,
+                workspace,
+                stats,
+            ) * weight
+        }
+        None => return RGB::black(),
+    }
+}
@@ -1,21 +1,142 @@
+use crate::measured::MeasuredBRDF;
+
 use image;
 use image::DynamicImage;
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 pub struct Resources {
     loaded_images: HashMap<String, Arc<DynamicImage>>,
+    loaded_brdfs: HashMap<String, Arc<MeasuredBRDF>>,
+    // Every file the scene was made from, for its fingerprint
+    dependencies: BTreeSet<PathBuf>,
+    // Whether missing images and meshes are stood in for by placeholders,
+    // with a warning, rather than failing the scene
+    placeholders: bool,
+    // Directories searched, in order, for assets that are not where the
+    // scene says they are, see find
+    search_paths: Vec<PathBuf>,
 }
 
+// Directories to search for assets in every scene, separated like PATH
+const ASSET_PATH_VARIABLE: &str = "RAYTRACER_ASSET_PATH";
+
+// Placeholder images are a magenta and black checkerboard, loud enough to
+// spot in any render
+const PLACEHOLDER_SIZE: u32 = 64;
+const PLACEHOLDER_CHECKS: u32 = 8;
+
 impl Resources {
     pub fn new() -> Resources {
         Resources {
             loaded_images: HashMap::new(),
+            loaded_brdfs: HashMap::new(),
+            dependencies: BTreeSet::new(),
+            placeholders: false,
+            search_paths: Vec::new(),
+        }
+    }
+
+    // Sets the directories to search for assets: the scene's own, relative
+    // to its directory, then those of the RAYTRACER_ASSET_PATH environment
+    // variable. Either may start with ~ for the home directory, so shared
+    // texture libraries can be named the same way on every machine.
+    pub fn set_search_paths(&mut self, scene_dir: &Path, scene_paths: &[String]) {
+        self.search_paths.clear();
+        for path in scene_paths {
+            self.search_paths
+                .push(scene_dir.join(expand_home(Path::new(path))));
+        }
+        match env::var_os(ASSET_PATH_VARIABLE) {
+            Some(paths) => {
+                for path in env::split_paths(&paths) {
+                    if !path.as_os_str().is_empty() {
+                        self.search_paths.push(expand_home(&path));
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    // Where an asset named by a path relative to dir is: there if it exists,
+    // otherwise under the first search path that has it. Absolute paths, and
+    // assets found nowhere, are left relative to dir, so errors name where
+    // the asset was expected first.
+    pub fn find(&self, dir: &Path, path: &Path) -> PathBuf {
+        let beside = dir.join(path);
+        if path.is_absolute() || beside.exists() {
+            return beside;
         }
+        for search_path in &self.search_paths {
+            let candidate = search_path.join(path);
+            if candidate.exists() {
+                return candidate;
+            }
+        }
+        return beside;
+    }
+
+    pub fn set_placeholders(&mut self, placeholders: bool) {
+        self.placeholders = placeholders;
+    }
+
+    pub fn placeholders(&self) -> bool {
+        return self.placeholders;
+    }
+
+    // Notes a file the scene was made from, once however often it is used
+    pub fn depend_on(&mut self, path: &Path) {
+        let absolute_path = match path.canonicalize() {
+            Ok(p) => p,
+            Err(_) => path.to_path_buf(),
+        };
+        self.dependencies.insert(absolute_path);
+    }
+
+    // The files the scene was made from, in order of their absolute paths
+    pub fn dependencies(&self) -> &BTreeSet<PathBuf> {
+        return &self.dependencies;
+    }
+
+    // Measured BRDFs are large, so materials sharing one share its data
+    pub fn load_measured_brdf(&mut self, brdf_path: &Path) -> Result<Arc<MeasuredBRDF>, String> {
+        let absolute_path = match brdf_path.canonicalize() {
+            Ok(p) => p,
+            Err(e) => {
+                return Err(format!(
+                    "There was a problem finding measured BRDF {}: {}",
+                    brdf_path.display(),
+                    e
+                ))
+            }
+        };
+        self.dependencies.insert(absolute_path.clone());
+        let key = absolute_path.to_string_lossy().into_owned();
+        match self.loaded_brdfs.get(&key) {
+            Some(b) => return Ok(Arc::clone(b)),
+            None => {}
+        }
+        let brdf = Arc::new(MeasuredBRDF::load(&absolute_path)?);
+        self.loaded_brdfs.insert(key, Arc::clone(&brdf));
+        return Ok(brdf);
     }
 
     pub fn load_image(&mut self, image_path: &Path) -> Result<Arc<DynamicImage>, String> {
         let absolute_path = match image_path.canonicalize() {
             Ok(p) => p,
+            Err(e) if self.placeholders => {
+                eprintln!(
+                    "Warning: using a placeholder for missing image {}: {}",
+                    image_path.display(),
+                    e
+                );
+                return Ok(self.placeholder_image());
+            }
             Err(e) => {
                 return Err(format!(
                     "There was a problem finding the given image path: {}",
@@ -23,6 +144,7 @@ impl Resources {
                 ))
             }
         };
+        self.dependencies.insert(absolute_path.clone());
         let path_str = match absolute_path.to_str() {
             Some(p) => p,
             None => {
@@ -49,4 +171,32 @@ impl Resources {
             None => Err(String::from("Unexpected issue loading from image map.")),
         };
     }
+
+    // The checkerboard standing in for every missing image, made once
+    pub fn placeholder_image(&mut self) -> Arc<DynamicImage> {
+        // Not a path any real image could have, so it can share the map
+        let key = "<placeholder>";
+        if let Some(v) = self.loaded_images.get(key) {
+            return Arc::clone(v);
+        }
+        let checks = PLACEHOLDER_SIZE / PLACEHOLDER_CHECKS;
+        let checkerboard = image::RgbImage::from_fn(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, |x, y| {
+            if (x / checks + y / checks) % 2 == 0 {
+                return image::Rgb([255, 0, 255]);
+            }
+            return image::Rgb([0, 0, 0]);
+        });
+        let placeholder = Arc::new(DynamicImage::ImageRgb8(checkerboard));
+        self.loaded_images
+            .insert(String::from(key), Arc::clone(&placeholder));
+        return placeholder;
+    }
+}
+
+// The path with a leading ~ replaced by the home directory, when there is one
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => return Path::new(&home).join(rest),
+        _ => return path.to_path_buf(),
+    }
 }
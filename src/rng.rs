@@ -0,0 +1,105 @@
+use rand;
+use std::cell::Cell;
+
+// The random numbers tracing draws from, one generator per thread. Each
+// can be set back to a seed, so that a path can be traced again with the
+// same random numbers from somewhere else, as gradient domain rendering
+// does for the pixels next to each sample (see the gradient module).
+// Seeds are drawn from a second generator, which replaying never touches,
+// so the paths after a replay are as independent as they were before.
+//
+// The generators are PCG32 (http://www.pcg-random.org), which are small
+// and fast to reseed, and seeded from the thread RNG as each thread starts,
+// or from the scene's seed before each camera sample, when it has one.
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+thread_local! {
+    static SAMPLES: Cell<u64> = Cell::new(seeded_state(rand::random::<u64>()));
+    static SEEDS: Cell<u64> = Cell::new(seeded_state(rand::random::<u64>()));
+}
+
+// A number in [0, 1)
+pub fn random() -> f32 {
+    // The top 24 bits, as many as an f32 holds exactly
+    return (SAMPLES.with(next) >> 8) as f32 / (1_u32 << 24) as f32;
+}
+
+// An index below count, or 0 when count is 0
+pub fn below(count: usize) -> usize {
+    return ((SAMPLES.with(next) as u64 * count as u64) >> 32) as usize;
+}
+
+// A seed for replay, drawn apart from the numbers tracing uses
+pub fn new_seed() -> u64 {
+    let high = SEEDS.with(next) as u64;
+    let low = SEEDS.with(next) as u64;
+    return (high << 32) | low;
+}
+
+// Sets this thread's generator to the seed, so the numbers drawn next are
+// the same every time the same seed is replayed
+pub fn replay(seed: u64) {
+    SAMPLES.with(|state| state.set(seeded_state(seed)));
+}
+
+// Sets both of this thread's generators from the seed, so that everything
+// drawn next is the same every time, whichever thread draws it
+pub fn reseed(seed: u64) {
+    SAMPLES.with(|state| state.set(seeded_state(seed)));
+    SEEDS.with(|state| state.set(seeded_state(!seed)));
+}
+
+// A seed for one camera sample through a pixel, from the scene's seed
+pub fn sample_seed(seed: u64, x: u32, y: u32, sample: u32) -> u64 {
+    let mut h = seed;
+    for value in &[x, y, sample] {
+        // SplitMix64's finalizer, so nearby pixels get unrelated seeds
+        h = (h ^ *value as u64).wrapping_add(0x9e3779b97f4a7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+    }
+    return h;
+}
+
+// The numbers below count in an order shuffled by the seed, the same every
+// time for the same seed, and apart from the numbers tracing uses
+pub fn permutation(count: usize, seed: u64) -> Vec<usize> {
+    let state = Cell::new(seeded_state(seed));
+    let mut numbers: Vec<usize> = (0..count).collect();
+    // Fisher-Yates
+    for i in (1..count).rev() {
+        let j = ((next(&state) as u64 * (i as u64 + 1)) >> 32) as usize;
+        numbers.swap(i, j);
+    }
+    return numbers;
+}
+
+// Numbers in [0, 1) that are the same every time for the same seed, and
+// apart from the numbers tracing uses
+pub fn seeded(count: usize, seed: u64) -> Vec<f32> {
+    let state = Cell::new(seeded_state(seed));
+    return (0..count)
+        .map(|_| (next(&state) >> 8) as f32 / (1_u32 << 24) as f32)
+        .collect();
+}
+
+fn seeded_state(seed: u64) -> u64 {
+    let state = step(0_u64).wrapping_add(seed);
+    return step(state);
+}
+
+fn step(state: u64) -> u64 {
+    return state.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+}
+
+// PCG32's XSH RR output of the state, before stepping it
+fn next(state: &Cell<u64>) -> u32 {
+    let old = state.get();
+    state.set(step(old));
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    let rotation = (old >> 59) as u32;
+    return xorshifted.rotate_right(rotation);
+}
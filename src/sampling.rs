@@ -0,0 +1,592 @@
+use crate::rng;
+use crate::vector::Vector3;
+
+use std::f32;
+
+// Random directions and points for Monte Carlo integration, each beside the
+// density it draws from. A sampler and its PDF must agree exactly, or the
+// image is quietly too bright or too dark where it is used, so every pair
+// here is checked against each other by the chi-square tests in check,
+// which new samplers should be added to.
+//
+// Directions are unit vectors about the z axis, to be turned to face a
+// surface's normal with an OrthonormalBasis, and densities are per unit of
+// solid angle, or of area for points. Some PDFs are only used by the
+// checks, where their callers work the density out in their own space.
+
+// Share of a tangent's squared length that must be left once the part
+// along the normal is taken out for its direction to be trusted
+const MIN_TANGENT_SCALE: f32 = 0.000001_f32;
+
+#[derive(Clone)]
+pub struct OrthonormalBasis {
+    axis: [Vector3; 3],
+}
+
+impl OrthonormalBasis {
+    pub fn new(v: &Vector3) -> OrthonormalBasis {
+        let mut o = OrthonormalBasis {
+            axis: [Vector3::new_empty(); 3],
+        };
+        o.axis[2] = *v;
+        let a = if v.x().abs() > 0.9_f32 {
+            Vector3::new(0.0_f32, 1.0_f32, 0.0_f32)
+        } else {
+            Vector3::new(1.0_f32, 0.0_f32, 0.0_f32)
+        };
+        o.axis[1] = v.cross(a).normalized();
+        o.axis[0] = v.cross(o.axis[1]);
+        return o;
+    }
+
+    // Basis around v whose first axis follows u and second follows w, as
+    // near as they can while perpendicular to v. Only one of them needs to
+    // be usable, and when neither is, as when a shape has no
+    // parameterization, the other axes are arbitrary like with new.
+    pub fn with_tangents(v: &Vector3, u: &Vector3, w: &Vector3) -> OrthonormalBasis {
+        let tangent = *u - u.dot(*v) * *v;
+        let bitangent = *w - w.dot(*v) * *v;
+        let mut o = OrthonormalBasis {
+            axis: [Vector3::new_empty(); 3],
+        };
+        o.axis[2] = *v;
+        if tangent.squared_length() > MIN_TANGENT_SCALE * u.squared_length() {
+            o.axis[0] = tangent.normalized();
+            o.axis[1] = v.cross(o.axis[0]);
+        } else if bitangent.squared_length() > MIN_TANGENT_SCALE * w.squared_length() {
+            o.axis[1] = bitangent.normalized();
+            o.axis[0] = o.axis[1].cross(*v);
+        } else {
+            return OrthonormalBasis::new(v);
+        }
+        return o;
+    }
+
+    pub fn local(&self, v: &Vector3) -> Vector3 {
+        (v.x() * self.axis[0]) + (v.y() * self.axis[1]) + (v.z() * self.axis[2])
+    }
+
+    // Inverse of local, expresses a world space vector in terms of this basis.
+    pub fn to_local(&self, v: &Vector3) -> Vector3 {
+        Vector3::new(
+            v.dot(self.axis[0]),
+            v.dot(self.axis[1]),
+            v.dot(self.axis[2]),
+        )
+    }
+}
+
+// A point spread evenly over the unit disk in the xy plane, for lenses
+pub fn random_unit_disk() -> Vector3 {
+    let radius = rng::random().sqrt();
+    let phi = 2.0_f32 * f32::consts::PI * rng::random();
+    Vector3::new(radius * phi.cos(), radius * phi.sin(), 0.0_f32)
+}
+
+pub fn unit_disk_pdf(p: &Vector3) -> f32 {
+    if p.x() * p.x() + p.y() * p.y() <= 1.0_f32 {
+        1.0_f32 / f32::consts::PI
+    } else {
+        0.0_f32
+    }
+}
+
+// A direction spread evenly over the whole sphere
+pub fn unit_sphere_random() -> Vector3 {
+    let azimuth = rng::random() * f32::consts::PI * 2.0_f32;
+    let y = 2.0_f32 * rng::random() - 1.0_f32;
+    let sin_elevation = (1.0_f32 - y * y).max(0.0_f32).sqrt();
+    let x = sin_elevation * azimuth.cos();
+    let z = sin_elevation * azimuth.sin();
+
+    Vector3::new(x, y, z)
+}
+
+pub fn unit_sphere_pdf() -> f32 {
+    1.0_f32 / (4.0_f32 * f32::consts::PI)
+}
+
+// A direction above the xy plane, more likely the nearer it is to the z
+// axis, as the cosine of the angle to it
+pub fn random_cosine_direction() -> Vector3 {
+    let r1 = rng::random();
+    let r2 = rng::random();
+    let z = (1.0_f32 - r2).sqrt();
+
+    let phi = 2.0_f32 * f32::consts::PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+
+    Vector3::new(x, y, z)
+}
+
+pub fn cosine_direction_pdf(v: &Vector3) -> f32 {
+    return v.z().max(0.0_f32) / f32::consts::PI;
+}
+
+// A direction spread evenly over the cone that a sphere of the radius
+// fills, seen from the distance along the z axis
+pub fn random_to_sphere(radius: f32, distance_squared: f32) -> Vector3 {
+    let r1 = rng::random();
+    let r2 = rng::random();
+    let z = 1.0_f32 + r2 * (cone_cos_max(radius, distance_squared) - 1.0_f32);
+
+    let phi = 2.0_f32 * f32::consts::PI * r1;
+    let x = phi.cos() * (1.0_f32 - z * z).max(0.0_f32).sqrt();
+    let y = phi.sin() * (1.0_f32 - z * z).max(0.0_f32).sqrt();
+
+    Vector3::new(x, y, z)
+}
+
+pub fn to_sphere_pdf(v: &Vector3, radius: f32, distance_squared: f32) -> f32 {
+    let cos_max = cone_cos_max(radius, distance_squared);
+    if v.z() < cos_max {
+        return 0.0_f32;
+    }
+    return 1.0_f32 / (2.0_f32 * f32::consts::PI * (1.0_f32 - cos_max));
+}
+
+fn cone_cos_max(radius: f32, distance_squared: f32) -> f32 {
+    return (1.0_f32 - radius * radius / distance_squared)
+        .max(0.0_f32)
+        .sqrt();
+}
+
+// The GGX (Trowbridge-Reitz) distribution of microfacet normals, with
+// alpha_u and alpha_v its roughness along x and y, for a facet normal about
+// the z axis
+pub fn ggx_distribution(half: &Vector3, alpha_u: f32, alpha_v: f32) -> f32 {
+    let u = half.x() / alpha_u;
+    let v = half.y() / alpha_v;
+    let d = u * u + v * v + half.z() * half.z();
+    return 1.0_f32 / (f32::consts::PI * alpha_u * alpha_v * d * d);
+}
+
+// A facet normal from the GGX distribution, picked by stretching a slope
+// from the distribution with an alpha of 1 by each alpha, which keeps its
+// PDF the distribution times the facet's cosine
+pub fn random_ggx_normal(alpha_u: f32, alpha_v: f32) -> Vector3 {
+    let r1 = rng::random();
+    let r2 = rng::random();
+    let tan = (r1 / (1.0_f32 - r1)).sqrt();
+    let phi = 2.0_f32 * f32::consts::PI * r2;
+    return Vector3::new(
+        alpha_u * tan * phi.cos(),
+        alpha_v * tan * phi.sin(),
+        1.0_f32,
+    )
+    .normalized();
+}
+
+pub fn ggx_normal_pdf(half: &Vector3, alpha_u: f32, alpha_v: f32) -> f32 {
+    if half.z() <= 0.0_f32 {
+        return 0.0_f32;
+    }
+    return ggx_distribution(half, alpha_u, alpha_v) * half.z();
+}
+
+// Chi-square tests of samplers against their PDFs, for tests built with the
+// "testing" feature. Samples are counted in bins of equal area over the
+// sphere or the disk, and the counts compared with what the PDF, integrated
+// over each bin, expects. A sampler that draws from a different density
+// than its PDF describes, by even a little, fails once enough samples are
+// taken. For example:
+//
+//     sampling::check::chi_square(
+//         sampling::check::Domain::Sphere,
+//         || Some(sampling::random_cosine_direction()),
+//         |v| sampling::cosine_direction_pdf(v),
+//         sampling::check::DEFAULT_SAMPLES,
+//     )?;
+//
+// check::builtin_samplers runs the test on every sampler in this module and
+// on the material PDFs built from them, and runs with cargo test.
+#[cfg(any(test, feature = "testing"))]
+pub mod check {
+    use crate::pdf;
+    use crate::point::Point3;
+    use crate::ray::Ray;
+    use crate::vector::Vector3;
+
+    use std::f64;
+
+    pub const DEFAULT_SAMPLES: usize = 1_000_000;
+    // Bins along each of the two coordinates of the domain
+    const BINS_THETA: usize = 10;
+    const BINS_PHI: usize = 20;
+    // Steps across each bin's azimuth its PDF is integrated over. Along the
+    // other coordinate, where lobes peak and hemispheres end, the
+    // integration is adaptive, down to this many halvings of a bin.
+    const AZIMUTH_STEPS: usize = 16;
+    const MAX_INTEGRATION_DEPTH: u32 = 12;
+    // Bins expecting fewer samples than this are pooled together, as the
+    // chi-square statistic is unreliable for them
+    const MIN_EXPECTED: f64 = 5.0;
+    // Chance of a correct sampler failing
+    pub const SIGNIFICANCE: f64 = 0.001;
+    // How far the share of samples that are kept may be from the PDF's
+    // integral
+    const INTEGRAL_TOLERANCE: f64 = 0.01;
+
+    #[derive(Clone, Copy)]
+    pub enum Domain {
+        // Unit directions, with densities per unit of solid angle
+        Sphere,
+        // Points in the unit disk in the xy plane, with densities per unit
+        // of area
+        Disk,
+    }
+
+    impl Domain {
+        fn area(&self) -> f64 {
+            match self {
+                Domain::Sphere => 4.0 * f64::consts::PI,
+                Domain::Disk => f64::consts::PI,
+            }
+        }
+
+        // Coordinates in [0, 1) of equal area bins: the height along z and
+        // the azimuth for the sphere, and the squared radius and the
+        // azimuth for the disk
+        fn coordinates(&self, p: &Vector3) -> (f64, f64) {
+            let phi = (p.y() as f64).atan2(p.x() as f64);
+            let phi = if phi < 0.0 {
+                phi + 2.0 * f64::consts::PI
+            } else {
+                phi
+            } / (2.0 * f64::consts::PI);
+            let first = match self {
+                Domain::Sphere => (p.z() as f64 + 1.0) / 2.0,
+                Domain::Disk => (p.x() as f64).powi(2) + (p.y() as f64).powi(2),
+            };
+            return (first, phi);
+        }
+
+        fn point(&self, first: f64, phi: f64) -> Vector3 {
+            let phi = phi * 2.0 * f64::consts::PI;
+            let radius = match self {
+                Domain::Sphere => (1.0 - (2.0 * first - 1.0).powi(2)).max(0.0).sqrt(),
+                Domain::Disk => first.sqrt(),
+            };
+            let z = match self {
+                Domain::Sphere => 2.0 * first - 1.0,
+                Domain::Disk => 0.0,
+            };
+            return Vector3::new(
+                (radius * phi.cos()) as f32,
+                (radius * phi.sin()) as f32,
+                z as f32,
+            );
+        }
+    }
+
+    // Draws samples, None standing for a sample the sampler gave up on, and
+    // tests them against the PDF. Samples where the PDF is zero are given
+    // up on too, as they add nothing to an image, but the share kept must
+    // match the PDF's integral. Returns the p-value when the test passes.
+    pub fn chi_square<S, P>(
+        domain: Domain,
+        mut sample: S,
+        pdf: P,
+        sample_count: usize,
+    ) -> Result<f64, String>
+    where
+        S: FnMut() -> Option<Vector3>,
+        P: Fn(&Vector3) -> f32,
+    {
+        let mut observed = vec![0.0_f64; BINS_THETA * BINS_PHI];
+        let mut kept = 0_usize;
+        for _ in 0..sample_count {
+            let p = match sample() {
+                Some(p) => p,
+                None => continue,
+            };
+            let density = pdf(&p);
+            if !density.is_finite() || density < 0.0_f32 {
+                return Err(format!("PDF is {} at a sampled point.", density));
+            }
+            if density == 0.0_f32 {
+                continue;
+            }
+            let (first, phi) = domain.coordinates(&p);
+            let i = ((first * BINS_THETA as f64) as usize).min(BINS_THETA - 1);
+            let j = ((phi * BINS_PHI as f64) as usize).min(BINS_PHI - 1);
+            observed[i * BINS_PHI + j] += 1.0;
+            kept += 1;
+        }
+
+        // The PDF integrated over each bin, by the midpoint rule across the
+        // azimuth and adaptive Simpson's rule along the other coordinate.
+        // The coordinates spread evenly over the domain, so integrals over
+        // them are scaled by its area.
+        let mut expected = vec![0.0_f64; BINS_THETA * BINS_PHI];
+        let mut integral = 0.0_f64;
+        for i in 0..BINS_THETA {
+            let first_start = i as f64 / BINS_THETA as f64;
+            let first_end = (i + 1) as f64 / BINS_THETA as f64;
+            for j in 0..BINS_PHI {
+                let mut sum = 0.0_f64;
+                for b in 0..AZIMUTH_STEPS {
+                    let phi =
+                        (j as f64 + (b as f64 + 0.5) / AZIMUTH_STEPS as f64) / BINS_PHI as f64;
+                    let f = |first: f64| pdf(&domain.point(first, phi)) as f64;
+                    sum += simpson(&f, first_start, first_end);
+                }
+                let bin_integral = sum / (AZIMUTH_STEPS * BINS_PHI) as f64 * domain.area();
+                integral += bin_integral;
+                expected[i * BINS_PHI + j] = bin_integral * sample_count as f64;
+            }
+        }
+        let kept_share = kept as f64 / sample_count as f64;
+        if (integral - kept_share).abs() > INTEGRAL_TOLERANCE {
+            return Err(format!(
+                "PDF integrates to {}, but {} of the samples were kept.",
+                integral, kept_share
+            ));
+        }
+
+        // Pools the bins expecting the fewest samples until every pool
+        // expects enough. Samples falling where the PDF expects next to
+        // none end up in the first pool, and fail the test if there are
+        // many of them.
+        let mut bins: Vec<(f64, f64)> = expected.into_iter().zip(observed).collect();
+        bins.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("Expected count is NaN."));
+        let mut statistic = 0.0_f64;
+        let mut degrees_of_freedom = 0_usize;
+        let (mut pooled_expected, mut pooled_observed) = (0.0_f64, 0.0_f64);
+        for (e, o) in bins {
+            pooled_expected += e;
+            pooled_observed += o;
+            if pooled_expected < MIN_EXPECTED {
+                continue;
+            }
+            statistic += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            degrees_of_freedom += 1;
+            pooled_expected = 0.0;
+            pooled_observed = 0.0;
+        }
+        if pooled_expected > 0.0 {
+            statistic += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            degrees_of_freedom += 1;
+        }
+        // One degree is lost to the counts' fixed total
+        let degrees_of_freedom = degrees_of_freedom.max(2) - 1;
+
+        let p_value = gamma_q(degrees_of_freedom as f64 / 2.0, statistic / 2.0);
+        if p_value < SIGNIFICANCE {
+            return Err(format!(
+                "Samples do not match the PDF, chi-square of {} with {} degrees of freedom, p-value {}.",
+                statistic, degrees_of_freedom, p_value
+            ));
+        }
+        return Ok(p_value);
+    }
+
+    // Tests every sampler of the sampling module, and the material PDFs,
+    // returning the name and result of each
+    pub fn builtin_samplers(sample_count: usize) -> Vec<(String, Result<f64, String>)> {
+        use crate::sampling::*;
+
+        let mut results = Vec::new();
+        let mut run = |name: String,
+                       domain,
+                       sample: &mut dyn FnMut() -> Option<Vector3>,
+                       pdf: &dyn Fn(&Vector3) -> f32| {
+            results.push((
+                name,
+                chi_square(domain, || sample(), |v| pdf(v), sample_count),
+            ));
+        };
+
+        run(
+            String::from("unit disk"),
+            Domain::Disk,
+            &mut || Some(random_unit_disk()),
+            &|p| unit_disk_pdf(p),
+        );
+        run(
+            String::from("unit sphere"),
+            Domain::Sphere,
+            &mut || Some(unit_sphere_random()),
+            &|_| unit_sphere_pdf(),
+        );
+        run(
+            String::from("cosine direction"),
+            Domain::Sphere,
+            &mut || Some(random_cosine_direction()),
+            &|v| cosine_direction_pdf(v),
+        );
+        for &(radius, distance) in &[(1.0_f32, 1.5_f32), (1.0_f32, 10.0_f32)] {
+            let distance_squared = distance * distance;
+            run(
+                format!("to sphere, radius {} at {}", radius, distance),
+                Domain::Sphere,
+                &mut || Some(random_to_sphere(radius, distance_squared)),
+                &|v| to_sphere_pdf(v, radius, distance_squared),
+            );
+        }
+        for &(alpha_u, alpha_v) in &[(0.1_f32, 0.1_f32), (0.5_f32, 0.5_f32), (0.2_f32, 0.7_f32)] {
+            run(
+                format!("GGX normal, alpha {} by {}", alpha_u, alpha_v),
+                Domain::Sphere,
+                &mut || Some(random_ggx_normal(alpha_u, alpha_v)),
+                &|h| ggx_normal_pdf(h, alpha_u, alpha_v),
+            );
+        }
+
+        // Material PDFs in world space, about a tilted normal
+        let normal = Vector3::new(0.3_f32, 0.2_f32, 1.0_f32).normalized();
+        let tangent = Vector3::new(1.0_f32, 0.0_f32, 0.0_f32);
+        let bitangent = normal.cross(tangent);
+        let origin = Point3::origin();
+        let direction_pdf = |p: &pdf::PDF, v: &Vector3| p.value(&Ray::new(origin, *v, 0.0_f32));
+        let cosine = pdf::PDF::Cosine(pdf::Cosine::new(normal));
+        run(
+            String::from("Cosine PDF"),
+            Domain::Sphere,
+            &mut || Some(cosine.generate(&origin).normalized()),
+            &|v| direction_pdf(&cosine, v),
+        );
+        for &(alpha_u, alpha_v, view_angle) in &[
+            (0.1_f32, 0.1_f32, 0.0_f32),
+            (0.4_f32, 0.4_f32, 60.0_f32),
+            (0.15_f32, 0.6_f32, 30.0_f32),
+        ] {
+            let view = view_angle.to_radians();
+            let in_dir = -(normal * view.cos() + tangent * view.sin()).normalized();
+            let ggx = pdf::PDF::GGX(pdf::GGX::new(
+                normal, tangent, bitangent, in_dir, alpha_u, alpha_v, 0.04_f32,
+            ));
+            run(
+                format!(
+                    "GGX PDF, alpha {} by {}, viewed {} degrees off the normal",
+                    alpha_u, alpha_v, view_angle
+                ),
+                Domain::Sphere,
+                &mut || Some(ggx.generate(&origin).normalized()),
+                &|v| direction_pdf(&ggx, v),
+            );
+        }
+        return results;
+    }
+
+    // Integrates f from a to b, halving intervals wherever Simpson's rule
+    // over them disagrees with the rule over their halves
+    fn simpson<F: Fn(f64) -> f64>(f: &F, a: f64, b: f64) -> f64 {
+        let (fa, fm, fb) = (f(a), f((a + b) / 2.0), f(b));
+        let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+        return simpson_step(f, (a, b), (fa, fm, fb), whole, MAX_INTEGRATION_DEPTH);
+    }
+
+    fn simpson_step<F: Fn(f64) -> f64>(
+        f: &F,
+        (a, b): (f64, f64),
+        (fa, fm, fb): (f64, f64, f64),
+        whole: f64,
+        depth: u32,
+    ) -> f64 {
+        let m = (a + b) / 2.0;
+        let (left_m, right_m) = (f((a + m) / 2.0), f((m + b) / 2.0));
+        let left = (m - a) / 6.0 * (fa + 4.0 * left_m + fm);
+        let right = (b - m) / 6.0 * (fm + 4.0 * right_m + fb);
+        let difference = left + right - whole;
+        // The PDFs are single precision, so there is no use asking for
+        // more than a few digits
+        if depth == 0 || difference.abs() <= 1e-5 * (left + right).abs() + 1e-9 {
+            return left + right + difference / 15.0;
+        }
+        return simpson_step(f, (a, m), (fa, left_m, fm), left, depth - 1)
+            + simpson_step(f, (m, b), (fm, right_m, fb), right, depth - 1);
+    }
+
+    // The regularized upper incomplete gamma function Q(a, x), which is the
+    // chance of a chi-square statistic of at least 2x with 2a degrees of
+    // freedom. From Numerical Recipes, by its series below a + 1 and its
+    // continued fraction above.
+    fn gamma_q(a: f64, x: f64) -> f64 {
+        if x <= 0.0 {
+            return 1.0;
+        }
+        let log_prefix = -x + a * x.ln() - ln_gamma(a);
+        if x < a + 1.0 {
+            let mut term = 1.0 / a;
+            let mut sum = term;
+            let mut n = a;
+            for _ in 0..1000 {
+                n += 1.0;
+                term *= x / n;
+                sum += term;
+                if term.abs() < sum.abs() * 1e-15 {
+                    break;
+                }
+            }
+            return 1.0 - sum * log_prefix.exp();
+        }
+        // Lentz's method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        return log_prefix.exp() * h;
+    }
+
+    // Lanczos' approximation of the log of the gamma function
+    fn ln_gamma(x: f64) -> f64 {
+        const COEFFICIENTS: [f64; 6] = [
+            76.18009172947146,
+            -86.50532032941677,
+            24.01409824083091,
+            -1.231739572450155,
+            0.1208650973866179e-2,
+            -0.5395239384953e-5,
+        ];
+        let mut y = x;
+        let tmp = x + 5.5;
+        let tmp = tmp - (x + 0.5) * tmp.ln();
+        let mut series = 1.000000000190015;
+        for c in COEFFICIENTS.iter() {
+            y += 1.0;
+            series += c / y;
+        }
+        return -tmp + (2.5066282746310005 * series / x).ln();
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::rng;
+
+        #[test]
+        fn builtin_samplers_match_their_pdfs() {
+            // Seeded, so that correct samplers never fail by chance
+            rng::reseed(1_u64);
+            let failures: Vec<String> = builtin_samplers(DEFAULT_SAMPLES)
+                .into_iter()
+                .filter_map(|(name, result)| match result {
+                    Ok(_) => None,
+                    Err(e) => Some(format!("{}: {}", name, e)),
+                })
+                .collect();
+            assert!(failures.is_empty(), "{}", failures.join("\n"));
+        }
+    }
+}
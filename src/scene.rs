@@ -1,28 +1,93 @@
-use crate::aggregate::{new_bvh, SyncAggregate};
+use crate::aggregate::{new_bvh, new_cached_bvh, BVHSettings, BuildQuality, SyncAggregate};
 use crate::camera::Camera;
+use crate::camerapath;
+use crate::clip;
+use crate::clip::ClipPlane;
+use crate::color::RGB;
+use crate::colorspace::ColorSpace;
+use crate::csg;
+use crate::fog::HeightFog;
+use crate::framebuffer::Precision;
+use crate::gradient::GradientDomain;
+use crate::instance;
+use crate::instance::Prototype;
+use crate::light;
+use crate::light::{LightLinks, SceneLight, Shadows, SyncLight};
 use crate::material;
-use crate::material::SyncMaterial;
+use crate::material::{Perturbation, SyncMaterial, ThinFilm, Visibility};
+use crate::matrix::Matrix4;
+use crate::meshcache;
+use crate::motion;
+use crate::mtl::MaterialLibraries;
+use crate::objstream;
+use crate::objstream::{ObjError, ObjObject};
+use crate::outline::Outline;
+use crate::passes::Pass;
 use crate::pdf;
 use crate::point::Point3;
+use crate::presets;
+use crate::projection::{Projector, UVProjection};
+use crate::resampling::LightResampling;
 use crate::resources::Resources;
 use crate::shape;
 use crate::shape::SyncShape;
+use crate::spectrum;
+use crate::stats;
+use crate::sun;
 use crate::texture;
 use crate::texture::SyncTexture;
-use crate::texture::TexCoord;
+use crate::texture::{Filter, Operation, RampInput, RampInterpolation, TexCoord};
 use crate::transform::Transform;
+use crate::utils;
+use crate::vector::Vector3;
+use crate::version;
 use crate::volume;
+use crate::wireframe::Wireframe;
 
 use serde::Deserialize;
 use serde_json;
-use std::{collections::HashMap, convert, fs, io, path, sync::Arc};
-use wavefront_obj::obj;
+use std::{
+    collections::hash_map::DefaultHasher,
+    collections::{HashMap, HashSet},
+    convert, fmt, fs,
+    hash::{Hash, Hasher},
+    io,
+    io::Read,
+    path,
+    sync::Arc,
+};
 
 pub struct Scene {
     pub logistics: Logistics,
     pub camera: Camera,
     pub shape_aggregate: Box<SyncAggregate>,
     pub important_samples: Arc<pdf::PDF>,
+    // Minimum distance along a ray for a hit to count, which keeps scattered
+    // rays from hitting the surface they left.
+    pub ray_epsilon: f32,
+    // Display names for render statistics, keyed by the address of each
+    // Shape and Material (see the stats module)
+    pub shape_names: HashMap<usize, String>,
+    pub material_names: HashMap<usize, String>,
+    pub fog: Option<HeightFog>,
+    // Lights that are not part of the geometry, sampled directly at every
+    // diffuse hit
+    pub lights: Vec<SceneLight>,
+    // The shapes that emissive shapes linked to some light, by the key of
+    // the emissive shape
+    pub emitter_links: HashMap<usize, LightLinks>,
+    // The kinds of ray that see materials hidden from some, by the key of
+    // the material
+    pub material_visibility: HashMap<usize, Visibility>,
+    // Outlines drawn around Toon materials after rendering, by the key of
+    // the material
+    pub outlines: HashMap<usize, Outline>,
+    // Masks whose brightness is how often rays stop at a shape rather than
+    // pass through it, by the key of the shape
+    pub alpha_masks: HashMap<usize, Arc<SyncTexture>>,
+    // A hash of the scene spec and every file it was made from, as 16 hex
+    // digits, see fingerprint
+    pub fingerprint: String,
 }
 
 #[derive(Deserialize)]
@@ -31,21 +96,127 @@ pub struct Logistics {
     pub resolution_y: u32,
     pub samples: u32,
     pub use_importance_sampling: Option<bool>,
+    // Overrides the ray epsilon derived from the scene's extent
+    pub ray_epsilon: Option<f32>,
+    // Renders tiles nearest this point first, given as a fraction of the
+    // image width and height from the top left. Tiles go in scanline order
+    // when not given.
+    pub tile_focus: Option<[f32; 2]>,
+    // Renders tiles overlapping any of these regions before all others,
+    // each given as fractions of the image width and height, [left, top,
+    // right, bottom] from the top left
+    pub priority_regions: Option<Vec<[f32; 4]>>,
+    // Linear space to render in, see the colorspace module
+    pub working_space: Option<ColorSpace>,
+    // Space to write the image out in. ACEScg is written as an EXR.
+    pub output_space: Option<ColorSpace>,
+    // Debug drawing of triangle edges, see the wireframe module
+    pub wireframe: Option<Wireframe>,
+    // Debug shading of front faces in green and back faces in red, also
+    // turned on by the --check-normals command line flag
+    pub check_normals: Option<bool>,
+    // Debug shading by the kind of scattering each camera ray's first hit
+    // took, see the lobes module. Also turned on by the --lobes command line
+    // flag.
+    pub lobes: Option<bool>,
+    // Replaces every material but lights and volume phase functions, for
+    // judging lighting and geometry apart from shading. Names one of the
+    // scene's materials or the builtin Clay, also set by the
+    // --override-material command line flag.
+    pub override_material: Option<String>,
+    // Pins each tracing thread to its own CPU, spread across NUMA nodes,
+    // also turned on by the --numa command line flag. See the numa module.
+    pub pin_threads: Option<bool>,
+    // Precision of the image held while rendering, and whether it is held
+    // in memory or on disk, see the framebuffer module
+    pub framebuffer: Option<Precision>,
+    // The frame of an animation being rendered, for cameras that follow a
+    // path, see the camerapath module. Also set by the --frame command line
+    // flag.
+    pub frame: Option<u32>,
+    // Traces each sample through neighboring pixels too and solves for the
+    // image from the differences, see the gradient module
+    pub gradient_domain: Option<GradientDomain>,
+    // Samples direct lighting by resampling light candidates, reused across
+    // neighboring pixels, see the resampling module
+    pub light_resampling: Option<LightResampling>,
+    // Images of the first hits' normals, depths, and albedos to write beside
+    // the rendered image, see the passes module
+    pub passes: Option<Vec<Pass>>,
+    // Denoises the finished image with its albedo and normals, see the
+    // denoise module. Also turned on by the --denoise command line flag.
+    pub denoise: Option<bool>,
+    // Directories to look for images, meshes, and measured BRDFs in when
+    // they are not where the scene says, relative to the scene's directory,
+    // searched before those of RAYTRACER_ASSET_PATH. See Resources.
+    pub search_paths: Option<Vec<String>>,
+    // Seeds the random numbers of every camera sample, so renders of the
+    // scene come out the same each time, on any number of threads
+    pub seed: Option<u64>,
 }
 
+impl Logistics {
+    pub fn working_space(&self) -> ColorSpace {
+        return self.working_space.unwrap_or(ColorSpace::Rec709);
+    }
+
+    pub fn output_space(&self) -> ColorSpace {
+        return self.output_space.unwrap_or(ColorSpace::SRGB);
+    }
+
+    pub fn framebuffer(&self) -> Precision {
+        return self.framebuffer.unwrap_or(Precision::Float);
+    }
+
+    // The passes to write, each once, in the order given
+    pub fn passes(&self) -> Vec<Pass> {
+        let mut passes = Vec::new();
+        for pass in self.passes.iter().flatten() {
+            if !passes.contains(pass) {
+                passes.push(*pass);
+            }
+        }
+        return passes;
+    }
+
+    // The passes to trace, those written along with the albedo and normals
+    // the denoiser needs
+    pub fn traced_passes(&self) -> Vec<Pass> {
+        let mut passes = self.passes();
+        if self.denoise.unwrap_or(false) {
+            for pass in [Pass::Albedo, Pass::Normal].iter() {
+                if !passes.contains(pass) {
+                    passes.push(*pass);
+                }
+            }
+        }
+        return passes;
+    }
+}
+
+// The ray epsilon is relative to the magnitude of the coordinates in the
+// scene, as that is what floating point error in hit points depends on.
+const RAY_EPSILON_SCALE: f32 = 0.00001_f32;
+// Keeps the epsilon from vanishing for scenes huddled around the origin.
+const RAY_EPSILON_MIN: f32 = 0.0000001_f32;
+
+// Builtin material for overriding a scene's materials, a neutral grey Lambert
+const CLAY_MATERIAL: &str = "Clay";
+const CLAY_ALBEDO: f32 = 0.5_f32;
+
 // Package together third party library errors and
 // Scene local errors to be returned from the
 // deserialize function.
 #[derive(Debug)]
 pub enum DeserializeError {
-    ObjLibraryError(wavefront_obj::ParseError),
+    ObjError(ObjError),
     JsonLibraryError(serde_json::Error),
     IoError(io::Error),
     LocalError(String),
 }
-impl convert::From<wavefront_obj::ParseError> for DeserializeError {
-    fn from(obj_error: wavefront_obj::ParseError) -> Self {
-        DeserializeError::ObjLibraryError(obj_error)
+impl convert::From<ObjError> for DeserializeError {
+    fn from(obj_error: ObjError) -> Self {
+        DeserializeError::ObjError(obj_error)
     }
 }
 impl convert::From<serde_json::Error> for DeserializeError {
@@ -58,6 +229,16 @@ impl convert::From<io::Error> for DeserializeError {
         DeserializeError::IoError(err)
     }
 }
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeserializeError::ObjError(e) => write!(f, "Malformed OBJ file, {}", e),
+            DeserializeError::JsonLibraryError(e) => write!(f, "{}", e),
+            DeserializeError::IoError(e) => write!(f, "{}", e),
+            DeserializeError::LocalError(e) => write!(f, "{}", e),
+        }
+    }
+}
 
 // Deserializes a JSON scene specification correctly
 // into a scene structure.
@@ -66,20 +247,54 @@ pub fn deserialize(
     spec_dir: &path::Path,
     res: &mut Resources,
 ) -> Result<Scene, DeserializeError> {
-    let top_level: serde_json::Value = serde_json::from_str(data)?;
+    let mut top_level: serde_json::Value = serde_json::from_str(data)?;
     if !top_level.is_object() {
         return Err(DeserializeError::LocalError(String::from(
             "Top level scene spec is not a JSON object.",
         )));
     }
+    // Everything below reads the current version of the format
+    match version::migrate(&mut top_level) {
+        Ok(()) => {}
+        Err(e) => return Err(DeserializeError::LocalError(e)),
+    }
+    // Library materials become ordinary ones
+    match presets::expand(&mut top_level) {
+        Ok(()) => {}
+        Err(e) => return Err(DeserializeError::LocalError(e)),
+    }
 
     // Pull out logistics struct
     let logistics_value = get_required_key(&top_level, "Logistics")?;
     let logistics: Logistics = serde_json::from_value(serde_json::Value::clone(logistics_value))?;
+    match &logistics.search_paths {
+        Some(paths) => res.set_search_paths(spec_dir, paths),
+        None => res.set_search_paths(spec_dir, &[]),
+    }
+    // Only the image itself is kept on disk, and passes and gradients would
+    // each take as much memory as it
+    if logistics.framebuffer() == Precision::Disk
+        && (!logistics.traced_passes().is_empty() || logistics.gradient_domain.is_some())
+    {
+        return Err(DeserializeError::LocalError(String::from(
+            "A Disk framebuffer can not be used with 'passes', 'denoise', or 'gradient_domain'.",
+        )));
+    }
+    // A shifted gradient path could not replay the light samples a pixel
+    // borrowed from its neighbors
+    if logistics.light_resampling.is_some() && logistics.gradient_domain.is_some() {
+        return Err(DeserializeError::LocalError(String::from(
+            "'light_resampling' can not be used with 'gradient_domain'.",
+        )));
+    }
 
-    // Pull out camera struct
-    let camera_value = get_required_key(&top_level, "Camera")?;
-    let camera: Camera = serde_json::from_value(serde_json::Value::clone(camera_value))?;
+    // Pull out camera struct, placed along its path for this frame
+    let mut camera_value = serde_json::Value::clone(get_required_key(&top_level, "Camera")?);
+    match camerapath::follow(&mut camera_value, logistics.frame.unwrap_or(0)) {
+        Ok(()) => {}
+        Err(e) => return Err(DeserializeError::LocalError(e)),
+    }
+    let camera: Camera = serde_json::from_value(camera_value)?;
 
     // Create textures library
     let textures_value = match get_required_key(&top_level, "Textures")?.as_object() {
@@ -90,12 +305,49 @@ pub fn deserialize(
             )));
         }
     };
+    let working_space = logistics.working_space();
     let mut textures = HashMap::new();
+    // Composed textures refer to other textures, so they wait until those
+    // are made. They are made from the textures before profiling, so the
+    // time spent in their inputs is not counted twice.
+    let mut unprofiled = HashMap::new();
+    let mut composed = Vec::new();
     for (key, value) in textures_value.iter() {
-        textures.insert(
-            String::clone(key),
-            deserialize_texture(value, spec_dir, res)?,
-        );
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some(t) if COMPOSE_TYPES.contains(&t) => {
+                composed.push((key, value));
+                continue;
+            }
+            _ => {}
+        }
+        let mut texture = deserialize_texture(value, spec_dir, res)?;
+        if !working_space.has_rec709_primaries() {
+            texture = Arc::new(texture::WorkingSpace::new(texture, working_space));
+        }
+        let texture = texture::fold_constant(texture);
+        unprofiled.insert(String::clone(key), Arc::clone(&texture));
+        textures.insert(String::clone(key), texture::profiled(texture));
+    }
+    while !composed.is_empty() {
+        let mut waiting = Vec::new();
+        for (key, value) in &composed {
+            match deserialize_compose(value, &unprofiled)? {
+                Some(t) => {
+                    let texture = texture::fold_constant(t);
+                    unprofiled.insert(String::clone(key), Arc::clone(&texture));
+                    textures.insert(String::clone(key), texture::profiled(texture));
+                }
+                None => waiting.push((*key, *value)),
+            }
+        }
+        if waiting.len() == composed.len() {
+            let names: Vec<&str> = waiting.iter().map(|(k, _)| k.as_str()).collect();
+            return Err(DeserializeError::LocalError(format!(
+                "Textures {} refer to missing textures, or to each other in a cycle.",
+                names.join(", ")
+            )));
+        }
+        composed = waiting;
     }
 
     // Create materials library
@@ -108,8 +360,87 @@ pub fn deserialize(
         }
     };
     let mut materials = HashMap::new();
+    // Blends and clearcoats refer to other materials, so they wait until
+    // those are made
+    let mut layered = Vec::new();
+    for (key, value) in materials_value.iter() {
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("Blend") | Some("Clearcoat") => {
+                layered.push((key, value));
+                continue;
+            }
+            _ => {}
+        }
+        materials.insert(
+            String::clone(key),
+            deserialize_material(value, &textures, working_space, spec_dir, res)?,
+        );
+    }
+    while !layered.is_empty() {
+        let mut waiting = Vec::new();
+        for (key, value) in &layered {
+            let made = match identify_type(value)? {
+                "Blend" => deserialize_blend(value, &textures, &materials)?,
+                _ => deserialize_clearcoat(value, &textures, working_space, &materials)?,
+            };
+            match made {
+                Some(m) => {
+                    materials.insert(String::clone(key), m);
+                }
+                None => waiting.push((*key, *value)),
+            }
+        }
+        if waiting.len() == layered.len() {
+            let names: Vec<&str> = waiting.iter().map(|(k, _)| k.as_str()).collect();
+            return Err(DeserializeError::LocalError(format!(
+                "Materials {} refer to missing materials, or to each other in a cycle.",
+                names.join(", ")
+            )));
+        }
+        layered = waiting;
+    }
+    let override_material = match &logistics.override_material {
+        Some(name) => Some(override_materials(name, materials_value, &mut materials)?),
+        None => None,
+    };
+    let mut material_names = HashMap::new();
+    for (key, material) in materials.iter() {
+        material_names.insert(stats::material_key(&(**material)), String::clone(key));
+    }
+    let mut material_visibility = HashMap::new();
+    let mut outlines = HashMap::new();
     for (key, value) in materials_value.iter() {
-        materials.insert(String::clone(key), deserialize_material(value, &textures)?);
+        let material = &materials[key];
+        // An overridden material's visibility would hide the override
+        // everywhere it is used
+        let overridden = match &override_material {
+            Some(m) => Arc::ptr_eq(m, material),
+            None => false,
+        };
+        match value.get("visibility") {
+            Some(v) if !overridden => {
+                material_visibility.insert(
+                    stats::material_key(&(**material)),
+                    deserialize_visibility(v)?,
+                );
+            }
+            _ => {}
+        }
+        match value.get("outline") {
+            Some(o) if !overridden && identify_type(value)? == "Toon" => {
+                outlines.insert(
+                    stats::material_key(&(**material)),
+                    serde_json::from_value::<Outline>(serde_json::Value::clone(o))?,
+                );
+            }
+            _ => {}
+        }
+    }
+    // Outlines are found from the whole image at once
+    if !outlines.is_empty() && logistics.framebuffer() == Precision::Disk {
+        return Err(DeserializeError::LocalError(String::from(
+            "Outlines can not be drawn on a Disk framebuffer.",
+        )));
     }
 
     // Set up shapes
@@ -121,219 +452,1347 @@ pub fn deserialize(
             )))
         }
     };
+    // Clip planes are optional
+    let mut clip_planes = Vec::new();
+    match top_level.get("ClipPlanes") {
+        Some(c) => match c.as_array() {
+            Some(clip_planes_value) => {
+                for clip_plane in clip_planes_value {
+                    clip_planes.push(deserialize_clip_plane(clip_plane)?);
+                }
+            }
+            None => {
+                return Err(DeserializeError::LocalError(String::from(
+                    "'ClipPlanes' is not a JSON array.",
+                )))
+            }
+        },
+        None => {}
+    }
+
+    // Material libraries are read as meshes need them
+    let mut libraries = MaterialLibraries::new(res, working_space, override_material);
+
+    // Prototypes are optional, and are only seen through Instances of them
+    let mut prototypes = HashMap::new();
+    match top_level.get("Prototypes") {
+        Some(p) => match p.as_array() {
+            Some(prototypes_value) => {
+                for (index, prototype) in prototypes_value.iter().enumerate() {
+                    let name = shape_name(prototype, index)?;
+                    if prototype.get("alpha").is_some() {
+                        return Err(DeserializeError::LocalError(format!(
+                            "Prototype {} can not have an 'alpha', its Instances can.",
+                            name
+                        )));
+                    }
+                    if prototypes.contains_key(&name) {
+                        return Err(DeserializeError::LocalError(format!(
+                            "Prototype name {} is used more than once.",
+                            name
+                        )));
+                    }
+                    let mut prototype_shapes = Vec::new();
+                    deserialize_shape(
+                        prototype,
+                        spec_dir,
+                        &materials,
+                        &prototypes,
+                        &mut libraries,
+                        &mut prototype_shapes,
+                    )?;
+                    if prototype_shapes.is_empty() {
+                        return Err(DeserializeError::LocalError(format!(
+                            "Prototype {} has no shapes.",
+                            name
+                        )));
+                    }
+                    prototypes.insert(name, Arc::new(Prototype::new(prototype_shapes)));
+                }
+            }
+            None => {
+                return Err(DeserializeError::LocalError(String::from(
+                    "'Prototypes' is not a JSON array.",
+                )))
+            }
+        },
+        None => {}
+    }
+
     // Iterate through the shapes and deserialize correctly
     let mut shapes: Vec<Arc<SyncShape>> = Vec::with_capacity(shapes_value.len());
-    for shape in shapes_value {
-        deserialize_shape(shape, spec_dir, &materials, &mut shapes)?;
+    let mut shape_names = HashMap::new();
+    // Links of emissive shapes, resolved once every shape has its name
+    let mut emitter_link_descs = Vec::new();
+    let mut alpha_masks = HashMap::new();
+    for (index, shape) in shapes_value.iter().enumerate() {
+        let first_new_shape = shapes.len();
+        deserialize_shape(
+            shape,
+            spec_dir,
+            &materials,
+            &prototypes,
+            &mut libraries,
+            &mut shapes,
+        )?;
+        // Every Shape created from one entry (each Triangle of a Mesh, for
+        // example) shares that entry's name
+        let name = shape_name(shape, index)?;
+
+        match shape_motion(shape)? {
+            Some(m) => {
+                let new_shapes = shapes.split_off(first_new_shape);
+                for new_shape in new_shapes {
+                    shapes.push(Arc::new(match motion::Moving::new(new_shape, &m) {
+                        Ok(s) => s,
+                        Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+                    }));
+                }
+            }
+            None => {}
+        }
+
+        let planes: Vec<ClipPlane> = clip_planes
+            .iter()
+            .filter(|c| match &c.shapes {
+                Some(names) => names.contains(&name),
+                None => true,
+            })
+            .map(|c| ClipPlane::new(c.point, c.normal))
+            .collect();
+        if !planes.is_empty() {
+            // Shapes entirely on the removed side of a plane are dropped
+            let new_shapes = shapes.split_off(first_new_shape);
+            for new_shape in new_shapes {
+                let bounds = new_shape.get_bounding_box();
+                if planes.iter().any(|p| p.removes(&bounds)) {
+                    continue;
+                }
+                shapes.push(Arc::new(clip::Clipped::new(new_shape, planes.clone())));
+            }
+        }
+
+        for new_shape in &shapes[first_new_shape..] {
+            shape_names.insert(stats::shape_key(&(**new_shape)), String::clone(&name));
+        }
+        match shape.get("light_links") {
+            Some(l) => {
+                let keys: Vec<usize> = shapes[first_new_shape..]
+                    .iter()
+                    .map(|s| stats::shape_key(&(**s)))
+                    .collect();
+                emitter_link_descs.push((keys, l));
+            }
+            None => {}
+        }
+        match shape.get("alpha") {
+            Some(a) => {
+                let mask = match a.as_str().and_then(|n| textures.get(n)) {
+                    Some(t) => t,
+                    None => {
+                        return Err(DeserializeError::LocalError(format!(
+                            "Shape {} has an 'alpha' that is not the name of a Texture.",
+                            name
+                        )))
+                    }
+                };
+                for new_shape in &shapes[first_new_shape..] {
+                    alpha_masks.insert(stats::shape_key(&(**new_shape)), Arc::clone(mask));
+                }
+            }
+            None => {}
+        }
+    }
+    // Shapes are linked to by name, which every Shape of a Mesh shares
+    let mut named_shapes: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (key, name) in shape_names.iter() {
+        named_shapes
+            .entry(name.as_str())
+            .or_insert_with(Vec::new)
+            .push(*key);
+    }
+    let mut emitter_links = HashMap::new();
+    for (keys, links_value) in emitter_link_descs {
+        for key in keys {
+            emitter_links.insert(key, deserialize_light_links(links_value, &named_shapes)?);
+        }
     }
+    // Materials from OBJ material libraries go by their names there
+    for (key, material) in libraries.materials() {
+        material_names
+            .entry(stats::material_key(&(**material)))
+            .or_insert_with(|| String::clone(key));
+    }
+
+    let ray_epsilon = match logistics.ray_epsilon {
+        Some(e) => {
+            if e < 0.0_f32 {
+                return Err(DeserializeError::LocalError(String::from(
+                    "'ray_epsilon' can not be negative.",
+                )));
+            }
+            e
+        }
+        None => scene_ray_epsilon(&shapes),
+    };
 
     // Pull out any important shapes for sampling in a separate list
     let use_importance_sampling = logistics.use_importance_sampling.unwrap_or(true);
-    let mut samples = Vec::new();
+    // Emitters with an image are sampled where the image is brightest, every
+    // shape with the same material together, and in the place of the first
+    let mut emitters: Vec<(Vec<Arc<SyncShape>>, Option<Arc<SyncTexture>>)> = Vec::new();
+    let mut textured_emitters: HashMap<usize, usize> = HashMap::new();
     if use_importance_sampling {
         for shape in &shapes {
-            if shape.get_material().is_important() {
-                samples.push(pdf::PDF::Shape(pdf::Shape::new(&shape)));
+            let material = shape.get_material();
+            if !material.is_important() {
+                continue;
+            }
+            match material.emission_texture() {
+                Some(texture) if texture.constant_value().is_none() => {
+                    let key = Arc::as_ptr(material) as *const u8 as usize;
+                    match textured_emitters.get(&key) {
+                        Some(i) => emitters[*i].0.push(Arc::clone(shape)),
+                        None => {
+                            textured_emitters.insert(key, emitters.len());
+                            emitters.push((vec![Arc::clone(shape)], Some(Arc::clone(texture))));
+                        }
+                    }
+                }
+                _ => emitters.push((vec![Arc::clone(shape)], None)),
+            }
+        }
+    }
+    // Each emitter is picked as often as its shapes would be on their own
+    let mut samples = Vec::new();
+    let mut sample_weights = Vec::new();
+    for (emitter_shapes, texture) in &emitters {
+        let textured = match texture {
+            Some(t) => pdf::TexturedEmitter::new(emitter_shapes, t.as_ref(), ray_epsilon),
+            None => None,
+        };
+        match textured {
+            Some(t) => {
+                samples.push(pdf::PDF::TexturedEmitter(t));
+                sample_weights.push(emitter_shapes.len() as f32);
+            }
+            None => {
+                for shape in emitter_shapes {
+                    samples.push(pdf::PDF::Shape(pdf::Shape::new(shape, ray_epsilon)));
+                    sample_weights.push(1.0_f32);
+                }
             }
         }
     }
-    let important_samples = Arc::new(pdf::PDF::Mixture(pdf::Mixture::new(samples)));
+    let mixture = if sample_weights.iter().all(|w| *w == 1.0_f32) {
+        pdf::Mixture::new(samples)
+    } else {
+        pdf::Mixture::weighted(samples, &sample_weights)
+    };
+    let important_samples = Arc::new(pdf::PDF::Mixture(mixture));
 
     // Break the shapes down into the aggregate structure
-    let aggregate_type = match get_required_key(&top_level, "Aggregate")?.as_str() {
-        Some(t) => t,
-        None => {
-            return Err(DeserializeError::LocalError(String::from(
-                "'Aggregate' is not a string.",
-            )))
-        }
+    let shape_aggregate =
+        create_aggregate(get_required_key(&top_level, "Aggregate")?, spec_dir, shapes)?;
+
+    // Lights are optional, as emissive shapes can light a scene too
+    let mut lights = Vec::new();
+    match top_level.get("Lights") {
+        Some(l) => match l.as_array() {
+            Some(lights_value) => {
+                for light in lights_value {
+                    let links = match light.get("light_links") {
+                        Some(l) => Some(deserialize_light_links(l, &named_shapes)?),
+                        None => None,
+                    };
+                    let shadows = match light.get("shadows") {
+                        Some(s) => deserialize_shadows(s, &named_shapes, working_space)?,
+                        None => Shadows::solid(),
+                    };
+                    lights.push(SceneLight {
+                        light: deserialize_light(light, working_space)?,
+                        links: links,
+                        shadows: shadows,
+                    });
+                }
+            }
+            None => {
+                return Err(DeserializeError::LocalError(String::from(
+                    "'Lights' is not a JSON array.",
+                )))
+            }
+        },
+        None => {}
+    }
+
+    // Fog is optional
+    let fog = match top_level.get("Fog") {
+        Some(f) => Some(deserialize_fog(f, working_space)?),
+        None => None,
     };
-    let shape_aggregate = create_aggregate(aggregate_type, shapes)?;
 
+    let fingerprint = fingerprint(&top_level, res)?;
     Ok(Scene {
         logistics: logistics,
         camera: camera,
         shape_aggregate: shape_aggregate,
         important_samples: important_samples,
+        ray_epsilon: ray_epsilon,
+        shape_names: shape_names,
+        material_names: material_names,
+        fog: fog,
+        lights: lights,
+        emitter_links: emitter_links,
+        material_visibility: material_visibility,
+        outlines: outlines,
+        alpha_masks: alpha_masks,
+        fingerprint: fingerprint,
     })
 }
 
-// Just a helper for getting a key expected in the JSON.
-fn get_required_key<'a>(
-    dict: &'a serde_json::Value,
-    key: &str,
-) -> Result<&'a serde_json::Value, DeserializeError> {
-    match dict.get(key) {
-        Some(v) => Ok(v),
-        None => {
-            return Err(DeserializeError::LocalError(format!(
-                "Required key {} is missing.",
-                key
-            )))
-        }
-    }
-}
-
-fn identify_type(dict: &serde_json::Value) -> Result<&str, DeserializeError> {
-    match get_required_key(dict, "type")?.as_str() {
-        Some(t) => Ok(t),
-        None => {
-            return Err(DeserializeError::LocalError(format!(
-                "Expected 'type' key to be a string: {}",
-                serde_json::to_string(dict)?
-            )))
+// Renders of the same scene from the same files share a fingerprint, which
+// goes in their images and is checked before anything is merged into one,
+// so renders of different scenes are not combined by mistake. It hashes the
+// spec as read, after migration and library materials are expanded, and
+// the contents of every file it loaded, so specs that differ only in
+// whitespace or the order of their keys, or scenes moved elsewhere, keep
+// theirs. Files are read again for it, from the disk cache for the most
+// part, having just been loaded.
+fn fingerprint(top_level: &serde_json::Value, res: &Resources) -> Result<String, DeserializeError> {
+    let mut hasher = DefaultHasher::new();
+    top_level.to_string().hash(&mut hasher);
+    let mut block = vec![0_u8; 1 << 16];
+    for path in res.dependencies() {
+        let mut file = fs::File::open(path)?;
+        loop {
+            let read = file.read(&mut block)?;
+            if read == 0 {
+                break;
+            }
+            hasher.write(&block[..read]);
         }
     }
+    return Ok(format!("{:016x}", hasher.finish()));
 }
 
-fn deserialize_texture(
+// Light colors are converted into the working space, like textures
+fn deserialize_light(
     json: &serde_json::Value,
-    spec_dir: &path::Path,
-    res: &mut Resources,
-) -> Result<Arc<SyncTexture>, DeserializeError> {
-    if !json.is_object() {
-        return Err(DeserializeError::LocalError(format!(
-            "Expected JSON object for value in Texture map: {}",
-            serde_json::to_string(json)?
-        )));
-    }
-
-    let tex_type = identify_type(json)?;
-    match tex_type {
-        "Constant" => Ok(serde_json::from_value::<Arc<texture::Constant>>(
-            serde_json::Value::clone(json),
-        )?),
-        "Test" => Ok(Arc::new(texture::Test)),
-        "Checker" => deserialize_checker(json, spec_dir, res),
-        "Image" => deserialize_image(json, spec_dir, res),
-        "Noise" => Ok(serde_json::from_value::<Arc<texture::Noise>>(
-            serde_json::Value::clone(json),
-        )?),
-        "Turbulence" => Ok(serde_json::from_value::<Arc<texture::Turbulence>>(
-            serde_json::Value::clone(json),
-        )?),
+    working_space: ColorSpace,
+) -> Result<Box<SyncLight>, DeserializeError> {
+    let light_type = identify_type(json)?;
+    match light_type {
+        "Point" => deserialize_point_light(json, working_space),
+        "Directional" => deserialize_directional_light(json, working_space),
         _ => Err(DeserializeError::LocalError(format!(
-            "Unsupported texture type: {}",
-            tex_type
+            "Unknown Light 'type' {} given.",
+            light_type
         ))),
     }
 }
 
-// Checker
+// Light links
+// Names of the shapes a light lights, or of those it does not
 #[derive(Deserialize)]
-struct CheckerDescription {
-    repeat: f32,
-    odd: serde_json::Value,
-    even: serde_json::Value,
+struct LightLinksDescription {
+    include: Option<Vec<String>>,
+    #[serde(default)]
+    exclude: Vec<String>,
 }
 
-fn deserialize_checker(
+fn deserialize_light_links(
     json: &serde_json::Value,
-    spec_dir: &path::Path,
-    res: &mut Resources,
-) -> Result<Arc<SyncTexture>, DeserializeError> {
-    let checker_desc: CheckerDescription = serde_json::from_value(serde_json::Value::clone(json))?;
-    return Ok(Arc::new(texture::Checker::new(
-        checker_desc.repeat,
-        deserialize_texture(&checker_desc.odd, spec_dir, res)?,
-        deserialize_texture(&checker_desc.even, spec_dir, res)?,
-    )));
+    named_shapes: &HashMap<&str, Vec<usize>>,
+) -> Result<LightLinks, DeserializeError> {
+    let links_desc: LightLinksDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    let include = match &links_desc.include {
+        Some(i) => Some(linked_shapes(i, named_shapes)?),
+        None => None,
+    };
+    return Ok(LightLinks::new(
+        include,
+        linked_shapes(&links_desc.exclude, named_shapes)?,
+    ));
 }
 
-// Image
+// Keys of every Shape with one of the names
+fn linked_shapes(
+    names: &Vec<String>,
+    named_shapes: &HashMap<&str, Vec<usize>>,
+) -> Result<HashSet<usize>, DeserializeError> {
+    let mut keys = HashSet::new();
+    for name in names {
+        match named_shapes.get(name.as_str()) {
+            Some(k) => keys.extend(k.iter().cloned()),
+            None => {
+                return Err(DeserializeError::LocalError(format!(
+                    "Light links name Shape {}, which is not in the scene.",
+                    name
+                )))
+            }
+        }
+    }
+    return Ok(keys);
+}
+
+// Shadows
+// Shapes that cast no shadow from a light, and how dark and what color the
+// shadows of the rest are. A density of 1 with the default black is a
+// solid shadow.
 #[derive(Deserialize)]
-struct ImageDescription {
-    image_path: String,
+struct ShadowsDescription {
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default = "default_shadow_density")]
+    density: f32,
+    color: Option<RGB>,
 }
 
-fn deserialize_image(
-    json: &serde_json::Value,
-    spec_dir: &path::Path,
-    res: &mut Resources,
-) -> Result<Arc<SyncTexture>, DeserializeError> {
-    let image_desc: ImageDescription = serde_json::from_value(serde_json::Value::clone(json))?;
-    return Ok(Arc::new(texture::Image::new(
-        match res.load_image(&spec_dir.join(image_desc.image_path)) {
-            Ok(i) => i,
-            Err(e) => return Err(DeserializeError::LocalError(e)),
-        },
-    )));
+fn default_shadow_density() -> f32 {
+    1.0_f32
 }
 
-fn deserialize_material(
+fn deserialize_shadows(
     json: &serde_json::Value,
-    textures: &HashMap<String, Arc<SyncTexture>>,
-) -> Result<Arc<SyncMaterial>, DeserializeError> {
-    if !json.is_object() {
-        return Err(DeserializeError::LocalError(format!(
-            "Expected JSON object for value in Materials map: {}",
-            serde_json::to_string(json)?
+    named_shapes: &HashMap<&str, Vec<usize>>,
+    working_space: ColorSpace,
+) -> Result<Shadows, DeserializeError> {
+    let shadows_desc: ShadowsDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if shadows_desc.density < 0.0_f32 || shadows_desc.density > 1.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Shadow 'density' must be between 0 and 1.",
         )));
     }
-
-    let material_type = identify_type(json)?;
-    match material_type {
-        "Lambert" => deserialize_lambert(json, textures),
-        "Metal" => deserialize_metal(json, textures),
-        "Dielectric" => Ok(serde_json::from_value::<Arc<material::Dielectric>>(
-            serde_json::Value::clone(json),
-        )?),
-        "DiffuseLight" => deserialize_diffuse_light(json, textures),
-        "Isotropic" => deserialize_isotropic(json, textures),
-        _ => Err(DeserializeError::LocalError(format!(
-            "Unsupported material type: {}",
-            material_type
-        ))),
-    }
+    let color = match shadows_desc.color {
+        Some(c) => working_space.from_rec709(c),
+        None => RGB::black(),
+    };
+    // The light let through is what the shadow's density leaves, plus the
+    // shadow's color where it is dense
+    let transmission = RGB::new(1.0_f32, 1.0_f32, 1.0_f32) * (1.0_f32 - shadows_desc.density)
+        + color * shadows_desc.density;
+    return Ok(Shadows::new(
+        linked_shapes(&shadows_desc.exclude, named_shapes)?,
+        transmission,
+    ));
 }
 
-// Lambert
+// Point Light
 #[derive(Deserialize)]
-struct LambertDescription {
-    albedo: String,
-    bump_map: Option<String>,
+struct PointLightDescription {
+    position: Point3,
+    intensity: f32,
+    // Or the temperature of a blackbody in kelvin
+    color: Option<RGB>,
+    temperature: Option<f32>,
 }
 
-fn deserialize_lambert(
+fn deserialize_point_light(
     json: &serde_json::Value,
-    textures: &HashMap<String, Arc<SyncTexture>>,
-) -> Result<Arc<SyncMaterial>, DeserializeError> {
-    let lambert_desc: LambertDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    working_space: ColorSpace,
+) -> Result<Box<SyncLight>, DeserializeError> {
+    let point_desc: PointLightDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if point_desc.intensity < 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Point light 'intensity' can not be negative.",
+        )));
+    }
+    let color = light_color(
+        point_desc.color,
+        point_desc.temperature,
+        "Point light",
+        "color",
+    )?;
+    return Ok(Box::new(light::Point::new(
+        point_desc.position,
+        point_desc.intensity,
+        working_space.from_rec709(color),
+    )));
+}
+
+// Directional Light
+#[derive(Deserialize)]
+struct DirectionalLightDescription {
+    // Or where the sun is at a place and time
+    direction: Option<Vector3>,
+    sun_position: Option<SunPositionDescription>,
+    // "Disk" or "Rectangle", defaulting to a disk
+    shape: Option<String>,
+    // Degrees across the sun, or across the rectangle's width
+    #[serde(default)]
+    angular_diameter: f32,
+    // Degrees across the rectangle's height, defaulting to its width
+    angular_height: Option<f32>,
+    // Or the temperature of a blackbody in kelvin, either scaled by the
+    // intensity
+    radiance: Option<RGB>,
+    temperature: Option<f32>,
+    #[serde(default = "default_directional_intensity")]
+    intensity: f32,
+}
+
+fn default_directional_intensity() -> f32 {
+    1.0_f32
+}
+
+fn deserialize_directional_light(
+    json: &serde_json::Value,
+    working_space: ColorSpace,
+) -> Result<Box<SyncLight>, DeserializeError> {
+    let directional_desc: DirectionalLightDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    let direction = match (&directional_desc.direction, &directional_desc.sun_position) {
+        (Some(d), None) => *d,
+        (None, Some(s)) => sun_direction(s)?,
+        _ => {
+            return Err(DeserializeError::LocalError(String::from(
+                "Directional light needs exactly one of 'direction' or 'sun_position'.",
+            )))
+        }
+    };
+    if direction.squared_length() == 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Directional light 'direction' can not be zero.",
+        )));
+    }
+    let angular_height = directional_desc
+        .angular_height
+        .unwrap_or(directional_desc.angular_diameter);
+    if directional_desc.angular_diameter < 0.0_f32 || angular_height < 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Directional light angular sizes can not be negative.",
+        )));
+    }
+    let shape = match directional_desc.shape.as_ref().map(String::as_str) {
+        None | Some("Disk") => light::Directional::disk(directional_desc.angular_diameter),
+        Some("Rectangle") => {
+            light::Directional::rectangle(directional_desc.angular_diameter, angular_height)
+        }
+        Some(s) => {
+            return Err(DeserializeError::LocalError(format!(
+                "Unknown Directional light 'shape' {} given.",
+                s
+            )))
+        }
+    };
+    if directional_desc.intensity < 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Directional light 'intensity' can not be negative.",
+        )));
+    }
+    let radiance = light_color(
+        directional_desc.radiance,
+        directional_desc.temperature,
+        "Directional light",
+        "radiance",
+    )?;
+    return Ok(Box::new(light::Directional::new(
+        &direction,
+        shape,
+        working_space.from_rec709(radiance * directional_desc.intensity),
+    )));
+}
+
+// Sun position
+// A place, in degrees of latitude north and longitude east, and a local
+// date and time, whose UTC offset in hours is given for time zones and
+// daylight saving. The scene's up is +Y, and north is along -Z unless
+// another direction is given, which is flattened onto the ground.
+#[derive(Deserialize)]
+struct SunPositionDescription {
+    latitude: f32,
+    longitude: f32,
+    date: String,
+    time: String,
+    #[serde(default)]
+    utc_offset: f32,
+    north: Option<Vector3>,
+}
+
+// The direction sunlight travels in
+fn sun_direction(sun_desc: &SunPositionDescription) -> Result<Vector3, DeserializeError> {
+    if sun_desc.latitude.abs() > 90.0_f32 || sun_desc.longitude.abs() > 180.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Sun 'latitude' must be within 90 degrees, and 'longitude' within 180.",
+        )));
+    }
+    let date = match sun::parse_date(&sun_desc.date) {
+        Some(d) => d,
+        None => {
+            return Err(DeserializeError::LocalError(format!(
+                "Sun 'date' {} is not a date like 2021-06-21.",
+                sun_desc.date
+            )))
+        }
+    };
+    let time = match sun::parse_time(&sun_desc.time) {
+        Some(t) => t,
+        None => {
+            return Err(DeserializeError::LocalError(format!(
+                "Sun 'time' {} is not a time like 14:30.",
+                sun_desc.time
+            )))
+        }
+    };
+    let position = sun::position(
+        sun_desc.latitude,
+        sun_desc.longitude,
+        date,
+        time - sun_desc.utc_offset as f64,
+    );
+    if position.altitude <= 0.0_f32 {
+        return Err(DeserializeError::LocalError(format!(
+            "The sun is below the horizon at {} {}.",
+            sun_desc.date, sun_desc.time
+        )));
+    }
+
+    let up = Vector3::new(0.0_f32, 1.0_f32, 0.0_f32);
+    let north = match sun_desc.north {
+        Some(n) => Vector3::new(n.x(), 0.0_f32, n.z()),
+        None => Vector3::new(0.0_f32, 0.0_f32, -1.0_f32),
+    };
+    if north.squared_length() == 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Sun 'north' can not be vertical.",
+        )));
+    }
+    let north = north.normalized();
+    let east = north.cross(up);
+    let (altitude, azimuth) = (
+        position.altitude.to_radians(),
+        position.azimuth.to_radians(),
+    );
+    let toward =
+        altitude.cos() * (azimuth.cos() * north + azimuth.sin() * east) + altitude.sin() * up;
+    return Ok(-toward);
+}
+
+// Height fog
+#[derive(Deserialize)]
+struct FogDescription {
+    density: f32,
+    // How quickly the fog thins out with height, zero for uniform fog
+    falloff: f32,
+    #[serde(default)]
+    base_height: f32,
+    color: RGB,
+}
+
+fn deserialize_fog(
+    json: &serde_json::Value,
+    working_space: ColorSpace,
+) -> Result<HeightFog, DeserializeError> {
+    let fog_desc: FogDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if fog_desc.density <= 0.0_f32 || fog_desc.falloff < 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Fog needs a positive 'density' and a non-negative 'falloff'.",
+        )));
+    }
+    return Ok(HeightFog::new(
+        fog_desc.density,
+        fog_desc.falloff,
+        fog_desc.base_height,
+        working_space.from_rec709(fog_desc.color),
+    ));
+}
+
+// Shapes move for motion blur when given an 'end_transform', which they
+// reach at time 1 from their 'transform' at time 0. ConstantMedium entries
+// have no transform of their own, so theirs starts from the identity.
+#[derive(Deserialize)]
+struct MotionDescription {
+    #[serde(default = "Transform::new")]
+    transform: Transform,
+    end_transform: Option<Transform>,
+}
+
+// Returns the matrix taking the shape from where it starts to where it ends,
+// or None if it does not move.
+fn shape_motion(json: &serde_json::Value) -> Result<Option<Matrix4>, DeserializeError> {
+    let motion_desc: MotionDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    match motion_desc.end_transform {
+        Some(end) => {
+            let start_inverse = match motion_desc.transform.create_matrix().inverse() {
+                Ok(m) => m,
+                Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+            };
+            return Ok(Some(end.create_matrix() * start_inverse));
+        }
+        None => return Ok(None),
+    }
+}
+
+// Clip Plane
+#[derive(Deserialize)]
+struct ClipPlaneDescription {
+    point: Point3,
+    // Points toward the side that is removed
+    normal: Vector3,
+    // Names of the shapes to clip, or every shape when not given
+    shapes: Option<Vec<String>>,
+}
+
+fn deserialize_clip_plane(
+    json: &serde_json::Value,
+) -> Result<ClipPlaneDescription, DeserializeError> {
+    let clip_desc: ClipPlaneDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if clip_desc.normal.squared_length() == 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Clip plane 'normal' can not be zero.",
+        )));
+    }
+    return Ok(clip_desc);
+}
+
+// Shapes may be given an optional 'name', otherwise they are named by their
+// type and position in the Shapes array, or by file for Meshes.
+fn shape_name(json: &serde_json::Value, index: usize) -> Result<String, DeserializeError> {
+    match json.get("name") {
+        Some(n) => match n.as_str() {
+            Some(name) => return Ok(String::from(name)),
+            None => {
+                return Err(DeserializeError::LocalError(format!(
+                    "Shape 'name' must be a string: {}",
+                    serde_json::to_string(n)?
+                )))
+            }
+        },
+        None => {}
+    }
+    let shape_type = identify_type(json)?;
+    match json.get("file_path").and_then(|f| f.as_str()) {
+        Some(f) if shape_type == "Mesh" => return Ok(format!("{} {}", shape_type, f)),
+        _ => return Ok(format!("{} {}", shape_type, index)),
+    }
+}
+
+fn scene_ray_epsilon(shapes: &Vec<Arc<SyncShape>>) -> f32 {
+    if shapes.is_empty() {
+        return utils::T_MIN;
+    }
+
+    let mut magnitude = 0.0_f32;
+    for shape in shapes {
+        // An infinite plane's bounds say nothing about the scene's size
+        if !shape.is_bounded() {
+            continue;
+        }
+        let bounds = shape.get_bounding_box();
+        for p in [bounds.min, bounds.max].iter() {
+            magnitude = utils::float_max(magnitude, p.x().abs());
+            magnitude = utils::float_max(magnitude, p.y().abs());
+            magnitude = utils::float_max(magnitude, p.z().abs());
+        }
+    }
+    return utils::float_max(magnitude * RAY_EPSILON_SCALE, RAY_EPSILON_MIN);
+}
+
+// Just a helper for getting a key expected in the JSON.
+fn get_required_key<'a>(
+    dict: &'a serde_json::Value,
+    key: &str,
+) -> Result<&'a serde_json::Value, DeserializeError> {
+    match dict.get(key) {
+        Some(v) => Ok(v),
+        None => {
+            return Err(DeserializeError::LocalError(format!(
+                "Required key {} is missing.",
+                key
+            )))
+        }
+    }
+}
+
+fn identify_type(dict: &serde_json::Value) -> Result<&str, DeserializeError> {
+    match get_required_key(dict, "type")?.as_str() {
+        Some(t) => Ok(t),
+        None => {
+            return Err(DeserializeError::LocalError(format!(
+                "Expected 'type' key to be a string: {}",
+                serde_json::to_string(dict)?
+            )))
+        }
+    }
+}
+
+fn deserialize_texture(
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncTexture>, DeserializeError> {
+    if !json.is_object() {
+        return Err(DeserializeError::LocalError(format!(
+            "Expected JSON object for value in Texture map: {}",
+            serde_json::to_string(json)?
+        )));
+    }
+
+    let tex_type = identify_type(json)?;
+    match tex_type {
+        "Constant" => Ok(serde_json::from_value::<Arc<texture::Constant>>(
+            serde_json::Value::clone(json),
+        )?),
+        "Test" => Ok(Arc::new(texture::Test)),
+        "Checker" => deserialize_checker(json, spec_dir, res),
+        "Image" => deserialize_image(json, spec_dir, res),
+        "Noise" => Ok(serde_json::from_value::<Arc<texture::Noise>>(
+            serde_json::Value::clone(json),
+        )?),
+        "Turbulence" => Ok(serde_json::from_value::<Arc<texture::Turbulence>>(
+            serde_json::Value::clone(json),
+        )?),
+        "Blackbody" => deserialize_blackbody(json),
+        "InstanceAttribute" => Ok(serde_json::from_value::<Arc<texture::InstanceAttribute>>(
+            serde_json::Value::clone(json),
+        )?),
+        "Ramp" => deserialize_ramp(json, spec_dir, res),
+        "Triplanar" => deserialize_triplanar(json, spec_dir, res),
+        t if COMPOSE_TYPES.contains(&t) => Err(DeserializeError::LocalError(format!(
+            "{} textures refer to others by name, so can only be given in the Textures map.",
+            t
+        ))),
+        _ => Err(DeserializeError::LocalError(format!(
+            "Unsupported texture type: {}",
+            tex_type
+        ))),
+    }
+}
+
+// Checker
+#[derive(Deserialize)]
+struct CheckerDescription {
+    repeat: f32,
+    odd: serde_json::Value,
+    even: serde_json::Value,
+}
+
+// Blackbody
+// The color of light from a blackbody at a temperature in kelvin, for
+// emission, scaled by an intensity
+#[derive(Deserialize)]
+struct BlackbodyDescription {
+    temperature: f32,
+    #[serde(default = "default_blackbody_intensity")]
+    intensity: f32,
+}
+
+fn default_blackbody_intensity() -> f32 {
+    1.0_f32
+}
+
+fn deserialize_blackbody(json: &serde_json::Value) -> Result<Arc<SyncTexture>, DeserializeError> {
+    let blackbody_desc: BlackbodyDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    if blackbody_desc.intensity < 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Blackbody 'intensity' can not be negative.",
+        )));
+    }
+    let color = blackbody_color(blackbody_desc.temperature, "Blackbody")?;
+    return Ok(Arc::new(texture::Constant::new(
+        color * blackbody_desc.intensity,
+    )));
+}
+
+// The Rec. 709 color, with a luminance of 1, of a blackbody at the
+// temperature in kelvin
+fn blackbody_color(temperature: f32, what: &str) -> Result<RGB, DeserializeError> {
+    if !(temperature > 0.0_f32) {
+        return Err(DeserializeError::LocalError(format!(
+            "{} 'temperature' must be positive.",
+            what
+        )));
+    }
+    return Ok(spectrum::blackbody(temperature));
+}
+
+// A light's color, given either as a color or as the temperature of a
+// blackbody
+fn light_color(
+    color: Option<RGB>,
+    temperature: Option<f32>,
+    what: &str,
+    color_key: &str,
+) -> Result<RGB, DeserializeError> {
+    match (color, temperature) {
+        (Some(c), None) => Ok(c),
+        (None, Some(t)) => blackbody_color(t, what),
+        _ => Err(DeserializeError::LocalError(format!(
+            "{} needs exactly one of '{}' or 'temperature'.",
+            what, color_key
+        ))),
+    }
+}
+
+fn deserialize_checker(
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncTexture>, DeserializeError> {
+    let checker_desc: CheckerDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    return Ok(Arc::new(texture::Checker::new(
+        checker_desc.repeat,
+        texture::fold_constant(deserialize_texture(&checker_desc.odd, spec_dir, res)?),
+        texture::fold_constant(deserialize_texture(&checker_desc.even, spec_dir, res)?),
+    )));
+}
+
+// Ramp
+#[derive(Deserialize)]
+struct RampDescription {
+    // "U", "V", or a texture whose luminance is the input
+    input: serde_json::Value,
+    stops: Vec<RampStopDescription>,
+    #[serde(default = "default_ramp_interpolation")]
+    interpolation: RampInterpolation,
+}
+
+#[derive(Deserialize)]
+struct RampStopDescription {
+    position: f32,
+    color: RGB,
+}
+
+fn default_ramp_interpolation() -> RampInterpolation {
+    RampInterpolation::Linear
+}
+
+fn deserialize_ramp(
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncTexture>, DeserializeError> {
+    let ramp_desc: RampDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if ramp_desc.stops.is_empty() {
+        return Err(DeserializeError::LocalError(String::from(
+            "Ramp needs at least one stop.",
+        )));
+    }
+    if ramp_desc.stops.iter().any(|s| !s.position.is_finite()) {
+        return Err(DeserializeError::LocalError(String::from(
+            "Ramp stop positions must be finite.",
+        )));
+    }
+    let input = match ramp_desc.input.as_str() {
+        Some("U") => RampInput::U,
+        Some("V") => RampInput::V,
+        Some(other) => {
+            return Err(DeserializeError::LocalError(format!(
+                "Ramp input must be U, V, or a texture, not {}.",
+                other
+            )))
+        }
+        None => RampInput::Luminance(texture::fold_constant(deserialize_texture(
+            &ramp_desc.input,
+            spec_dir,
+            res,
+        )?)),
+    };
+    return Ok(Arc::new(texture::Ramp::new(
+        input,
+        ramp_desc
+            .stops
+            .iter()
+            .map(|s| (s.position, s.color))
+            .collect(),
+        ramp_desc.interpolation,
+    )));
+}
+
+// Triplanar
+#[derive(Deserialize)]
+struct TriplanarDescription {
+    texture: serde_json::Value,
+    #[serde(default = "default_triplanar_scale")]
+    scale: f32,
+    #[serde(default = "default_triplanar_sharpness")]
+    sharpness: f32,
+}
+
+fn default_triplanar_scale() -> f32 {
+    1.0_f32
+}
+
+fn default_triplanar_sharpness() -> f32 {
+    4.0_f32
+}
+
+fn deserialize_triplanar(
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncTexture>, DeserializeError> {
+    let triplanar_desc: TriplanarDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    if !triplanar_desc.scale.is_finite() {
+        return Err(DeserializeError::LocalError(String::from(
+            "Triplanar 'scale' must be finite.",
+        )));
+    }
+    if !(triplanar_desc.sharpness >= 0.0_f32 && triplanar_desc.sharpness.is_finite()) {
+        return Err(DeserializeError::LocalError(String::from(
+            "Triplanar 'sharpness' can not be negative.",
+        )));
+    }
+    let texture =
+        texture::fold_constant(deserialize_texture(&triplanar_desc.texture, spec_dir, res)?);
+    return Ok(Arc::new(texture::Triplanar::new(
+        texture,
+        triplanar_desc.scale,
+        triplanar_desc.sharpness,
+    )));
+}
+
+// Multiply, Add, Invert, Clamp, and Mix
+// Textures made from others in the Textures map, given by name
+const COMPOSE_TYPES: [&str; 5] = ["Multiply", "Add", "Invert", "Clamp", "Mix"];
+
+// Multiply and Add
+#[derive(Deserialize)]
+struct CombineDescription {
+    textures: Vec<String>,
+}
+
+// Invert
+#[derive(Deserialize)]
+struct InvertDescription {
+    texture: String,
+}
+
+// Clamp
+#[derive(Deserialize)]
+struct ClampDescription {
+    texture: String,
+    #[serde(default)]
+    min: f32,
+    #[serde(default = "default_clamp_max")]
+    max: f32,
+}
+
+fn default_clamp_max() -> f32 {
+    1.0_f32
+}
+
+// Mix
+// Like a Blend of materials, the top is mixed over the base by the mask
+#[derive(Deserialize)]
+struct MixDescription {
+    base: String,
+    top: String,
+    mask: String,
+}
+
+// Returns None while any of the textures composed is still to be made
+fn deserialize_compose(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+) -> Result<Option<Arc<SyncTexture>>, DeserializeError> {
+    let tex_type = identify_type(json)?;
+    let (operation, names) = match tex_type {
+        "Multiply" | "Add" => {
+            let combine_desc: CombineDescription =
+                serde_json::from_value(serde_json::Value::clone(json))?;
+            if combine_desc.textures.len() < 2 {
+                return Err(DeserializeError::LocalError(format!(
+                    "{} needs at least two 'textures'.",
+                    tex_type
+                )));
+            }
+            let operation = if tex_type == "Multiply" {
+                Operation::Multiply
+            } else {
+                Operation::Add
+            };
+            (operation, combine_desc.textures)
+        }
+        "Invert" => {
+            let invert_desc: InvertDescription =
+                serde_json::from_value(serde_json::Value::clone(json))?;
+            (Operation::Invert, vec![invert_desc.texture])
+        }
+        "Clamp" => {
+            let clamp_desc: ClampDescription =
+                serde_json::from_value(serde_json::Value::clone(json))?;
+            if !(clamp_desc.min <= clamp_desc.max) {
+                return Err(DeserializeError::LocalError(String::from(
+                    "Clamp 'min' can not be more than 'max'.",
+                )));
+            }
+            (
+                Operation::Clamp {
+                    min: clamp_desc.min,
+                    max: clamp_desc.max,
+                },
+                vec![clamp_desc.texture],
+            )
+        }
+        _ => {
+            let mix_desc: MixDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+            (
+                Operation::Mix,
+                vec![mix_desc.base, mix_desc.top, mix_desc.mask],
+            )
+        }
+    };
+    let mut inputs = Vec::with_capacity(names.len());
+    for name in &names {
+        match textures.get(name) {
+            Some(t) => inputs.push(Arc::clone(t)),
+            None => return Ok(None),
+        }
+    }
+    return Ok(Some(Arc::new(texture::Compose::new(operation, inputs))));
+}
+
+// Image
+#[derive(Deserialize)]
+struct ImageDescription {
+    image_path: String,
+    // For data like bump, normal, and roughness maps, which are not sRGB
+    // encoded
+    #[serde(default)]
+    linear: bool,
+    #[serde(default = "default_filter")]
+    filter: Filter,
+}
+
+fn default_filter() -> Filter {
+    Filter::Bilinear
+}
+
+fn deserialize_image(
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncTexture>, DeserializeError> {
+    let image_desc: ImageDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    let image = match res.load_image(&res.find(spec_dir, path::Path::new(&image_desc.image_path))) {
+        Ok(i) => i,
+        Err(e) => return Err(DeserializeError::LocalError(e)),
+    };
+    return Ok(Arc::new(texture::Image::new(
+        image,
+        image_desc.linear,
+        image_desc.filter,
+    )));
+}
+
+fn deserialize_material(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    if !json.is_object() {
+        return Err(DeserializeError::LocalError(format!(
+            "Expected JSON object for value in Materials map: {}",
+            serde_json::to_string(json)?
+        )));
+    }
+
+    let material_type = identify_type(json)?;
+    match material_type {
+        "Lambert" => deserialize_lambert(json, textures, working_space),
+        "Metal" => deserialize_metal(json, textures, working_space),
+        "Conductor" => deserialize_conductor(json, textures, working_space),
+        "GGX" => deserialize_ggx(json, textures, working_space),
+        "MetallicRoughness" => deserialize_metallic_roughness(json, textures, working_space),
+        "Dielectric" => deserialize_dielectric(json, textures, working_space),
+        "DiffuseLight" => deserialize_diffuse_light(json, textures),
+        "SpotLight" => deserialize_spot_light(json, textures),
+        "Isotropic" => deserialize_isotropic(json, textures),
+        "Toon" => deserialize_toon(json, textures, working_space),
+        "Sheen" => deserialize_sheen(json, textures, working_space),
+        "CarPaint" => deserialize_car_paint(json, textures, working_space),
+        "Measured" => deserialize_measured(json, textures, working_space, spec_dir, res),
+        "Blend" | "Clearcoat" => Err(DeserializeError::LocalError(format!(
+            "{} materials can only be made once the materials they refer to are.",
+            material_type
+        ))),
+        _ => Err(DeserializeError::LocalError(format!(
+            "Unsupported material type: {}",
+            material_type
+        ))),
+    }
+}
+
+// Visibility
+#[derive(Deserialize)]
+struct VisibilityDescription {
+    #[serde(default = "default_visible")]
+    camera: bool,
+    #[serde(default = "default_visible")]
+    diffuse: bool,
+    #[serde(default = "default_visible")]
+    glossy: bool,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn deserialize_visibility(json: &serde_json::Value) -> Result<Visibility, DeserializeError> {
+    let visibility_desc: VisibilityDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    return Ok(Visibility::new(
+        visibility_desc.camera,
+        visibility_desc.diffuse,
+        visibility_desc.glossy,
+    ));
+}
+
+// Swaps the named material in for every material that does not emit light
+// or act as a volume's phase function. Lights keep lighting the scene, and
+// volumes would not scatter properly with a surface material. The material
+// swapped in is returned, for meshes' material libraries to use as well.
+fn override_materials(
+    name: &str,
+    materials_value: &serde_json::Map<String, serde_json::Value>,
+    materials: &mut HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let replacement: Arc<SyncMaterial> = match materials.get(name) {
+        Some(m) => Arc::clone(m),
+        None if name == CLAY_MATERIAL => Arc::new(material::Lambert::new(
+            Arc::new(texture::Constant::new(RGB::new(
+                CLAY_ALBEDO,
+                CLAY_ALBEDO,
+                CLAY_ALBEDO,
+            ))),
+            None,
+        )),
+        None => {
+            return Err(DeserializeError::LocalError(format!(
+                "Missing Material {} to override materials with.",
+                name
+            )))
+        }
+    };
+    for (key, value) in materials_value.iter() {
+        match identify_type(value)? {
+            "DiffuseLight" | "SpotLight" | "Isotropic" => {}
+            _ => {
+                materials.insert(String::clone(key), Arc::clone(&replacement));
+            }
+        }
+    }
+    return Ok(replacement);
+}
+
+// Lambert
+#[derive(Deserialize)]
+struct LambertDescription {
+    albedo: String,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn deserialize_lambert(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let lambert_desc: LambertDescription = serde_json::from_value(serde_json::Value::clone(json))?;
     if !textures.contains_key(&lambert_desc.albedo) {
         return Err(DeserializeError::LocalError(format!(
             "Missing Texture {} for Lambert.",
             lambert_desc.albedo
         )));
     }
-    let bump_map = match &lambert_desc.bump_map {
-        None => None,
-        Some(b) => {
-            if !textures.contains_key(b) {
-                return Err(DeserializeError::LocalError(format!(
-                    "Missing bump map Texture {} for Lambert.",
-                    b
-                )));
-            }
-            Some(Arc::clone(&textures[b]))
-        }
-    };
+    let perturbation = deserialize_perturbation(
+        &lambert_desc.bump_map,
+        &lambert_desc.normal_map,
+        textures,
+        working_space,
+        "Lambert",
+    )?;
     return Ok(Arc::new(material::Lambert::new(
         Arc::clone(&textures[&lambert_desc.albedo]),
-        bump_map,
+        perturbation,
     )));
 }
 
+// A bump map or a normal map for a material, by the name of its texture
+fn deserialize_perturbation(
+    bump_map: &Option<String>,
+    normal_map: &Option<String>,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+    material: &str,
+) -> Result<Option<Perturbation>, DeserializeError> {
+    let texture = |name: &String, role: &str| match textures.get(name) {
+        Some(t) => Ok(Arc::clone(t)),
+        None => Err(DeserializeError::LocalError(format!(
+            "Missing {} Texture {} for {}.",
+            role, name, material
+        ))),
+    };
+    match (bump_map, normal_map) {
+        (None, None) => Ok(None),
+        (Some(b), None) => Ok(Some(Perturbation::Bump(texture(b, "bump map")?))),
+        (None, Some(n)) => Ok(Some(Perturbation::NormalMap(
+            texture(n, "normal map")?,
+            working_space,
+        ))),
+        (Some(_), Some(_)) => Err(DeserializeError::LocalError(format!(
+            "{} can have a 'bump_map' or a 'normal_map', not both.",
+            material
+        ))),
+    }
+}
+
+// A material input like roughness, given as a number or as the name of a
+// texture whose brightness is read, under 'name' or 'name_map'. A number
+// becomes a constant texture, like Blend's factor.
+fn deserialize_scalar(
+    value: Option<f32>,
+    map: &Option<String>,
+    default: Option<f32>,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    name: &str,
+    material: &str,
+) -> Result<Arc<SyncTexture>, DeserializeError> {
+    let value = if value.is_none() && map.is_none() {
+        default
+    } else {
+        value
+    };
+    match (value, map) {
+        (Some(v), None) => Ok(Arc::new(texture::Constant::new(RGB::new(v, v, v)))),
+        (None, Some(m)) => match textures.get(m) {
+            Some(t) => Ok(Arc::clone(t)),
+            None => Err(DeserializeError::LocalError(format!(
+                "Missing {} map Texture {} for {}.",
+                name, m, material
+            ))),
+        },
+        (Some(_), Some(_)) => Err(DeserializeError::LocalError(format!(
+            "{} can have a '{}' or a '{}_map', not both.",
+            material, name, name
+        ))),
+        (None, None) => Err(DeserializeError::LocalError(format!(
+            "{} needs a '{}' or a '{}_map'.",
+            material, name, name
+        ))),
+    }
+}
+
 // Metal
 #[derive(Deserialize)]
 struct MetalDescription {
     albedo: String,
-    roughness: f32,
+    roughness: Option<f32>,
+    roughness_map: Option<String>,
     bump_map: Option<String>,
+    normal_map: Option<String>,
+    thin_film: Option<ThinFilm>,
 }
 
 fn deserialize_metal(
     json: &serde_json::Value,
     textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
 ) -> Result<Arc<SyncMaterial>, DeserializeError> {
     let metal_desc: MetalDescription = serde_json::from_value(serde_json::Value::clone(json))?;
     if !textures.contains_key(&metal_desc.albedo) {
@@ -342,22 +1801,543 @@ fn deserialize_metal(
             metal_desc.albedo
         )));
     }
-    let bump_map = match &metal_desc.bump_map {
-        None => None,
-        Some(b) => {
-            if !textures.contains_key(b) {
-                return Err(DeserializeError::LocalError(format!(
-                    "Missing bump map Texture {} for Metal.",
-                    b
+    let perturbation = deserialize_perturbation(
+        &metal_desc.bump_map,
+        &metal_desc.normal_map,
+        textures,
+        working_space,
+        "Metal",
+    )?;
+    check_thin_film(&metal_desc.thin_film, "Metal")?;
+    let roughness = deserialize_scalar(
+        metal_desc.roughness,
+        &metal_desc.roughness_map,
+        None,
+        textures,
+        "roughness",
+        "Metal",
+    )?;
+    return Ok(Arc::new(
+        material::Metal::new(
+            Arc::clone(&textures[&metal_desc.albedo]),
+            roughness,
+            perturbation,
+        )
+        .with_thin_film(metal_desc.thin_film, working_space),
+    ));
+}
+
+// A thin film needs a thickness, in nanometers, and a refractive index
+fn check_thin_film(thin_film: &Option<ThinFilm>, material: &str) -> Result<(), DeserializeError> {
+    match thin_film {
+        Some(f) if f.thickness < 0.0_f32 || f.refractive_index <= 0.0_f32 => {
+            Err(DeserializeError::LocalError(format!(
+                "{} 'thin_film' needs a non-negative 'thickness' and a positive 'refractive_index'.",
+                material
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Conductor
+// Either a preset metal, or its own eta and k.
+#[derive(Deserialize)]
+struct ConductorDescription {
+    preset: Option<material::ConductorPreset>,
+    eta: Option<RGB>,
+    k: Option<RGB>,
+    roughness: Option<f32>,
+    roughness_map: Option<String>,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn deserialize_conductor(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let conductor_desc: ConductorDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    let (eta, k) = match (conductor_desc.preset, conductor_desc.eta, conductor_desc.k) {
+        (Some(p), None, None) => p.eta_k(),
+        (None, Some(eta), Some(k)) => (eta, k),
+        _ => {
+            return Err(DeserializeError::LocalError(String::from(
+                "Conductor needs either a 'preset', or both 'eta' and 'k'.",
+            )))
+        }
+    };
+    let perturbation = deserialize_perturbation(
+        &conductor_desc.bump_map,
+        &conductor_desc.normal_map,
+        textures,
+        working_space,
+        "Conductor",
+    )?;
+    let roughness = deserialize_scalar(
+        conductor_desc.roughness,
+        &conductor_desc.roughness_map,
+        Some(0.0_f32),
+        textures,
+        "roughness",
+        "Conductor",
+    )?;
+    return Ok(Arc::new(material::Conductor::new(
+        eta,
+        k,
+        roughness,
+        perturbation,
+        working_space,
+    )));
+}
+
+// GGX
+#[derive(Deserialize)]
+struct GGXDescription {
+    albedo: String,
+    // Along the u texture direction, and along v too unless roughness_v is
+    // given
+    roughness: Option<f32>,
+    roughness_map: Option<String>,
+    roughness_v: Option<f32>,
+    roughness_v_map: Option<String>,
+    #[serde(default = "default_refractive_index")]
+    refractive_index: f32,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn default_refractive_index() -> f32 {
+    1.5_f32
+}
+
+fn deserialize_ggx(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let ggx_desc: GGXDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !textures.contains_key(&ggx_desc.albedo) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Texture {} for GGX.",
+            ggx_desc.albedo
+        )));
+    }
+    let perturbation = deserialize_perturbation(
+        &ggx_desc.bump_map,
+        &ggx_desc.normal_map,
+        textures,
+        working_space,
+        "GGX",
+    )?;
+    let roughness_u = deserialize_scalar(
+        ggx_desc.roughness,
+        &ggx_desc.roughness_map,
+        None,
+        textures,
+        "roughness",
+        "GGX",
+    )?;
+    let roughness_v = if ggx_desc.roughness_v.is_none() && ggx_desc.roughness_v_map.is_none() {
+        Arc::clone(&roughness_u)
+    } else {
+        deserialize_scalar(
+            ggx_desc.roughness_v,
+            &ggx_desc.roughness_v_map,
+            None,
+            textures,
+            "roughness_v",
+            "GGX",
+        )?
+    };
+    return Ok(Arc::new(material::GGX::new(
+        Arc::clone(&textures[&ggx_desc.albedo]),
+        roughness_u,
+        roughness_v,
+        ggx_desc.refractive_index,
+        perturbation,
+    )));
+}
+
+// Metallic roughness
+// Metalness is 0 and roughness 0.5 by default
+#[derive(Deserialize)]
+struct MetallicRoughnessDescription {
+    base_color: String,
+    metalness: Option<f32>,
+    metalness_map: Option<String>,
+    roughness: Option<f32>,
+    roughness_map: Option<String>,
+    #[serde(default = "default_refractive_index")]
+    refractive_index: f32,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn deserialize_metallic_roughness(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let mr_desc: MetallicRoughnessDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    if !textures.contains_key(&mr_desc.base_color) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Texture {} for MetallicRoughness.",
+            mr_desc.base_color
+        )));
+    }
+    let metalness = deserialize_scalar(
+        mr_desc.metalness,
+        &mr_desc.metalness_map,
+        Some(0.0_f32),
+        textures,
+        "metalness",
+        "MetallicRoughness",
+    )?;
+    let roughness = deserialize_scalar(
+        mr_desc.roughness,
+        &mr_desc.roughness_map,
+        Some(0.5_f32),
+        textures,
+        "roughness",
+        "MetallicRoughness",
+    )?;
+    let perturbation = deserialize_perturbation(
+        &mr_desc.bump_map,
+        &mr_desc.normal_map,
+        textures,
+        working_space,
+        "MetallicRoughness",
+    )?;
+    return Ok(Arc::new(material::MetallicRoughness::new(
+        Arc::clone(&textures[&mr_desc.base_color]),
+        metalness,
+        roughness,
+        mr_desc.refractive_index,
+        perturbation,
+    )));
+}
+
+// Dielectric
+// Either a fixed refractive index, or the coefficients of a dispersion
+// formula for the index at each wavelength, and optionally a thin film.
+#[derive(Deserialize)]
+struct DielectricDescription {
+    refractive_index: Option<f32>,
+    cauchy: Option<Vec<f32>>,
+    sellmeier: Option<SellmeierDescription>,
+    thin_film: Option<ThinFilm>,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SellmeierDescription {
+    b: Vec<f32>,
+    c: Vec<f32>,
+}
+
+fn deserialize_dielectric(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let dielectric_desc: DielectricDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    check_thin_film(&dielectric_desc.thin_film, "Dielectric")?;
+    let perturbation = deserialize_perturbation(
+        &dielectric_desc.bump_map,
+        &dielectric_desc.normal_map,
+        textures,
+        working_space,
+        "Dielectric",
+    )?;
+    let dielectric = match (
+        dielectric_desc.refractive_index,
+        dielectric_desc.cauchy,
+        dielectric_desc.sellmeier,
+    ) {
+        (Some(n), None, None) => material::Dielectric::new(n),
+        (None, Some(cauchy), None) => {
+            if cauchy.is_empty() {
+                return Err(DeserializeError::LocalError(String::from(
+                    "Dielectric 'cauchy' needs at least one coefficient.",
+                )));
+            }
+            material::Dielectric::dispersive(material::Dispersion::Cauchy(cauchy), working_space)
+        }
+        (None, None, Some(sellmeier)) => {
+            if sellmeier.b.is_empty() || sellmeier.b.len() != sellmeier.c.len() {
+                return Err(DeserializeError::LocalError(String::from(
+                    "Dielectric 'sellmeier' needs as many 'b' as 'c' coefficients, and at least one.",
                 )));
             }
-            Some(Arc::clone(&textures[b]))
+            material::Dielectric::dispersive(
+                material::Dispersion::Sellmeier(sellmeier.b, sellmeier.c),
+                working_space,
+            )
+        }
+        _ => {
+            return Err(DeserializeError::LocalError(String::from(
+                "Dielectric needs exactly one of 'refractive_index', 'cauchy', or 'sellmeier'.",
+            )))
         }
     };
-    return Ok(Arc::new(material::Metal::new(
-        Arc::clone(&textures[&metal_desc.albedo]),
-        metal_desc.roughness,
-        bump_map,
+    return Ok(Arc::new(
+        dielectric
+            .with_thin_film(dielectric_desc.thin_film, working_space)
+            .with_perturbation(perturbation),
+    ));
+}
+
+// Car Paint
+// Metallic flakes under a clearcoat, see MetallicFlakes
+#[derive(Deserialize)]
+struct CarPaintDescription {
+    color: String,
+    flop_color: Option<String>,
+    flake_color: Option<String>,
+    #[serde(default = "default_paint_roughness")]
+    roughness: f32,
+    #[serde(default = "default_flake_roughness")]
+    flake_roughness: f32,
+    #[serde(default = "default_flake_size")]
+    flake_size: f32,
+    #[serde(default = "default_flake_density")]
+    flake_density: f32,
+    #[serde(default = "default_flake_tilt")]
+    flake_tilt: f32,
+    #[serde(default)]
+    coat_roughness: f32,
+    #[serde(default = "default_coat_refractive_index")]
+    coat_refractive_index: f32,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn default_paint_roughness() -> f32 {
+    0.35_f32
+}
+
+fn default_flake_roughness() -> f32 {
+    0.05_f32
+}
+
+fn default_flake_size() -> f32 {
+    0.01_f32
+}
+
+fn default_flake_density() -> f32 {
+    0.3_f32
+}
+
+fn default_flake_tilt() -> f32 {
+    0.3_f32
+}
+
+fn deserialize_car_paint(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let paint_desc: CarPaintDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !(paint_desc.flake_size > 0.0_f32) {
+        return Err(DeserializeError::LocalError(String::from(
+            "CarPaint 'flake_size' must be greater than 0.",
+        )));
+    }
+    let texture = |name: &String, role: &str| match textures.get(name) {
+        Some(t) => Ok(Arc::clone(t)),
+        None => Err(DeserializeError::LocalError(format!(
+            "Missing {} Texture {} for CarPaint.",
+            role, name
+        ))),
+    };
+    let color = texture(&paint_desc.color, "color")?;
+    let flop_color = match &paint_desc.flop_color {
+        Some(f) => Some(texture(f, "flop color")?),
+        None => None,
+    };
+    // Flakes are the paint's color unless they have their own
+    let flake_color = match &paint_desc.flake_color {
+        Some(f) => texture(f, "flake color")?,
+        None => Arc::clone(&color),
+    };
+    // The paint follows the same bumps as its coat
+    let perturbation = deserialize_perturbation(
+        &paint_desc.bump_map,
+        &paint_desc.normal_map,
+        textures,
+        working_space,
+        "CarPaint",
+    )?;
+    let base = Arc::new(material::MetallicFlakes::new(
+        color,
+        flop_color,
+        flake_color,
+        paint_desc.roughness,
+        paint_desc.flake_roughness,
+        paint_desc.flake_size,
+        paint_desc.flake_density,
+        paint_desc.flake_tilt,
+        perturbation.clone(),
+    ));
+    return Ok(Arc::new(material::Clearcoat::new(
+        base,
+        paint_desc.coat_roughness,
+        paint_desc.coat_refractive_index,
+        perturbation,
+    )));
+}
+
+// Measured
+// A MERL file, relative to the scene specification
+#[derive(Deserialize)]
+struct MeasuredDescription {
+    file_path: String,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn deserialize_measured(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+    spec_dir: &path::Path,
+    res: &mut Resources,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let measured_desc: MeasuredDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    let brdf = match res
+        .load_measured_brdf(&res.find(spec_dir, path::Path::new(&measured_desc.file_path)))
+    {
+        Ok(b) => b,
+        Err(e) => return Err(DeserializeError::LocalError(e)),
+    };
+    let perturbation = deserialize_perturbation(
+        &measured_desc.bump_map,
+        &measured_desc.normal_map,
+        textures,
+        working_space,
+        "Measured",
+    )?;
+    return Ok(Arc::new(material::Measured::new(
+        brdf,
+        perturbation,
+        working_space,
+    )));
+}
+
+// Sheen
+#[derive(Deserialize)]
+struct SheenDescription {
+    albedo: Option<String>,
+    sheen: String,
+    #[serde(default = "default_sheen_roughness")]
+    roughness: f32,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+fn default_sheen_roughness() -> f32 {
+    0.5_f32
+}
+
+fn deserialize_sheen(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let sheen_desc: SheenDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    let texture = |name: &String, role: &str| match textures.get(name) {
+        Some(t) => Ok(Arc::clone(t)),
+        None => Err(DeserializeError::LocalError(format!(
+            "Missing {}Texture {} for Sheen.",
+            role, name
+        ))),
+    };
+    let albedo = match &sheen_desc.albedo {
+        Some(a) => Some(texture(a, "")?),
+        None => None,
+    };
+    let perturbation = deserialize_perturbation(
+        &sheen_desc.bump_map,
+        &sheen_desc.normal_map,
+        textures,
+        working_space,
+        "Sheen",
+    )?;
+    return Ok(Arc::new(material::Sheen::new(
+        albedo,
+        texture(&sheen_desc.sheen, "")?,
+        sheen_desc.roughness,
+        perturbation,
+    )));
+}
+
+// Toon
+#[derive(Deserialize)]
+struct ToonDescription {
+    albedo: String,
+    #[serde(default = "default_toon_bands")]
+    bands: u32,
+    rim: Option<RimDescription>,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RimDescription {
+    color: RGB,
+    #[serde(default = "default_rim_width")]
+    width: f32,
+}
+
+fn default_toon_bands() -> u32 {
+    3
+}
+
+fn default_rim_width() -> f32 {
+    0.2_f32
+}
+
+fn deserialize_toon(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    working_space: ColorSpace,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let toon_desc: ToonDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !textures.contains_key(&toon_desc.albedo) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Texture {} for Toon.",
+            toon_desc.albedo
+        )));
+    }
+    if toon_desc.bands == 0 {
+        return Err(DeserializeError::LocalError(String::from(
+            "Toon needs at least one band.",
+        )));
+    }
+    let (rim_color, rim_width) = match toon_desc.rim {
+        Some(rim) => (working_space.from_rec709(rim.color), rim.width),
+        None => (RGB::black(), 0.0_f32),
+    };
+    let perturbation = deserialize_perturbation(
+        &toon_desc.bump_map,
+        &toon_desc.normal_map,
+        textures,
+        working_space,
+        "Toon",
+    )?;
+    return Ok(Arc::new(material::Toon::new(
+        Arc::clone(&textures[&toon_desc.albedo]),
+        toon_desc.bands,
+        rim_color,
+        rim_width,
+        perturbation,
     )));
 }
 
@@ -365,6 +2345,20 @@ fn deserialize_metal(
 #[derive(Deserialize)]
 struct DiffuseLightDescription {
     emission: String,
+    #[serde(default = "default_emission_intensity")]
+    intensity: f32,
+    #[serde(default = "default_two_sided")]
+    two_sided: bool,
+    #[serde(default)]
+    falloff: f32,
+}
+
+fn default_emission_intensity() -> f32 {
+    1.0_f32
+}
+
+fn default_two_sided() -> bool {
+    true
 }
 
 fn deserialize_diffuse_light(
@@ -379,37 +2373,181 @@ fn deserialize_diffuse_light(
             diffuse_desc.emission
         )));
     }
-    return Ok(Arc::new(material::DiffuseLight::new(Arc::clone(
-        &textures[&diffuse_desc.emission],
-    ))));
+    if diffuse_desc.intensity < 0.0_f32 || diffuse_desc.falloff < 0.0_f32 {
+        return Err(DeserializeError::LocalError(String::from(
+            "DiffuseLight can not have a negative 'intensity' or 'falloff'.",
+        )));
+    }
+    return Ok(Arc::new(material::DiffuseLight::new(
+        Arc::clone(&textures[&diffuse_desc.emission]),
+        diffuse_desc.intensity,
+        diffuse_desc.two_sided,
+        diffuse_desc.falloff,
+    )));
+}
+
+// Spot Light
+#[derive(Deserialize)]
+struct SpotLightDescription {
+    emission: String,
+    direction: Vector3,
+    cone_angle: f32,
+    falloff_angle: Option<f32>,
+    gobo: Option<String>,
+}
+
+fn deserialize_spot_light(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let spot_desc: SpotLightDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !textures.contains_key(&spot_desc.emission) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Texture {} for SpotLight.",
+            spot_desc.emission
+        )));
+    }
+    let gobo = match &spot_desc.gobo {
+        None => None,
+        Some(g) => {
+            if !textures.contains_key(g) {
+                return Err(DeserializeError::LocalError(format!(
+                    "Missing gobo Texture {} for SpotLight.",
+                    g
+                )));
+            }
+            Some(Arc::clone(&textures[g]))
+        }
+    };
+    return Ok(Arc::new(material::SpotLight::new(
+        Arc::clone(&textures[&spot_desc.emission]),
+        &spot_desc.direction,
+        spot_desc.cone_angle,
+        spot_desc.falloff_angle.unwrap_or(spot_desc.cone_angle),
+        gobo,
+    )));
+}
+
+// Isotropic Phase Function
+#[derive(Deserialize)]
+struct IsotropicDescription {
+    albedo: String,
+}
+
+fn deserialize_isotropic(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+) -> Result<Arc<SyncMaterial>, DeserializeError> {
+    let iso_desc: IsotropicDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !textures.contains_key(&iso_desc.albedo) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Texture {} for Isotropic.",
+            iso_desc.albedo
+        )));
+    }
+    return Ok(Arc::new(volume::Isotropic::new(Arc::clone(
+        &textures[&iso_desc.albedo],
+    ))));
+}
+
+// Blend
+// The top material is mixed in by a constant factor or by a mask texture
+#[derive(Deserialize)]
+struct BlendDescription {
+    base: String,
+    top: String,
+    factor: Option<f32>,
+    mask: Option<String>,
+}
+
+// Returns None while either blended material is still to be made
+fn deserialize_blend(
+    json: &serde_json::Value,
+    textures: &HashMap<String, Arc<SyncTexture>>,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Option<Arc<SyncMaterial>>, DeserializeError> {
+    let blend_desc: BlendDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    let mask: Arc<SyncTexture> = match (blend_desc.factor, &blend_desc.mask) {
+        (Some(f), None) => Arc::new(texture::Constant::new(RGB::new(f, f, f))),
+        (None, Some(m)) => match textures.get(m) {
+            Some(t) => Arc::clone(t),
+            None => {
+                return Err(DeserializeError::LocalError(format!(
+                    "Missing mask Texture {} for Blend.",
+                    m
+                )))
+            }
+        },
+        _ => {
+            return Err(DeserializeError::LocalError(String::from(
+                "Blend needs exactly one of 'factor' or 'mask'.",
+            )))
+        }
+    };
+    match (
+        materials.get(&blend_desc.base),
+        materials.get(&blend_desc.top),
+    ) {
+        (Some(base), Some(top)) => {
+            return Ok(Some(Arc::new(material::Blend::new(
+                Arc::clone(base),
+                Arc::clone(top),
+                mask,
+            ))))
+        }
+        _ => return Ok(None),
+    }
 }
 
-// Isotropic Phase Function
+// Clearcoat
 #[derive(Deserialize)]
-struct IsotropicDescription {
-    albedo: String,
+struct ClearcoatDescription {
+    base: String,
+    #[serde(default)]
+    roughness: f32,
+    #[serde(default = "default_coat_refractive_index")]
+    refractive_index: f32,
+    bump_map: Option<String>,
+    normal_map: Option<String>,
 }
 
-fn deserialize_isotropic(
+fn default_coat_refractive_index() -> f32 {
+    1.5_f32
+}
+
+// Returns None while the base material is still to be made
+fn deserialize_clearcoat(
     json: &serde_json::Value,
     textures: &HashMap<String, Arc<SyncTexture>>,
-) -> Result<Arc<SyncMaterial>, DeserializeError> {
-    let iso_desc: IsotropicDescription = serde_json::from_value(serde_json::Value::clone(json))?;
-    if !textures.contains_key(&iso_desc.albedo) {
-        return Err(DeserializeError::LocalError(format!(
-            "Missing Texture {} for Isotropic.",
-            iso_desc.albedo
-        )));
+    working_space: ColorSpace,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Option<Arc<SyncMaterial>>, DeserializeError> {
+    let coat_desc: ClearcoatDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    match materials.get(&coat_desc.base) {
+        Some(base) => {
+            return Ok(Some(Arc::new(material::Clearcoat::new(
+                Arc::clone(base),
+                coat_desc.roughness,
+                coat_desc.refractive_index,
+                deserialize_perturbation(
+                    &coat_desc.bump_map,
+                    &coat_desc.normal_map,
+                    textures,
+                    working_space,
+                    "Clearcoat",
+                )?,
+            ))))
+        }
+        None => return Ok(None),
     }
-    return Ok(Arc::new(volume::Isotropic::new(Arc::clone(
-        &textures[&iso_desc.albedo],
-    ))));
 }
 
 fn deserialize_shape(
     json: &serde_json::Value,
     spec_dir: &path::Path,
     materials: &HashMap<String, Arc<SyncMaterial>>,
+    prototypes: &HashMap<String, Arc<Prototype>>,
+    libraries: &mut MaterialLibraries,
     shapes: &mut Vec<Arc<SyncShape>>,
 ) -> Result<(), DeserializeError> {
     if !json.is_object() {
@@ -425,8 +2563,36 @@ fn deserialize_shape(
             shapes.push(deserialize_sphere(json, materials)?);
             Ok(())
         }
-        "Mesh" => deserialize_mesh(json, spec_dir, materials, shapes),
-        "ConstantMedium" => deserialize_constant_medium(json, spec_dir, materials, shapes),
+        "Box" => {
+            shapes.push(deserialize_box(json, materials)?);
+            Ok(())
+        }
+        "Plane" => {
+            shapes.push(deserialize_plane(json, materials)?);
+            Ok(())
+        }
+        "Disk" => {
+            shapes.push(deserialize_disk(json, materials)?);
+            Ok(())
+        }
+        "Cylinder" | "Cone" => {
+            shapes.push(deserialize_frustum(json, shape_type, materials)?);
+            Ok(())
+        }
+        "CSG" => {
+            shapes.push(deserialize_csg(
+                json, spec_dir, materials, prototypes, libraries,
+            )?);
+            Ok(())
+        }
+        "Instance" => {
+            shapes.push(deserialize_instance(json, materials, prototypes)?);
+            Ok(())
+        }
+        "Mesh" => deserialize_mesh(json, spec_dir, materials, libraries, shapes),
+        "ConstantMedium" => {
+            deserialize_constant_medium(json, spec_dir, materials, prototypes, libraries, shapes)
+        }
         _ => {
             return Err(DeserializeError::LocalError(format!(
                 "Unknown Shape 'type' {} given.",
@@ -469,12 +2635,340 @@ fn deserialize_sphere(
     ));
 }
 
+// Box
+// Given either by its min and max corners, or by a size centered on the
+// origin, before the transform is applied.
+#[derive(Deserialize)]
+struct BoxDescription {
+    min: Option<Point3>,
+    max: Option<Point3>,
+    size: Option<Vector3>,
+    material: String,
+
+    #[serde(default = "Transform::new")]
+    transform: Transform,
+}
+
+fn deserialize_box(
+    json: &serde_json::Value,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Arc<shape::Cuboid>, DeserializeError> {
+    let box_desc: BoxDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !materials.contains_key(&box_desc.material) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Material {} for Box.",
+            box_desc.material
+        )));
+    }
+    let (min, max) = match (box_desc.min, box_desc.max, box_desc.size) {
+        (Some(min), Some(max), None) => (min, max),
+        (None, None, Some(size)) => (
+            Point3::origin() - size * 0.5_f32,
+            Point3::origin() + size * 0.5_f32,
+        ),
+        _ => {
+            return Err(DeserializeError::LocalError(String::from(
+                "Box needs either a min and max, or a size.",
+            )))
+        }
+    };
+    return Ok(Arc::new(
+        match shape::Cuboid::new(
+            &box_desc.transform.create_matrix(),
+            min,
+            max,
+            Arc::clone(&materials[&box_desc.material]),
+        ) {
+            Ok(b) => b,
+            Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+        },
+    ));
+}
+
+// Plane
+#[derive(Deserialize)]
+struct PlaneDescription {
+    point: Point3,
+    normal: Vector3,
+    // Width and height of a finite rectangle, infinite when not given
+    extent: Option<[f32; 2]>,
+    // Distance between texture repeats on an infinite plane
+    #[serde(default = "default_uv_scale")]
+    uv_scale: f32,
+    material: String,
+}
+
+fn default_uv_scale() -> f32 {
+    1.0_f32
+}
+
+fn deserialize_plane(
+    json: &serde_json::Value,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Arc<shape::Plane>, DeserializeError> {
+    let plane_desc: PlaneDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !materials.contains_key(&plane_desc.material) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Material {} for Plane.",
+            plane_desc.material
+        )));
+    }
+    return Ok(Arc::new(
+        match shape::Plane::new(
+            plane_desc.point,
+            plane_desc.normal,
+            match plane_desc.extent {
+                Some(e) => Some((e[0], e[1])),
+                None => None,
+            },
+            plane_desc.uv_scale,
+            Arc::clone(&materials[&plane_desc.material]),
+        ) {
+            Ok(p) => p,
+            Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+        },
+    ));
+}
+
+// Disk
+#[derive(Deserialize)]
+struct DiskDescription {
+    center: Point3,
+    normal: Vector3,
+    radius: f32,
+    material: String,
+}
+
+fn deserialize_disk(
+    json: &serde_json::Value,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Arc<shape::Disk>, DeserializeError> {
+    let disk_desc: DiskDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !materials.contains_key(&disk_desc.material) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Material {} for Disk.",
+            disk_desc.material
+        )));
+    }
+    return Ok(Arc::new(
+        match shape::Disk::new(
+            disk_desc.center,
+            disk_desc.normal,
+            disk_desc.radius,
+            Arc::clone(&materials[&disk_desc.material]),
+        ) {
+            Ok(d) => d,
+            Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+        },
+    ));
+}
+
+// Cylinder and Cone
+// Both stand on the origin and rise up the Y axis before the transform is
+// applied. The ends are capped unless caps is false.
+#[derive(Deserialize)]
+struct FrustumDescription {
+    radius: f32,
+    height: f32,
+    #[serde(default = "default_caps")]
+    caps: bool,
+    material: String,
+
+    #[serde(default = "Transform::new")]
+    transform: Transform,
+}
+
+fn default_caps() -> bool {
+    true
+}
+
+fn deserialize_frustum(
+    json: &serde_json::Value,
+    shape_type: &str,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+) -> Result<Arc<shape::Frustum>, DeserializeError> {
+    let frustum_desc: FrustumDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !materials.contains_key(&frustum_desc.material) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Material {} for {}.",
+            frustum_desc.material, shape_type
+        )));
+    }
+    let new_frustum = if shape_type == "Cone" {
+        shape::Frustum::cone
+    } else {
+        shape::Frustum::cylinder
+    };
+    return Ok(Arc::new(
+        match new_frustum(
+            &frustum_desc.transform.create_matrix(),
+            frustum_desc.radius,
+            frustum_desc.height,
+            frustum_desc.caps,
+            Arc::clone(&materials[&frustum_desc.material]),
+        ) {
+            Ok(f) => f,
+            Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+        },
+    ));
+}
+
+// CSG
+// Children are full shape descriptions, nested as deep as needed. Their own
+// materials are not used, the combined solid is shaded with its material.
+#[derive(Deserialize)]
+struct CSGDescription {
+    operation: csg::CSGOperation,
+    left: serde_json::Value,
+    right: serde_json::Value,
+    material: String,
+}
+
+fn deserialize_csg(
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+    prototypes: &HashMap<String, Arc<Prototype>>,
+    libraries: &mut MaterialLibraries,
+) -> Result<Arc<csg::CSG>, DeserializeError> {
+    let csg_desc: CSGDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    if !materials.contains_key(&csg_desc.material) {
+        return Err(DeserializeError::LocalError(format!(
+            "Missing Material {} for CSG.",
+            csg_desc.material
+        )));
+    }
+    let mut children = Vec::new();
+    for child in &[&csg_desc.left, &csg_desc.right] {
+        let mut shapes_temp = Vec::new();
+        deserialize_shape(
+            child,
+            spec_dir,
+            materials,
+            prototypes,
+            libraries,
+            &mut shapes_temp,
+        )?;
+        // A mesh is many shapes and has no inside to track
+        if shapes_temp.len() != 1 {
+            return Err(DeserializeError::LocalError(String::from(
+                "Only single shapes are allowed as CSG children.",
+            )));
+        }
+        children.push(shapes_temp.remove(0_usize));
+    }
+    let right = children.remove(1_usize);
+    let left = children.remove(0_usize);
+
+    return Ok(Arc::new(csg::CSG::new(
+        csg_desc.operation,
+        left,
+        right,
+        Arc::clone(&materials[&csg_desc.material]),
+    )));
+}
+
+// Instance
+// A Prototype placed with its own transform. The material may be left out
+// when every shape of the prototype has the same one.
+#[derive(Deserialize)]
+struct InstanceDescription {
+    prototype: String,
+    material: Option<String>,
+    // Sets the instance apart from the others of its prototype, see
+    // instance::Variation
+    variation: Option<VariationDescription>,
+
+    #[serde(default = "Transform::new")]
+    transform: Transform,
+}
+
+#[derive(Deserialize)]
+struct VariationDescription {
+    // The instance's index among its prototype's instances when not given
+    seed: Option<u64>,
+    // Degrees either way
+    #[serde(default)]
+    hue_shift: f32,
+    #[serde(default)]
+    attributes: HashMap<String, f32>,
+}
+
+fn deserialize_instance(
+    json: &serde_json::Value,
+    materials: &HashMap<String, Arc<SyncMaterial>>,
+    prototypes: &HashMap<String, Arc<Prototype>>,
+) -> Result<Arc<instance::Instance>, DeserializeError> {
+    let instance_desc: InstanceDescription =
+        serde_json::from_value(serde_json::Value::clone(json))?;
+    let prototype = match prototypes.get(&instance_desc.prototype) {
+        Some(p) => p,
+        None => {
+            return Err(DeserializeError::LocalError(format!(
+                "Missing Prototype {} for Instance.",
+                instance_desc.prototype
+            )))
+        }
+    };
+    let material = match &instance_desc.material {
+        Some(m) => match materials.get(m) {
+            Some(material) => material,
+            None => {
+                return Err(DeserializeError::LocalError(format!(
+                    "Missing Material {} for Instance.",
+                    m
+                )))
+            }
+        },
+        None => match prototype.shared_material() {
+            Some(material) => material,
+            None => {
+                return Err(DeserializeError::LocalError(format!(
+                    "Instance of Prototype {} needs a material, its shapes have more than one.",
+                    instance_desc.prototype
+                )))
+            }
+        },
+    };
+    // Every instance counts toward the index, varied or not, so adding a
+    // variation to one leaves the others' as they were
+    let index = prototype.next_instance_index();
+    let material: Arc<SyncMaterial> = match &instance_desc.variation {
+        Some(v) => Arc::new(instance::Varied::new(
+            Arc::clone(material),
+            instance::Variation::new(index, v.seed, v.hue_shift, &v.attributes),
+        )),
+        None => Arc::clone(material),
+    };
+    return Ok(Arc::new(
+        match instance::Instance::new(
+            Arc::clone(prototype),
+            &instance_desc.transform.create_matrix(),
+            material,
+        ) {
+            Ok(i) => i,
+            Err(e) => return Err(DeserializeError::LocalError(String::from(e))),
+        },
+    ));
+}
+
 // Mesh
+// Materials come from the OBJ's material library (.mtl) unless the mesh gives
+// its own. 'material' is used for the whole mesh, and 'materials' picks scene
+// materials for individual material groups (usemtl) of the OBJ by name,
+// taking precedence over both.
 #[derive(Deserialize)]
 struct MeshDescription {
     file_path: String,
     enable_backface_culling: bool,
-    material: String,
+    material: Option<String>,
+    #[serde(default)]
+    materials: HashMap<String, String>,
+    // Texture coordinates for faces the file gives none
+    uv_projection: Option<UVProjection>,
+    // A directory to keep the mesh's processed objects in, for other scenes
+    // and frames to reuse, see meshcache
+    cache: Option<String>,
 
     #[serde(default = "Transform::new")]
     transform: Transform,
@@ -484,84 +2978,279 @@ fn deserialize_mesh(
     json: &serde_json::Value,
     spec_dir: &path::Path,
     materials: &HashMap<String, Arc<SyncMaterial>>,
+    libraries: &mut MaterialLibraries,
     shapes: &mut Vec<Arc<SyncShape>>,
 ) -> Result<(), DeserializeError> {
     let mesh_desc: MeshDescription = serde_json::from_value(serde_json::Value::clone(json))?;
-    if !materials.contains_key(&mesh_desc.material) {
-        return Err(DeserializeError::LocalError(format!(
+    let scene_material = |name: &String| match materials.get(name) {
+        Some(m) => Ok(Arc::clone(m)),
+        None => Err(DeserializeError::LocalError(format!(
             "Missing Material {} for Mesh.",
-            mesh_desc.material
-        )));
+            name
+        ))),
+    };
+    // Check every material named up front, even ones no group uses
+    match &mesh_desc.material {
+        Some(m) => {
+            scene_material(m)?;
+        }
+        None => {}
+    }
+    for m in mesh_desc.materials.values() {
+        scene_material(m)?;
     }
 
     let local_to_world = mesh_desc.transform.create_matrix();
 
-    // TODO: Proper support for OBJ material (.mtl) files.
-    let obj_string = fs::read_to_string(spec_dir.join(&mesh_desc.file_path))?;
-    let obj_set = obj::parse(obj_string)?;
-    // Pull apart the object set read from the OBJ file.
-    for object in obj_set.objects {
-        // Need to convert the library's vertex struct to ours.
-        let mut converted_vertices = Vec::with_capacity(object.vertices.len());
-        for vert in object.vertices {
-            converted_vertices.push(&local_to_world * Point3::from(vert));
-        }
-        // Also need to convert the texture coordinates.
-        let mut converted_tex_coords = Vec::with_capacity(object.tex_vertices.len());
-        for tex in object.tex_vertices {
-            converted_tex_coords.push(TexCoord::new(tex.u as f32, tex.v as f32));
-        }
-        // Create shared mesh, which all Triangles will reference.
-        let t_mesh = Arc::new(shape::TriangleMesh::new(
-            converted_vertices,
-            converted_tex_coords,
+    let obj_path = libraries.find(spec_dir, path::Path::new(&mesh_desc.file_path));
+    // Material libraries are relative to the OBJ file
+    let obj_dir = match obj_path.parent() {
+        Some(d) => d.to_path_buf(),
+        None => path::PathBuf::new(),
+    };
+    if !obj_path.exists() && libraries.placeholders() {
+        eprintln!(
+            "Warning: using a placeholder cube for missing mesh {}",
+            obj_path.display()
+        );
+        let mut cube = ObjObject::placeholder_cube();
+        for vert in cube.vertices.iter_mut() {
+            *vert = &local_to_world * *vert;
+        }
+        return add_mesh_triangles(
+            cube,
+            vec![libraries.placeholder_material()],
             mesh_desc.enable_backface_culling,
-            Arc::clone(&materials[&mesh_desc.material]),
-        ));
-
-        // Geometry -> Shape -> Primitive -> Triangle -> Vertices
-        for geom in object.geometry {
-            for obj_shape in geom.shapes {
-                match obj_shape.primitive {
-                    obj::Primitive::Triangle(v0, v1, v2) => {
-                        let (v_index0, t_index0, _) = v0;
-                        let (v_index1, t_index1, _) = v1;
-                        let (v_index2, t_index2, _) = v2;
-
-                        shapes.push(Arc::new(
-                            match shape::Triangle::new(
-                                Arc::clone(&t_mesh),
-                                v_index0,
-                                v_index1,
-                                v_index2,
-                                t_index0,
-                                t_index1,
-                                t_index2,
-                            ) {
-                                Ok(t) => t,
-                                Err(e) => {
-                                    return Err(DeserializeError::LocalError(format!(
-                                        "Error creating Triangle for file {}, object {}: {}",
-                                        mesh_desc.file_path, object.name, e
-                                    )))
-                                }
-                            },
-                        ));
+            &mesh_desc.file_path,
+            shapes,
+        );
+    }
+    libraries.depend_on(&obj_path);
+    // The material library is only read if some group needs it
+    let mut library = None;
+    // Makes the triangles of an object whose vertices are already in world
+    // space
+    let mut build = |object: ObjObject,
+                     material_library: Option<&String>,
+                     shapes: &mut Vec<Arc<SyncShape>>|
+     -> Result<(), DeserializeError> {
+        let mut object_materials = Vec::with_capacity(object.material_names.len());
+        for material_name in &object.material_names {
+            let group_material = match material_name {
+                Some(n) => mesh_desc.materials.get(n),
+                None => None,
+            };
+            let material = match (group_material, &mesh_desc.material) {
+                (Some(m), _) | (None, Some(m)) => scene_material(m)?,
+                (None, None) => {
+                    let name = match material_name {
+                        Some(n) => n,
+                        None => {
+                            return Err(DeserializeError::LocalError(format!(
+                                "Mesh needs a material, file {}, object {} has faces without one.",
+                                mesh_desc.file_path, object.name
+                            )))
+                        }
+                    };
+                    if library.is_none() {
+                        let library_path = match material_library {
+                            Some(l) => libraries.find(&obj_dir, path::Path::new(l)),
+                            None => {
+                                return Err(DeserializeError::LocalError(format!(
+                                    "Mesh needs a material, file {} names no material library.",
+                                    mesh_desc.file_path
+                                )))
+                            }
+                        };
+                        library = match libraries.load(&library_path) {
+                            Ok(l) => Some(l),
+                            Err(e) => return Err(DeserializeError::LocalError(e)),
+                        };
                     }
-                    _ => {
-                        return Err(DeserializeError::LocalError(format!(
-                            "Only triangles are allowed in meshes, 
-								but file {}, object {} had another type of primitive.",
-                            mesh_desc.file_path, object.name
-                        )));
+                    match library.as_ref().and_then(|l| l.get(name)) {
+                        Some(m) => Arc::clone(m),
+                        None => {
+                            return Err(DeserializeError::LocalError(format!(
+                                "Missing Material {} in the material library of file {}.",
+                                name, mesh_desc.file_path
+                            )))
+                        }
                     }
                 }
+            };
+            object_materials.push(material);
+        }
+
+        return add_mesh_triangles(
+            object,
+            object_materials,
+            mesh_desc.enable_backface_culling,
+            &mesh_desc.file_path,
+            shapes,
+        );
+    };
+
+    let cache = match &mesh_desc.cache {
+        Some(dir) => {
+            let key = meshcache::key(&obj_path, &local_to_world, mesh_desc.uv_projection)?;
+            Some((meshcache::file_path(&spec_dir.join(dir), key), key))
+        }
+        None => None,
+    };
+    match &cache {
+        Some((cache_path, key)) if cache_path.exists() => {
+            let first_shape = shapes.len();
+            match meshcache::read(cache_path, *key, |object, material_library| {
+                build(object, material_library, shapes)
+            }) {
+                Ok(result) => return result,
+                Err(e) => {
+                    eprintln!(
+                        "Reprocessing mesh {}, could not use cache {}: {}",
+                        mesh_desc.file_path,
+                        cache_path.display(),
+                        e
+                    );
+                    shapes.truncate(first_shape);
+                }
+            }
+        }
+        _ => {}
+    }
+    let mut writer = match &cache {
+        Some((cache_path, key)) => match meshcache::CacheWriter::create(cache_path, *key) {
+            Ok(w) => Some(w),
+            Err(e) => {
+                eprintln!("Failed to write mesh cache {}: {}", cache_path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Each object of the OBJ file becomes a mesh as soon as it is read,
+    // before the next is
+    objstream::read(&obj_path, |mut object, material_library| {
+        // Projected texture coordinates come from the untransformed
+        // vertices.
+        let mut converted_vertices = Vec::with_capacity(object.vertices.len());
+        for vert in &object.vertices {
+            converted_vertices.push(&local_to_world * *vert);
+        }
+        match mesh_desc.uv_projection {
+            Some(p) => project_uvs(
+                &Projector::new(p, &object.vertices),
+                &object.vertices,
+                &mut object.faces,
+                &mut object.tex_coords,
+            ),
+            None => {}
+        }
+        object.vertices = converted_vertices;
+
+        let failed = match &mut writer {
+            Some(w) => w.add(&object, material_library).err(),
+            None => None,
+        };
+        match failed {
+            Some(e) => {
+                eprintln!(
+                    "Failed to write mesh cache for {}: {}",
+                    mesh_desc.file_path, e
+                );
+                writer = None;
             }
+            None => {}
         }
+        return build(object, material_library, shapes);
+    })?;
+    match writer {
+        Some(w) => match w.finish() {
+            Ok(()) => {}
+            Err(e) => eprintln!(
+                "Failed to write mesh cache for {}: {}",
+                mesh_desc.file_path, e
+            ),
+        },
+        None => {}
+    }
+    return Ok(());
+}
+
+// Makes the triangles of an object whose vertices are already in world
+// space, with a material for each of its material groups
+fn add_mesh_triangles(
+    object: ObjObject,
+    object_materials: Vec<Arc<SyncMaterial>>,
+    enable_backface_culling: bool,
+    file_path: &str,
+    shapes: &mut Vec<Arc<SyncShape>>,
+) -> Result<(), DeserializeError> {
+    // Create shared mesh, which all Triangles will reference.
+    let t_mesh = Arc::new(shape::TriangleMesh::new(
+        object.vertices,
+        object.tex_coords,
+        &object.faces,
+        enable_backface_culling,
+        object_materials,
+    ));
+
+    for (face, material_index) in object.faces.into_iter().zip(object.face_materials) {
+        let [(v_index0, t_index0), (v_index1, t_index1), (v_index2, t_index2)] = face;
+        shapes.push(Arc::new(
+            match shape::Triangle::new(
+                Arc::clone(&t_mesh),
+                v_index0,
+                v_index1,
+                v_index2,
+                t_index0,
+                t_index1,
+                t_index2,
+                material_index,
+            ) {
+                Ok(t) => t,
+                Err(e) => {
+                    return Err(DeserializeError::LocalError(format!(
+                        "Error creating Triangle for file {}, object {}: {}",
+                        file_path, object.name, e
+                    )))
+                }
+            },
+        ));
     }
     return Ok(());
 }
 
+// Gives projected texture coordinates to the faces missing any. Corners
+// that project to the same place share coordinates, so tangents are still
+// smoothed across faces.
+fn project_uvs(
+    projector: &Projector,
+    vertices: &Vec<Point3>,
+    faces: &mut Vec<[shape::MeshCorner; 3]>,
+    tex_coords: &mut Vec<TexCoord>,
+) {
+    let mut projected: HashMap<(usize, u32, u32), usize> = HashMap::new();
+    for face in faces.iter_mut() {
+        if face.iter().all(|(_, t)| t.is_some()) {
+            continue;
+        }
+        let uvs = projector.face_uvs([
+            vertices[face[0].0],
+            vertices[face[1].0],
+            vertices[face[2].0],
+        ]);
+        for (corner, uv) in face.iter_mut().zip(uvs.iter()) {
+            let key = (corner.0, uv.u().to_bits(), uv.v().to_bits());
+            let index = *projected.entry(key).or_insert_with(|| {
+                tex_coords.push(*uv);
+                tex_coords.len() - 1
+            });
+            corner.1 = Some(index);
+        }
+    }
+}
+
 // ConstantMedium
 #[derive(Deserialize)]
 struct ConstantMediumDescription {
@@ -574,6 +3263,8 @@ fn deserialize_constant_medium(
     json: &serde_json::Value,
     spec_dir: &path::Path,
     materials: &HashMap<String, Arc<SyncMaterial>>,
+    prototypes: &HashMap<String, Arc<Prototype>>,
+    libraries: &mut MaterialLibraries,
     shapes: &mut Vec<Arc<SyncShape>>,
 ) -> Result<(), DeserializeError> {
     let med_desc: ConstantMediumDescription =
@@ -585,7 +3276,14 @@ fn deserialize_constant_medium(
         )));
     }
     let mut shapes_temp = Vec::new();
-    deserialize_shape(&med_desc.boundary, spec_dir, materials, &mut shapes_temp)?;
+    deserialize_shape(
+        &med_desc.boundary,
+        spec_dir,
+        materials,
+        prototypes,
+        libraries,
+        &mut shapes_temp,
+    )?;
     // TODO: For now, just single shapes are valid for boundaries
     if shapes_temp.len() != 1 {
         return Err(DeserializeError::LocalError(String::from(
@@ -602,13 +3300,55 @@ fn deserialize_constant_medium(
 }
 
 // Aggregates
+// The aggregate may be given as just a type string, or as an object with a
+// type and construction settings.
+#[derive(Deserialize)]
+struct BVHDescription {
+    quality: Option<String>,
+    leaf_size: Option<usize>,
+    max_leaf_size: Option<usize>,
+    traversal_cost: Option<f32>,
+    intersection_cost: Option<f32>,
+    // Relative path to read a prebuilt BVH from, or write one to
+    cache: Option<String>,
+}
+
 fn create_aggregate(
-    aggregate_type: &str,
+    json: &serde_json::Value,
+    spec_dir: &path::Path,
     shapes: Vec<Arc<SyncShape>>,
 ) -> Result<Box<SyncAggregate>, DeserializeError> {
+    let aggregate_type = if json.is_object() {
+        identify_type(json)?
+    } else {
+        match json.as_str() {
+            Some(t) => t,
+            None => {
+                return Err(DeserializeError::LocalError(String::from(
+                    "'Aggregate' is not a string or a JSON object.",
+                )))
+            }
+        }
+    };
+
     match aggregate_type {
         "List" => return Ok(Box::new(shapes)),
-        "BVH" => return Ok(new_bvh(shapes)),
+        "BVH" | "SBVH" => {
+            let mut settings = deserialize_bvh_settings(json)?;
+            // SBVH is kept as shorthand for a high quality BVH
+            if aggregate_type == "SBVH" {
+                settings.quality = BuildQuality::High;
+            }
+            let cache = if json.is_object() {
+                serde_json::from_value::<BVHDescription>(serde_json::Value::clone(json))?.cache
+            } else {
+                None
+            };
+            return Ok(match cache {
+                Some(c) => new_cached_bvh(shapes, &settings, &spec_dir.join(c)),
+                None => new_bvh(shapes, &settings),
+            });
+        }
         _ => {
             return Err(DeserializeError::LocalError(format!(
                 "Unknown Aggregate 'type' {} given.",
@@ -617,3 +3357,55 @@ fn create_aggregate(
         }
     }
 }
+
+fn deserialize_bvh_settings(json: &serde_json::Value) -> Result<BVHSettings, DeserializeError> {
+    let mut settings = BVHSettings::new();
+    if !json.is_object() {
+        return Ok(settings);
+    }
+
+    let bvh_desc: BVHDescription = serde_json::from_value(serde_json::Value::clone(json))?;
+    match &bvh_desc.quality {
+        None => {}
+        Some(q) => {
+            settings.quality = match q.as_str() {
+                "low" => BuildQuality::Low,
+                "medium" => BuildQuality::Medium,
+                "high" => BuildQuality::High,
+                _ => {
+                    return Err(DeserializeError::LocalError(format!(
+                        "Unknown BVH quality {} given, expected low, medium, or high.",
+                        q
+                    )))
+                }
+            }
+        }
+    }
+    match bvh_desc.leaf_size {
+        None => {}
+        Some(l) => {
+            if l == 0 {
+                return Err(DeserializeError::LocalError(String::from(
+                    "BVH leaf_size must be at least 1.",
+                )));
+            }
+            settings.leaf_size = l;
+        }
+    }
+    match bvh_desc.max_leaf_size {
+        None => {}
+        Some(m) => {
+            if m < settings.leaf_size {
+                return Err(DeserializeError::LocalError(String::from(
+                    "BVH max_leaf_size must be at least leaf_size.",
+                )));
+            }
+            settings.max_leaf_size = m;
+        }
+    }
+    settings.traversal_cost = bvh_desc.traversal_cost.unwrap_or(settings.traversal_cost);
+    settings.intersection_cost = bvh_desc
+        .intersection_cost
+        .unwrap_or(settings.intersection_cost);
+    return Ok(settings);
+}
@@ -0,0 +1,954 @@
+use crate::presets;
+use crate::version;
+
+use serde_json::json;
+
+// A JSON Schema (draft 7) for the scene specification format, written out by
+// the schema subcommand so editors can autocomplete and check scenes as they
+// are written. It mirrors the descriptions the scene module deserializes, so
+// a key or type added there needs adding here too. Unknown keys are allowed,
+// as they are when loading.
+pub fn scene_schema() -> serde_json::Value {
+    return json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "rust-raytracer scene",
+        "type": "object",
+        "required": ["Logistics", "Camera", "Textures", "Materials", "Aggregate", "Shapes"],
+        "properties": {
+            "Version": {
+                "description": "Version of the scene format the scene was written for, 1 if left out",
+                "type": "integer",
+                "minimum": 1,
+                "maximum": version::CURRENT_VERSION
+            },
+            "Logistics": logistics(),
+            "Camera": camera(),
+            "Textures": {
+                "description": "Textures by name",
+                "type": "object",
+                "additionalProperties": {"oneOf": [
+                    {"$ref": "#/definitions/Texture"},
+                    {"$ref": "#/definitions/ComposedTexture"}
+                ]}
+            },
+            "Materials": {
+                "description": "Materials by name",
+                "type": "object",
+                "additionalProperties": {"oneOf": [
+                    {"$ref": "#/definitions/Material"},
+                    {"$ref": "#/definitions/LibraryMaterial"}
+                ]}
+            },
+            "Aggregate": aggregate(),
+            "Shapes": {
+                "type": "array",
+                "items": {"$ref": "#/definitions/Shape"}
+            },
+            "Prototypes": {
+                "description": "Shapes that are only seen through Instances of them, by name",
+                "type": "array",
+                "items": {
+                    "allOf": [{"$ref": "#/definitions/Shape"}, {"required": ["name"]}]
+                }
+            },
+            "ClipPlanes": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["point", "normal"],
+                    "properties": {
+                        "point": triple(),
+                        "normal": described(triple(), "Points toward the side that is removed"),
+                        "shapes": described(
+                            json!({"type": "array", "items": {"type": "string"}}),
+                            "Names of the shapes to clip, every shape when not given"
+                        )
+                    }
+                }
+            },
+            "Lights": {
+                "type": "array",
+                "items": {"$ref": "#/definitions/Light"}
+            },
+            "Fog": {
+                "type": "object",
+                "required": ["density", "falloff", "color"],
+                "properties": {
+                    "density": number(),
+                    "falloff": described(number(), "How quickly the fog thins with height, 0 for uniform fog"),
+                    "base_height": number(),
+                    "color": triple()
+                }
+            }
+        },
+        "definitions": {
+            "Texture": {"oneOf": textures()},
+            "ComposedTexture": {"oneOf": composed_textures()},
+            "Material": {"oneOf": materials()},
+            "LibraryMaterial": {
+                "description": "A ready made material by name, with any of its properties replaced",
+                "type": "object",
+                "required": ["library"],
+                "not": {"required": ["type"]},
+                "properties": {"library": one_of(&presets::NAMES)}
+            },
+            "Shape": {"oneOf": shapes()},
+            "Light": {"oneOf": lights()},
+            "LightLinks": {
+                "description": "The shapes a light lights, by name",
+                "type": "object",
+                "properties": {
+                    "include": described(
+                        json!({"type": "array", "items": {"type": "string"}}),
+                        "Lights only these shapes, every shape when not given"
+                    ),
+                    "exclude": {"type": "array", "items": {"type": "string"}}
+                }
+            },
+            "ThinFilm": {
+                "description": "A clear film on the surface whose interference colors its reflections",
+                "type": "object",
+                "required": ["thickness", "refractive_index"],
+                "properties": {
+                    "thickness": described(json!({"type": "number", "minimum": 0}), "Nanometers"),
+                    "refractive_index": json!({"type": "number", "exclusiveMinimum": 0})
+                }
+            },
+            "Visibility": {
+                "description": "The kinds of ray that see a material, all of them by default",
+                "type": "object",
+                "properties": {
+                    "camera": {"type": "boolean"},
+                    "diffuse": {"type": "boolean"},
+                    "glossy": described(json!({"type": "boolean"}), "Mirrors, glass, metal, GGX, and volumes")
+                }
+            },
+            "Shadows": {
+                "description": "The shapes that shadow a light, by name, and how dark the shadows are",
+                "type": "object",
+                "properties": {
+                    "exclude": described(
+                        json!({"type": "array", "items": {"type": "string"}}),
+                        "Shapes that cast no shadow from the light"
+                    ),
+                    "density": described(
+                        json!({"type": "number", "minimum": 0, "maximum": 1}),
+                        "How much of the light a shadow blocks, 1 by default"
+                    ),
+                    "color": described(triple(), "Color of the shadows, black by default")
+                }
+            },
+            "Transform": {
+                "type": "object",
+                "properties": {
+                    "translate": triple(),
+                    "rotate": described(triple(), "Degrees about each axis"),
+                    "scale": triple()
+                }
+            }
+        }
+    });
+}
+
+fn number() -> serde_json::Value {
+    return json!({"type": "number"});
+}
+
+fn string() -> serde_json::Value {
+    return json!({"type": "string"});
+}
+
+// Points, vectors and colors
+fn triple() -> serde_json::Value {
+    return json!({
+        "type": "array",
+        "items": {"type": "number"},
+        "minItems": 3,
+        "maxItems": 3
+    });
+}
+
+fn pair() -> serde_json::Value {
+    return json!({
+        "type": "array",
+        "items": {"type": "number"},
+        "minItems": 2,
+        "maxItems": 2
+    });
+}
+
+fn one_of(values: &[&str]) -> serde_json::Value {
+    return json!({"type": "string", "enum": values});
+}
+
+fn described(schema: serde_json::Value, description: &str) -> serde_json::Value {
+    let mut schema = schema;
+    schema["description"] = json!(description);
+    return schema;
+}
+
+// An object picked out by its 'type', with the given properties
+fn typed(type_name: &str, required: &[&str], properties: serde_json::Value) -> serde_json::Value {
+    let mut properties = properties;
+    properties["type"] = json!({"const": type_name});
+    let mut all_required = vec!["type"];
+    all_required.extend_from_slice(required);
+    return json!({
+        "type": "object",
+        "required": all_required,
+        "properties": properties
+    });
+}
+
+fn logistics() -> serde_json::Value {
+    let color_space = one_of(&["sRGB", "Rec709", "ACEScg"]);
+    return json!({
+        "type": "object",
+        "required": ["resolution_x", "resolution_y", "samples"],
+        "properties": {
+            "resolution_x": {"type": "integer", "minimum": 1},
+            "resolution_y": {"type": "integer", "minimum": 1},
+            "samples": described(json!({"type": "integer", "minimum": 1}), "Samples per pixel"),
+            "use_importance_sampling": {"type": "boolean"},
+            "ray_epsilon": described(
+                json!({"type": "number", "minimum": 0}),
+                "Overrides the ray epsilon derived from the scene's extent"
+            ),
+            "tile_focus": described(
+                pair(),
+                "Renders tiles nearest this point first, as fractions of the image from the top left"
+            ),
+            "priority_regions": described(
+                json!({
+                    "type": "array",
+                    "items": {
+                        "type": "array",
+                        "items": {"type": "number"},
+                        "minItems": 4,
+                        "maxItems": 4
+                    }
+                }),
+                "Renders tiles overlapping these regions first, each [left, top, right, bottom] as fractions of the image from the top left"
+            ),
+            "framebuffer": described(
+                one_of(&["Float", "Half", "Disk"]),
+                "Precision of the image while rendering, Half for very large images, Disk for images too large for memory (without passes, denoise, or gradient_domain)"
+            ),
+            "working_space": described(color_space.clone(), "Linear space to render in"),
+            "output_space": described(color_space, "Space to write the image out in"),
+            "wireframe": {
+                "type": "object",
+                "required": ["mode"],
+                "properties": {
+                    "mode": one_of(&["Overlay", "Only"]),
+                    "line_width": described(number(), "Degrees, as seen from the camera"),
+                    "color": triple()
+                }
+            },
+            "check_normals": {"type": "boolean"},
+            "lobes": described(
+                json!({"type": "boolean"}),
+                "Colors each pixel by the share of its samples whose first hit scattered diffusely (red), glossily (green), by transmission (blue), or emitted (white)"
+            ),
+            "override_material": described(
+                string(),
+                "A material of the scene, or Clay, to replace every material but lights with"
+            ),
+            "gradient_domain": described(
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "alpha": described(number(), "How closely the solved image keeps to the colors, against the gradients"),
+                        "iterations": {"type": "integer", "minimum": 0}
+                    }
+                }),
+                "Traces each sample through the neighboring pixels too and solves for the image from its gradients"
+            ),
+            "light_resampling": described(
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "candidates": described(json!({"type": "integer", "minimum": 1}), "Light samples drawn at each shading point, 8 by default"),
+                        "neighbors": described(json!({"type": "integer", "minimum": 0}), "Reservoirs of nearby pixels merged in at camera hits, 8 by default"),
+                        "radius": described(json!({"type": "integer", "minimum": 0}), "How far away, in pixels, neighbors are taken from, 3 by default")
+                    }
+                }),
+                "Samples direct lighting from one of several light candidates, reused across neighboring pixels"
+            ),
+            "passes": described(
+                json!({"type": "array", "items": one_of(&["Normal", "Depth", "Albedo"])}),
+                "First hit images to write beside the output as EXRs, named like image.normal.exr, for denoisers"
+            ),
+            "denoise": described(
+                json!({"type": "boolean"}),
+                "Denoises the finished image with Intel Open Image Denoise, in builds with the denoise feature"
+            ),
+            "search_paths": described(
+                json!({"type": "array", "items": string()}),
+                "Directories to look for assets in when they aren't next to the scene, relative to it, before those of RAYTRACER_ASSET_PATH"
+            ),
+            "seed": described(
+                json!({"type": "integer", "minimum": 0}),
+                "Seeds the random numbers of every sample, so the scene renders the same each time"
+            ),
+            "pin_threads": {"type": "boolean"},
+            "frame": described(
+                json!({"type": "integer", "minimum": 0}),
+                "The frame of an animation to render, for cameras that follow a path"
+            )
+        }
+    });
+}
+
+fn camera() -> serde_json::Value {
+    return json!({
+        "type": "object",
+        "required": ["up"],
+        // A path gives the position, and may give the look_at
+        "allOf": [
+            {"anyOf": [{"required": ["position"]}, {"required": ["path"]}]},
+            {"anyOf": [
+                {"required": ["look_at"]},
+                {"required": ["path"], "properties": {"path": {"required": ["look_at"]}}}
+            ]}
+        ],
+        "properties": {
+            "projection": one_of(&["Perspective", "Fisheye", "Equirectangular"]),
+            "position": triple(),
+            "look_at": triple(),
+            "up": triple(),
+            "fov": described(
+                number(),
+                "Vertical field of view, or the angle across a fisheye's circle, in degrees"
+            ),
+            "aspect_ratio": number(),
+            "aperture": number(),
+            "focus_distance": number(),
+            "near": number(),
+            "far": number(),
+            "shutter_open": number(),
+            "shutter_close": number(),
+            "rolling_shutter": described(
+                json!({"type": "number", "minimum": 0, "maximum": 1}),
+                "Fraction of the shutter interval spent reading rows out from top to bottom, for matching CMOS footage; 0, a global shutter, by default"
+            ),
+            "path": described(
+                json!({
+                    "type": "object",
+                    "required": ["curve", "points", "frames"],
+                    "properties": {
+                        "curve": described(
+                            one_of(&["CatmullRom", "Bezier"]),
+                            "CatmullRom passes through every point, Bezier takes an anchor and two control points a segment, then a last anchor"
+                        ),
+                        "points": {"type": "array", "items": triple(), "minItems": 2},
+                        "look_at": described(
+                            json!({"type": "array", "items": triple(), "minItems": 2}),
+                            "Targets following the same curve, one for each point"
+                        ),
+                        "frames": described(
+                            json!({"type": "integer", "minimum": 2}),
+                            "Frames from the start of the path to its end"
+                        ),
+                        "ease": one_of(&["Linear", "EaseIn", "EaseOut", "EaseInOut"])
+                    }
+                }),
+                "Moves the camera along a curve over the frames of an animation, in place of a position"
+            )
+        }
+    });
+}
+
+fn aggregate() -> serde_json::Value {
+    return json!({
+        "oneOf": [
+            one_of(&["List", "BVH", "SBVH"]),
+            {
+                "type": "object",
+                "required": ["type"],
+                "properties": {
+                    "type": one_of(&["List", "BVH", "SBVH"]),
+                    "quality": one_of(&["low", "medium", "high"]),
+                    "leaf_size": {"type": "integer", "minimum": 1},
+                    "max_leaf_size": {"type": "integer", "minimum": 1},
+                    "traversal_cost": number(),
+                    "intersection_cost": number(),
+                    "cache": described(string(), "Path to read a prebuilt BVH from, or write one to")
+                }
+            }
+        ]
+    });
+}
+
+fn textures() -> Vec<serde_json::Value> {
+    let texture = json!({"$ref": "#/definitions/Texture"});
+    return vec![
+        typed("Constant", &["color"], json!({"color": triple()})),
+        typed("Test", &[], json!({})),
+        typed(
+            "Checker",
+            &["repeat", "odd", "even"],
+            json!({"repeat": number(), "odd": texture, "even": texture}),
+        ),
+        typed(
+            "Triplanar",
+            &["texture"],
+            json!({
+                "texture": texture,
+                "scale": described(number(), "Repeats of the texture per unit along each axis, 1 by default"),
+                "sharpness": described(
+                    json!({"type": "number", "minimum": 0}),
+                    "How sharply the three projections are blended by the normal, 4 by default"
+                )
+            }),
+        ),
+        typed(
+            "Image",
+            &["image_path"],
+            json!({
+                "image_path": string(),
+                "linear": described(
+                    json!({"type": "boolean"}),
+                    "Read the pixels as they are rather than as sRGB, for data like bump, normal, and roughness maps"
+                ),
+                "filter": described(
+                    one_of(&["Nearest", "Bilinear", "Trilinear"]),
+                    "How texels are blended, Bilinear by default; Trilinear also averages over mipmaps by how much of the texture each pixel covers"
+                )
+            }),
+        ),
+        typed(
+            "Ramp",
+            &["input", "stops"],
+            json!({
+                "input": described(
+                    json!({"oneOf": [one_of(&["U", "V"]), texture]}),
+                    "Looks colors up by a texture coordinate, or by another texture's luminance"
+                ),
+                "stops": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "required": ["position", "color"],
+                        "properties": {"position": number(), "color": triple()}
+                    }
+                },
+                "interpolation": described(
+                    one_of(&["Linear", "Constant"]),
+                    "Linear by default; Constant takes the color of the stop at or before the input"
+                )
+            }),
+        ),
+        typed(
+            "Noise",
+            &["scale"],
+            json!({
+                "scale": number(),
+                "seed": described(json!({"type": "integer", "minimum": 0}), "Shuffles the noise into a pattern of its own"),
+                "octaves": described(json!({"type": "integer", "minimum": 1}), "Layers of noise summed, 1 by default"),
+                "lacunarity": described(json!({"type": "number", "exclusiveMinimum": 0}), "Frequency of each octave over the last, 2 by default"),
+                "gain": described(json!({"type": "number", "minimum": 0}), "Weight of each octave over the last, 0.5 by default")
+            }),
+        ),
+        typed(
+            "InstanceAttribute",
+            &["attribute"],
+            json!({
+                "attribute": described(string(), "random, index, or one given in the instance's variation"),
+                "default": described(number(), "Outside of instances with the attribute, 0 by default")
+            }),
+        ),
+        typed(
+            "Blackbody",
+            &["temperature"],
+            json!({
+                "temperature": described(json!({"type": "number", "exclusiveMinimum": 0}), "Kelvin"),
+                "intensity": described(json!({"type": "number", "minimum": 0}), "Luminance of the color, 1 by default")
+            }),
+        ),
+        typed(
+            "Turbulence",
+            &["scale", "depth", "omega"],
+            json!({
+                "scale": number(),
+                "depth": {"type": "integer", "minimum": 0},
+                "omega": {"type": "number", "minimum": 0, "maximum": 1},
+                "seed": described(json!({"type": "integer", "minimum": 0}), "Shuffles the noise into a pattern of its own"),
+                "lacunarity": described(json!({"type": "number", "exclusiveMinimum": 0}), "Frequency of each octave over the last, 1.99 by default")
+            }),
+        ),
+    ];
+}
+
+// Textures made from others, which are named and looked up in Textures, so
+// they are only allowed there rather than nested in other textures
+fn composed_textures() -> Vec<serde_json::Value> {
+    let textures = json!({"type": "array", "minItems": 2, "items": string()});
+    return vec![
+        typed(
+            "Multiply",
+            &["textures"],
+            json!({"textures": described(textures.clone(), "Textures multiplied together, channel by channel")}),
+        ),
+        typed(
+            "Add",
+            &["textures"],
+            json!({"textures": described(textures, "Textures added together, channel by channel")}),
+        ),
+        typed(
+            "Invert",
+            &["texture"],
+            json!({"texture": described(string(), "A texture, each channel of which is taken from 1")}),
+        ),
+        typed(
+            "Clamp",
+            &["texture"],
+            json!({
+                "texture": string(),
+                "min": described(number(), "0 by default"),
+                "max": described(number(), "1 by default, no less than min")
+            }),
+        ),
+        typed(
+            "Mix",
+            &["base", "top", "mask"],
+            json!({
+                "base": string(),
+                "top": string(),
+                "mask": described(string(), "A texture, the top where it is 1 and the base where it is 0, channel by channel")
+            }),
+        ),
+    ];
+}
+
+// Materials name their textures, which are looked up in Textures, and
+// Blends and Clearcoats name the materials they are made of, and every
+// material can be hidden from some kinds of ray
+fn materials() -> Vec<serde_json::Value> {
+    let roughness_map = described(
+        string(),
+        "A texture, its brightness is the roughness, in place of a number",
+    );
+    let mut materials = vec![
+        typed(
+            "Lambert",
+            &["albedo"],
+            json!({"albedo": string(), "bump_map": string()}),
+        ),
+        {
+            let mut metal = typed(
+                "Metal",
+                &["albedo"],
+                json!({
+                    "albedo": string(),
+                    "roughness": number(),
+                    "roughness_map": roughness_map.clone(),
+                    "bump_map": string(),
+                    "thin_film": {"$ref": "#/definitions/ThinFilm"}
+                }),
+            );
+            metal["oneOf"] = json!([{"required": ["roughness"]}, {"required": ["roughness_map"]}]);
+            metal
+        },
+        {
+            let mut conductor = typed(
+                "Conductor",
+                &[],
+                json!({
+                    "preset": one_of(&["Gold", "Silver", "Copper", "Aluminum"]),
+                    "eta": described(triple(), "Real part of the refractive index at red, green, and blue"),
+                    "k": described(triple(), "Imaginary part of the refractive index at red, green, and blue"),
+                    "roughness": described(number(), "0 by default"),
+                    "roughness_map": roughness_map.clone(),
+                    "bump_map": string()
+                }),
+            );
+            conductor["oneOf"] = json!([{"required": ["preset"]}, {"required": ["eta", "k"]}]);
+            conductor["allOf"] = json!([{"not": {"required": ["roughness", "roughness_map"]}}]);
+            conductor
+        },
+        {
+            let mut ggx = typed(
+                "GGX",
+                &["albedo"],
+                json!({
+                    "albedo": string(),
+                    "roughness": described(number(), "Along u, and along v unless roughness_v is given"),
+                    "roughness_map": roughness_map.clone(),
+                    "roughness_v": number(),
+                    "roughness_v_map": roughness_map.clone(),
+                    "refractive_index": number(),
+                    "bump_map": string()
+                }),
+            );
+            ggx["oneOf"] = json!([{"required": ["roughness"]}, {"required": ["roughness_map"]}]);
+            ggx["allOf"] = json!([{"not": {"required": ["roughness_v", "roughness_v_map"]}}]);
+            ggx
+        },
+        {
+            let mut metallic_roughness = typed(
+                "MetallicRoughness",
+                &["base_color"],
+                json!({
+                    "base_color": string(),
+                    "metalness": described(
+                        json!({"type": "number", "minimum": 0, "maximum": 1}),
+                        "0 by default"
+                    ),
+                    "metalness_map": described(
+                        string(),
+                        "A texture, its brightness is the metalness, in place of a number"
+                    ),
+                    "roughness": described(number(), "0.5 by default"),
+                    "roughness_map": roughness_map,
+                    "refractive_index": described(number(), "Of the dielectric parts, 1.5 by default"),
+                    "bump_map": string()
+                }),
+            );
+            metallic_roughness["allOf"] = json!([
+                {"not": {"required": ["metalness", "metalness_map"]}},
+                {"not": {"required": ["roughness", "roughness_map"]}}
+            ]);
+            metallic_roughness
+        },
+        {
+            let mut dielectric = typed(
+                "Dielectric",
+                &[],
+                json!({
+                    "refractive_index": number(),
+                    "cauchy": described(
+                        json!({"type": "array", "items": number(), "minItems": 1}),
+                        "Coefficients A, B, C, ... of n = A + B/λ² + C/λ⁴ + ..., λ in micrometers"
+                    ),
+                    "sellmeier": {
+                        "description": "Coefficients of n² = 1 + Σ Bᵢλ²/(λ² - Cᵢ), λ in micrometers",
+                        "type": "object",
+                        "required": ["b", "c"],
+                        "properties": {
+                            "b": {"type": "array", "items": number(), "minItems": 1},
+                            "c": {"type": "array", "items": number(), "minItems": 1}
+                        }
+                    },
+                    "thin_film": {"$ref": "#/definitions/ThinFilm"},
+                    "bump_map": string()
+                }),
+            );
+            dielectric["oneOf"] = json!([
+                {"required": ["refractive_index"]},
+                {"required": ["cauchy"]},
+                {"required": ["sellmeier"]}
+            ]);
+            dielectric
+        },
+        typed(
+            "DiffuseLight",
+            &["emission"],
+            json!({
+                "emission": string(),
+                "intensity": described(json!({"type": "number", "minimum": 0}), "Scales the emission, 1 by default"),
+                "two_sided": described(
+                    json!({"type": "boolean"}),
+                    "Emits from the back as well as the front, true by default"
+                ),
+                "falloff": described(
+                    json!({"type": "number", "minimum": 0}),
+                    "Power of the cosine to the normal the emission dims by, 0 by default"
+                )
+            }),
+        ),
+        typed(
+            "SpotLight",
+            &["emission", "direction", "cone_angle"],
+            json!({
+                "emission": string(),
+                "direction": triple(),
+                "cone_angle": number(),
+                "falloff_angle": number(),
+                "gobo": string()
+            }),
+        ),
+        typed("Isotropic", &["albedo"], json!({"albedo": string()})),
+        typed(
+            "Clearcoat",
+            &["base"],
+            json!({
+                "base": described(string(), "A material of the scene, seen under the coat"),
+                "roughness": described(number(), "Of the coat, 0 by default"),
+                "refractive_index": described(number(), "Of the coat, 1.5 by default"),
+                "bump_map": described(string(), "Of the coat, the base keeps its own")
+            }),
+        ),
+        typed(
+            "CarPaint",
+            &["color"],
+            json!({
+                "color": string(),
+                "flop_color": described(string(), "Color at grazing angles, for color shifting paints"),
+                "flake_color": described(string(), "The paint's color by default"),
+                "roughness": described(number(), "Of the paint under the coat, 0.35 by default"),
+                "flake_roughness": described(number(), "0.05 by default"),
+                "flake_size": described(number(), "Size of the grid cells flakes fill, 0.01 by default"),
+                "flake_density": described(
+                    json!({"type": "number", "minimum": 0, "maximum": 1}),
+                    "Share of cells with a flake, 0.3 by default"
+                ),
+                "flake_tilt": described(number(), "How far flakes tilt from the surface, 0.3 by default"),
+                "coat_roughness": described(number(), "0 by default"),
+                "coat_refractive_index": described(number(), "1.5 by default"),
+                "bump_map": string()
+            }),
+        ),
+        typed(
+            "Measured",
+            &["file_path"],
+            json!({
+                "file_path": described(string(), "A MERL .binary file, relative to the scene"),
+                "bump_map": string()
+            }),
+        ),
+        typed(
+            "Sheen",
+            &["sheen"],
+            json!({
+                "albedo": described(string(), "Diffuse base under the sheen, none by default"),
+                "sheen": string(),
+                "roughness": described(number(), "0.5 by default"),
+                "bump_map": string()
+            }),
+        ),
+        typed(
+            "Toon",
+            &["albedo"],
+            json!({
+                "albedo": string(),
+                "bands": described(json!({"type": "integer", "minimum": 1}), "3 by default"),
+                "rim": {
+                    "type": "object",
+                    "required": ["color"],
+                    "properties": {
+                        "color": triple(),
+                        "width": described(number(), "Cosine to the viewer below which the rim shows, 0.2 by default")
+                    }
+                },
+                "outline": {
+                    "type": "object",
+                    "properties": {
+                        "color": triple(),
+                        "depth_threshold": described(number(), "Relative jump in distance for a line, 0.1 by default"),
+                        "normal_threshold": described(number(), "Degrees between normals for a line, 60 by default")
+                    }
+                },
+                "bump_map": string()
+            }),
+        ),
+        {
+            let mut blend = typed(
+                "Blend",
+                &["base", "top"],
+                json!({
+                    "base": described(string(), "A material of the scene"),
+                    "top": described(string(), "A material of the scene"),
+                    "factor": {"type": "number", "minimum": 0, "maximum": 1},
+                    "mask": described(string(), "A texture, its brightness is the share of top")
+                }),
+            );
+            blend["oneOf"] = json!([{"required": ["factor"]}, {"required": ["mask"]}]);
+            blend
+        },
+    ];
+    for material in materials.iter_mut() {
+        material["properties"]["visibility"] = json!({"$ref": "#/definitions/Visibility"});
+        if material["properties"].get("bump_map").is_some() {
+            material["properties"]["normal_map"] = described(
+                string(),
+                "A tangent space normal map, in place of a bump map",
+            );
+            material["not"] = json!({"required": ["bump_map", "normal_map"]});
+        }
+    }
+    return materials;
+}
+
+// Every shape can be named, moved for motion blur, cut out by an alpha mask,
+// and linked to the shapes it lights if it emits light
+fn shape(type_name: &str, required: &[&str], properties: serde_json::Value) -> serde_json::Value {
+    let mut properties = properties;
+    properties["name"] = string();
+    properties["light_links"] = json!({"$ref": "#/definitions/LightLinks"});
+    properties["alpha"] = described(
+        string(),
+        "A texture, rays pass through where it is dark, at random where it is grey",
+    );
+    properties["transform"] = json!({"$ref": "#/definitions/Transform"});
+    properties["end_transform"] = described(
+        json!({"$ref": "#/definitions/Transform"}),
+        "Where the shape ends up at time 1, for motion blur",
+    );
+    return typed(type_name, required, properties);
+}
+
+fn shapes() -> Vec<serde_json::Value> {
+    let child = json!({"$ref": "#/definitions/Shape"});
+    let frustum = json!({
+        "radius": number(),
+        "height": number(),
+        "caps": {"type": "boolean"},
+        "material": string()
+    });
+    return vec![
+        shape(
+            "Sphere",
+            &["radius", "material"],
+            json!({"radius": number(), "material": string()}),
+        ),
+        shape(
+            "Box",
+            &["material"],
+            json!({"min": triple(), "max": triple(), "size": triple(), "material": string()}),
+        ),
+        shape(
+            "Plane",
+            &["point", "normal", "material"],
+            json!({
+                "point": triple(),
+                "normal": triple(),
+                "extent": described(pair(), "Width and height of a rectangle, infinite when not given"),
+                "uv_scale": number(),
+                "material": string()
+            }),
+        ),
+        shape(
+            "Disk",
+            &["center", "normal", "radius", "material"],
+            json!({
+                "center": triple(),
+                "normal": triple(),
+                "radius": number(),
+                "material": string()
+            }),
+        ),
+        shape(
+            "Cylinder",
+            &["radius", "height", "material"],
+            frustum.clone(),
+        ),
+        shape("Cone", &["radius", "height", "material"], frustum),
+        shape(
+            "CSG",
+            &["operation", "left", "right", "material"],
+            json!({
+                "operation": one_of(&["Union", "Intersection", "Difference"]),
+                "left": child,
+                "right": child,
+                "material": string()
+            }),
+        ),
+        shape(
+            "Instance",
+            &["prototype"],
+            json!({
+                "prototype": string(),
+                "material": string(),
+                "variation": described(
+                    json!({
+                        "type": "object",
+                        "properties": {
+                            "seed": described(json!({"type": "integer", "minimum": 0}), "The instance's index among its prototype's instances by default"),
+                            "hue_shift": described(json!({"type": "number", "minimum": 0}), "Most degrees the hue of the material turns either way, at random, 0 by default"),
+                            "attributes": described(
+                                json!({"type": "object", "additionalProperties": number()}),
+                                "Values for InstanceAttribute textures to read"
+                            )
+                        }
+                    }),
+                    "Sets the instance apart from the other instances of its prototype"
+                )
+            }),
+        ),
+        shape(
+            "Mesh",
+            &["file_path", "enable_backface_culling"],
+            json!({
+                "file_path": string(),
+                "enable_backface_culling": {"type": "boolean"},
+                "material": described(
+                    string(),
+                    "The mesh's material library is used when not given"
+                ),
+                "materials": described(
+                    json!({"type": "object", "additionalProperties": {"type": "string"}}),
+                    "Scene materials for the mesh's usemtl groups, by group"
+                ),
+                "uv_projection": described(
+                    one_of(&["Planar", "Spherical", "Cubic"]),
+                    "Makes up texture coordinates for faces the file gives none"
+                ),
+                "cache": described(
+                    string(),
+                    "Directory to keep the processed mesh in, reused by scenes and frames that load the same file the same way"
+                )
+            }),
+        ),
+        shape(
+            "ConstantMedium",
+            &["boundary", "density", "phase_func"],
+            json!({"boundary": child, "density": number(), "phase_func": string()}),
+        ),
+    ];
+}
+
+fn lights() -> Vec<serde_json::Value> {
+    let temperature = described(
+        json!({"type": "number", "exclusiveMinimum": 0}),
+        "Kelvin of a blackbody giving the color",
+    );
+    let mut point = typed(
+        "Point",
+        &["position", "intensity"],
+        json!({
+            "position": triple(),
+            "intensity": number(),
+            "color": triple(),
+            "temperature": temperature
+        }),
+    );
+    point["oneOf"] = json!([{"required": ["color"]}, {"required": ["temperature"]}]);
+    let mut directional = typed(
+        "Directional",
+        &[],
+        json!({
+            "direction": described(triple(), "The direction the light travels in"),
+            "sun_position": {
+                "description": "Points the light from where the sun is at a place and local time, with +Y up",
+                "type": "object",
+                "required": ["latitude", "longitude", "date", "time"],
+                "properties": {
+                    "latitude": described(json!({"type": "number", "minimum": -90, "maximum": 90}), "Degrees north"),
+                    "longitude": described(json!({"type": "number", "minimum": -180, "maximum": 180}), "Degrees east"),
+                    "date": described(json!({"type": "string", "pattern": "^[0-9]+-[0-9]{1,2}-[0-9]{1,2}$"}), "YYYY-MM-DD"),
+                    "time": described(json!({"type": "string", "pattern": "^[0-9]{1,2}:[0-9]{1,2}(:[0-9]{1,2}(\\.[0-9]+)?)?$"}), "HH:MM or HH:MM:SS, local"),
+                    "utc_offset": described(number(), "Hours ahead of UTC of the local time, 0 by default"),
+                    "north": described(triple(), "Along the ground, -Z by default")
+                }
+            },
+            "shape": one_of(&["Disk", "Rectangle"]),
+            "angular_diameter": described(number(), "Degrees across the sun, or the rectangle's width"),
+            "angular_height": described(number(), "Degrees across the rectangle's height"),
+            "radiance": triple(),
+            "temperature": temperature,
+            "intensity": described(json!({"type": "number", "minimum": 0}), "Scales the radiance, 1 by default")
+        }),
+    );
+    directional["allOf"] = json!([
+        {"oneOf": [{"required": ["direction"]}, {"required": ["sun_position"]}]},
+        {"oneOf": [{"required": ["radiance"]}, {"required": ["temperature"]}]}
+    ]);
+    let mut lights = vec![point, directional];
+    for light in lights.iter_mut() {
+        light["properties"]["light_links"] = json!({"$ref": "#/definitions/LightLinks"});
+        light["properties"]["shadows"] = json!({"$ref": "#/definitions/Shadows"});
+    }
+    return lights;
+}
@@ -3,10 +3,14 @@ use crate::material::SyncMaterial;
 use crate::matrix::Matrix4;
 use crate::point::Point3;
 use crate::ray::Ray;
+use crate::rng;
+use crate::sampling;
 use crate::texture::TexCoord;
 use crate::utils;
+use crate::vector::Axis;
 use crate::vector::Vector3;
 
+use std::collections::HashMap;
 use std::f32;
 use std::sync::Arc;
 
@@ -16,16 +20,183 @@ pub struct HitProperties {
     pub uv: TexCoord,
     pub pu: Vector3,
     pub pv: Vector3,
+    // About how wide, in texture coordinates, what the ray sees around the
+    // hit is, for filtering textures. Set by set_footprint, 0 until then.
+    pub uv_footprint: f32,
+}
+
+impl HitProperties {
+    // The footprint of a ray with a spread at the hit, widened as the ray
+    // meets the surface at a glancing angle, and measured in texture
+    // coordinates by the surface's area per unit of u and v
+    pub fn set_footprint(&mut self, r: &Ray, t: f32) {
+        if r.spread <= 0.0_f32 {
+            return;
+        }
+        let direction = r.dir.normalized();
+        let width = r.spread * t * r.dir.length();
+        let cos = self.normal.normalized().dot(direction).abs().max(0.01_f32);
+        let uv_area = self.pu.cross(self.pv).length();
+        if uv_area > 0.0_f32 {
+            self.uv_footprint = width / cos / uv_area.sqrt();
+        }
+    }
+}
+
+// How deep Instances can be nested, through prototypes that instance other
+// prototypes
+pub const MAX_INSTANCE_DEPTH: usize = 4;
+
+// Hit
+// Where along a ray a shape was hit, and for a hit on an Instance, the shape
+// of its prototype that was hit, then the shape of that one's prototype for
+// nested instances, and so on. For a hit on a CSG, it also keeps which child
+// was hit, one bit for each CSG from the outermost in, which runs out past
+// 64 nested CSGs. Shading takes the hit back, so shapes made of other shapes
+// never have to search them again for what was hit.
+#[derive(Clone, Copy)]
+pub struct Hit<'a> {
+    pub t: f32,
+    parts: [Option<&'a SyncShape>; MAX_INSTANCE_DEPTH],
+    sides: u64,
+}
+
+impl<'a> Hit<'a> {
+    pub fn new(t: f32) -> Hit<'a> {
+        Hit {
+            t: t,
+            parts: [None; MAX_INSTANCE_DEPTH],
+            sides: 0_u64,
+        }
+    }
+
+    // A hit on a shape of a prototype, seen from an Instance of it. Instance
+    // refuses prototypes nested too deep for the parts to hold.
+    pub fn through(part: &'a SyncShape, part_hit: Hit<'a>) -> Hit<'a> {
+        let mut parts = [None; MAX_INSTANCE_DEPTH];
+        parts[0] = Some(part);
+        parts[1..].copy_from_slice(&part_hit.parts[..MAX_INSTANCE_DEPTH - 1]);
+        Hit {
+            t: part_hit.t,
+            parts: parts,
+            sides: part_hit.sides,
+        }
+    }
+
+    // The shape of the prototype that was hit and the hit on it, for a hit
+    // made by Hit::through
+    pub fn part(&self) -> Option<(&'a SyncShape, Hit<'a>)> {
+        let part = self.parts[0]?;
+        let mut parts = [None; MAX_INSTANCE_DEPTH];
+        parts[..MAX_INSTANCE_DEPTH - 1].copy_from_slice(&self.parts[1..]);
+        return Some((
+            part,
+            Hit {
+                t: self.t,
+                parts: parts,
+                sides: self.sides,
+            },
+        ));
+    }
+
+    // A hit on the right child of a CSG or the left
+    pub fn on_child(right: bool, child_hit: Hit<'a>) -> Hit<'a> {
+        Hit {
+            t: child_hit.t,
+            parts: child_hit.parts,
+            sides: (child_hit.sides << 1) | right as u64,
+        }
+    }
+
+    // Whether a hit made by Hit::on_child was on the right child, and the
+    // hit on the child
+    pub fn child(&self) -> (bool, Hit<'a>) {
+        return (
+            self.sides & 1_u64 == 1_u64,
+            Hit {
+                t: self.t,
+                parts: self.parts,
+                sides: self.sides >> 1,
+            },
+        );
+    }
+
+    // The same hit moved along the ray, for shapes hit inside rather than
+    // on their boundary
+    pub fn at(&self, t: f32) -> Hit<'a> {
+        Hit {
+            t: t,
+            parts: self.parts,
+            sides: self.sides,
+        }
+    }
 }
 
 pub trait Shape {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32>;
-    fn get_hit_properties(&self, r: &Ray, t_hit: f32) -> HitProperties;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>>;
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties;
     fn get_material(&self) -> &Arc<SyncMaterial>;
     fn get_bounding_box(&self) -> AABB;
 
-    fn pdf(&self, r: &Ray) -> f32;
+    // Bounds of only the part of this shape that lies within the clip box,
+    // or None if no part of it does. Used when splitting large shapes during
+    // BVH construction. The default is conservative, shapes that can do
+    // better should.
+    fn get_clipped_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        AABB::intersection(&self.get_bounding_box(), clip)
+    }
+
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32;
     fn random_dir_towards(&self, from_origin: &Point3) -> Vector3;
+
+    // An optional area preserving mapping from the unit square onto the
+    // surface, which lets emitters be sampled in proportion to their texture.
+    // Returns the point and its texture coordinates for parameters (s, t).
+    fn surface_point(&self, _s: f32, _t: f32) -> Option<(Point3, TexCoord)> {
+        None
+    }
+
+    // The inverse of surface_point, for a point known to be on the surface
+    fn surface_parameters(&self, _p: &Point3) -> (f32, f32) {
+        (0.0_f32, 0.0_f32)
+    }
+
+    fn surface_area(&self) -> f32 {
+        0.0_f32
+    }
+
+    // Distance from the hit point to the nearest edge of the shape's
+    // tessellation, for the wireframe debug overlay. Shapes without edges
+    // return None.
+    fn edge_distance(&self, _r: &Ray, _hit: &Hit) -> Option<f32> {
+        None
+    }
+
+    // Whether the bounding box covers the whole shape, rather than standing
+    // in for a shape that goes on forever. Unbounded shapes are left out when
+    // measuring the size of the scene.
+    fn is_bounded(&self) -> bool {
+        true
+    }
+
+    // Whether the ray hit the front of the surface, the side its normal
+    // points out of before any flipping to face the ray. Used to find
+    // inverted meshes. Shapes that always give their outward normal can use
+    // the default.
+    fn is_front_face(&self, r: &Ray, hit: &Hit) -> bool {
+        r.dir.dot(self.get_hit_properties(r, hit).normal) < 0.0_f32
+    }
+
+    // How many Instances deep hits on the shape go, see Hit
+    fn instance_depth(&self) -> usize {
+        0
+    }
+
+    // Texture coordinates at the corners of a mesh triangle that was given
+    // them, for inspecting UV layouts. Other shapes return None.
+    fn corner_uvs(&self) -> Option<[TexCoord; 3]> {
+        None
+    }
 }
 pub type SyncShape = dyn Shape + Send + Sync;
 
@@ -62,63 +233,1034 @@ impl Sphere {
 
 const ONE_OVER_2_PI: f32 = 1.0_f32 / (2.0_f32 * f32::consts::PI);
 impl Shape for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let local_ray = &self.world_to_local * r;
+
+        let towards_origin = local_ray.origin - Point3::origin();
+        let a = local_ray.dir.dot(local_ray.dir);
+        let b = 2.0_f32 * towards_origin.dot(local_ray.dir);
+        let c = towards_origin.dot(towards_origin) - (self.radius * self.radius);
+        let discriminant = b * b - 4.0_f32 * a * c;
+
+        if discriminant > 0.0_f32 {
+            let mut t_hit = (-b - discriminant.sqrt()) / (2.0_f32 * a);
+            if t_hit >= t_max || t_hit <= t_min {
+                t_hit = (-b + discriminant.sqrt()) / (2.0_f32 * a);
+            }
+
+            if t_hit < t_max && t_hit > t_min {
+                return Some(Hit::new(t_hit));
+            }
+        }
+        return None;
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let t_hit = hit.t;
+        let local_ray = &self.world_to_local * r;
+        let mut hit_point = local_ray.point_at(t_hit);
+        hit_point = hit_point * (self.radius.abs() / (hit_point - Point3::origin()).length());
+
+        let theta = utils::clamp(hit_point.y() / self.radius, -1.0_f32, 1.0_f32).asin();
+        let inverse_y_radius = (self.radius.signum() * 1.0_f32)
+            / (hit_point.x() * hit_point.x() + hit_point.z() * hit_point.z()).sqrt();
+
+        let pu = Vector3::new(
+            2.0_f32 * f32::consts::PI * hit_point.z(),
+            0.0_f32,
+            -2.0_f32 * f32::consts::PI * hit_point.x(),
+        );
+        let pv = (-f32::consts::PI)
+            * Vector3::new(
+                hit_point.y() * hit_point.x() * inverse_y_radius,
+                (-self.radius) * theta.cos(),
+                hit_point.y() * hit_point.z() * inverse_y_radius,
+            );
+
+        HitProperties {
+            hit_point: r.point_at(t_hit),
+
+            normal: (&self.local_to_world
+                * ((local_ray.point_at(t_hit) - Point3::origin()) / self.radius))
+                .normalized(),
+
+            uv: TexCoord::new(
+                1.0_f32 - ((hit_point.z().atan2(hit_point.x()) + f32::consts::PI) * ONE_OVER_2_PI),
+                (theta + f32::consts::FRAC_PI_2) * f32::consts::FRAC_1_PI,
+            ),
+
+            pu: &self.local_to_world * pu,
+            pv: &self.local_to_world * pv,
+            uv_footprint: 0.0_f32,
+        }
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        &self.material
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        let local_min_in_world = &self.local_to_world * Point3::origin()
+            - Vector3::new(self.radius, self.radius, self.radius);
+        let local_max_in_world = &self.local_to_world * Point3::origin()
+            + Vector3::new(self.radius, self.radius, self.radius);
+
+        AABB::new(
+            Point3::min(local_min_in_world, local_max_in_world),
+            Point3::max(local_min_in_world, local_max_in_world),
+        )
+    }
+
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        match self.hit(r, t_min, utils::T_MAX) {
+            Some(_) => {}
+            None => return 0.0_f32,
+        };
+
+        let local_ray = &self.world_to_local * r;
+        let cos_theta_max = utils::float_max(
+            1.0_f32
+                - self.radius * self.radius
+                    / (Point3::origin() - local_ray.origin).squared_length(),
+            0.0_f32,
+        )
+        .sqrt();
+        let solid_angle = 2.0_f32 * f32::consts::PI * (1.0_f32 - cos_theta_max);
+        return 1.0_f32 / solid_angle;
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        let local_point = &self.world_to_local * (*from_origin);
+        let dir = Point3::origin() - local_point;
+        return &self.local_to_world
+            * sampling::OrthonormalBasis::new(&dir).local(&sampling::random_to_sphere(
+                self.radius,
+                dir.squared_length(),
+            ));
+    }
+}
+
+// Cuboid
+// A box, axis aligned in its local space between two corners, and placed in
+// the world by a transform. Rays can start inside one (as a ConstantMedium
+// boundary, for example), in which case they hit the face they leave by.
+pub struct Cuboid {
+    local_to_world: Matrix4,
+    world_to_local: Matrix4,
+    min: Point3,
+    max: Point3,
+    // Faces in world space, for sampling the box as a light
+    faces: [CuboidFace; 6],
+    material: Arc<SyncMaterial>,
+}
+
+// A face's corner at (u, v) = (0, 0), and its edges along u and v, which
+// are ordered so that their cross product points out of the box.
+struct CuboidFace {
+    corner: Point3,
+    u_edge: Vector3,
+    v_edge: Vector3,
+    normal: Vector3,
+    area: f32,
+}
+
+impl Copy for CuboidFace {}
+impl Clone for CuboidFace {
+    fn clone(&self) -> CuboidFace {
+        *self
+    }
+}
+
+// For each face, the axis it faces along and whether it is the max side,
+// followed by the axes (and directions) u and v run along on it
+const CUBOID_FACES: [(Axis, bool, Axis, bool, Axis, bool); 6] = [
+    (Axis::X, true, Axis::Z, false, Axis::Y, true),
+    (Axis::X, false, Axis::Z, true, Axis::Y, true),
+    (Axis::Y, true, Axis::X, true, Axis::Z, false),
+    (Axis::Y, false, Axis::X, true, Axis::Z, true),
+    (Axis::Z, true, Axis::X, true, Axis::Y, true),
+    (Axis::Z, false, Axis::X, false, Axis::Y, true),
+];
+
+impl Cuboid {
+    pub fn new(
+        local_to_world: &Matrix4,
+        min: Point3,
+        max: Point3,
+        mat: Arc<SyncMaterial>,
+    ) -> Result<Cuboid, &'static str> {
+        if min.x() >= max.x() || min.y() >= max.y() || min.z() >= max.z() {
+            return Err("Box min must be less than its max on every axis.");
+        }
+
+        let size = max - min;
+        let mut faces = [CuboidFace {
+            corner: Point3::origin(),
+            u_edge: Vector3::new_empty(),
+            v_edge: Vector3::new_empty(),
+            normal: Vector3::new_empty(),
+            area: 0.0_f32,
+        }; 6];
+        for (i, (axis, is_max, u_axis, u_positive, v_axis, v_positive)) in
+            CUBOID_FACES.iter().enumerate()
+        {
+            // The corner is where u and v are zero
+            let mut corner = [min.x(), min.y(), min.z()];
+            let mut u_edge = [0.0_f32; 3];
+            let mut v_edge = [0.0_f32; 3];
+            if *is_max {
+                corner[*axis as usize] = max[*axis];
+            }
+            if !*u_positive {
+                corner[*u_axis as usize] = max[*u_axis];
+            }
+            if !*v_positive {
+                corner[*v_axis as usize] = max[*v_axis];
+            }
+            u_edge[*u_axis as usize] = if *u_positive {
+                size[*u_axis]
+            } else {
+                -size[*u_axis]
+            };
+            v_edge[*v_axis as usize] = if *v_positive {
+                size[*v_axis]
+            } else {
+                -size[*v_axis]
+            };
+
+            let world_u_edge = local_to_world * Vector3::new(u_edge[0], u_edge[1], u_edge[2]);
+            let world_v_edge = local_to_world * Vector3::new(v_edge[0], v_edge[1], v_edge[2]);
+            let outward = world_u_edge.cross(world_v_edge);
+            faces[i] = CuboidFace {
+                corner: local_to_world * Point3::new(corner[0], corner[1], corner[2]),
+                u_edge: world_u_edge,
+                v_edge: world_v_edge,
+                normal: outward.normalized(),
+                area: outward.length(),
+            };
+        }
+
+        Ok(Cuboid {
+            local_to_world: local_to_world.clone(),
+            world_to_local: local_to_world.inverse()?,
+            min: min,
+            max: max,
+            faces: faces,
+            material: mat,
+        })
+    }
+
+    // Faces a point can see from outside, or all of them from inside
+    fn faces_seen_from(&self, p: &Point3) -> Vec<&CuboidFace> {
+        let outside: Vec<&CuboidFace> = self
+            .faces
+            .iter()
+            .filter(|f| f.normal.dot(*p - f.corner) > 0.0_f32)
+            .collect();
+        if outside.is_empty() {
+            return self.faces.iter().collect();
+        }
+        return outside;
+    }
+}
+
+impl Shape for Cuboid {
+    // Slab test in local space
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         let local_ray = &self.world_to_local * r;
 
-        let towards_origin = local_ray.origin - Point3::origin();
-        let a = local_ray.dir.dot(local_ray.dir);
-        let b = 2.0_f32 * towards_origin.dot(local_ray.dir);
-        let c = towards_origin.dot(towards_origin) - (self.radius * self.radius);
-        let discriminant = b * b - 4.0_f32 * a * c;
+        let mut t_near = -f32::MAX;
+        let mut t_far = f32::MAX;
+        for axis in [Axis::X, Axis::Y, Axis::Z].iter() {
+            let inverse_direction = 1.0_f32 / local_ray.dir[*axis];
+            let mut t0 = (self.min[*axis] - local_ray.origin[*axis]) * inverse_direction;
+            let mut t1 = (self.max[*axis] - local_ray.origin[*axis]) * inverse_direction;
+            if inverse_direction < 0.0_f32 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_near = utils::float_max(t_near, t0);
+            t_far = utils::float_min(t_far, t1);
+        }
+        if t_near > t_far {
+            return None;
+        }
+
+        if t_near < t_max && t_near > t_min {
+            return Some(Hit::new(t_near));
+        }
+        if t_far < t_max && t_far > t_min {
+            return Some(Hit::new(t_far));
+        }
+        return None;
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let t_hit = hit.t;
+        let local_ray = &self.world_to_local * r;
+        let hit_point = local_ray.point_at(t_hit);
+
+        // The face hit is the one the point is relatively closest to
+        let center = Point3::new(
+            (self.min.x() + self.max.x()) * 0.5_f32,
+            (self.min.y() + self.max.y()) * 0.5_f32,
+            (self.min.z() + self.max.z()) * 0.5_f32,
+        );
+        let size = self.max - self.min;
+        let mut face = 0_usize;
+        let mut closest = -1.0_f32;
+        for (i, (axis, is_max, _, _, _, _)) in CUBOID_FACES.iter().enumerate() {
+            let offset = (hit_point[*axis] - center[*axis]) / size[*axis];
+            let toward_face = if *is_max { offset } else { -offset };
+            if toward_face > closest {
+                closest = toward_face;
+                face = i;
+            }
+        }
+
+        let (axis, is_max, u_axis, u_positive, v_axis, v_positive) = CUBOID_FACES[face];
+        let u = (hit_point[u_axis] - self.min[u_axis]) / size[u_axis];
+        let v = (hit_point[v_axis] - self.min[v_axis]) / size[v_axis];
+        let mut local_normal = [0.0_f32; 3];
+        local_normal[axis as usize] = if is_max { 1.0_f32 } else { -1.0_f32 };
+
+        HitProperties {
+            hit_point: r.point_at(t_hit),
+            normal: (&self.local_to_world
+                * Vector3::new(local_normal[0], local_normal[1], local_normal[2]))
+            .normalized(),
+            uv: TexCoord::new(
+                if u_positive { u } else { 1.0_f32 - u },
+                if v_positive { v } else { 1.0_f32 - v },
+            ),
+            pu: self.faces[face].u_edge,
+            pv: self.faces[face].v_edge,
+            uv_footprint: 0.0_f32,
+        }
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        &self.material
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(-f32::MAX, -f32::MAX, -f32::MAX);
+        for i in 0..8 {
+            let corner = &self.local_to_world
+                * Point3::new(
+                    if i & 1 == 0 {
+                        self.min.x()
+                    } else {
+                        self.max.x()
+                    },
+                    if i & 2 == 0 {
+                        self.min.y()
+                    } else {
+                        self.max.y()
+                    },
+                    if i & 4 == 0 {
+                        self.min.z()
+                    } else {
+                        self.max.z()
+                    },
+                );
+            min = Point3::min(min, corner);
+            max = Point3::max(max, corner);
+        }
+        AABB::new(min, max)
+    }
+
+    // Points are sampled uniformly over the faces the origin can see, so a
+    // direction's density is that of the first face it hits
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        let hit = match self.hit(r, t_min, utils::T_MAX) {
+            Some(h) => h,
+            None => return 0.0_f32,
+        };
+        let hit_props = self.get_hit_properties(r, &hit);
+        let t_hit = hit.t;
+
+        let area: f32 = self.faces_seen_from(&r.origin).iter().map(|f| f.area).sum();
+        let dist_squared = t_hit * t_hit * r.dir.squared_length();
+        let cosine = (r.dir.dot(hit_props.normal) / r.dir.length()).abs();
+        return dist_squared / (cosine * area);
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        let faces = self.faces_seen_from(from_origin);
+        let area: f32 = faces.iter().map(|f| f.area).sum();
+        let mut pick = rng::random() * area;
+        let mut face = faces[faces.len() - 1_usize];
+        for f in &faces {
+            if pick < f.area {
+                face = f;
+                break;
+            }
+            pick -= f.area;
+        }
+        let point = face.corner + face.u_edge * rng::random() + face.v_edge * rng::random();
+        return point - *from_origin;
+    }
+
+    fn surface_area(&self) -> f32 {
+        return self.faces.iter().map(|f| f.area).sum();
+    }
+}
+
+// Infinite planes are bounded by a square this far across in each direction,
+// which keeps the BVH's arithmetic finite. Hits any further away are missed.
+const UNBOUNDED_EXTENT: f32 = 1000000.0_f32;
+
+// Unit vectors across a plane with the given unit normal, ordered so that
+// their cross product is the normal
+fn plane_axes(normal: &Vector3) -> (Vector3, Vector3) {
+    let u = sampling::OrthonormalBasis::new(normal).local(&Vector3::new(1.0_f32, 0.0_f32, 0.0_f32));
+    return (u, normal.cross(u));
+}
+
+fn plane_hit(point: &Point3, normal: &Vector3, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+    let speed = normal.dot(r.dir);
+    if speed > -f32::EPSILON && speed < f32::EPSILON {
+        return None; // Parallel to the plane
+    }
+    let t_hit = normal.dot(*point - r.origin) / speed;
+    if t_hit < t_max && t_hit > t_min {
+        return Some(t_hit);
+    }
+    return None;
+}
+
+// Bounds of a rectangle centered on a point and spanned by two vectors
+fn rectangle_bounding_box(center: &Point3, half_u: Vector3, half_v: Vector3) -> AABB {
+    let corners = [
+        *center - half_u - half_v,
+        *center + half_u - half_v,
+        *center - half_u + half_v,
+        *center + half_u + half_v,
+    ];
+    let mut min = corners[0];
+    let mut max = corners[0];
+    for c in &corners[1..] {
+        min = Point3::min(min, *c);
+        max = Point3::max(max, *c);
+    }
+    return AABB::new(min, max);
+}
+
+// Plane
+// A plane through a point, facing along its normal. Without an extent it is
+// infinite, and textures repeat across it every uv_scale units. With one it
+// is a rectangle of that width and height centered on the point, and
+// textures are stretched across it once. Both sides can be hit.
+pub struct Plane {
+    point: Point3,
+    normal: Vector3,
+    u: Vector3,
+    v: Vector3,
+    extent: Option<(f32, f32)>,
+    uv_scale: f32,
+    material: Arc<SyncMaterial>,
+}
+
+impl Plane {
+    pub fn new(
+        point: Point3,
+        normal: Vector3,
+        extent: Option<(f32, f32)>,
+        uv_scale: f32,
+        mat: Arc<SyncMaterial>,
+    ) -> Result<Plane, &'static str> {
+        if normal.squared_length() == 0.0_f32 {
+            return Err("Plane normal can not be zero.");
+        }
+        match extent {
+            Some((width, height)) if width <= 0.0_f32 || height <= 0.0_f32 => {
+                return Err("Plane extent must be positive.")
+            }
+            _ => {}
+        }
+        if uv_scale <= 0.0_f32 {
+            return Err("Plane uv_scale must be positive.");
+        }
+
+        let unit_normal = normal.normalized();
+        let (u, v) = plane_axes(&unit_normal);
+        Ok(Plane {
+            point: point,
+            normal: unit_normal,
+            u: u,
+            v: v,
+            extent: extent,
+            uv_scale: uv_scale,
+            material: mat,
+        })
+    }
+}
+
+impl Shape for Plane {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let t_hit = plane_hit(&self.point, &self.normal, r, t_min, t_max)?;
+        match self.extent {
+            Some((width, height)) => {
+                let offset = r.point_at(t_hit) - self.point;
+                if offset.dot(self.u).abs() > width * 0.5_f32
+                    || offset.dot(self.v).abs() > height * 0.5_f32
+                {
+                    return None;
+                }
+            }
+            None => {}
+        }
+        return Some(Hit::new(t_hit));
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let t_hit = hit.t;
+        let hit_point = r.point_at(t_hit);
+        let offset = hit_point - self.point;
+        let x = offset.dot(self.u);
+        let y = offset.dot(self.v);
+
+        let (uv, mut pu, pv) = match self.extent {
+            Some((width, height)) => (
+                TexCoord::new(x / width + 0.5_f32, y / height + 0.5_f32),
+                self.u * width,
+                self.v * height,
+            ),
+            None => {
+                let s = x / self.uv_scale;
+                let t = y / self.uv_scale;
+                (
+                    TexCoord::new(s - s.floor(), t - t.floor()),
+                    self.u * self.uv_scale,
+                    self.v * self.uv_scale,
+                )
+            }
+        };
+
+        // Face the ray, like double sided triangles
+        let mut normal = self.normal;
+        if r.dir.dot(normal) > 0.0_f32 {
+            normal = -normal;
+            pu = -pu;
+        }
+
+        HitProperties {
+            hit_point: hit_point,
+            normal: normal,
+            uv: uv,
+            pu: pu,
+            pv: pv,
+            uv_footprint: 0.0_f32,
+        }
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        &self.material
+    }
+
+    fn get_bounding_box(&self) -> AABB {
+        let (half_width, half_height) = match self.extent {
+            Some((width, height)) => (width * 0.5_f32, height * 0.5_f32),
+            None => (UNBOUNDED_EXTENT, UNBOUNDED_EXTENT),
+        };
+        return rectangle_bounding_box(&self.point, self.u * half_width, self.v * half_height);
+    }
+
+    // Rectangles are sampled by area. An infinite plane fills the half of
+    // every direction facing it, which is sampled by the cosine to the normal.
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        let t_hit = match self.hit(r, t_min, utils::T_MAX) {
+            Some(h) => h.t,
+            None => return 0.0_f32,
+        };
+        let cosine = (r.dir.dot(self.normal) / r.dir.length()).abs();
+        match self.extent {
+            Some(_) => {
+                let dist_squared = t_hit * t_hit * r.dir.squared_length();
+                return dist_squared / (cosine * self.surface_area());
+            }
+            None => return cosine * f32::consts::FRAC_1_PI,
+        }
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        match self.extent {
+            Some((width, height)) => {
+                let point = self.point
+                    + self.u * ((rng::random() - 0.5_f32) * width)
+                    + self.v * ((rng::random() - 0.5_f32) * height);
+                return point - *from_origin;
+            }
+            None => {
+                let toward = if self.normal.dot(self.point - *from_origin) > 0.0_f32 {
+                    self.normal
+                } else {
+                    -self.normal
+                };
+                return sampling::OrthonormalBasis::new(&toward)
+                    .local(&sampling::random_cosine_direction());
+            }
+        }
+    }
+
+    fn surface_point(&self, s: f32, t: f32) -> Option<(Point3, TexCoord)> {
+        let (width, height) = self.extent?;
+        let point =
+            self.point + self.u * ((s - 0.5_f32) * width) + self.v * ((t - 0.5_f32) * height);
+        return Some((point, TexCoord::new(s, t)));
+    }
+
+    fn surface_parameters(&self, p: &Point3) -> (f32, f32) {
+        match self.extent {
+            Some((width, height)) => {
+                let offset = *p - self.point;
+                return (
+                    utils::clamp(offset.dot(self.u) / width + 0.5_f32, 0.0_f32, 1.0_f32),
+                    utils::clamp(offset.dot(self.v) / height + 0.5_f32, 0.0_f32, 1.0_f32),
+                );
+            }
+            None => return (0.0_f32, 0.0_f32),
+        }
+    }
+
+    fn surface_area(&self) -> f32 {
+        match self.extent {
+            Some((width, height)) => return width * height,
+            None => return 0.0_f32,
+        }
+    }
+
+    fn is_bounded(&self) -> bool {
+        return self.extent.is_some();
+    }
+
+    fn is_front_face(&self, r: &Ray, _hit: &Hit) -> bool {
+        return r.dir.dot(self.normal) < 0.0_f32;
+    }
+}
+
+// Disk
+// A flat disk around a center point, facing along its normal. Textures are
+// mapped across the square the disk fits in. Both sides can be hit.
+pub struct Disk {
+    center: Point3,
+    normal: Vector3,
+    u: Vector3,
+    v: Vector3,
+    radius: f32,
+    material: Arc<SyncMaterial>,
+}
+
+impl Disk {
+    pub fn new(
+        center: Point3,
+        normal: Vector3,
+        radius: f32,
+        mat: Arc<SyncMaterial>,
+    ) -> Result<Disk, &'static str> {
+        if normal.squared_length() == 0.0_f32 {
+            return Err("Disk normal can not be zero.");
+        }
+        if radius <= 0.0_f32 {
+            return Err("Disk radius must be positive.");
+        }
+
+        let unit_normal = normal.normalized();
+        let (u, v) = plane_axes(&unit_normal);
+        Ok(Disk {
+            center: center,
+            normal: unit_normal,
+            u: u,
+            v: v,
+            radius: radius,
+            material: mat,
+        })
+    }
+
+    // Area preserving polar mapping, with s picking the squared distance from
+    // the center and t the angle around it
+    fn disk_point(&self, s: f32, t: f32) -> (Point3, TexCoord) {
+        let distance = self.radius * s.sqrt();
+        let angle = t * 2.0_f32 * f32::consts::PI - f32::consts::PI;
+        let x = distance * angle.cos();
+        let y = distance * angle.sin();
+        let diameter = 2.0_f32 * self.radius;
+        return (
+            self.center + self.u * x + self.v * y,
+            TexCoord::new(x / diameter + 0.5_f32, y / diameter + 0.5_f32),
+        );
+    }
+}
+
+impl Shape for Disk {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let t_hit = plane_hit(&self.center, &self.normal, r, t_min, t_max)?;
+        if (r.point_at(t_hit) - self.center).squared_length() > self.radius * self.radius {
+            return None;
+        }
+        return Some(Hit::new(t_hit));
+    }
+
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let t_hit = hit.t;
+        let hit_point = r.point_at(t_hit);
+        let offset = hit_point - self.center;
+        let diameter = 2.0_f32 * self.radius;
+
+        let mut normal = self.normal;
+        let mut pu = self.u * diameter;
+        if r.dir.dot(normal) > 0.0_f32 {
+            normal = -normal;
+            pu = -pu;
+        }
+
+        HitProperties {
+            hit_point: hit_point,
+            normal: normal,
+            uv: TexCoord::new(
+                offset.dot(self.u) / diameter + 0.5_f32,
+                offset.dot(self.v) / diameter + 0.5_f32,
+            ),
+            pu: pu,
+            pv: self.v * diameter,
+            uv_footprint: 0.0_f32,
+        }
+    }
+
+    fn get_material(&self) -> &Arc<SyncMaterial> {
+        &self.material
+    }
+
+    // The disk reaches out along each axis by the radius, scaled by how
+    // much the axis lies within the disk's plane
+    fn get_bounding_box(&self) -> AABB {
+        let reach = Vector3::new(
+            self.radius
+                * utils::float_max(1.0_f32 - self.normal.x() * self.normal.x(), 0.0_f32).sqrt(),
+            self.radius
+                * utils::float_max(1.0_f32 - self.normal.y() * self.normal.y(), 0.0_f32).sqrt(),
+            self.radius
+                * utils::float_max(1.0_f32 - self.normal.z() * self.normal.z(), 0.0_f32).sqrt(),
+        );
+        return AABB::new(self.center - reach, self.center + reach);
+    }
+
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        let t_hit = match self.hit(r, t_min, utils::T_MAX) {
+            Some(h) => h.t,
+            None => return 0.0_f32,
+        };
+        let dist_squared = t_hit * t_hit * r.dir.squared_length();
+        let cosine = (r.dir.dot(self.normal) / r.dir.length()).abs();
+        return dist_squared / (cosine * self.surface_area());
+    }
+
+    fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
+        let (point, _) = self.disk_point(rng::random(), rng::random());
+        return point - *from_origin;
+    }
+
+    fn surface_point(&self, s: f32, t: f32) -> Option<(Point3, TexCoord)> {
+        return Some(self.disk_point(s, t));
+    }
+
+    fn surface_parameters(&self, p: &Point3) -> (f32, f32) {
+        let offset = *p - self.center;
+        let x = offset.dot(self.u);
+        let y = offset.dot(self.v);
+        let s = (x * x + y * y) / (self.radius * self.radius);
+        let t = (y.atan2(x) + f32::consts::PI) * ONE_OVER_2_PI;
+        return (
+            utils::clamp(s, 0.0_f32, 1.0_f32),
+            utils::clamp(t, 0.0_f32, 1.0_f32),
+        );
+    }
+
+    fn surface_area(&self) -> f32 {
+        return f32::consts::PI * self.radius * self.radius;
+    }
+
+    fn is_front_face(&self, r: &Ray, _hit: &Hit) -> bool {
+        return r.dir.dot(self.normal) < 0.0_f32;
+    }
+}
+
+// Relative size of a negative discriminant still treated as a tangent hit
+const TANGENT_EPSILON: f64 = 0.00001_f64;
+
+// Frustum
+// A cone cut off parallel to its base, around the local Y axis from the
+// bottom radius at y = 0 to the top radius at y = height, and placed in the
+// world by a transform. Cylinders have equal radii and cones have a top
+// radius of zero. Optional caps close off the ends.
+pub struct Frustum {
+    local_to_world: Matrix4,
+    world_to_local: Matrix4,
+    bottom_radius: f32,
+    top_radius: f32,
+    height: f32,
+    caps: bool,
+    material: Arc<SyncMaterial>,
+}
+
+enum FrustumPart {
+    Side,
+    Bottom,
+    Top,
+}
+
+impl Copy for FrustumPart {}
+impl Clone for FrustumPart {
+    fn clone(&self) -> FrustumPart {
+        *self
+    }
+}
+
+impl Frustum {
+    pub fn cylinder(
+        local_to_world: &Matrix4,
+        radius: f32,
+        height: f32,
+        caps: bool,
+        mat: Arc<SyncMaterial>,
+    ) -> Result<Frustum, &'static str> {
+        return Frustum::new(local_to_world, radius, radius, height, caps, mat);
+    }
+
+    pub fn cone(
+        local_to_world: &Matrix4,
+        radius: f32,
+        height: f32,
+        caps: bool,
+        mat: Arc<SyncMaterial>,
+    ) -> Result<Frustum, &'static str> {
+        return Frustum::new(local_to_world, radius, 0.0_f32, height, caps, mat);
+    }
+
+    fn new(
+        local_to_world: &Matrix4,
+        bottom_radius: f32,
+        top_radius: f32,
+        height: f32,
+        caps: bool,
+        mat: Arc<SyncMaterial>,
+    ) -> Result<Frustum, &'static str> {
+        if bottom_radius <= 0.0_f32 || height <= 0.0_f32 {
+            return Err("Cylinder and Cone radius and height must be positive.");
+        }
+        Ok(Frustum {
+            local_to_world: local_to_world.clone(),
+            world_to_local: local_to_world.inverse()?,
+            bottom_radius: bottom_radius,
+            top_radius: top_radius,
+            height: height,
+            caps: caps,
+            material: mat,
+        })
+    }
+
+    fn radius_at(&self, y: f32) -> f32 {
+        return utils::lerp(y / self.height, self.bottom_radius, self.top_radius);
+    }
+
+    // Times the local ray crosses the surface, in no particular order. There
+    // are at most two, as the shape is convex, but an unlucky ray through an
+    // edge may be counted by both parts there.
+    fn local_hits(&self, r: &Ray) -> ([f32; 4], usize) {
+        let mut hits = [0.0_f32; 4];
+        let mut count = 0_usize;
+
+        // Side, where x^2 + z^2 = radius_at(y)^2. Solved in double precision,
+        // as rays toward points near the silhouette, which light sampling
+        // picks often, are nearly tangent and lose the hit to cancellation.
+        let slope = ((self.top_radius - self.bottom_radius) / self.height) as f64;
+        let (ox, oy, oz) = (
+            r.origin.x() as f64,
+            r.origin.y() as f64,
+            r.origin.z() as f64,
+        );
+        let (dx, dy, dz) = (r.dir.x() as f64, r.dir.y() as f64, r.dir.z() as f64);
+        let radius_start = self.bottom_radius as f64 + slope * oy;
+        let radius_speed = slope * dy;
+        let a = dx * dx + dz * dz - radius_speed * radius_speed;
+        let b = 2.0_f64 * (ox * dx + oz * dz - radius_start * radius_speed);
+        let c = ox * ox + oz * oz - radius_start * radius_start;
+        let mut side_hits = [f32::NAN; 2];
+        if a.abs() > std::f64::EPSILON * (dx * dx + dy * dy + dz * dz) {
+            let mut discriminant = b * b - 4.0_f64 * a * c;
+            // Just touching the side, within rounding
+            if discriminant < 0.0_f64 && discriminant > -TANGENT_EPSILON * b * b {
+                discriminant = 0.0_f64;
+            }
+            if discriminant >= 0.0_f64 {
+                let root = discriminant.sqrt();
+                side_hits = [
+                    ((-b - root) / (2.0_f64 * a)) as f32,
+                    ((-b + root) / (2.0_f64 * a)) as f32,
+                ];
+            }
+        } else if b.abs() > 0.0_f64 {
+            // Along the slope of a cone, so only one crossing
+            side_hits[0] = (-c / b) as f32;
+        }
+        for t in side_hits.iter() {
+            if t.is_nan() {
+                continue;
+            }
+            let y = r.origin.y() + r.dir.y() * t;
+            // Also rules out the mirrored cone beyond the apex
+            if y >= 0.0_f32 && y <= self.height && self.radius_at(y) >= 0.0_f32 {
+                hits[count] = *t;
+                count += 1;
+            }
+        }
+
+        if self.caps && r.dir.y() != 0.0_f32 {
+            for (y, radius) in [
+                (0.0_f32, self.bottom_radius),
+                (self.height, self.top_radius),
+            ]
+            .iter()
+            {
+                let t = (y - r.origin.y()) / r.dir.y();
+                let x = r.origin.x() + r.dir.x() * t;
+                let z = r.origin.z() + r.dir.z() * t;
+                if x * x + z * z <= radius * radius {
+                    hits[count] = t;
+                    count += 1;
+                }
+            }
+        }
+        return (hits, count);
+    }
 
-        if discriminant > 0.0_f32 {
-            let mut t_hit = (-b - discriminant.sqrt()) / (2.0_f32 * a);
-            if t_hit >= t_max || t_hit <= t_min {
-                t_hit = (-b + discriminant.sqrt()) / (2.0_f32 * a);
+    // The part a local point on the surface is closest to
+    fn part_at(&self, p: &Point3) -> FrustumPart {
+        let side_distance = ((p.x() * p.x() + p.z() * p.z()).sqrt() - self.radius_at(p.y())).abs();
+        if self.caps {
+            if p.y().abs() < side_distance && p.y().abs() <= (self.height - p.y()).abs() {
+                return FrustumPart::Bottom;
+            }
+            // A cone's top is only a point
+            if self.top_radius > 0.0_f32 && (self.height - p.y()).abs() < side_distance {
+                return FrustumPart::Top;
             }
+        }
+        return FrustumPart::Side;
+    }
 
-            if t_hit < t_max && t_hit > t_min {
-                return Some(t_hit);
+    // Texture coordinates and their partial derivatives at a local point.
+    // The cross product of the derivatives points out of the shape.
+    fn local_parameterization(
+        &self,
+        p: &Point3,
+        part: FrustumPart,
+    ) -> (TexCoord, Vector3, Vector3) {
+        match part {
+            FrustumPart::Side => {
+                let radius = self.radius_at(p.y());
+                let slope_outward = if radius > 0.0_f32 {
+                    (self.top_radius - self.bottom_radius) / radius
+                } else {
+                    0.0_f32
+                };
+                let mut pu = 2.0_f32 * f32::consts::PI * Vector3::new(p.z(), 0.0_f32, -p.x());
+                if radius <= 0.0_f32 {
+                    // Every direction around the apex is as good as another
+                    pu = Vector3::new(1.0_f32, 0.0_f32, 0.0_f32);
+                }
+                return (
+                    TexCoord::new(
+                        1.0_f32 - ((p.z().atan2(p.x()) + f32::consts::PI) * ONE_OVER_2_PI),
+                        p.y() / self.height,
+                    ),
+                    pu,
+                    Vector3::new(p.x() * slope_outward, self.height, p.z() * slope_outward),
+                );
+            }
+            FrustumPart::Bottom | FrustumPart::Top => {
+                let (radius, flip) = match part {
+                    FrustumPart::Top => (self.top_radius, -1.0_f32),
+                    _ => (self.bottom_radius, 1.0_f32),
+                };
+                let diameter = 2.0_f32 * radius;
+                return (
+                    TexCoord::new(
+                        p.x() / diameter + 0.5_f32,
+                        flip * p.z() / diameter + 0.5_f32,
+                    ),
+                    Vector3::new(diameter, 0.0_f32, 0.0_f32),
+                    Vector3::new(0.0_f32, 0.0_f32, flip * diameter),
+                );
             }
         }
-        return None;
     }
 
-    fn get_hit_properties(&self, r: &Ray, t_hit: f32) -> HitProperties {
+    fn local_areas(&self) -> (f32, f32, f32) {
+        let slant = ((self.top_radius - self.bottom_radius)
+            * (self.top_radius - self.bottom_radius)
+            + self.height * self.height)
+            .sqrt();
+        let side = f32::consts::PI * (self.bottom_radius + self.top_radius) * slant;
+        if !self.caps {
+            return (side, 0.0_f32, 0.0_f32);
+        }
+        return (
+            side,
+            f32::consts::PI * self.bottom_radius * self.bottom_radius,
+            f32::consts::PI * self.top_radius * self.top_radius,
+        );
+    }
+
+    // Probability density per unit of world area at a local point, when
+    // sampling uniformly by local area. The transform may stretch some parts
+    // of the surface more than others.
+    fn world_area_pdf(&self, p: &Point3) -> f32 {
+        let (side, bottom, top) = self.local_areas();
+        let (_, pu, pv) = self.local_parameterization(p, self.part_at(p));
+        let local_area = pu.cross(pv).length();
+        let world_area = (&self.local_to_world * pu)
+            .cross(&self.local_to_world * pv)
+            .length();
+        if world_area <= 0.0_f32 {
+            return 0.0_f32;
+        }
+        return local_area / (world_area * (side + bottom + top));
+    }
+}
+
+impl Shape for Frustum {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         let local_ray = &self.world_to_local * r;
-        let mut hit_point = local_ray.point_at(t_hit);
-        hit_point = hit_point * (self.radius.abs() / (hit_point - Point3::origin()).length());
+        let (hits, count) = self.local_hits(&local_ray);
 
-        let theta = utils::clamp(hit_point.y() / self.radius, -1.0_f32, 1.0_f32).asin();
-        let inverse_y_radius = (self.radius.signum() * 1.0_f32)
-            / (hit_point.x() * hit_point.x() + hit_point.z() * hit_point.z()).sqrt();
+        let mut closest = None;
+        let mut closest_t = t_max;
+        for t in &hits[..count] {
+            if *t < closest_t && *t > t_min {
+                closest_t = *t;
+                closest = Some(*t);
+            }
+        }
+        return closest.map(Hit::new);
+    }
 
-        let pu = Vector3::new(
-            2.0_f32 * f32::consts::PI * hit_point.z(),
-            0.0_f32,
-            -2.0_f32 * f32::consts::PI * hit_point.x(),
-        );
-        let pv = (-f32::consts::PI)
-            * Vector3::new(
-                hit_point.y() * hit_point.x() * inverse_y_radius,
-                (-self.radius) * theta.cos(),
-                hit_point.y() * hit_point.z() * inverse_y_radius,
-            );
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let t_hit = hit.t;
+        let local_ray = &self.world_to_local * r;
+        let local_point = local_ray.point_at(t_hit);
+        let (uv, pu, pv) = self.local_parameterization(&local_point, self.part_at(&local_point));
+        let world_pu = &self.local_to_world * pu;
+        let world_pv = &self.local_to_world * pv;
 
         HitProperties {
             hit_point: r.point_at(t_hit),
-
-            normal: (&self.local_to_world
-                * ((local_ray.point_at(t_hit) - Point3::origin()) / self.radius))
-                .normalized(),
-
-            uv: TexCoord::new(
-                1.0_f32 - ((hit_point.z().atan2(hit_point.x()) + f32::consts::PI) * ONE_OVER_2_PI),
-                (theta + f32::consts::FRAC_PI_2) * f32::consts::FRAC_1_PI,
-            ),
-
-            pu: &self.local_to_world * pu,
-            pv: &self.local_to_world * pv,
+            normal: world_pu.cross(world_pv).normalized(),
+            uv: uv,
+            pu: world_pu,
+            pv: world_pv,
+            uv_footprint: 0.0_f32,
         }
     }
 
@@ -127,63 +1269,171 @@ impl Shape for Sphere {
     }
 
     fn get_bounding_box(&self) -> AABB {
-        let local_min_in_world = &self.local_to_world * Point3::origin()
-            - Vector3::new(self.radius, self.radius, self.radius);
-        let local_max_in_world = &self.local_to_world * Point3::origin()
-            + Vector3::new(self.radius, self.radius, self.radius);
-
-        AABB::new(
-            Point3::min(local_min_in_world, local_max_in_world),
-            Point3::max(local_min_in_world, local_max_in_world),
-        )
+        let radius = utils::float_max(self.bottom_radius, self.top_radius);
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(-f32::MAX, -f32::MAX, -f32::MAX);
+        for i in 0..8 {
+            let corner = &self.local_to_world
+                * Point3::new(
+                    if i & 1 == 0 { -radius } else { radius },
+                    if i & 2 == 0 { 0.0_f32 } else { self.height },
+                    if i & 4 == 0 { -radius } else { radius },
+                );
+            min = Point3::min(min, corner);
+            max = Point3::max(max, corner);
+        }
+        AABB::new(min, max)
     }
 
-    fn pdf(&self, r: &Ray) -> f32 {
-        match self.hit(r, utils::T_MIN, utils::T_MAX) {
-            Some(_) => {}
-            None => return 0.0_f32,
-        };
-
+    // Points are sampled over the whole surface, so a direction may have
+    // come from either of the points the ray passes through
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
         let local_ray = &self.world_to_local * r;
-        let cos_theta_max = utils::float_max(
-            1.0_f32
-                - self.radius * self.radius
-                    / (Point3::origin() - local_ray.origin).squared_length(),
-            0.0_f32,
-        )
-        .sqrt();
-        let solid_angle = 2.0_f32 * f32::consts::PI * (1.0_f32 - cos_theta_max);
-        return 1.0_f32 / solid_angle;
+        let (hits, count) = self.local_hits(&local_ray);
+
+        let mut total = 0.0_f32;
+        for t in &hits[..count] {
+            if *t <= t_min {
+                continue;
+            }
+            let local_point = local_ray.point_at(*t);
+            let (_, pu, pv) = self.local_parameterization(&local_point, self.part_at(&local_point));
+            let normal = (&self.local_to_world * pu)
+                .cross(&self.local_to_world * pv)
+                .normalized();
+            let dist_squared = t * t * r.dir.squared_length();
+            let cosine = (r.dir.dot(normal) / r.dir.length()).abs();
+            if cosine > 0.0_f32 {
+                total += self.world_area_pdf(&local_point) * dist_squared / cosine;
+            }
+        }
+        return total;
     }
 
     fn random_dir_towards(&self, from_origin: &Point3) -> Vector3 {
-        let local_point = &self.world_to_local * (*from_origin);
-        let dir = Point3::origin() - local_point;
-        return &self.local_to_world
-            * utils::OrthonormalBasis::new(&dir)
-                .local(&utils::random_to_sphere(self.radius, dir.squared_length()));
+        let (side, bottom, top) = self.local_areas();
+        let pick = rng::random() * (side + bottom + top);
+        let angle = rng::random() * 2.0_f32 * f32::consts::PI;
+        let r = rng::random();
+
+        let (radius, y) = if pick < side {
+            // The side's area grows with its radius, which changes linearly
+            // with height
+            let bottom_squared = self.bottom_radius * self.bottom_radius;
+            let top_squared = self.top_radius * self.top_radius;
+            if (self.top_radius - self.bottom_radius).abs() <= f32::EPSILON {
+                (self.bottom_radius, r * self.height)
+            } else {
+                let radius = (bottom_squared + r * (top_squared - bottom_squared)).sqrt();
+                (
+                    radius,
+                    (radius - self.bottom_radius) / (self.top_radius - self.bottom_radius)
+                        * self.height,
+                )
+            }
+        } else if pick < side + bottom {
+            (self.bottom_radius * r.sqrt(), 0.0_f32)
+        } else {
+            (self.top_radius * r.sqrt(), self.height)
+        };
+
+        let point =
+            &self.local_to_world * Point3::new(radius * angle.cos(), y, radius * angle.sin());
+        return point - *from_origin;
     }
 }
 
+// One corner of a face, as indices into the mesh's vertices and texture
+// coordinates
+pub type MeshCorner = (usize, Option<usize>);
+
 pub struct TriangleMesh {
     vertices: Vec<Point3>,
     tex_coords: Vec<TexCoord>,
+    // Partial derivatives at each corner, averaged over the faces that share
+    // it. Corners are told apart by texture coordinate as well as vertex, so
+    // the tangents split along UV seams like MikkTSpace's do.
+    tangents: HashMap<MeshCorner, (Vector3, Vector3)>,
     enable_backface_culling: bool,
-    material: Arc<SyncMaterial>,
+    // Each Triangle picks one of these by index, so groups of faces can have
+    // their own material while sharing vertices
+    materials: Vec<Arc<SyncMaterial>>,
 }
 
 impl TriangleMesh {
     pub fn new(
         vertices: Vec<Point3>,
         tex_coords: Vec<TexCoord>,
+        faces: &Vec<[MeshCorner; 3]>,
         enable_backface_culling: bool,
-        material: Arc<SyncMaterial>,
+        materials: Vec<Arc<SyncMaterial>>,
     ) -> TriangleMesh {
-        TriangleMesh {
+        let mut mesh = TriangleMesh {
             vertices: vertices,
             tex_coords: tex_coords,
+            tangents: HashMap::new(),
             enable_backface_culling: enable_backface_culling,
-            material: material,
+            materials: materials,
+        };
+        mesh.generate_tangents(faces);
+        return mesh;
+    }
+
+    // Each face adds its partial derivatives to its corners, weighted by the
+    // angle at the corner so that splitting a face does not change the
+    // result. Faces with bad indices are skipped here and reported when the
+    // Triangle is made. Faces without texture coordinates of their own, or
+    // with degenerate ones, keep their own flat derivatives.
+    fn generate_tangents(&mut self, faces: &Vec<[MeshCorner; 3]>) {
+        let mut sums: HashMap<MeshCorner, (Vector3, Vector3, f32)> = HashMap::new();
+        for face in faces {
+            let in_range = face.iter().all(|(v, t)| {
+                *v < self.vertices.len()
+                    && match t {
+                        Some(t) => *t < self.tex_coords.len(),
+                        None => false,
+                    }
+            });
+            if !in_range {
+                continue;
+            }
+            let points = [
+                self.vertices[face[0].0],
+                self.vertices[face[1].0],
+                self.vertices[face[2].0],
+            ];
+            let (uv0, uv1, uv2) = self.get_uvs(face[0].1, face[1].1, face[2].1);
+            let (pu, pv) = match uv_derivatives(&points, &[uv0, uv1, uv2]) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            for i in 0..3 {
+                let to_next = points[(i + 1) % 3] - points[i];
+                let to_prev = points[(i + 2) % 3] - points[i];
+                let lengths = to_next.length() * to_prev.length();
+                if lengths == 0.0_f32 {
+                    continue;
+                }
+                let cos_angle = utils::clamp(to_next.dot(to_prev) / lengths, -1.0_f32, 1.0_f32);
+                let angle = cos_angle.acos();
+
+                let sum = sums.entry(face[i]).or_insert((
+                    Vector3::new_empty(),
+                    Vector3::new_empty(),
+                    0.0_f32,
+                ));
+                sum.0 = sum.0 + angle * pu;
+                sum.1 = sum.1 + angle * pv;
+                sum.2 += angle;
+            }
+        }
+
+        for (corner, (pu_sum, pv_sum, weight)) in sums {
+            if weight > 0.0_f32 {
+                self.tangents
+                    .insert(corner, (pu_sum / weight, pv_sum / weight));
+            }
         }
     }
 
@@ -218,11 +1468,87 @@ pub struct Triangle {
     t0: Option<usize>,
     t1: Option<usize>,
     t2: Option<usize>,
-    pu: Vector3,
-    pv: Vector3,
+    material: usize,
+    // Partial derivatives at each corner, in the same order as the vertices
+    corner_pu: [Vector3; 3],
+    corner_pv: [Vector3; 3],
+}
+
+// Partial derivatives of position with respect to texture coordinates across
+// a flat face, or None if the texture coordinates do not span an area.
+fn uv_derivatives(points: &[Point3; 3], uvs: &[TexCoord; 3]) -> Option<(Vector3, Vector3)> {
+    let duv02 = uvs[0] - uvs[2];
+    let duv12 = uvs[1] - uvs[2];
+    let dp02 = points[0] - points[2];
+    let dp12 = points[1] - points[2];
+    let uv_determinant = duv02.u() * duv12.v() - duv02.v() * duv12.u();
+    if uv_determinant.abs() < std::f32::EPSILON {
+        return None;
+    }
+    let inv_det = 1.0_f32 / uv_determinant;
+    let pu = (duv12.v() * dp02 - duv02.v() * dp12) * inv_det;
+    let pv = (-duv12.u() * dp02 + duv02.u() * dp12) * inv_det;
+    if pu.cross(pv).squared_length() == 0.0_f32 {
+        return None;
+    }
+    return Some((pu, pv));
 }
 
 impl Triangle {
+    // Uses Moller-Trumbore ray-triangle intersection algorithm.
+    // https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+    //
+    // Backface culling expects a counter-clockwise winding order.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32, cull: bool) -> Option<f32> {
+        let vertex0 = self.triangle_mesh.vertices[self.v0];
+        let vertex1 = self.triangle_mesh.vertices[self.v1];
+        let vertex2 = self.triangle_mesh.vertices[self.v2];
+
+        let edge_1 = vertex1 - vertex0;
+        let edge_2 = vertex2 - vertex0;
+        let p_vec = r.dir.cross(edge_2);
+        let determinant = edge_1.dot(p_vec);
+
+        if !cull && determinant > -std::f32::EPSILON && determinant < std::f32::EPSILON {
+            return None; // Indicates parallel ray and triangle
+        } else if cull && determinant < std::f32::EPSILON {
+            return None; // Either parallel or ray approaching triangle from back
+        }
+
+        let inverse_determinant = 1.0_f32 / determinant;
+        let t_vec = r.origin - vertex0;
+        let u = t_vec.dot(p_vec) * inverse_determinant;
+        if u < 0.0_f32 || u > 1.0_f32 {
+            return None;
+        }
+
+        let q_vec = t_vec.cross(edge_1);
+        let v = r.dir.dot(q_vec) * inverse_determinant;
+        if v < 0.0_f32 || u + v > 1.0_f32 {
+            return None;
+        }
+
+        let t_hit = edge_2.dot(q_vec) * inverse_determinant;
+        if t_hit < t_max && t_hit > t_min {
+            return Some(t_hit);
+        }
+        return None;
+    }
+
+    // Texture coordinates at the point with Moller-Trumbore barycentric
+    // coordinates u and v.
+    fn interpolate_uv(&self, u: f32, v: f32) -> TexCoord {
+        let w = 1.0_f32 - u - v;
+
+        let (uv0, uv1, uv2) = self.triangle_mesh.get_uvs(self.t0, self.t1, self.t2);
+
+        // Apply to UV coordinates from mesh
+        TexCoord::new(
+            uv0.u() * u + uv1.u() * v + uv2.u() * w,
+            uv0.v() * u + uv1.v() * v + uv2.v() * w,
+        )
+    }
+
     pub fn new(
         mesh: Arc<TriangleMesh>,
         v0: usize,
@@ -231,7 +1557,15 @@ impl Triangle {
         t0: Option<usize>,
         t1: Option<usize>,
         t2: Option<usize>,
+        material: usize,
     ) -> Result<Triangle, String> {
+        if material >= mesh.materials.len() {
+            return Err(format!(
+                "Triangle mesh has {} materials but attempted to make a Triangle with material index {}.",
+                mesh.materials.len(),
+                material
+            ));
+        }
         if mesh.vertices.is_empty()
             || mesh.vertices.len() - 1 < v0
             || mesh.vertices.len() - 1 < v1
@@ -281,36 +1615,37 @@ impl Triangle {
         let vertex2 = mesh.vertices[v2];
         let (uv0, uv1, uv2) = mesh.get_uvs(t0, t1, t2);
 
-        let mut pu = Vector3::new_empty();
-        let mut pv = Vector3::new_empty();
-        let duv02 = uv0 - uv2;
-        let duv12 = uv1 - uv2;
-        let dp02 = vertex0 - vertex2;
-        let dp12 = vertex1 - vertex2;
-        let uv_determinant = duv02.u() * duv12.v() - duv02.v() * duv12.u();
-        let degenerate_uv = uv_determinant.abs() < std::f32::EPSILON;
-        if !degenerate_uv {
-            let inv_det = 1.0_f32 / uv_determinant;
-            pu = (duv12.v() * dp02 - duv02.v() * dp12) * inv_det;
-            pv = (-duv12.u() * dp02 + duv02.u() * dp12) * inv_det;
-        }
-        if degenerate_uv || pu.cross(pv).squared_length() == 0.0_f32 {
-            let mut ng = (vertex2 - vertex0).cross(vertex1 - vertex0);
-            if ng.squared_length() == 0.0_f32 {
-                return Err(format!("Triangle texture coordinates are degenerate. UV1: ({}, {}), UV2: ({}, {}), UV3: ({}, {})",
+        let (pu, pv) = match uv_derivatives(&[vertex0, vertex1, vertex2], &[uv0, uv1, uv2]) {
+            Some(d) => d,
+            None => {
+                let mut ng = (vertex2 - vertex0).cross(vertex1 - vertex0);
+                if ng.squared_length() == 0.0_f32 {
+                    return Err(format!("Triangle texture coordinates are degenerate. UV1: ({}, {}), UV2: ({}, {}), UV3: ({}, {})",
             uv0.u(), uv0.v(), uv1.u(), uv1.v(), uv2.u(), uv2.v()
             ));
-            } else {
+                }
                 ng = ng.normalized();
-                if ng.x().abs() > ng.y().abs() {
-                    pu = Vector3::new(-ng.z(), 0.0_f32, ng.x())
-                        / (ng.x() * ng.x() + ng.z() * ng.z()).sqrt();
+                let pu = if ng.x().abs() > ng.y().abs() {
+                    Vector3::new(-ng.z(), 0.0_f32, ng.x())
+                        / (ng.x() * ng.x() + ng.z() * ng.z()).sqrt()
                 } else {
-                    pu = Vector3::new(0.0_f32, ng.z(), -ng.y())
-                        / (ng.y() * ng.y() + ng.z() * ng.z()).sqrt();
-                }
-                pv = ng.cross(pu);
+                    Vector3::new(0.0_f32, ng.z(), -ng.y())
+                        / (ng.y() * ng.y() + ng.z() * ng.z()).sqrt()
+                };
+                (pu, ng.cross(pu))
             }
+        };
+
+        // Corners the mesh has no tangents for keep this face's own
+        let mut corner_pu = [pu; 3];
+        let mut corner_pv = [pv; 3];
+        for (i, corner) in [(v0, t0), (v1, t1), (v2, t2)].iter().enumerate() {
+            let (corner_u, corner_v) = match mesh.tangents.get(corner) {
+                Some(d) => *d,
+                None => (pu, pv),
+            };
+            corner_pu[i] = corner_u;
+            corner_pv[i] = corner_v;
         }
 
         Ok(Triangle {
@@ -321,57 +1656,47 @@ impl Triangle {
             t0: t0,
             t1: t1,
             t2: t2,
-            pu: pu,
-            pv: pv,
+            material: material,
+            corner_pu: corner_pu,
+            corner_pv: corner_pv,
         })
     }
 }
 
-impl Shape for Triangle {
-    // Uses Moller-Trumbore ray-triangle intersection algorithm.
-    // https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-    //
-    // Backface culling expects a counter-clockwise winding order.
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
-        let vertex0 = self.triangle_mesh.vertices[self.v0];
-        let vertex1 = self.triangle_mesh.vertices[self.v1];
-        let vertex2 = self.triangle_mesh.vertices[self.v2];
-
-        let edge_1 = vertex1 - vertex0;
-        let edge_2 = vertex2 - vertex0;
-        let p_vec = r.dir.cross(edge_2);
-        let determinant = edge_1.dot(p_vec);
-
-        if !self.triangle_mesh.enable_backface_culling
-            && determinant > -std::f32::EPSILON
-            && determinant < std::f32::EPSILON
-        {
-            return None; // Indicates parallel ray and triangle
-        } else if self.triangle_mesh.enable_backface_culling && determinant < std::f32::EPSILON {
-            return None; // Either parallel or ray approaching triangle from back
+// Keeps the part of a convex polygon on one side of an axis aligned plane.
+fn clip_polygon(polygon: &Vec<Point3>, axis: Axis, plane: f32, keep_above: bool) -> Vec<Point3> {
+    let inside = |p: &Point3| {
+        if keep_above {
+            p[axis] >= plane
+        } else {
+            p[axis] <= plane
         }
+    };
 
-        let inverse_determinant = 1.0_f32 / determinant;
-        let t_vec = r.origin - vertex0;
-        let u = t_vec.dot(p_vec) * inverse_determinant;
-        if u < 0.0_f32 || u > 1.0_f32 {
-            return None;
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for idx in 0..polygon.len() {
+        let current = polygon[idx];
+        let next = polygon[(idx + 1) % polygon.len()];
+        if inside(&current) {
+            clipped.push(current);
         }
-
-        let q_vec = t_vec.cross(edge_1);
-        let v = r.dir.dot(q_vec) * inverse_determinant;
-        if v < 0.0_f32 || u + v > 1.0_f32 {
-            return None;
+        if inside(&current) != inside(&next) {
+            let t = (plane - current[axis]) / (next[axis] - current[axis]);
+            clipped.push(current + (next - current) * t);
         }
+    }
+    return clipped;
+}
 
-        let t_hit = edge_2.dot(q_vec) * inverse_determinant;
-        if t_hit < t_max && t_hit > t_min {
-            return Some(t_hit);
-        }
-        return None;
+impl Shape for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        return self
+            .intersect(r, t_min, t_max, self.triangle_mesh.enable_backface_culling)
+            .map(Hit::new);
     }
 
-    fn get_hit_properties(&self, r: &Ray, t_hit: f32) -> HitProperties {
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        let t_hit = hit.t;
         let vertex0 = self.triangle_mesh.vertices[self.v0];
         let vertex1 = self.triangle_mesh.vertices[self.v1];
         let vertex2 = self.triangle_mesh.vertices[self.v2];
@@ -399,17 +1724,15 @@ impl Shape for Triangle {
         let q_vec = t_vec.cross(edge_1);
         let v = r.dir.dot(q_vec) * inverse_determinant;
 
-        let w = 1.0_f32 - u - v;
-
-        let (uv0, uv1, uv2) = self.triangle_mesh.get_uvs(self.t0, self.t1, self.t2);
-
-        // Apply to UV coordinates from mesh
-        let uv = TexCoord::new(
-            uv0.u() * u + uv1.u() * v + uv2.u() * w,
-            uv0.v() * u + uv1.v() * v + uv2.v() * w,
-        );
+        let uv = self.interpolate_uv(u, v);
 
-        let mut pu = self.pu;
+        // Blend the corner tangents with the same weights as the texture
+        // coordinates, then keep them in the plane of the face
+        let w = 1.0_f32 - u - v;
+        let mut pu = self.corner_pu[0] * u + self.corner_pu[1] * v + self.corner_pu[2] * w;
+        let mut pv = self.corner_pv[0] * u + self.corner_pv[1] * v + self.corner_pv[2] * w;
+        pu = pu - pu.dot(normal) * normal;
+        pv = pv - pv.dot(normal) * normal;
         if determinant < 0.0_f32 {
             pu = -pu; // Flip if ray comes from back
         }
@@ -419,12 +1742,52 @@ impl Shape for Triangle {
             normal: normal,
             uv: uv,
             pu: pu,
-            pv: self.pv,
+            pv: pv,
+            uv_footprint: 0.0_f32,
         }
     }
 
     fn get_material(&self) -> &Arc<SyncMaterial> {
-        &self.triangle_mesh.material
+        &self.triangle_mesh.materials[self.material]
+    }
+
+    // Each barycentric coordinate is the distance to the opposite edge over
+    // the triangle's height there, so this is the smallest of the three
+    // scaled back to world units.
+    fn edge_distance(&self, r: &Ray, hit: &Hit) -> Option<f32> {
+        let vertices = [
+            self.triangle_mesh.vertices[self.v0],
+            self.triangle_mesh.vertices[self.v1],
+            self.triangle_mesh.vertices[self.v2],
+        ];
+        let hit_point = r.point_at(hit.t);
+
+        let mut nearest = f32::MAX;
+        for i in 0..3 {
+            let start = vertices[i];
+            let edge = vertices[(i + 1) % 3] - start;
+            let distance = edge.cross(hit_point - start).length() / edge.length();
+            nearest = utils::float_min(nearest, distance);
+        }
+        return Some(nearest);
+    }
+
+    // The front is the side the vertices wind counter-clockwise around
+    fn is_front_face(&self, r: &Ray, _hit: &Hit) -> bool {
+        let vertex0 = self.triangle_mesh.vertices[self.v0];
+        let edge_1 = self.triangle_mesh.vertices[self.v1] - vertex0;
+        let edge_2 = self.triangle_mesh.vertices[self.v2] - vertex0;
+        return r.dir.dot(edge_1.cross(edge_2)) < 0.0_f32;
+    }
+
+    fn corner_uvs(&self) -> Option<[TexCoord; 3]> {
+        match (self.t0, self.t1, self.t2) {
+            (Some(_), Some(_), Some(_)) => {
+                let (uv0, uv1, uv2) = self.triangle_mesh.get_uvs(self.t0, self.t1, self.t2);
+                Some([uv0, uv1, uv2])
+            }
+            _ => None,
+        }
     }
 
     fn get_bounding_box(&self) -> AABB {
@@ -438,16 +1801,44 @@ impl Shape for Triangle {
         )
     }
 
-    fn pdf(&self, r: &Ray) -> f32 {
+    // Sutherland-Hodgman clipping of the triangle against each of the six
+    // planes of the clip box, returning the bounds of the resulting polygon.
+    fn get_clipped_bounding_box(&self, clip: &AABB) -> Option<AABB> {
+        let mut polygon = vec![
+            self.triangle_mesh.vertices[self.v0],
+            self.triangle_mesh.vertices[self.v1],
+            self.triangle_mesh.vertices[self.v2],
+        ];
+
+        for axis in [Axis::X, Axis::Y, Axis::Z].iter() {
+            polygon = clip_polygon(&polygon, *axis, clip.min[*axis], true);
+            polygon = clip_polygon(&polygon, *axis, clip.max[*axis], false);
+            if polygon.is_empty() {
+                return None;
+            }
+        }
+
+        let mut min = polygon[0];
+        let mut max = polygon[0];
+        for p in &polygon[1..] {
+            min = Point3::min(min, *p);
+            max = Point3::max(max, *p);
+        }
+        Some(AABB::new(min, max))
+    }
+
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
         let vertex0 = self.triangle_mesh.vertices[self.v0];
         let vertex1 = self.triangle_mesh.vertices[self.v1];
         let vertex2 = self.triangle_mesh.vertices[self.v2];
 
-        let t_hit = match self.hit(r, utils::T_MIN, utils::T_MAX) {
+        // Directions are sampled toward the triangle from either side, so
+        // its back counts even where it is culled
+        let t_hit = match self.intersect(r, t_min, utils::T_MAX, false) {
             Some(t) => t,
             None => return 0.0_f32,
         };
-        let hit_props = self.get_hit_properties(r, t_hit);
+        let hit_props = self.get_hit_properties(r, &Hit::new(t_hit));
 
         // TODO: Make area a function on Shape trait, which allows a single implementation
         // of PDF that leverages area for most Shapes
@@ -462,11 +1853,56 @@ impl Shape for Triangle {
         let vertex1 = self.triangle_mesh.vertices[self.v1];
         let vertex2 = self.triangle_mesh.vertices[self.v2];
 
-        let r1 = rand::random::<f32>();
-        let r2 = rand::random::<f32>();
+        let r1 = rng::random();
+        let r2 = rng::random();
         let random_point = vertex0 * (1.0_f32 - r1.sqrt())
             + vertex1 * (r1.sqrt() * (1.0_f32 - r2))
             + vertex2 * (r2 * r1.sqrt());
         return random_point - *from_origin;
     }
+
+    // The same mapping random_dir_towards uses to pick uniform points
+    fn surface_point(&self, s: f32, t: f32) -> Option<(Point3, TexCoord)> {
+        let vertex0 = self.triangle_mesh.vertices[self.v0];
+        let vertex1 = self.triangle_mesh.vertices[self.v1];
+        let vertex2 = self.triangle_mesh.vertices[self.v2];
+
+        let sqrt_s = s.sqrt();
+        let u = sqrt_s * (1.0_f32 - t);
+        let v = t * sqrt_s;
+        let point = vertex0 * (1.0_f32 - sqrt_s) + vertex1 * u + vertex2 * v;
+        return Some((point, self.interpolate_uv(u, v)));
+    }
+
+    fn surface_parameters(&self, p: &Point3) -> (f32, f32) {
+        let vertex0 = self.triangle_mesh.vertices[self.v0];
+        let edge_1 = self.triangle_mesh.vertices[self.v1] - vertex0;
+        let edge_2 = self.triangle_mesh.vertices[self.v2] - vertex0;
+        let to_p = *p - vertex0;
+
+        // Barycentric coordinates of the point
+        let d11 = edge_1.dot(edge_1);
+        let d12 = edge_1.dot(edge_2);
+        let d22 = edge_2.dot(edge_2);
+        let dp1 = to_p.dot(edge_1);
+        let dp2 = to_p.dot(edge_2);
+        let denominator = d11 * d22 - d12 * d12;
+        let u = (d22 * dp1 - d12 * dp2) / denominator;
+        let v = (d11 * dp2 - d12 * dp1) / denominator;
+
+        let sqrt_s = utils::clamp(u + v, 0.0_f32, 1.0_f32);
+        let t = if sqrt_s > 0.0_f32 {
+            utils::clamp(v / sqrt_s, 0.0_f32, 1.0_f32)
+        } else {
+            0.0_f32
+        };
+        return (sqrt_s * sqrt_s, t);
+    }
+
+    fn surface_area(&self) -> f32 {
+        let vertex0 = self.triangle_mesh.vertices[self.v0];
+        let vertex1 = self.triangle_mesh.vertices[self.v1];
+        let vertex2 = self.triangle_mesh.vertices[self.v2];
+        return 0.5_f32 * (vertex1 - vertex0).cross(vertex2 - vertex0).length();
+    }
 }
@@ -0,0 +1,125 @@
+use crate::color::RGB;
+use crate::rng;
+
+// Wavelengths of visible light, in nanometers, that paths are traced at
+// when they need a single wavelength, like when refracting through a
+// dispersive material
+pub const MIN_WAVELENGTH: f32 = 380.0_f32;
+pub const MAX_WAVELENGTH: f32 = 780.0_f32;
+
+// CIE XYZ to linear Rec. 709
+const XYZ_TO_REC709: [[f32; 3]; 3] = [
+    [3.2404542_f32, -1.5371385_f32, -0.4985314_f32],
+    [-0.9692660_f32, 1.8760108_f32, 0.0415560_f32],
+    [0.0556434_f32, -0.2040259_f32, 1.0572252_f32],
+];
+
+// Scales the clamped Rec. 709 response of each channel so that it averages
+// to 1 over wavelengths sampled uniformly, which keeps white light white
+const CHANNEL_SCALE: [f32; 3] = [2.2704398_f32, 3.4666380_f32, 3.6590345_f32];
+
+// A wavelength picked uniformly at random from the visible range
+pub fn sample_wavelength() -> f32 {
+    return MIN_WAVELENGTH + rng::random() * (MAX_WAVELENGTH - MIN_WAVELENGTH);
+}
+
+// The weight, in linear Rec. 709, of a path traced at a single wavelength
+// picked by sample_wavelength. Over many paths, the weights average to
+// white, so the paths add up to the color they would have had without
+// picking a wavelength, apart from how the wavelength changed their course.
+pub fn wavelength_weight(wavelength: f32) -> RGB {
+    let xyz = cie_xyz(wavelength);
+    let mut rgb = [0.0_f32; 3];
+    for c in 0..3 {
+        let response = XYZ_TO_REC709[c][0] * xyz[0]
+            + XYZ_TO_REC709[c][1] * xyz[1]
+            + XYZ_TO_REC709[c][2] * xyz[2];
+        // Pure wavelengths are outside of the Rec. 709 gamut, and negative
+        // weights would only add noise
+        rgb[c] = response.max(0.0_f32) * CHANNEL_SCALE[c];
+    }
+    return RGB::new(rgb[0], rgb[1], rgb[2]);
+}
+
+// The linear Rec. 709 color of light from a blackbody at the temperature in
+// kelvin, with a luminance of 1. Planck's law is integrated against the
+// color matching functions every 5 nanometers over the visible range.
+pub fn blackbody(kelvin: f32) -> RGB {
+    // Second radiation constant, in nanometer kelvin
+    const C2: f32 = 1.4387769e7_f32;
+    let mut xyz = [0.0_f32; 3];
+    let mut wavelength = MIN_WAVELENGTH;
+    while wavelength <= MAX_WAVELENGTH {
+        // Planck's law, up to a constant which the luminance divides out
+        let radiance =
+            1.0_f32 / (wavelength.powi(5) * ((C2 / (wavelength * kelvin)).exp() - 1.0_f32));
+        let cmf = cie_xyz(wavelength);
+        for c in 0..3 {
+            xyz[c] += radiance * cmf[c];
+        }
+        wavelength += 5.0_f32;
+    }
+    if !(xyz[1] > 0.0_f32) {
+        return RGB::black();
+    }
+    let mut rgb = [0.0_f32; 3];
+    for c in 0..3 {
+        let response = XYZ_TO_REC709[c][0] * xyz[0]
+            + XYZ_TO_REC709[c][1] * xyz[1]
+            + XYZ_TO_REC709[c][2] * xyz[2];
+        // Candle light is redder than Rec. 709 can show
+        rgb[c] = response.max(0.0_f32) / xyz[1];
+    }
+    return RGB::new(rgb[0], rgb[1], rgb[2]);
+}
+
+// The linear Rec. 709 color of a surface whose reflectance at each
+// wavelength the function gives, under white light, found every 10
+// nanometers over the visible range. A reflectance of 1 everywhere is white.
+pub fn reflectance_color<F: Fn(f32) -> f32>(reflectance: F) -> RGB {
+    let mut xyz = [0.0_f32; 3];
+    let mut white = [0.0_f32; 3];
+    let mut wavelength = MIN_WAVELENGTH;
+    while wavelength <= MAX_WAVELENGTH {
+        let r = reflectance(wavelength);
+        let cmf = cie_xyz(wavelength);
+        for c in 0..3 {
+            xyz[c] += r * cmf[c];
+            white[c] += cmf[c];
+        }
+        wavelength += 10.0_f32;
+    }
+    let mut rgb = [0.0_f32; 3];
+    for c in 0..3 {
+        let response = |v: &[f32; 3]| {
+            XYZ_TO_REC709[c][0] * v[0] + XYZ_TO_REC709[c][1] * v[1] + XYZ_TO_REC709[c][2] * v[2]
+        };
+        rgb[c] = (response(&xyz) / response(&white)).max(0.0_f32);
+    }
+    return RGB::new(rgb[0], rgb[1], rgb[2]);
+}
+
+// The CIE 1931 color matching functions, with the multi-lobe fit from
+// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+// (Wyman, Sloan, and Shirley 2013)
+fn cie_xyz(wavelength: f32) -> [f32; 3] {
+    let x = 1.056_f32 * lobe(wavelength, 599.8_f32, 37.9_f32, 31.0_f32)
+        + 0.362_f32 * lobe(wavelength, 442.0_f32, 16.0_f32, 26.7_f32)
+        - 0.065_f32 * lobe(wavelength, 501.1_f32, 20.4_f32, 26.2_f32);
+    let y = 0.821_f32 * lobe(wavelength, 568.8_f32, 46.9_f32, 40.5_f32)
+        + 0.286_f32 * lobe(wavelength, 530.9_f32, 16.3_f32, 31.1_f32);
+    let z = 1.217_f32 * lobe(wavelength, 437.0_f32, 11.8_f32, 36.0_f32)
+        + 0.681_f32 * lobe(wavelength, 459.0_f32, 26.0_f32, 13.8_f32);
+    return [x, y, z];
+}
+
+// A Gaussian with a different width either side of its peak
+fn lobe(wavelength: f32, peak: f32, width_below: f32, width_above: f32) -> f32 {
+    let width = if wavelength < peak {
+        width_below
+    } else {
+        width_above
+    };
+    let t = (wavelength - peak) / width;
+    return (-0.5_f32 * t * t).exp();
+}
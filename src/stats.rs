@@ -0,0 +1,172 @@
+use crate::material::SyncMaterial;
+use crate::shape::SyncShape;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Only one in this many camera samples is timed, which keeps the cost of
+// calling into the clock out of the common path.
+pub const SAMPLE_RATE: u64 = 16;
+// Number of entries shown in each list of the report.
+const HOT_LIST_LENGTH: usize = 10;
+
+// Render time statistics, gathered per thread and merged at the end.
+// Shapes and Materials are keyed by address, and given names at the end
+// using the names recorded by the scene.
+pub struct Statistics {
+    // Whether the path currently being traced should be timed
+    pub sampling: bool,
+    // Every ray traced is counted, sampled or not
+    rays: u64,
+    // Time spent finding the closest hit, attributed to the shape that was
+    // hit. Rays that hit nothing are keyed as 0.
+    shape_times: HashMap<usize, Duration>,
+    // Time spent in emission and scattering, attributed to the material
+    material_times: HashMap<usize, Duration>,
+    // Where tracing currently is, so that a panic while tracing can be
+    // reported against the pixel, shape and material that caused it. The
+    // shape and material are keyed as above, and are 0 until a ray hits
+    // something.
+    pub pixel: (u32, u32),
+    pub shape: usize,
+    pub material: usize,
+}
+
+pub fn shape_key(shape: &SyncShape) -> usize {
+    shape as *const SyncShape as *const () as usize
+}
+
+pub fn material_key(material: &SyncMaterial) -> usize {
+    match material.varies() {
+        Some(m) => return material_key(m),
+        None => return material as *const SyncMaterial as *const () as usize,
+    }
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics {
+            sampling: false,
+            rays: 0_u64,
+            shape_times: HashMap::new(),
+            material_times: HashMap::new(),
+            pixel: (0_u32, 0_u32),
+            shape: 0,
+            material: 0,
+        }
+    }
+
+    pub fn record_ray(&mut self) {
+        self.rays += 1_u64;
+    }
+
+    pub fn rays(&self) -> u64 {
+        return self.rays;
+    }
+
+    pub fn record_intersection(&mut self, shape: Option<&SyncShape>, elapsed: Duration) {
+        let key = match shape {
+            Some(s) => shape_key(s),
+            None => 0,
+        };
+        *self.shape_times.entry(key).or_insert(Duration::new(0, 0)) += elapsed;
+    }
+
+    pub fn record_shading(&mut self, material: &SyncMaterial, elapsed: Duration) {
+        *self
+            .material_times
+            .entry(material_key(material))
+            .or_insert(Duration::new(0, 0)) += elapsed;
+    }
+
+    // Describes where tracing was, for reporting a failure
+    pub fn position(
+        &self,
+        shape_names: &HashMap<usize, String>,
+        material_names: &HashMap<usize, String>,
+    ) -> String {
+        let name = |names: &HashMap<usize, String>, key: usize| match names.get(&key) {
+            Some(n) => String::clone(n),
+            None => String::from("(unnamed)"),
+        };
+        if self.shape == 0 {
+            return format!(
+                "pixel ({}, {}), while finding a hit",
+                self.pixel.0, self.pixel.1
+            );
+        }
+        return format!(
+            "pixel ({}, {}), shading shape {} with material {}",
+            self.pixel.0,
+            self.pixel.1,
+            name(shape_names, self.shape),
+            name(material_names, self.material)
+        );
+    }
+
+    pub fn merge(&mut self, other: &Statistics) {
+        self.rays += other.rays;
+        for (key, elapsed) in &other.shape_times {
+            *self.shape_times.entry(*key).or_insert(Duration::new(0, 0)) += *elapsed;
+        }
+        for (key, elapsed) in &other.material_times {
+            *self
+                .material_times
+                .entry(*key)
+                .or_insert(Duration::new(0, 0)) += *elapsed;
+        }
+    }
+
+    pub fn report(
+        &self,
+        shape_names: &HashMap<usize, String>,
+        material_names: &HashMap<usize, String>,
+    ) -> String {
+        let mut report = format!("Rays traced: {}\n", self.rays);
+        report.push_str(&format!(
+            "Slowest materials (shading time, estimated from 1 in {} samples):\n",
+            SAMPLE_RATE
+        ));
+        report.push_str(&hot_list(&self.material_times, material_names));
+        report.push_str(&format!(
+            "Slowest shapes (intersection time attributed to the shape hit, estimated from 1 in {} samples):\n",
+            SAMPLE_RATE
+        ));
+        report.push_str(&hot_list(&self.shape_times, shape_names));
+        return report;
+    }
+}
+
+// Sums times by name, since many keys can share a name (every Triangle
+// in a Mesh, for example), and formats the largest few.
+fn hot_list(times: &HashMap<usize, Duration>, names: &HashMap<usize, String>) -> String {
+    let mut by_name: HashMap<&str, Duration> = HashMap::new();
+    let mut total = Duration::new(0, 0);
+    for (key, elapsed) in times {
+        let name = if *key == 0 {
+            "(nothing hit)"
+        } else {
+            match names.get(key) {
+                Some(n) => n.as_str(),
+                None => "(unnamed)",
+            }
+        };
+        *by_name.entry(name).or_insert(Duration::new(0, 0)) += *elapsed;
+        total += *elapsed;
+    }
+
+    let mut sorted: Vec<(&str, Duration)> = by_name.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let mut list = String::new();
+    for (rank, (name, elapsed)) in sorted.iter().take(HOT_LIST_LENGTH).enumerate() {
+        list.push_str(&format!(
+            "    {:>2}. {}: {:.3} s ({:.1}%)\n",
+            rank + 1,
+            name,
+            elapsed.as_secs_f64() * SAMPLE_RATE as f64,
+            100.0_f64 * elapsed.as_secs_f64() / total.as_secs_f64().max(std::f64::EPSILON)
+        ));
+    }
+    return list;
+}
@@ -0,0 +1,98 @@
+// Where the sun is in the sky for a place and a time, from the low
+// precision formulas of the Astronomical Almanac, good to about a hundredth
+// of a degree between 1950 and 2050, far finer than the sun's own half
+// degree. Angles are in degrees, longitudes are east of Greenwich, and
+// times are in hours of Coordinated Universal Time.
+pub struct SunPosition {
+    // Above the horizon
+    pub altitude: f32,
+    // Clockwise from north, so east is 90
+    pub azimuth: f32,
+}
+
+pub fn position(
+    latitude: f32,
+    longitude: f32,
+    (year, month, day): (i32, u32, u32),
+    utc_hours: f64,
+) -> SunPosition {
+    // Days since noon on January 1st 2000
+    let n = julian_day(year, month, day) + utc_hours / 24.0_f64 - 2451545.0_f64;
+
+    // The sun's place along the ecliptic, and on the celestial sphere
+    let mean_longitude = 280.460_f64 + 0.9856474_f64 * n;
+    let mean_anomaly = (357.528_f64 + 0.9856003_f64 * n).to_radians();
+    let ecliptic_longitude = (mean_longitude
+        + 1.915_f64 * mean_anomaly.sin()
+        + 0.020_f64 * (2.0_f64 * mean_anomaly).sin())
+    .to_radians();
+    let obliquity = (23.439_f64 - 0.0000004_f64 * n).to_radians();
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin())
+        .atan2(ecliptic_longitude.cos())
+        .to_degrees();
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    // How far the sun is past the meridian
+    let sidereal_time = (18.697374558_f64 + 24.06570982441908_f64 * n) * 15.0_f64;
+    let hour_angle = (sidereal_time + longitude as f64 - right_ascension).to_radians();
+
+    let lat = (latitude as f64).to_radians();
+    let altitude =
+        (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos()).asin();
+    let azimuth = (-declination.cos() * hour_angle.sin())
+        .atan2(declination.sin() * lat.cos() - declination.cos() * hour_angle.cos() * lat.sin());
+    return SunPosition {
+        altitude: altitude.to_degrees() as f32,
+        azimuth: azimuth.to_degrees().rem_euclid(360.0_f64) as f32,
+    };
+}
+
+// The Julian day number at midnight starting a date of the Gregorian
+// calendar
+fn julian_day(year: i32, month: u32, day: u32) -> f64 {
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 12)
+    } else {
+        (year, month)
+    };
+    let century = (y as f64 / 100.0_f64).floor();
+    let leap_correction = 2.0_f64 - century + (century / 4.0_f64).floor();
+    return (365.25_f64 * (y as f64 + 4716.0_f64)).floor()
+        + (30.6001_f64 * (m as f64 + 1.0_f64)).floor()
+        + day as f64
+        + leap_correction
+        - 1524.5_f64;
+}
+
+// A date as "YYYY-MM-DD"
+pub fn parse_date(date: &str) -> Option<(i32, u32, u32)> {
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let year = parts[0].parse::<i32>().ok()?;
+    let month = parts[1].parse::<u32>().ok()?;
+    let day = parts[2].parse::<u32>().ok()?;
+    if month < 1 || month > 12 || day < 1 || day > 31 {
+        return None;
+    }
+    return Some((year, month, day));
+}
+
+// A time of day as "HH:MM" or "HH:MM:SS", in hours
+pub fn parse_time(time: &str) -> Option<f64> {
+    let parts: Vec<&str> = time.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    let mut hours = 0.0_f64;
+    for (i, part) in parts.iter().enumerate() {
+        let value = part.parse::<f64>().ok()?;
+        let limit = if i == 0 { 24.0_f64 } else { 60.0_f64 };
+        if value < 0.0_f64 || value >= limit {
+            return None;
+        }
+        hours += value / 60.0_f64.powi(i as i32);
+    }
+    return Some(hours);
+}
@@ -0,0 +1,154 @@
+// Golden image helpers for rendering tests, enabled with the "testing"
+// feature. A test renders a tiny scene in process and compares it against a
+// reference PNG, for example:
+//
+//     let rendered = testing::render_test_scene(SCENE_JSON, spec_dir)?;
+//     testing::compare_to_reference(
+//         &rendered,
+//         include_bytes!("../assets/references/sphere.png"),
+//         testing::DEFAULT_TOLERANCE,
+//     )?;
+//
+// The tests themselves are in tests/golden.rs. New references can be
+// created with write_reference, and should be checked by eye before being
+// committed.
+//
+// Test scenes are rendered with a fixed seed, so the same build renders the
+// same image every time. Images are still compared by their mean absolute
+// difference over small blocks of pixels against a tolerance, rather than
+// exactly, so that references survive the small floating point differences
+// between compilers and platforms.
+
+use crate::resources::Resources;
+use crate::scene;
+
+use image::png::PNGEncoder;
+use image::{ColorType, ImageFormat};
+use std::{fs::OpenOptions, io, path::Path, sync::Arc, sync::Mutex};
+
+pub const TEST_RESOLUTION: u32 = 64;
+pub const TEST_SAMPLES: u32 = 16;
+pub const TEST_SEED: u64 = 1;
+// Images are compared in blocks of this many pixels on a side
+const COMPARE_BLOCK_SIZE: u32 = 4;
+// Mean absolute difference per 8 bit channel, after averaging each block.
+// Unseeded renders of the same scene at the test resolution and sample
+// count typically differ by about 3, so this catches changes well within
+// the noise.
+pub const DEFAULT_TOLERANCE: f32 = 1.0_f32;
+
+// Renders the given scene specification at the test resolution, sample
+// count, and seed, whatever its Logistics say, returning 8 bit RGB data in row-major
+// order. Relative paths in the scene are resolved against spec_dir.
+pub fn render_test_scene(scene_json: &str, spec_dir: &Path) -> Result<Vec<u8>, String> {
+    let mut json: serde_json::Value = match serde_json::from_str(scene_json) {
+        Ok(j) => j,
+        Err(e) => return Err(format!("Failed to parse test scene: {}", e)),
+    };
+    match json.get_mut("Logistics") {
+        Some(logistics) => {
+            logistics["resolution_x"] = serde_json::Value::from(TEST_RESOLUTION);
+            logistics["resolution_y"] = serde_json::Value::from(TEST_RESOLUTION);
+            logistics["samples"] = serde_json::Value::from(TEST_SAMPLES);
+            logistics["seed"] = serde_json::Value::from(TEST_SEED);
+        }
+        None => return Err(String::from("Test scene is missing 'Logistics'.")),
+    }
+
+    let mut res = Resources::new();
+    let scene_spec = match scene::deserialize(&json.to_string(), spec_dir, &mut res) {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Failed to load test scene: {}", e)),
+    };
+    let (colors, _, _) =
+        match crate::render::render(&scene_spec, 1_u32, Arc::new(Mutex::new(io::sink())), None) {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Failed to render test scene: {}", e)),
+        };
+    return crate::render::to_rgb8(&colors, &scene_spec.logistics)
+        .map_err(|e| format!("Failed to read the rendered test scene: {}", e));
+}
+
+// Compares rendered 8 bit RGB data against an encoded reference PNG of the
+// test resolution, failing if their mean absolute difference per channel,
+// averaged over blocks of pixels, exceeds the tolerance.
+pub fn compare_to_reference(
+    rendered: &[u8],
+    reference_png: &[u8],
+    tolerance: f32,
+) -> Result<(), String> {
+    let reference = match image::load_from_memory_with_format(reference_png, ImageFormat::PNG) {
+        Ok(i) => i.to_rgb(),
+        Err(e) => return Err(format!("Failed to decode reference image: {}", e)),
+    };
+    if reference.width() != TEST_RESOLUTION || reference.height() != TEST_RESOLUTION {
+        return Err(format!(
+            "Reference image is {}x{}, but test renders are {}x{}.",
+            reference.width(),
+            reference.height(),
+            TEST_RESOLUTION,
+            TEST_RESOLUTION
+        ));
+    }
+    let reference_data = reference.into_raw();
+    if reference_data.len() != rendered.len() {
+        return Err(format!(
+            "Rendered data has {} values, but the reference has {}.",
+            rendered.len(),
+            reference_data.len()
+        ));
+    }
+
+    // Pixels are averaged over small blocks first so that a path here and
+    // there taking another turn, from rounding, mostly cancels out, leaving
+    // real differences in the image.
+    let blocks = TEST_RESOLUTION / COMPARE_BLOCK_SIZE;
+    let mut total_difference = 0.0_f32;
+    for block_y in 0..blocks {
+        for block_x in 0..blocks {
+            for channel in 0..3 {
+                let mut block_difference = 0_i32;
+                for y in (block_y * COMPARE_BLOCK_SIZE)..((block_y + 1) * COMPARE_BLOCK_SIZE) {
+                    for x in (block_x * COMPARE_BLOCK_SIZE)..((block_x + 1) * COMPARE_BLOCK_SIZE) {
+                        let idx = ((y * TEST_RESOLUTION + x) * 3 + channel) as usize;
+                        block_difference += rendered[idx] as i32 - reference_data[idx] as i32;
+                    }
+                }
+                total_difference += (block_difference.abs() as f32)
+                    / (COMPARE_BLOCK_SIZE * COMPARE_BLOCK_SIZE) as f32;
+            }
+        }
+    }
+    let mean_difference = total_difference / (blocks * blocks * 3) as f32;
+    if mean_difference > tolerance {
+        return Err(format!(
+            "Render differs from the reference by {} on average, more than the tolerance of {}.",
+            mean_difference, tolerance
+        ));
+    }
+    return Ok(());
+}
+
+// Writes rendered 8 bit RGB data as a new reference PNG. Fails rather than
+// overwriting an existing reference.
+pub fn write_reference(rendered: &[u8], path: &Path) -> Result<(), String> {
+    let out_file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(format!(
+                "Failed to create reference {}: {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    match PNGEncoder::new(out_file).encode(
+        rendered,
+        TEST_RESOLUTION,
+        TEST_RESOLUTION,
+        ColorType::RGB(8),
+    ) {
+        Ok(()) => return Ok(()),
+        Err(e) => return Err(format!("Failed to encode reference image: {}", e)),
+    }
+}
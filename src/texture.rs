@@ -1,7 +1,12 @@
 use crate::base::BasicTwoTuple;
 use crate::color::RGB;
+use crate::colorspace;
+use crate::colorspace::ColorSpace;
+use crate::instance;
 use crate::point::Point3;
-use crate::utils::{clamp, noise, turbulence};
+use crate::profile;
+use crate::utils::{clamp, Perlin};
+use crate::vector::Vector3;
 
 use image::{DynamicImage, GenericImageView};
 use serde::Deserialize;
@@ -60,21 +65,99 @@ impl ops::Mul<f32> for TexCoord {
 
 pub trait Texture {
     fn value(&self, uv: &TexCoord, p: &Point3) -> RGB;
-    fn bump_value(&self, uv: &TexCoord, p: &Point3) -> f32 {
-        let bump = self.value(uv, p);
+    // The value averaged over a footprint about this wide in texture
+    // coordinates, for textures that can filter, see Image, on a surface
+    // with the normal, for textures projected along it, see Triplanar
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, _normal: &Vector3, _footprint: f32) -> RGB {
+        self.value(uv, p)
+    }
+    fn bump_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3) -> f32 {
+        let bump = self.filtered_value(uv, p, normal, 0.0_f32);
         (bump.r() + bump.g() + bump.b()) / 3.0_f32
     }
+    // The color of the texture if it is the same everywhere
+    fn constant_value(&self) -> Option<RGB> {
+        None
+    }
 }
 pub type SyncTexture = dyn Texture + Send + Sync;
 
+// Replaces a texture that is the same everywhere, like a Checker of two
+// equal colors or a Constant converted into the working space, with a plain
+// Constant, so that nothing is evaluated for it while rendering. Run on
+// every texture as the scene loads.
+pub fn fold_constant(texture: Arc<SyncTexture>) -> Arc<SyncTexture> {
+    match texture.constant_value() {
+        Some(color) => Arc::new(Constant::new(color)),
+        None => texture,
+    }
+}
+
+// Wraps a texture in a Profiled one when the profile feature is on. Only the
+// textures materials use directly are wrapped, so nested textures are not
+// counted twice.
+pub fn profiled(texture: Arc<SyncTexture>) -> Arc<SyncTexture> {
+    if profile::enabled() {
+        return Arc::new(Profiled { texture: texture });
+    }
+    return texture;
+}
+
+// Times every lookup of another texture
+pub struct Profiled {
+    texture: Arc<SyncTexture>,
+}
+impl Texture for Profiled {
+    fn value(&self, uv: &TexCoord, p: &Point3) -> RGB {
+        let _timer = profile::start(profile::Stage::Texture);
+        self.texture.value(uv, p)
+    }
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3, footprint: f32) -> RGB {
+        let _timer = profile::start(profile::Stage::Texture);
+        self.texture.filtered_value(uv, p, normal, footprint)
+    }
+    fn bump_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3) -> f32 {
+        let _timer = profile::start(profile::Stage::Texture);
+        self.texture.bump_value(uv, p, normal)
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        self.texture.constant_value()
+    }
+}
+
 #[derive(Deserialize)]
 pub struct Constant {
     color: RGB,
 }
+impl Constant {
+    pub fn new(color: RGB) -> Constant {
+        Constant { color: color }
+    }
+}
 impl Texture for Constant {
     fn value(&self, _uv: &TexCoord, _p: &Point3) -> RGB {
         self.color
     }
+    fn constant_value(&self) -> Option<RGB> {
+        Some(self.color)
+    }
+}
+
+// The value of an attribute of the instance being shaded, as a grey, so
+// that Ramps and composed textures can tell instances apart, see
+// instance::Variation. Outside of instances with the attribute it is the
+// default.
+#[derive(Deserialize)]
+pub struct InstanceAttribute {
+    attribute: String,
+    #[serde(default)]
+    default: f32,
+}
+impl Texture for InstanceAttribute {
+    fn value(&self, _uv: &TexCoord, _p: &Point3) -> RGB {
+        let value = instance::attribute(&self.attribute).unwrap_or(self.default);
+        RGB::new(value, value, value)
+    }
 }
 
 pub struct Test;
@@ -116,45 +199,558 @@ impl Texture for Checker {
             self.even.value(uv, p)
         }
     }
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3, footprint: f32) -> RGB {
+        let sines =
+            (self.repeat * p.x()).sin() * (self.repeat * p.y()).sin() * (self.repeat * p.z()).sin();
+        if sines < 0.0_f32 {
+            self.odd.filtered_value(uv, p, normal, footprint)
+        } else {
+            self.even.filtered_value(uv, p, normal, footprint)
+        }
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        let even = self.even.constant_value()?;
+        // With no repeat every sine is 0, which is always even
+        if self.repeat == 0.0_f32 {
+            return Some(even);
+        }
+        let odd = self.odd.constant_value()?;
+        if odd.r() == even.r() && odd.g() == even.g() && odd.b() == even.b() {
+            return Some(even);
+        }
+        return None;
+    }
+}
+
+// How an Image blends its texels
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // The nearest texel, blocky up close and aliased far away
+    Nearest,
+    // The four nearest texels, weighted by distance
+    Bilinear,
+    // Bilinear on the two mipmap levels nearest in size to what the ray
+    // sees, blended, so that textures far away or at grazing angles are
+    // averaged rather than aliased. Camera rays know their footprint,
+    // other rays read the full resolution texture.
+    Trilinear,
 }
 
 pub struct Image {
     img: Arc<DynamicImage>,
+    // Whether the pixels are data, like a normal map, rather than sRGB
+    // encoded colors
+    linear: bool,
+    filter: Filter,
+    // For Trilinear filtering, the image halved again and again down to a
+    // single texel, each texel averaging four of the level above it in
+    // linear space. The full resolution image is level 0, and not stored
+    // here.
+    mip_levels: Vec<MipLevel>,
+}
+
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<RGB>,
 }
+
 impl Image {
-    pub fn new(img: Arc<DynamicImage>) -> Image {
-        Image { img: img }
+    pub fn new(img: Arc<DynamicImage>, linear: bool, filter: Filter) -> Image {
+        let mut image = Image {
+            img: img,
+            linear: linear,
+            filter: filter,
+            mip_levels: Vec::new(),
+        };
+        if filter == Filter::Trilinear {
+            image.build_mip_levels();
+        }
+        return image;
+    }
+
+    fn build_mip_levels(&mut self) {
+        let (mut width, mut height) = (self.img.width(), self.img.height());
+        while width > 1 || height > 1 {
+            let level = self.mip_levels.len();
+            let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+            let mut texels = Vec::with_capacity((next_width * next_height) as usize);
+            for y in 0..next_height {
+                for x in 0..next_width {
+                    // Odd sizes leave the last row or column to the texels
+                    // beside them
+                    let (x0, y0) = ((2 * x).min(width - 1), (2 * y).min(height - 1));
+                    let (x1, y1) = ((2 * x + 1).min(width - 1), (2 * y + 1).min(height - 1));
+                    let sum = self.texel(level, x0, y0)
+                        + self.texel(level, x1, y0)
+                        + self.texel(level, x0, y1)
+                        + self.texel(level, x1, y1);
+                    texels.push(sum * 0.25_f32);
+                }
+            }
+            self.mip_levels.push(MipLevel {
+                width: next_width,
+                height: next_height,
+                texels: texels,
+            });
+            width = next_width;
+            height = next_height;
+        }
+    }
+
+    fn level_size(&self, level: usize) -> (u32, u32) {
+        if level == 0 {
+            return (self.img.width(), self.img.height());
+        }
+        let mip = &self.mip_levels[level - 1];
+        return (mip.width, mip.height);
+    }
+
+    // The linear color of a texel of a mipmap level, with 0 the full image
+    fn texel(&self, level: usize, x: u32, y: u32) -> RGB {
+        if level > 0 {
+            let mip = &self.mip_levels[level - 1];
+            return mip.texels[(y * mip.width + x) as usize];
+        }
+        let pixel = self.img.get_pixel(x, y);
+        let decode = |v: u8| {
+            if self.linear {
+                v as f32 / 255_f32
+            } else {
+                colorspace::srgb_to_linear(v as f32 / 255_f32)
+            }
+        };
+        RGB::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2]))
+    }
+
+    // Texels are centered on half coordinates, and the image repeats, as
+    // the nearest lookup's wrapping does
+    fn bilinear(&self, level: usize, uv: &TexCoord) -> RGB {
+        let (width, height) = self.level_size(level);
+        let x = uv.u() * width as f32 - 0.5_f32;
+        let y = (1_f32 - uv.v()) * height as f32 - 0.5_f32;
+        let (x_floor, y_floor) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x_floor, y - y_floor);
+        let wrap = |i: f32, size: u32| (i as i64).rem_euclid(size as i64) as u32;
+        let (x0, x1) = (wrap(x_floor, width), wrap(x_floor + 1.0_f32, width));
+        let (y0, y1) = (wrap(y_floor, height), wrap(y_floor + 1.0_f32, height));
+        let top = self.texel(level, x0, y0) * (1.0_f32 - fx) + self.texel(level, x1, y0) * fx;
+        let bottom = self.texel(level, x0, y1) * (1.0_f32 - fx) + self.texel(level, x1, y1) * fx;
+        return top * (1.0_f32 - fy) + bottom * fy;
     }
 }
 impl Texture for Image {
     fn value(&self, uv: &TexCoord, _p: &Point3) -> RGB {
-        let i = (uv.u() * self.img.width() as f32) as u32 % self.img.width();
-        let j = ((1_f32 - uv.v()) * self.img.height() as f32) as u32 % self.img.height();
-        let pixel = self.img.get_pixel(i, j);
-        RGB::new(
-            pixel[0] as f32 / 255_f32,
-            pixel[1] as f32 / 255_f32,
-            pixel[2] as f32 / 255_f32,
-        )
-        .inverse_gamma_correct()
+        return self.lookup(uv, 0.0_f32);
+    }
+    fn filtered_value(&self, uv: &TexCoord, _p: &Point3, _normal: &Vector3, footprint: f32) -> RGB {
+        return self.lookup(uv, footprint);
+    }
+}
+
+impl Image {
+    fn lookup(&self, uv: &TexCoord, footprint: f32) -> RGB {
+        match self.filter {
+            Filter::Nearest => {
+                let i = (uv.u() * self.img.width() as f32) as u32 % self.img.width();
+                let j = ((1_f32 - uv.v()) * self.img.height() as f32) as u32 % self.img.height();
+                return self.texel(0, i, j);
+            }
+            Filter::Bilinear => return self.bilinear(0, uv),
+            Filter::Trilinear => {
+                // The level whose texels are as wide as the footprint
+                let texels_across = footprint * self.img.width().max(self.img.height()) as f32;
+                if !(texels_across > 1.0_f32) {
+                    return self.bilinear(0, uv);
+                }
+                let level = texels_across.log2().min(self.mip_levels.len() as f32);
+                let below = level.floor() as usize;
+                if below == self.mip_levels.len() {
+                    return self.bilinear(below, uv);
+                }
+                let blend = level - below as f32;
+                return self.bilinear(below, uv) * (1.0_f32 - blend)
+                    + self.bilinear(below + 1, uv) * blend;
+            }
+        }
+    }
+}
+
+// Projects another texture onto the surface along each of the three world
+// axes, blended by how squarely the surface faces each, for meshes without
+// texture coordinates and volumes. The texture repeats every 1 / scale
+// units. The blend weights are the normal's components raised to the
+// sharpness, so 1 blends broadly and higher values approach hard seams.
+// Lookups without a normal, like alpha masks and gobos, blend the three
+// evenly. The ray's footprint is measured in the surface's own texture
+// coordinates, so projected lookups are not filtered by it.
+pub struct Triplanar {
+    texture: Arc<SyncTexture>,
+    scale: f32,
+    sharpness: f32,
+}
+impl Triplanar {
+    pub fn new(texture: Arc<SyncTexture>, scale: f32, sharpness: f32) -> Triplanar {
+        Triplanar {
+            texture: texture,
+            scale: scale,
+            sharpness: sharpness,
+        }
+    }
+
+    // The projections along x, y, and z, weighted
+    fn projected(&self, p: &Point3, weights: [f32; 3]) -> RGB {
+        let coords = |a: f32, b: f32| {
+            TexCoord::new(
+                (a * self.scale).rem_euclid(1.0_f32),
+                (b * self.scale).rem_euclid(1.0_f32),
+            )
+        };
+        let projections = [
+            coords(p.z(), p.y()),
+            coords(p.x(), p.z()),
+            coords(p.x(), p.y()),
+        ];
+        let mut color = RGB::black();
+        for (uv, weight) in projections.iter().zip(weights.iter()) {
+            if *weight > 0.0_f32 {
+                color = color + self.texture.value(uv, p) * *weight;
+            }
+        }
+        return color;
+    }
+}
+impl Texture for Triplanar {
+    fn value(&self, _uv: &TexCoord, p: &Point3) -> RGB {
+        return self.projected(p, [1.0_f32 / 3.0_f32; 3]);
+    }
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3, _footprint: f32) -> RGB {
+        let weights = [
+            normal.x().abs().powf(self.sharpness),
+            normal.y().abs().powf(self.sharpness),
+            normal.z().abs().powf(self.sharpness),
+        ];
+        let total = weights[0] + weights[1] + weights[2];
+        if !(total > 0.0_f32) {
+            return self.value(uv, p);
+        }
+        return self.projected(
+            p,
+            [weights[0] / total, weights[1] / total, weights[2] / total],
+        );
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        self.texture.constant_value()
+    }
+}
+
+// Converts the colors of another texture, authored with the sRGB primaries,
+// into a scene's working space.
+pub struct WorkingSpace {
+    texture: Arc<SyncTexture>,
+    space: ColorSpace,
+}
+impl WorkingSpace {
+    pub fn new(texture: Arc<SyncTexture>, space: ColorSpace) -> WorkingSpace {
+        WorkingSpace {
+            texture: texture,
+            space: space,
+        }
+    }
+}
+impl Texture for WorkingSpace {
+    fn value(&self, uv: &TexCoord, p: &Point3) -> RGB {
+        self.space.from_rec709(self.texture.value(uv, p))
+    }
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3, footprint: f32) -> RGB {
+        self.space
+            .from_rec709(self.texture.filtered_value(uv, p, normal, footprint))
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        Some(self.space.from_rec709(self.texture.constant_value()?))
+    }
+}
+
+// Combines other textures of the scene, channel by channel, so textures can
+// be built up from one another as a small graph rather than each standing
+// alone: a dirt mask multiplied into an albedo, or two colors mixed by
+// noise. The inputs are in the working space already.
+pub struct Compose {
+    operation: Operation,
+    // The textures operated on, for Mix the base, the top, and the mask
+    inputs: Vec<Arc<SyncTexture>>,
+}
+
+pub enum Operation {
+    Multiply,
+    Add,
+    // One minus each channel
+    Invert,
+    Clamp { min: f32, max: f32 },
+    // The base where the mask is 0, the top where it is 1, and in between
+    // elsewhere
+    Mix,
+}
+
+impl Compose {
+    pub fn new(operation: Operation, inputs: Vec<Arc<SyncTexture>>) -> Compose {
+        Compose {
+            operation: operation,
+            inputs: inputs,
+        }
+    }
+
+    // The operation on the inputs, each looked up by the given function
+    fn apply<F: Fn(&SyncTexture) -> RGB>(&self, lookup: F) -> RGB {
+        let input = |i: usize| lookup(&*self.inputs[i]);
+        match self.operation {
+            Operation::Multiply => {
+                return (1..self.inputs.len()).fold(input(0), |c, i| c * input(i));
+            }
+            Operation::Add => {
+                return (1..self.inputs.len()).fold(input(0), |c, i| c + input(i));
+            }
+            Operation::Invert => {
+                let c = input(0);
+                return RGB::new(1.0_f32 - c.r(), 1.0_f32 - c.g(), 1.0_f32 - c.b());
+            }
+            Operation::Clamp { min, max } => {
+                let c = input(0);
+                return RGB::new(
+                    clamp(c.r(), min, max),
+                    clamp(c.g(), min, max),
+                    clamp(c.b(), min, max),
+                );
+            }
+            Operation::Mix => {
+                let mask = input(2);
+                let unmasked = RGB::new(1.0_f32 - mask.r(), 1.0_f32 - mask.g(), 1.0_f32 - mask.b());
+                return input(0) * unmasked + input(1) * mask;
+            }
+        }
+    }
+}
+impl Texture for Compose {
+    fn value(&self, uv: &TexCoord, p: &Point3) -> RGB {
+        self.apply(|t| t.value(uv, p))
+    }
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3, footprint: f32) -> RGB {
+        self.apply(|t| t.filtered_value(uv, p, normal, footprint))
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        for t in &self.inputs {
+            t.constant_value()?;
+        }
+        Some(self.apply(|t| {
+            t.constant_value()
+                .expect("Constant input changed while composing.")
+        }))
+    }
+}
+
+// Maps a number through a list of colors at positions along it, for toon
+// shading ramps, sky gradients, and recoloring other textures
+pub struct Ramp {
+    input: RampInput,
+    // Sorted by position
+    stops: Vec<(f32, RGB)>,
+    interpolation: RampInterpolation,
+}
+
+// What a Ramp looks its colors up by
+pub enum RampInput {
+    U,
+    V,
+    // The brightness of another texture
+    Luminance(Arc<SyncTexture>),
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+pub enum RampInterpolation {
+    // Blends between the stops on either side
+    Linear,
+    // The color of the stop at or before the input, for hard bands
+    Constant,
+}
+
+impl Ramp {
+    // Expects at least one stop. Stops at the same position make a hard
+    // edge even with Linear interpolation.
+    pub fn new(
+        input: RampInput,
+        mut stops: Vec<(f32, RGB)>,
+        interpolation: RampInterpolation,
+    ) -> Ramp {
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("Ramp stop position is NaN."));
+        Ramp {
+            input: input,
+            stops: stops,
+            interpolation: interpolation,
+        }
+    }
+
+    // Inputs before the first stop or after the last take its color
+    fn lookup(&self, x: f32) -> RGB {
+        let after = match self.stops.iter().position(|s| s.0 > x) {
+            Some(0) => return self.stops[0].1,
+            Some(i) => i,
+            None => return self.stops[self.stops.len() - 1].1,
+        };
+        let (start, start_color) = self.stops[after - 1];
+        let (end, end_color) = self.stops[after];
+        return match self.interpolation {
+            RampInterpolation::Constant => start_color,
+            RampInterpolation::Linear => {
+                let t = (x - start) / (end - start);
+                start_color * (1.0_f32 - t) + end_color * t
+            }
+        };
+    }
+}
+impl Texture for Ramp {
+    fn value(&self, uv: &TexCoord, p: &Point3) -> RGB {
+        self.lookup(match &self.input {
+            RampInput::U => uv.u(),
+            RampInput::V => uv.v(),
+            RampInput::Luminance(texture) => texture.value(uv, p).luminance(),
+        })
+    }
+    fn filtered_value(&self, uv: &TexCoord, p: &Point3, normal: &Vector3, footprint: f32) -> RGB {
+        match &self.input {
+            RampInput::Luminance(texture) => {
+                self.lookup(texture.filtered_value(uv, p, normal, footprint).luminance())
+            }
+            _ => self.value(uv, p),
+        }
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        let first = self.stops[0].1;
+        if self
+            .stops
+            .iter()
+            .all(|s| s.1.r() == first.r() && s.1.g() == first.g() && s.1.b() == first.b())
+        {
+            return Some(first);
+        }
+        match &self.input {
+            RampInput::Luminance(texture) => {
+                Some(self.lookup(texture.constant_value()?.luminance()))
+            }
+            _ => None,
+        }
     }
 }
 
+// Perlin noise, summed over octaves as fractal Brownian motion and kept to
+// [0, 1]. Noise with a seed uses its own permutation, so Noise textures with
+// different seeds each show a different pattern.
 #[derive(Deserialize)]
+#[serde(try_from = "NoiseDescription")]
 pub struct Noise {
     scale: f32,
+    perlin: Perlin,
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32,
+    // The largest the octaves can sum to, so they can be scaled back down
+    total_weight: f32,
+}
+#[derive(Deserialize)]
+struct NoiseDescription {
+    scale: f32,
+    seed: Option<u32>,
+    #[serde(default = "default_octaves")]
+    octaves: u32,
+    #[serde(default = "default_lacunarity")]
+    lacunarity: f32,
+    #[serde(default = "default_gain")]
+    gain: f32,
+}
+fn default_octaves() -> u32 {
+    1
+}
+fn default_lacunarity() -> f32 {
+    2.0_f32
+}
+fn default_gain() -> f32 {
+    0.5_f32
+}
+impl TryFrom<NoiseDescription> for Noise {
+    type Error = &'static str;
+    fn try_from(noise_desc: NoiseDescription) -> Result<Self, Self::Error> {
+        if noise_desc.octaves == 0 {
+            return Err("Noise needs at least 1 octave.");
+        }
+        check_fractal(noise_desc.lacunarity, noise_desc.gain)?;
+        let mut total_weight = 0.0_f32;
+        let mut weight = 1.0_f32;
+        for _ in 0..noise_desc.octaves {
+            total_weight += weight;
+            weight *= noise_desc.gain;
+        }
+        return Ok(Noise {
+            scale: noise_desc.scale,
+            perlin: Perlin::new(noise_desc.seed),
+            octaves: noise_desc.octaves,
+            lacunarity: noise_desc.lacunarity,
+            gain: noise_desc.gain,
+            total_weight: total_weight,
+        });
+    }
+}
+// Lacunarity and gain are shared by Noise and Turbulence
+fn check_fractal(lacunarity: f32, gain: f32) -> Result<(), &'static str> {
+    if !(lacunarity > 0.0_f32 && lacunarity.is_finite()) {
+        return Err("Noise lacunarity must be a positive number.");
+    }
+    if !(gain >= 0.0_f32 && gain.is_finite()) {
+        return Err("Noise gain can not be negative.");
+    }
+    return Ok(());
 }
 impl Texture for Noise {
     fn value(&self, _uv: &TexCoord, p: &Point3) -> RGB {
-        return RGB::new(0.5_f32, 0.5_f32, 0.5_f32) * (1.0_f32 + noise(&(*p * self.scale)));
+        let n = self
+            .perlin
+            .fbm(&(*p * self.scale), self.octaves, self.lacunarity, self.gain)
+            / self.total_weight;
+        return RGB::new(0.5_f32, 0.5_f32, 0.5_f32) * (1.0_f32 + n);
+    }
+    // A scale of 0 samples the noise at one point
+    fn constant_value(&self) -> Option<RGB> {
+        if self.scale != 0.0_f32 {
+            return None;
+        }
+        return Some(self.value(
+            &TexCoord::new(0.0_f32, 0.0_f32),
+            &Point3::new(0.0_f32, 0.0_f32, 0.0_f32),
+        ));
     }
 }
 
+// The absolute value of fractal Brownian motion, with depth octaves each
+// weighted omega times the last
 #[derive(Deserialize)]
+#[serde(try_from = "TurbulenceDescription")]
 pub struct Turbulence {
+    scale: f32,
+    perlin: Perlin,
+    depth: u32,
+    omega: f32,
+    lacunarity: f32,
+}
+#[derive(Deserialize)]
+struct TurbulenceDescription {
     scale: f32,
     depth: u32,
     omega: Omega,
+    seed: Option<u32>,
+    #[serde(default = "default_turbulence_lacunarity")]
+    lacunarity: f32,
+}
+// Just short of 2, so the octaves' lattices rarely line up
+fn default_turbulence_lacunarity() -> f32 {
+    1.99_f32
 }
 #[derive(Deserialize)]
 #[serde(try_from = "f32")]
@@ -171,9 +767,33 @@ impl TryFrom<f32> for Omega {
         }
     }
 }
+impl TryFrom<TurbulenceDescription> for Turbulence {
+    type Error = &'static str;
+    fn try_from(turbulence_desc: TurbulenceDescription) -> Result<Self, Self::Error> {
+        check_fractal(turbulence_desc.lacunarity, turbulence_desc.omega.0)?;
+        return Ok(Turbulence {
+            scale: turbulence_desc.scale,
+            perlin: Perlin::new(turbulence_desc.seed),
+            depth: turbulence_desc.depth,
+            omega: turbulence_desc.omega.0,
+            lacunarity: turbulence_desc.lacunarity,
+        });
+    }
+}
 impl Texture for Turbulence {
     fn value(&self, _uv: &TexCoord, p: &Point3) -> RGB {
-        return RGB::new(1.0_f32, 1.0_f32, 1.0_f32)
-            * turbulence(&(*p * self.scale), self.depth, self.omega.0);
+        let sum = self
+            .perlin
+            .fbm(&(*p * self.scale), self.depth, self.lacunarity, self.omega);
+        return RGB::new(1.0_f32, 1.0_f32, 1.0_f32) * sum.abs();
+    }
+    fn constant_value(&self) -> Option<RGB> {
+        if self.scale != 0.0_f32 && self.depth > 0 {
+            return None;
+        }
+        return Some(self.value(
+            &TexCoord::new(0.0_f32, 0.0_f32),
+            &Point3::new(0.0_f32, 0.0_f32, 0.0_f32),
+        ));
     }
 }
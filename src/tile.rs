@@ -0,0 +1,173 @@
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use std::cmp;
+
+// Default width and height of a tile in pixels
+pub const TILE_SIZE: u32 = 32;
+
+// A rectangular bucket of pixels rendered as one unit of work. Covers
+// x in [x_min, x_max) and y in [y_min, y_max).
+pub struct Tile {
+    pub x_min: u32,
+    pub y_min: u32,
+    pub x_max: u32,
+    pub y_max: u32,
+}
+
+impl Copy for Tile {}
+impl Clone for Tile {
+    fn clone(&self) -> Tile {
+        *self
+    }
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        return self.x_max - self.x_min;
+    }
+
+    pub fn height(&self) -> u32 {
+        return self.y_max - self.y_min;
+    }
+
+    pub fn pixel_count(&self) -> u32 {
+        return self.width() * self.height();
+    }
+}
+
+// Splits an image into tiles of at most size x size pixels, in scanline
+// order. Tiles on the right and bottom edges may be smaller.
+pub fn split_into_tiles(res_x: u32, res_y: u32, size: u32) -> Vec<Tile> {
+    return split_region(
+        &Tile {
+            x_min: 0,
+            y_min: 0,
+            x_max: res_x,
+            y_max: res_y,
+        },
+        size,
+    );
+}
+
+// Splits part of an image the same way, with tiles starting from its top
+// left corner
+pub fn split_region(region: &Tile, size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    let mut y_min = region.y_min;
+    while y_min < region.y_max {
+        let y_max = cmp::min(y_min + size, region.y_max);
+        let mut x_min = region.x_min;
+        while x_min < region.x_max {
+            let x_max = cmp::min(x_min + size, region.x_max);
+            tiles.push(Tile {
+                x_min: x_min,
+                y_min: y_min,
+                x_max: x_max,
+                y_max: y_max,
+            });
+            x_min = x_max;
+        }
+        y_min = y_max;
+    }
+    return tiles;
+}
+
+// Whether a tile overlaps a region given as fractions of the image width
+// and height, [left, top, right, bottom] from the top left
+pub fn overlaps(t: &Tile, res_x: u32, res_y: u32, region: [f32; 4]) -> bool {
+    return (t.x_min as f32) < region[2] * res_x as f32
+        && (t.x_max as f32) > region[0] * res_x as f32
+        && (t.y_min as f32) < region[3] * res_y as f32
+        && (t.y_max as f32) > region[1] * res_y as f32;
+}
+
+// How many tiles each thread is dealt up front, before the rest are left in
+// the shared overflow queue
+const DEALT_PER_THREAD: usize = 16;
+
+// Hands out tiles, by their index, to the tracing threads. Each thread has a
+// queue of its own, dealt tiles in turn from the front of the render order,
+// which it takes from without contending with the others. The rest wait in
+// a shared overflow queue that threads take batches from as theirs run dry,
+// and a thread with nothing left to take steals from the others. Priority
+// tiles are in a shared queue of their own, taken before anything else.
+pub struct TileQueue {
+    priority: Injector<usize>,
+    overflow: Injector<usize>,
+    stealers: Vec<Stealer<usize>>,
+}
+
+impl TileQueue {
+    // The queue for tiles in render order, and each thread's own end of it
+    pub fn new(
+        tile_count: usize,
+        threads: usize,
+        is_priority: impl Fn(usize) -> bool,
+    ) -> (TileQueue, Vec<Worker<usize>>) {
+        let workers: Vec<Worker<usize>> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let priority = Injector::new();
+        let overflow = Injector::new();
+        let mut dealt = 0_usize;
+        for i in 0..tile_count {
+            if is_priority(i) {
+                priority.push(i);
+            } else if dealt < threads * DEALT_PER_THREAD {
+                workers[dealt % threads].push(i);
+                dealt += 1;
+            } else {
+                overflow.push(i);
+            }
+        }
+        let queue = TileQueue {
+            priority: priority,
+            overflow: overflow,
+            stealers: workers.iter().map(|w| w.stealer()).collect(),
+        };
+        return (queue, workers);
+    }
+
+    // The next tile for a thread, given its index and its own queue, or
+    // None once every tile has been taken
+    pub fn next(&self, thread: usize, local: &Worker<usize>) -> Option<usize> {
+        loop {
+            let taken = self
+                .priority
+                .steal()
+                .or_else(|| match local.pop() {
+                    Some(t) => Steal::Success(t),
+                    None => Steal::Empty,
+                })
+                .or_else(|| self.overflow.steal_batch_and_pop(local))
+                .or_else(|| {
+                    // Starting from the next thread over, so the threads
+                    // that run dry don't all crowd the first
+                    (1..self.stealers.len())
+                        .map(|i| self.stealers[(thread + i) % self.stealers.len()].steal())
+                        .collect()
+                });
+            match taken {
+                Steal::Success(t) => return Some(t),
+                Steal::Empty => return None,
+                Steal::Retry => {}
+            }
+        }
+    }
+}
+
+// Reorders tiles so those closest to a focus point are rendered first. The
+// focus is given as a fraction of the image width and height, measured from
+// the top left, so (0.5, 0.5) renders center-out.
+pub fn sort_by_focus(tiles: &mut Vec<Tile>, res_x: u32, res_y: u32, focus: [f32; 2]) {
+    let focus_x = focus[0] * res_x as f32;
+    let focus_y = focus[1] * res_y as f32;
+    let distance_squared = |t: &Tile| {
+        let dx = (t.x_min + t.x_max) as f32 * 0.5_f32 - focus_x;
+        let dy = (t.y_min + t.y_max) as f32 * 0.5_f32 - focus_y;
+        dx * dx + dy * dy
+    };
+    // A stable sort keeps scanline order between tiles at equal distances
+    tiles.sort_by(|a, b| {
+        distance_squared(a)
+            .partial_cmp(&distance_squared(b))
+            .unwrap_or(cmp::Ordering::Equal)
+    });
+}
@@ -0,0 +1,192 @@
+use crate::texture::TexCoord;
+
+use std::collections::HashMap;
+
+// Width and height of the grid the unit UV square is rasterized into
+pub const UV_RESOLUTION: u32 = 512_u32;
+
+// What the UV layout of one mesh looks like, for the info subcommand
+pub struct UVReport {
+    pub triangles: usize,
+    pub islands: usize,
+    // Islands reaching outside the unit square, where image textures repeat
+    pub out_of_range_islands: usize,
+    // Islands sharing texels with another island
+    pub overlapping_islands: usize,
+    // Islands that cover some texels more than once by themselves, as a
+    // mirrored or folded layout does
+    pub folded_islands: usize,
+    // Share of the covered texels that are covered more than once
+    pub overlap_fraction: f32,
+    // Occupancy of the unit square as 8 bit RGB in row-major order, with v
+    // going up the image. Texels covered once are grey, more than once red.
+    pub image: Vec<u8>,
+}
+
+impl UVReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} triangles in {} UV islands, {} outside the unit square, {} overlapping another, \
+             {} folded over themselves. {:.1}% of covered texels are covered more than once.",
+            self.triangles,
+            self.islands,
+            self.out_of_range_islands,
+            self.overlapping_islands,
+            self.folded_islands,
+            self.overlap_fraction * 100.0_f32
+        )
+    }
+}
+
+// Islands are found by joining triangles that share a corner with exactly
+// the same texture coordinates, so a seam splits islands even where the
+// vertices on either side are shared.
+pub fn inspect(triangles: &Vec<[TexCoord; 3]>) -> UVReport {
+    let mut parents: Vec<usize> = (0..triangles.len()).collect();
+    let mut first_with_corner: HashMap<(u32, u32), usize> = HashMap::new();
+    for (i, tri) in triangles.iter().enumerate() {
+        for uv in tri {
+            let key = (uv.u().to_bits(), uv.v().to_bits());
+            match first_with_corner.get(&key) {
+                Some(other) => join(&mut parents, i, *other),
+                None => {
+                    first_with_corner.insert(key, i);
+                }
+            }
+        }
+    }
+
+    let mut island_ids: HashMap<usize, usize> = HashMap::new();
+    let mut island_of = Vec::with_capacity(triangles.len());
+    for i in 0..triangles.len() {
+        let root = find(&mut parents, i);
+        let next_id = island_ids.len();
+        island_of.push(*island_ids.entry(root).or_insert(next_id));
+    }
+    let island_count = island_ids.len();
+
+    let mut out_of_range = vec![false; island_count];
+    for (i, tri) in triangles.iter().enumerate() {
+        if tri
+            .iter()
+            .any(|uv| uv.u() < 0.0_f32 || uv.u() > 1.0_f32 || uv.v() < 0.0_f32 || uv.v() > 1.0_f32)
+        {
+            out_of_range[island_of[i]] = true;
+        }
+    }
+
+    // Each texel remembers the first island to cover it and how many
+    // triangles did
+    let texel_count = (UV_RESOLUTION * UV_RESOLUTION) as usize;
+    let mut coverage = vec![0_u32; texel_count];
+    let mut owner = vec![0_usize; texel_count];
+    let mut overlapping = vec![false; island_count];
+    let mut folded = vec![false; island_count];
+    for (i, tri) in triangles.iter().enumerate() {
+        let island = island_of[i];
+        for texel in covered_texels(tri) {
+            if coverage[texel] > 0 {
+                if owner[texel] == island {
+                    folded[island] = true;
+                } else {
+                    overlapping[island] = true;
+                    overlapping[owner[texel]] = true;
+                }
+            } else {
+                owner[texel] = island;
+            }
+            coverage[texel] += 1;
+        }
+    }
+
+    let covered = coverage.iter().filter(|c| **c > 0).count();
+    let overlapped = coverage.iter().filter(|c| **c > 1).count();
+    let mut image = Vec::with_capacity(texel_count * 3);
+    for c in &coverage {
+        let rgb = match c {
+            0 => [0_u8, 0_u8, 0_u8],
+            1 => [160_u8, 160_u8, 160_u8],
+            _ => [255_u8, 0_u8, 0_u8],
+        };
+        image.extend_from_slice(&rgb);
+    }
+
+    let count = |flags: &Vec<bool>| flags.iter().filter(|f| **f).count();
+    UVReport {
+        triangles: triangles.len(),
+        islands: island_count,
+        out_of_range_islands: count(&out_of_range),
+        overlapping_islands: count(&overlapping),
+        folded_islands: count(&folded),
+        overlap_fraction: if covered > 0 {
+            overlapped as f32 / covered as f32
+        } else {
+            0.0_f32
+        },
+        image: image,
+    }
+}
+
+fn find(parents: &mut Vec<usize>, i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // Point everything on the way straight at the root
+    let mut cur = i;
+    while parents[cur] != root {
+        let next = parents[cur];
+        parents[cur] = root;
+        cur = next;
+    }
+    return root;
+}
+
+fn join(parents: &mut Vec<usize>, a: usize, b: usize) {
+    let root_a = find(parents, a);
+    let root_b = find(parents, b);
+    parents[root_a] = root_b;
+}
+
+// Indices, in image order, of the texels whose centers are strictly inside
+// the triangle. Centers exactly on an edge are left out, so triangles that
+// share an edge never both claim a texel. Parts outside the unit square are
+// not drawn.
+fn covered_texels(tri: &[TexCoord; 3]) -> Vec<usize> {
+    let res = UV_RESOLUTION as f32;
+    let points: Vec<(f32, f32)> = tri
+        .iter()
+        .map(|uv| (uv.u() * res, (1.0_f32 - uv.v()) * res))
+        .collect();
+    let edge = |a: (f32, f32), b: (f32, f32), x: f32, y: f32| {
+        (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0)
+    };
+    let area = edge(points[0], points[1], points[2].0, points[2].1);
+    if area == 0.0_f32 {
+        return Vec::new();
+    }
+
+    let min_x = points.iter().map(|p| p.0).fold(f32::MAX, f32::min);
+    let max_x = points.iter().map(|p| p.0).fold(f32::MIN, f32::max);
+    let min_y = points.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+    let max_y = points.iter().map(|p| p.1).fold(f32::MIN, f32::max);
+    let x_start = min_x.floor().max(0.0_f32) as u32;
+    let x_end = (max_x.ceil().max(0.0_f32) as u32).min(UV_RESOLUTION);
+    let y_start = min_y.floor().max(0.0_f32) as u32;
+    let y_end = (max_y.ceil().max(0.0_f32) as u32).min(UV_RESOLUTION);
+
+    let mut texels = Vec::new();
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            let (cx, cy) = (x as f32 + 0.5_f32, y as f32 + 0.5_f32);
+            // Same sign as the whole triangle on every edge, whichever way
+            // it winds
+            let inside = (0..3)
+                .all(|i| edge(points[i], points[(i + 1) % 3], cx, cy) * area.signum() > 0.0_f32);
+            if inside {
+                texels.push((y * UV_RESOLUTION + x) as usize);
+            }
+        }
+    }
+    return texels;
+}
@@ -0,0 +1,99 @@
+// Versions of the scene specification format. Every scene gives the version
+// it was written for as a top level "Version", and scenes of an older
+// version are migrated up to the current one, one version at a time, before
+// anything else reads them. Scenes without one were written before versions
+// were added, and are version 1. Scenes of a newer version than this build
+// knows are refused rather than read as if they were current.
+//
+// A change to the format that would read an old scene differently bumps
+// CURRENT_VERSION and adds a migration from the previous version to the end
+// of MIGRATIONS.
+
+pub const CURRENT_VERSION: u64 = 3;
+
+// MIGRATIONS[i] rewrites a scene of version i + 1 as a scene of version i + 2
+const MIGRATIONS: [fn(&mut serde_json::Value) -> Result<(), String>;
+    (CURRENT_VERSION - 1) as usize] = [unbumped_materials, no_reconstruction];
+
+// Version 2
+// Dielectric, Clearcoat, CarPaint, Measured, and Toon materials read
+// 'bump_map' and 'normal_map', which version 1 left unread, so version 1
+// scenes that gave them one rendered without it
+fn unbumped_materials(top_level: &mut serde_json::Value) -> Result<(), String> {
+    let materials = match top_level.get_mut("Materials") {
+        Some(serde_json::Value::Object(m)) => m,
+        _ => return Ok(()),
+    };
+    for material in materials.values_mut() {
+        let fields = match material {
+            serde_json::Value::Object(f) => f,
+            _ => continue,
+        };
+        match fields.get("type").and_then(|t| t.as_str()) {
+            Some("Dielectric") | Some("Clearcoat") | Some("CarPaint") | Some("Measured")
+            | Some("Toon") => {
+                fields.remove("bump_map");
+                fields.remove("normal_map");
+            }
+            _ => {}
+        }
+    }
+    return Ok(());
+}
+
+// Version 3
+// Gradient domain renders always reconstruct with L2, and no longer read a
+// 'reconstruction', which L1 renders gave. Triangles of culled meshes are
+// also counted from behind in light sampling pdfs, so culled emissive meshes
+// no longer light the scene too brightly, which no rewrite of the scene can
+// bring back.
+fn no_reconstruction(top_level: &mut serde_json::Value) -> Result<(), String> {
+    match top_level
+        .get_mut("Logistics")
+        .and_then(|l| l.get_mut("gradient_domain"))
+    {
+        Some(serde_json::Value::Object(g)) => {
+            g.remove("reconstruction");
+        }
+        _ => {}
+    }
+    return Ok(());
+}
+
+// Brings the top level of a scene up to the current version in place
+pub fn migrate(top_level: &mut serde_json::Value) -> Result<(), String> {
+    let version = match top_level.get("Version") {
+        Some(v) => match v.as_u64() {
+            Some(v) if v > 0 => v,
+            _ => {
+                return Err(format!(
+                    "'Version' is not a positive whole number, it is {}.",
+                    v
+                ))
+            }
+        },
+        None => 1,
+    };
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "Scene spec is version {}, newer than the latest version this build can read, {}.",
+            version, CURRENT_VERSION
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip((version - 1) as usize) {
+        match migration(top_level) {
+            Ok(()) => {}
+            Err(e) => {
+                return Err(format!(
+                    "Failed to migrate scene spec from version {} to {}: {}",
+                    index + 1,
+                    index + 2,
+                    e
+                ))
+            }
+        }
+    }
+    top_level["Version"] = serde_json::Value::from(CURRENT_VERSION);
+    return Ok(());
+}
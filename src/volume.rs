@@ -3,12 +3,12 @@ use crate::material::Reflectance;
 use crate::material::ScatterProperties;
 use crate::material::{Material, SyncMaterial};
 use crate::ray::Ray;
+use crate::rng;
+use crate::sampling::unit_sphere_random;
 use crate::shape::HitProperties;
-use crate::shape::{Shape, SyncShape};
+use crate::shape::{Hit, Shape, SyncShape};
 use crate::texture::SyncTexture;
-use crate::utils::unit_sphere_random;
 
-use rand;
 use std::sync::Arc;
 
 // TODO: Separate Phase Functions from Materials, and make them specific to Mediums
@@ -25,12 +25,21 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, _in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
+    fn scatter(&self, in_ray: &Ray, hit_props: &HitProperties) -> Option<ScatterProperties> {
         Some(ScatterProperties {
             // TODO: Technically not correct, this volume is not specular, but for now
             // I just want it to not use a PDF
-            reflectance: Reflectance::Specular(Ray::new(hit_props.hit_point, unit_sphere_random())),
-            attenuation: self.albedo.value(&hit_props.uv, &hit_props.hit_point),
+            reflectance: Reflectance::Specular(Ray::new(
+                hit_props.hit_point,
+                unit_sphere_random(),
+                in_ray.time,
+            )),
+            attenuation: self.albedo.filtered_value(
+                &hit_props.uv,
+                &hit_props.hit_point,
+                &hit_props.normal,
+                hit_props.uv_footprint,
+            ),
         })
     }
 
@@ -62,17 +71,18 @@ impl ConstantMedium {
 }
 
 impl Shape for ConstantMedium {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
-        let mut t_hit1 = match self.boundary.hit(r, -std::f32::MAX, std::f32::MAX) {
-            Some(t) => t,
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let entry = match self.boundary.hit(r, -std::f32::MAX, std::f32::MAX) {
+            Some(h) => h,
             None => return None,
         };
+        let mut t_hit1 = entry.t;
 
         let mut t_hit2 = match self
             .boundary
             .hit(r, t_hit1 + std::f32::EPSILON, std::f32::MAX)
         {
-            Some(t) => t,
+            Some(h) => h.t,
             None => return None,
         };
 
@@ -92,17 +102,17 @@ impl Shape for ConstantMedium {
         }
 
         let distance_inside_boundary = (t_hit2 - t_hit1) * r.dir.length();
-        let hit_dist = (-1.0_f32 / self.density) * rand::random::<f32>().ln();
+        let hit_dist = (-1.0_f32 / self.density) * rng::random().ln();
 
         if hit_dist > distance_inside_boundary {
             return None;
         }
 
-        return Some(t_hit1 + (hit_dist / r.dir.length()));
+        return Some(entry.at(t_hit1 + (hit_dist / r.dir.length())));
     }
 
-    fn get_hit_properties(&self, r: &Ray, t_hit: f32) -> HitProperties {
-        self.boundary.get_hit_properties(r, t_hit)
+    fn get_hit_properties(&self, r: &Ray, hit: &Hit) -> HitProperties {
+        self.boundary.get_hit_properties(r, hit)
     }
 
     fn get_material(&self) -> &Arc<SyncMaterial> {
@@ -113,11 +123,21 @@ impl Shape for ConstantMedium {
         self.boundary.get_bounding_box()
     }
 
-    fn pdf(&self, r: &Ray) -> f32 {
-        self.boundary.pdf(r)
+    fn pdf(&self, r: &Ray, t_min: f32) -> f32 {
+        self.boundary.pdf(r, t_min)
     }
 
     fn random_dir_towards(&self, from_origin: &crate::point::Point3) -> crate::vector::Vector3 {
         self.boundary.random_dir_towards(from_origin)
     }
+
+    // Hits are scattered through the volume rather than on a surface, so
+    // there is no back to see
+    fn is_front_face(&self, _r: &Ray, _hit: &Hit) -> bool {
+        true
+    }
+
+    fn instance_depth(&self) -> usize {
+        self.boundary.instance_depth()
+    }
 }
@@ -0,0 +1,75 @@
+use crate::aggregate::Workspace;
+use crate::color::RGB;
+use crate::ray::Ray;
+use crate::scene::Scene;
+
+use serde::Deserialize;
+
+// A debug render option that draws the edges of triangles, which makes
+// problems with imported or tessellated meshes easy to spot. Lines are only
+// drawn on the surfaces the camera sees directly, and shapes without edges,
+// like spheres, get none.
+#[derive(Deserialize)]
+pub struct Wireframe {
+    mode: WireframeMode,
+    // Width of the lines in degrees, as seen from the camera
+    #[serde(default = "default_line_width")]
+    line_width: f32,
+    #[serde(default = "default_color")]
+    color: RGB,
+}
+
+#[derive(Deserialize)]
+pub enum WireframeMode {
+    // Lines over the shaded render
+    Overlay,
+    // Just the lines, over black
+    Only,
+}
+
+fn default_line_width() -> f32 {
+    0.2_f32
+}
+
+fn default_color() -> RGB {
+    RGB::new(1.0_f32, 1.0_f32, 1.0_f32)
+}
+
+impl Wireframe {
+    // The line color if the first thing the camera ray hits is near an edge.
+    // The allowed distance grows with the distance to the hit, so lines keep
+    // the same width on the image however far away they are.
+    pub fn edge_color(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        scene: &Scene,
+        workspace: &mut Workspace,
+    ) -> Option<RGB> {
+        let (shape, hit) = match scene.shape_aggregate.hit(r, t_min, t_max, workspace) {
+            Some(h) => h,
+            None => return None,
+        };
+        let t_hit = hit.t;
+        let edge_distance = match shape.edge_distance(r, &hit) {
+            Some(d) => d,
+            None => return None,
+        };
+
+        let hit_distance = t_hit * r.dir.length();
+        let half_width = (self.line_width.to_radians() / 2.0_f32).tan() * hit_distance;
+        if edge_distance < half_width {
+            return Some(self.color);
+        }
+        return None;
+    }
+
+    // Whether the render under the lines is traced at all
+    pub fn shades(&self) -> bool {
+        match self.mode {
+            WireframeMode::Overlay => true,
+            WireframeMode::Only => false,
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// Golden image tests, run with `cargo test --features testing`. After an
+// intended change to how a scene renders, delete its reference, write a new
+// one with `cargo test --features testing -- --ignored`, and check it by eye
+// before committing it.
+
+use rust_raytracer::testing;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The test scenes, which are the example scenes in assets/, with the name
+// of the reference each is compared against in assets/references/
+const SCENES: [(&str, &str); 1] = [(
+    "Cornell_Box_With_Sphere.json",
+    "cornell_box_with_sphere.png",
+)];
+
+fn assets() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+}
+
+fn render(scene_file: &str) -> Vec<u8> {
+    let scene_json = match fs::read_to_string(assets().join(scene_file)) {
+        Ok(s) => s,
+        Err(e) => panic!("Failed to read {}: {}", scene_file, e),
+    };
+    match testing::render_test_scene(&scene_json, &assets()) {
+        Ok(rendered) => return rendered,
+        Err(e) => panic!("{}: {}", scene_file, e),
+    }
+}
+
+#[test]
+fn matches_references() {
+    for (scene_file, reference) in SCENES.iter() {
+        let reference_png = match fs::read(assets().join("references").join(reference)) {
+            Ok(r) => r,
+            Err(e) => panic!("Failed to read reference {}: {}", reference, e),
+        };
+        match testing::compare_to_reference(
+            &render(scene_file),
+            &reference_png,
+            testing::DEFAULT_TOLERANCE,
+        ) {
+            Ok(()) => {}
+            Err(e) => panic!("{}: {}", scene_file, e),
+        }
+    }
+}
+
+// Writes the references that are missing
+#[test]
+#[ignore]
+fn write_references() {
+    for (scene_file, reference) in SCENES.iter() {
+        let path = assets().join("references").join(reference);
+        if path.exists() {
+            continue;
+        }
+        match testing::write_reference(&render(scene_file), &path) {
+            Ok(()) => println!("Wrote {}", path.display()),
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// Checks every example scene in assets/ against the schema the schema
+// subcommand writes, so the hand written schema can not drift from the
+// scenes the raytracer reads. The checker covers the parts of JSON Schema
+// (draft 7) that the schema uses, apart from string patterns.
+
+use rust_raytracer::schema;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+// Where a value is in the scene, for failures
+fn at(path: &str, key: &str) -> String {
+    return format!("{}/{}", path, key);
+}
+
+fn check_type(type_name: &str, value: &Value) -> bool {
+    return match type_name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => match value.as_f64() {
+            Some(n) => n.fract() == 0.0_f64,
+            None => false,
+        },
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => panic!("Unknown type {} in the schema", type_name),
+    };
+}
+
+// The schema a $ref refers to, or the schema itself
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    return match schema.get("$ref").and_then(|r| r.as_str()) {
+        Some(reference) => match reference.strip_prefix("#/definitions/") {
+            Some(name) => resolve(root, &root["definitions"][name]),
+            None => panic!("Unsupported $ref {} in the schema", reference),
+        },
+        None => schema,
+    };
+}
+
+fn validate(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let schema = resolve(root, schema);
+    match schema.get("type").and_then(|t| t.as_str()) {
+        Some(t) if !check_type(t, value) => {
+            return Err(format!("{} is not of type {}", path, t));
+        }
+        _ => {}
+    }
+    if let Some(constant) = schema.get("const") {
+        if value != constant {
+            return Err(format!("{} is not {}", path, constant));
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !values.contains(value) {
+            return Err(format!("{} is not one of {:?}", path, values));
+        }
+    }
+
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(|m| m.as_f64()) {
+            if n < min {
+                return Err(format!("{} is below {}", path, min));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(|m| m.as_f64()) {
+            if n > max {
+                return Err(format!("{} is above {}", path, max));
+            }
+        }
+        if let Some(min) = schema.get("exclusiveMinimum").and_then(|m| m.as_f64()) {
+            if n <= min {
+                return Err(format!("{} is not above {}", path, min));
+            }
+        }
+    }
+
+    if let Some(items) = value.as_array() {
+        if let Some(min) = schema.get("minItems").and_then(|m| m.as_u64()) {
+            if (items.len() as u64) < min {
+                return Err(format!("{} has fewer than {} items", path, min));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(|m| m.as_u64()) {
+            if items.len() as u64 > max {
+                return Err(format!("{} has more than {} items", path, max));
+            }
+        }
+        if let Some(item_schema) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                validate(root, item_schema, item, &at(path, &i.to_string()))?;
+            }
+        }
+    }
+
+    if let Some(fields) = value.as_object() {
+        if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+            for key in required {
+                let key = key.as_str().unwrap_or_default();
+                if !fields.contains_key(key) {
+                    return Err(format!("{} is missing {}", path, key));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for (key, field) in fields {
+            match properties.and_then(|p| p.get(key)) {
+                Some(property) => validate(root, property, field, &at(path, key))?,
+                None => match schema.get("additionalProperties") {
+                    Some(additional) => validate(root, additional, field, &at(path, key))?,
+                    None => {}
+                },
+            }
+        }
+    }
+
+    if let Some(all) = schema.get("allOf").and_then(|a| a.as_array()) {
+        for sub in all {
+            validate(root, sub, value, path)?;
+        }
+    }
+    if let Some(any) = schema.get("anyOf").and_then(|a| a.as_array()) {
+        if !any
+            .iter()
+            .any(|sub| validate(root, sub, value, path).is_ok())
+        {
+            return Err(format!("{} matches none of its choices", path));
+        }
+    }
+    if let Some(one) = schema.get("oneOf").and_then(|o| o.as_array()) {
+        let matches: Vec<Result<(), String>> = one
+            .iter()
+            .map(|sub| validate(root, sub, value, path))
+            .collect();
+        match matches.iter().filter(|m| m.is_ok()).count() {
+            1 => {}
+            0 => {
+                // Choices are mostly picked out by the value's 'type', so the
+                // reason the choice of that type failed is the one to report,
+                // and otherwise one that failed inside the value
+                let meant = one.iter().position(|sub| {
+                    let properties = &resolve(root, sub)["properties"];
+                    return value.get("type").is_some()
+                        && properties["type"]["const"] == value["type"];
+                });
+                let inside = format!("{}/", path);
+                return match meant {
+                    Some(i) => matches.into_iter().nth(i).unwrap(),
+                    None => match matches
+                        .into_iter()
+                        .filter_map(|m| m.err())
+                        .find(|e| e.starts_with(&inside))
+                    {
+                        Some(e) => Err(e),
+                        None => Err(format!("{} matches none of its choices", path)),
+                    },
+                };
+            }
+            n => return Err(format!("{} matches {} of its choices, not one", path, n)),
+        }
+    }
+    if let Some(not) = schema.get("not") {
+        if validate(root, not, value, path).is_ok() {
+            return Err(format!("{} matches what it must not", path));
+        }
+    }
+    return Ok(());
+}
+
+#[test]
+fn example_scenes_match_schema() {
+    let scene_schema = schema::scene_schema();
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let mut checked = 0;
+    for entry in fs::read_dir(&assets).expect("Failed to list assets") {
+        let path = entry.expect("Failed to list assets").path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let scene: Value = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).expect("Example scene is not JSON"),
+            Err(e) => panic!("Failed to read {}: {}", path.display(), e),
+        };
+        match validate(&scene_schema, &scene_schema, &scene, "") {
+            Ok(()) => {}
+            Err(e) => panic!("{} does not match the schema: {}", path.display(), e),
+        }
+        checked += 1;
+    }
+    assert!(checked > 0, "No example scenes in {}", assets.display());
+}